@@ -1,27 +1,162 @@
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll};
 use std::time::{Duration, Instant};
+use tokio::io::AsyncWrite;
+use tokio::time::Sleep;
+
+
+const BURST_SECONDS: f64 = 0.1;
+
+
+const MIN_BURST_BYTES: f64 = 4096.0;
+
+
+pub struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    state: Mutex<(f64, Instant)>,
+}
+
+impl TokenBucket {
+
+    pub fn new(bytes_per_sec: u64) -> Self {
+        let rate = bytes_per_sec.max(1) as f64;
+        let capacity = (rate * BURST_SECONDS).max(MIN_BURST_BYTES);
+        TokenBucket {
+            rate,
+            capacity,
+            state: Mutex::new((capacity, Instant::now())),
+        }
+    }
+
+
+    pub fn consume(&self, bytes: u64) -> Duration {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let (tokens, last) = &mut *state;
+
+        let now = Instant::now();
+        *tokens = (*tokens + now.duration_since(*last).as_secs_f64() * self.rate).min(self.capacity);
+        *last = now;
+        *tokens -= bytes as f64;
+
+        if *tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-*tokens / self.rate)
+        }
+    }
+}
+
 
 pub struct BandwidthLimiter {
-    limit: u64,
-    start_time: Instant,
-    bytes_sent: u64,
+    bucket: TokenBucket,
 }
 
 impl BandwidthLimiter {
     pub fn new(limit: u64) -> Self {
         BandwidthLimiter {
-            limit,
-            start_time: Instant::now(),
-            bytes_sent: 0,
+            bucket: TokenBucket::new(limit),
         }
     }
 
     pub fn limit(&mut self, bytes: u64) {
-        self.bytes_sent += bytes;
-        let elapsed = self.start_time.elapsed();
-        let expected_time = Duration::from_secs_f64(self.bytes_sent as f64 / self.limit as f64);
-        if expected_time > elapsed {
-            let delay = expected_time - elapsed;
+        let delay = self.bucket.consume(bytes);
+        if !delay.is_zero() {
             std::thread::sleep(delay);
         }
     }
 }
+
+
+pub struct ThrottledWriter<W> {
+    inner: W,
+    buckets: Vec<Arc<TokenBucket>>,
+    delay: Option<Pin<Box<Sleep>>>,
+}
+
+impl<W: AsyncWrite + Unpin> ThrottledWriter<W> {
+
+    pub fn new(inner: W, buckets: Vec<Arc<TokenBucket>>) -> Self {
+        ThrottledWriter {
+            inner,
+            buckets,
+            delay: None,
+        }
+    }
+
+    fn poll_delay(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        if let Some(delay) = self.delay.as_mut() {
+            ready!(delay.as_mut().poll(cx));
+            self.delay = None;
+        }
+        Poll::Ready(())
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for ThrottledWriter<W> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_delay(cx));
+
+        let n = ready!(Pin::new(&mut this.inner).poll_write(cx, buf))?;
+
+        let delay = this.buckets.iter()
+            .map(|bucket| bucket.consume(n as u64))
+            .max()
+            .unwrap_or_default();
+        if !delay.is_zero() {
+            this.delay = Some(Box::pin(tokio::time::sleep(delay)));
+        }
+
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+
+    #[test]
+    fn test_burst_is_free_then_debt_is_charged() {
+        let bucket = TokenBucket::new(100_000);
+        assert_eq!(bucket.consume(10_000), Duration::ZERO);
+
+        let delay = bucket.consume(50_000);
+        assert!(delay > Duration::from_millis(450));
+        assert!(delay <= Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_shared_bucket_accumulates_debt() {
+        let bucket = Arc::new(TokenBucket::new(10_000));
+        let other = bucket.clone();
+        bucket.consume(4096);
+
+        let first = bucket.consume(1000);
+        let second = other.consume(1000);
+        assert!(second > first);
+    }
+
+    #[tokio::test]
+    async fn test_throttled_writer_passes_data_through() -> io::Result<()> {
+        let buckets = vec![Arc::new(TokenBucket::new(1_000_000))];
+        let mut writer = ThrottledWriter::new(Vec::new(), buckets);
+        writer.write_all(b"flist").await?;
+        writer.write_all(&[7u8; 8192]).await?;
+        writer.flush().await?;
+        assert_eq!(writer.inner.len(), 5 + 8192);
+        Ok(())
+    }
+}
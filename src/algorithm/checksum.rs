@@ -3,15 +3,24 @@ use blake2::Blake2b512;
 use digest::Digest;
 use md4::Md4 as Md4Hasher;
 use md5::Md5 as Md5Hasher;
+use twox_hash::{XxHash3_128, XxHash3_64, XxHash64};
 
 
 
+const CHAR_OFFSET: u32 = 0;
+
+
+fn signed(byte: u8) -> u32 {
+    (byte as i8 as i32 as u32).wrapping_add(CHAR_OFFSET)
+}
+
+
 #[derive(Debug, Clone)]
 pub struct RollingChecksum {
 
-    a: u16,
+    s1: u32,
 
-    b: u16,
+    s2: u32,
 
     block_size: usize,
 }
@@ -19,47 +28,38 @@ pub struct RollingChecksum {
 impl RollingChecksum {
 
     pub fn new(data: &[u8]) -> Self {
-        let mut checksum = Self {
-            a: 0,
-            b: 0,
+        let (s1, s2) = data.iter().fold((0u32, 0u32), |(s1, s2), &byte| {
+            let s1 = s1.wrapping_add(signed(byte));
+            (s1, s2.wrapping_add(s1))
+        });
+        Self {
+            s1,
+            s2,
             block_size: data.len(),
-        };
-        checksum.update(data);
-        checksum
-    }
-
-
-    fn update(&mut self, data: &[u8]) {
-        self.a = 0;
-        self.b = 0;
-
-        for (i, &byte) in data.iter().enumerate() {
-            self.a = self.a.wrapping_add(byte as u16);
-            self.b = self
-                .b
-                .wrapping_add(((data.len() - i) as u16).wrapping_mul(byte as u16));
         }
     }
 
 
 
     pub fn roll(&mut self, old_byte: u8, new_byte: u8) {
-
-        self.a = self
-            .a
-            .wrapping_sub(old_byte as u16)
-            .wrapping_add(new_byte as u16);
+        let old = signed(old_byte);
+        self.s1 = self.s1.wrapping_sub(old).wrapping_add(signed(new_byte));
+        self.s2 = self.s2
+            .wrapping_sub((self.block_size as u32).wrapping_mul(old))
+            .wrapping_add(self.s1);
+    }
 
 
-        self.b = self
-            .b
-            .wrapping_sub((self.block_size as u16).wrapping_mul(old_byte as u16))
-            .wrapping_add(self.a);
+    pub fn roll_out(&mut self, old_byte: u8) {
+        let old = signed(old_byte);
+        self.s1 = self.s1.wrapping_sub(old);
+        self.s2 = self.s2.wrapping_sub((self.block_size as u32).wrapping_mul(old));
+        self.block_size = self.block_size.saturating_sub(1);
     }
 
 
     pub fn checksum(&self) -> u32 {
-        ((self.b as u32) << 16) | (self.a as u32)
+        (self.s1 & 0xffff) | (self.s2 << 16)
     }
 
 
@@ -75,6 +75,9 @@ pub enum StrongChecksum {
     Md4([u8; 16]),
     Md5([u8; 16]),
     Blake2([u8; 64]),
+    Xxh64([u8; 8]),
+    Xxh3([u8; 8]),
+    Xxh128([u8; 16]),
 }
 
 impl StrongChecksum {
@@ -84,16 +87,44 @@ impl StrongChecksum {
             StrongChecksum::Md4(bytes) => bytes,
             StrongChecksum::Md5(bytes) => bytes,
             StrongChecksum::Blake2(bytes) => bytes,
+            StrongChecksum::Xxh64(bytes) => bytes,
+            StrongChecksum::Xxh3(bytes) => bytes,
+            StrongChecksum::Xxh128(bytes) => bytes,
         }
     }
 }
 
 
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChecksumSeed {
+
+    pub value: i32,
+
+    pub seed_first: bool,
+}
+
+impl ChecksumSeed {
+    pub fn new(value: i32, seed_first: bool) -> Self {
+        Self { value, seed_first }
+    }
+}
+
+
 pub fn compute_strong_checksum(data: &[u8], algorithm: &ChecksumAlgorithm) -> StrongChecksum {
+    compute_seeded_checksum(data, algorithm, ChecksumSeed::default())
+}
+
+
+pub fn compute_seeded_checksum(data: &[u8], algorithm: &ChecksumAlgorithm, seed: ChecksumSeed) -> StrongChecksum {
+    let seed_bytes = seed.value.to_le_bytes();
+    let xxh_seed = seed.value as i64 as u64;
     match algorithm {
         ChecksumAlgorithm::Md4 => {
             let mut hasher = Md4Hasher::new();
             hasher.update(data);
+            if seed.value != 0 {
+                hasher.update(seed_bytes);
+            }
             let result = hasher.finalize();
             let mut bytes = [0u8; 16];
             bytes.copy_from_slice(&result);
@@ -101,7 +132,13 @@ pub fn compute_strong_checksum(data: &[u8], algorithm: &ChecksumAlgorithm) -> St
         }
         ChecksumAlgorithm::Md5 => {
             let mut hasher = Md5Hasher::new();
+            if seed.seed_first && seed.value != 0 {
+                hasher.update(seed_bytes);
+            }
             hasher.update(data);
+            if !seed.seed_first && seed.value != 0 {
+                hasher.update(seed_bytes);
+            }
             let result = hasher.finalize();
             let mut bytes = [0u8; 16];
             bytes.copy_from_slice(&result);
@@ -115,16 +152,9 @@ pub fn compute_strong_checksum(data: &[u8], algorithm: &ChecksumAlgorithm) -> St
             bytes.copy_from_slice(&result);
             StrongChecksum::Blake2(bytes)
         }
-        ChecksumAlgorithm::Xxh128 => {
-
-
-            let mut hasher = Md5Hasher::new();
-            hasher.update(data);
-            let result = hasher.finalize();
-            let mut bytes = [0u8; 16];
-            bytes.copy_from_slice(&result);
-            StrongChecksum::Md5(bytes)
-        }
+        ChecksumAlgorithm::Xxh64 => StrongChecksum::Xxh64(XxHash64::oneshot(xxh_seed, data).to_le_bytes()),
+        ChecksumAlgorithm::Xxh3 => StrongChecksum::Xxh3(XxHash3_64::oneshot_with_seed(xxh_seed, data).to_le_bytes()),
+        ChecksumAlgorithm::Xxh128 => StrongChecksum::Xxh128(XxHash3_128::oneshot_with_seed(xxh_seed, data).to_le_bytes()),
     }
 }
 
@@ -179,6 +209,25 @@ mod tests {
         assert_ne!(first_checksum, second_checksum);
     }
 
+    #[test]
+    fn test_rolling_checksum_matches_rsync() {
+
+        assert_eq!(RollingChecksum::new(&[0x80]).checksum(), 0xff80_ff80);
+        assert_eq!(RollingChecksum::new(&[1, 2, 3]).checksum(), 6 | ((1 + 3 + 6) << 16));
+        assert_eq!(RollingChecksum::new(b"").checksum(), 0);
+
+        let data: Vec<u8> = (0..64u32).map(|i| (i * 37 + 200) as u8).collect();
+        let mut rolling = RollingChecksum::new(&data[..16]);
+        for start in 1..=48 {
+            rolling.roll(data[start - 1], data[start + 15]);
+            assert_eq!(rolling.checksum(), RollingChecksum::new(&data[start..start + 16]).checksum());
+        }
+        for start in 49..64 {
+            rolling.roll_out(data[start - 1]);
+            assert_eq!(rolling.checksum(), RollingChecksum::new(&data[start..]).checksum());
+        }
+    }
+
     #[test]
     fn test_strong_checksum_md5() {
         let data = b"test data";
@@ -218,4 +267,55 @@ mod tests {
 
         assert_eq!(checksum1, checksum2);
     }
+
+    #[test]
+    fn test_strong_checksum_xxhash() {
+        assert_eq!(
+            compute_strong_checksum(b"", &ChecksumAlgorithm::Xxh64),
+            StrongChecksum::Xxh64(0xEF46DB3751D8E999u64.to_le_bytes())
+        );
+        assert_eq!(
+            compute_strong_checksum(b"", &ChecksumAlgorithm::Xxh3),
+            StrongChecksum::Xxh3(0x2D06800538D394C2u64.to_le_bytes())
+        );
+        assert_eq!(
+            compute_strong_checksum(b"", &ChecksumAlgorithm::Xxh128),
+            StrongChecksum::Xxh128(0x99AA06D3014798D86001C324468D497Fu128.to_le_bytes())
+        );
+
+        let md5 = compute_strong_checksum(b"test data", &ChecksumAlgorithm::Md5);
+        let xxh128 = compute_strong_checksum(b"test data", &ChecksumAlgorithm::Xxh128);
+        assert_ne!(md5.as_bytes(), xxh128.as_bytes());
+    }
+
+    #[test]
+    fn test_seeded_checksums() {
+        let data = b"seeded block";
+        let seed = 0x1234_5678i32;
+        let mut seeded_tail = data.to_vec();
+        seeded_tail.extend_from_slice(&seed.to_le_bytes());
+        let mut seeded_head = seed.to_le_bytes().to_vec();
+        seeded_head.extend_from_slice(data);
+
+        assert_eq!(
+            compute_seeded_checksum(data, &ChecksumAlgorithm::Md4, ChecksumSeed::new(seed, true)),
+            compute_strong_checksum(&seeded_tail, &ChecksumAlgorithm::Md4)
+        );
+        assert_eq!(
+            compute_seeded_checksum(data, &ChecksumAlgorithm::Md5, ChecksumSeed::new(seed, false)),
+            compute_strong_checksum(&seeded_tail, &ChecksumAlgorithm::Md5)
+        );
+        assert_eq!(
+            compute_seeded_checksum(data, &ChecksumAlgorithm::Md5, ChecksumSeed::new(seed, true)),
+            compute_strong_checksum(&seeded_head, &ChecksumAlgorithm::Md5)
+        );
+        assert_eq!(
+            compute_seeded_checksum(data, &ChecksumAlgorithm::Md5, ChecksumSeed::new(0, true)),
+            compute_strong_checksum(data, &ChecksumAlgorithm::Md5)
+        );
+        assert_ne!(
+            compute_seeded_checksum(data, &ChecksumAlgorithm::Xxh64, ChecksumSeed::new(seed, true)),
+            compute_strong_checksum(data, &ChecksumAlgorithm::Xxh64)
+        );
+    }
 }
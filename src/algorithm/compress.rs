@@ -1,5 +1,44 @@
 use crate::options::CompressionAlgorithm;
 use anyhow::Result;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use std::path::Path;
+
+
+pub const DEFAULT_DONT_COMPRESS: &[&str] = &[
+    "*.7z", "*.avi", "*.bz2", "*.deb", "*.gif", "*.gz", "*.iso", "*.jpeg", "*.jpg",
+    "*.lz4", "*.mkv", "*.mov", "*.mp3", "*.mp4", "*.ogg", "*.png", "*.rar", "*.rpm",
+    "*.tbz", "*.tgz", "*.webp", "*.xz", "*.z", "*.zip", "*.zst",
+];
+
+
+pub struct DontCompress {
+    patterns: Vec<String>,
+    set: GlobSet,
+}
+
+impl DontCompress {
+    pub fn new<S: AsRef<str>>(patterns: &[S]) -> Result<Self> {
+        let mut builder = GlobSetBuilder::new();
+        for pattern in patterns {
+            builder.add(GlobBuilder::new(pattern.as_ref()).case_insensitive(true).build()?);
+        }
+
+        Ok(DontCompress {
+            patterns: patterns.iter().map(|p| p.as_ref().to_string()).collect(),
+            set: builder.build()?,
+        })
+    }
+
+    pub fn patterns(&self) -> &[String] {
+        &self.patterns
+    }
+
+    pub fn matches(&self, path: &Path) -> bool {
+        path.file_name()
+            .map(|name| self.set.is_match(Path::new(name)))
+            .unwrap_or(false)
+    }
+}
 
 pub struct Compressor {
     algorithm: CompressionAlgorithm,
@@ -55,3 +94,30 @@ impl Compressor {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip_all_algorithms() -> Result<()> {
+        let data = b"compressible compressible compressible data".repeat(32);
+        for algorithm in [CompressionAlgorithm::Zstd, CompressionAlgorithm::Lz4, CompressionAlgorithm::Zlib] {
+            let compressor = Compressor::new(algorithm);
+            let packed = compressor.compress(&data)?;
+            assert!(packed.len() < data.len());
+            assert_eq!(compressor.decompress(&packed)?, data);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_dont_compress_matches_file_name() -> Result<()> {
+        let skip = DontCompress::new(DEFAULT_DONT_COMPRESS)?;
+        assert!(skip.matches(Path::new("photos/IMG_0001.JPG")));
+        assert!(skip.matches(Path::new("backup.tar.gz")));
+        assert!(!skip.matches(Path::new("notes.txt")));
+        assert!(!skip.matches(Path::new("archive.gz/readme")));
+        Ok(())
+    }
+}
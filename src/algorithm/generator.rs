@@ -3,7 +3,7 @@ use std::io::{BufReader, Read};
 use std::path::Path;
 use crate::error::Result;
 use crate::options::ChecksumAlgorithm;
-use crate::algorithm::checksum::{RollingChecksum, StrongChecksum, compute_strong_checksum};
+use crate::algorithm::checksum::{ChecksumSeed, RollingChecksum, StrongChecksum, compute_seeded_checksum};
 use crate::filesystem::buffer_optimizer::BufferOptimizer;
 use crate::algorithm::parallel_checksum::ParallelChecksumEngine;
 
@@ -24,6 +24,8 @@ pub struct Generator {
     block_size: usize,
 
     checksum_algorithm: ChecksumAlgorithm,
+
+    seed: ChecksumSeed,
 }
 
 impl Generator {
@@ -32,10 +34,17 @@ impl Generator {
         Self {
             block_size,
             checksum_algorithm,
+            seed: ChecksumSeed::default(),
         }
     }
 
 
+    pub fn seed(mut self, seed: ChecksumSeed) -> Self {
+        self.seed = seed;
+        self
+    }
+
+
 
     pub fn calculate_block_size(file_size: u64) -> usize {
         let optimizer = BufferOptimizer::new();
@@ -51,7 +60,7 @@ impl Generator {
 
         if file_size >= PARALLEL_THRESHOLD {
             let data = std::fs::read(file_path)?;
-            let parallel_engine = ParallelChecksumEngine::new(self.checksum_algorithm);
+            let parallel_engine = ParallelChecksumEngine::new(self.checksum_algorithm).seed(self.seed);
             Ok(parallel_engine.compute_block_checksums_parallel(&data, self.block_size))
         } else {
             let optimizer = BufferOptimizer::new();
@@ -73,7 +82,7 @@ impl Generator {
                 let rolling = RollingChecksum::new(block);
                 let weak = rolling.checksum();
 
-                let strong = compute_strong_checksum(block, &self.checksum_algorithm);
+                let strong = compute_seeded_checksum(block, &self.checksum_algorithm, self.seed);
 
                 checksums.push(BlockChecksum {
                     index,
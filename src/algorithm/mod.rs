@@ -8,7 +8,7 @@ pub mod bwlimit;
 pub mod parallel_checksum;
 
 pub use generator::Generator;
-pub use sender::Sender;
+pub use sender::{DeltaStats, Sender};
 pub use receiver::Receiver;
-pub use bwlimit::BandwidthLimiter;
-pub use compress::Compressor;
+pub use bwlimit::{BandwidthLimiter, ThrottledWriter, TokenBucket};
+pub use compress::{Compressor, DontCompress, DEFAULT_DONT_COMPRESS};
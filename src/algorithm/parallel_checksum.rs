@@ -4,13 +4,14 @@
 
 
 use rayon::prelude::*;
-use crate::algorithm::checksum::compute_strong_checksum;
+use crate::algorithm::checksum::{compute_seeded_checksum, ChecksumSeed};
 use crate::algorithm::generator::BlockChecksum;
 use crate::options::ChecksumAlgorithm;
 
 
 pub struct ParallelChecksumEngine {
     algorithm: ChecksumAlgorithm,
+    seed: ChecksumSeed,
     #[allow(dead_code)]
     num_threads: Option<usize>,
 }
@@ -20,11 +21,18 @@ impl ParallelChecksumEngine {
     pub fn new(algorithm: ChecksumAlgorithm) -> Self {
         Self {
             algorithm,
+            seed: ChecksumSeed::default(),
             num_threads: None,
         }
     }
 
 
+    pub fn seed(mut self, seed: ChecksumSeed) -> Self {
+        self.seed = seed;
+        self
+    }
+
+
     #[allow(dead_code)]
     pub fn with_threads(mut self, num_threads: usize) -> Self {
         self.num_threads = Some(num_threads);
@@ -55,7 +63,7 @@ impl ParallelChecksumEngine {
                 let weak = rolling.checksum();
 
 
-                let strong = compute_strong_checksum(block, &self.algorithm);
+                let strong = compute_seeded_checksum(block, &self.algorithm, self.seed);
 
                 BlockChecksum {
                     index: *idx as u32,
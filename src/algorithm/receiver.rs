@@ -3,12 +3,195 @@ use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
 use crate::error::{Result, RsyncError};
 use crate::algorithm::delta::DeltaInstruction;
-use crate::options::Options;
+use crate::options::{BasisMode, Options};
+use crate::filesystem::FileInfo;
+use crate::filter::FilterEngine;
+use crate::filesystem::hard_links::link_to;
 use crate::algorithm::compress::Compressor;
 use crate::filesystem::buffer_optimizer::BufferOptimizer;
+use crate::filesystem::preallocate::preallocate;
+use crate::filesystem::sparse::SparseWriter;
 use tempfile::NamedTempFile;
 
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FnameCmpType {
+    BasisDir(u8),
+    Fname,
+    PartialDir,
+    Backup,
+    Fuzzy,
+}
+
+impl FnameCmpType {
+    pub fn from_u8(value: u8) -> Result<Self> {
+        match value {
+            0..=0x7F => Ok(FnameCmpType::BasisDir(value)),
+            0x80 => Ok(FnameCmpType::Fname),
+            0x81 => Ok(FnameCmpType::PartialDir),
+            0x82 => Ok(FnameCmpType::Backup),
+            0x83 => Ok(FnameCmpType::Fuzzy),
+            other => Err(RsyncError::Other(format!("Unknown fnamecmp type: {:#04x}", other))),
+        }
+    }
+
+
+    pub fn as_u8(&self) -> u8 {
+        match self {
+            FnameCmpType::BasisDir(index) => *index,
+            FnameCmpType::Fname => 0x80,
+            FnameCmpType::PartialDir => 0x81,
+            FnameCmpType::Backup => 0x82,
+            FnameCmpType::Fuzzy => 0x83,
+        }
+    }
+}
+
+
+const PARTIAL_SUFFIX: &str = ".partial";
+
+
+pub fn partial_path(output: &Path, options: &Options) -> PathBuf {
+    if let Some(partial_dir) = &options.partial_dir {
+        let partial_dir = match output.parent() {
+            Some(parent) if partial_dir.is_relative() => parent.join(partial_dir),
+            _ => partial_dir.clone(),
+        };
+        partial_dir.join(output.file_name().unwrap_or_default())
+    } else {
+        let mut name = output.as_os_str().to_os_string();
+        name.push(PARTIAL_SUFFIX);
+        PathBuf::from(name)
+    }
+}
+
+
+pub fn is_partial_path(relative: &Path, options: &Options) -> bool {
+    if !options.partial {
+        return false;
+    }
+    match &options.partial_dir {
+        Some(partial_dir) if partial_dir.is_relative() => relative.components().any(|c| c.as_os_str() == partial_dir.as_os_str()),
+        Some(_) => false,
+        None => relative.to_string_lossy().ends_with(PARTIAL_SUFFIX),
+    }
+}
+
+
+pub fn backup_path(file: &Path, relative: &Path, options: &Options) -> PathBuf {
+    if let Some(ref backup_dir) = options.backup_dir {
+        backup_dir.join(relative)
+    } else {
+        let mut name = file.as_os_str().to_os_string();
+        name.push(&options.suffix);
+        PathBuf::from(name)
+    }
+}
+
+
+pub fn backup_file(file: &Path, relative: &Path, options: &Options) -> Result<PathBuf> {
+    let backup = backup_path(file, relative, options);
+    if let Some(parent) = backup.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    if options.inplace {
+        std::fs::copy(file, &backup)?;
+    } else if std::fs::rename(file, &backup).is_err() {
+        std::fs::copy(file, &backup)?;
+        std::fs::remove_file(file)?;
+    }
+    Ok(backup)
+}
+
+
+pub fn remove_unprotected_dir(dir: &Path, relative: &Path, filter: &FilterEngine) -> Result<bool> {
+    if !filter.has_protect_rules() {
+        std::fs::remove_dir_all(dir)?;
+        return Ok(true);
+    }
+    let mut removed_all = true;
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let child = relative.join(entry.file_name());
+        if filter.is_protected(&child) {
+            removed_all = false;
+        } else if entry.file_type()?.is_dir() {
+            removed_all &= remove_unprotected_dir(&entry.path(), &child, filter)?;
+        } else {
+            std::fs::remove_file(entry.path())?;
+        }
+    }
+    if removed_all {
+        std::fs::remove_dir(dir)?;
+    }
+    Ok(removed_all)
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BasisMatch {
+
+    pub index: u8,
+
+    pub path: PathBuf,
+
+    pub identical: bool,
+}
+
+
+pub fn basis_dir_path(output: &Path, relative: &Path, index: u8, options: &Options) -> Option<PathBuf> {
+    let (_, dirs) = options.basis_dirs()?;
+    let dir = dirs.get(usize::from(index))?;
+    let root = output.ancestors().nth(relative.components().count())?;
+    Some(root.join(dir).join(relative))
+}
+
+
+pub fn find_basis_dir(
+    output: &Path,
+    relative: &Path,
+    options: &Options,
+    mut identical: impl FnMut(&Path, &FileInfo) -> Result<bool>,
+) -> Result<Option<BasisMatch>> {
+    let Some((_, dirs)) = options.basis_dirs() else {
+        return Ok(None);
+    };
+    let mut fallback = None;
+    for index in 0..dirs.len().min(0x80) as u8 {
+        let Some(path) = basis_dir_path(output, relative, index, options) else {
+            continue;
+        };
+        let Ok(metadata) = std::fs::symlink_metadata(&path) else {
+            continue;
+        };
+        if !metadata.is_file() {
+            continue;
+        }
+        if identical(&path, &FileInfo::from_metadata(path.clone(), &metadata))? {
+            return Ok(Some(BasisMatch { index, path, identical: true }));
+        }
+        fallback.get_or_insert(BasisMatch { index, path, identical: false });
+    }
+    Ok(fallback)
+}
+
+
+pub fn adopt_basis(basis: &Path, output: &Path, mode: BasisMode) -> Result<BasisMode> {
+    if mode == BasisMode::Compare {
+        return Ok(mode);
+    }
+    if let Some(parent) = output.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    if mode == BasisMode::Link && link_to(basis, output).is_ok() {
+        return Ok(BasisMode::Link);
+    }
+    std::fs::copy(basis, output)?;
+    Ok(BasisMode::Copy)
+}
+
+
 pub struct Receiver {
 
     temp_dir: Option<PathBuf>,
@@ -16,6 +199,8 @@ pub struct Receiver {
     block_size: usize,
 
     compressor: Option<Compressor>,
+
+    file_size: Option<u64>,
 }
 
 impl Receiver {
@@ -30,6 +215,21 @@ impl Receiver {
             temp_dir: None,
             block_size,
             compressor,
+            file_size: None,
+        }
+    }
+
+
+    pub fn with_file_size(mut self, file_size: u64) -> Self {
+        self.file_size = Some(file_size);
+        self
+    }
+
+
+    pub fn preallocate(&self, file: &File, options: &Options) -> Result<()> {
+        match self.file_size {
+            Some(size) if options.preallocate && !options.sparse => Ok(preallocate(file, size)?),
+            _ => Ok(()),
         }
     }
 
@@ -41,6 +241,41 @@ impl Receiver {
     }
 
 
+    pub fn basis_file(
+        &self,
+        fnamecmp_type: FnameCmpType,
+        output: &Path,
+        relative: &Path,
+        fuzzy_name: Option<&Path>,
+        options: &Options,
+    ) -> Result<PathBuf> {
+        let basis = match fnamecmp_type {
+            FnameCmpType::Fname => output.to_path_buf(),
+            FnameCmpType::PartialDir => partial_path(output, options),
+            FnameCmpType::Backup => backup_path(output, relative, options),
+            FnameCmpType::Fuzzy => {
+                let name = fuzzy_name.ok_or_else(|| RsyncError::Other(
+                    format!("No fuzzy basis name sent for {}", output.display())
+                ))?;
+                output.with_file_name(name)
+            }
+            FnameCmpType::BasisDir(index) => basis_dir_path(output, relative, index, options).ok_or_else(|| RsyncError::Other(
+                format!("Basis directory #{} requested for {} but none is configured", index, output.display())
+            ))?,
+        };
+
+        if !basis.is_file() {
+            return Err(RsyncError::Other(format!(
+                "Basis file {} for {} does not exist",
+                basis.display(),
+                output.display()
+            )));
+        }
+
+        Ok(basis)
+    }
+
+
     pub fn reconstruct_file(
         &self,
         base_file: Option<&Path>,
@@ -49,79 +284,46 @@ impl Receiver {
         options: &Options,
     ) -> Result<()> {
         if options.inplace {
-            return self.reconstruct_file_inplace(base_file, delta, output);
+            return self.reconstruct_file_inplace(base_file, delta, output, options);
         }
 
-        let partial_path = if options.partial {
-            if let Some(partial_dir) = &options.partial_dir {
-                partial_dir.join(output.file_name().unwrap())
-            } else {
-                output.with_extension("partial")
-            }
+
+        let saved_partial = partial_path(output, options);
+        let basis_is_partial = options.partial && base_file == Some(saved_partial.as_path());
+
+        let partial_path = if options.partial && !basis_is_partial {
+            saved_partial.clone()
         } else {
 
-            let temp_file = if let Some(temp_dir) = &self.temp_dir {
-                NamedTempFile::new_in(temp_dir)?
-            } else {
-                NamedTempFile::new()?
+            let temp_dir = match (&self.temp_dir, output.parent()) {
+                (Some(temp_dir), _) => temp_dir.as_path(),
+                (None, Some(parent)) if !parent.as_os_str().is_empty() => parent,
+                (None, _) => Path::new("."),
             };
+            let temp_file = NamedTempFile::new_in(temp_dir)?;
             temp_file.into_temp_path().to_path_buf()
         };
 
 
         let result = (|| -> Result<()> {
-            let optimizer = BufferOptimizer::new();
-            let writer_buffer_size = optimizer.optimal_buffer_for_file(&partial_path);
-            let mut writer = BufWriter::with_capacity(writer_buffer_size, File::create(&partial_path)?);
-
-
-            let mut base_reader = if let Some(base_path) = base_file {
-                if base_path.exists() {
-                    let reader_buffer_size = optimizer.optimal_buffer_for_file(base_path);
-                    Some(BufReader::with_capacity(reader_buffer_size, File::open(base_path)?))
-                } else {
-                    None
-                }
-            } else {
-                None
-            };
-
-
-            for instruction in delta {
-                match instruction {
-                    DeltaInstruction::MatchedBlock { index } => {
-                        if let Some(ref mut reader) = base_reader {
-                            let offset = (*index as u64) * (self.block_size as u64);
-                            reader.seek(SeekFrom::Start(offset))?;
-                            let mut block_buffer = vec![0u8; self.block_size];
-                            let bytes_read = reader.read(&mut block_buffer)?;
-                            writer.write_all(&block_buffer[..bytes_read])?;
-                        } else {
-                            return Err(RsyncError::Other(
-                                "Matched block reference but no base file provided".to_string(),
-                            ));
-                        }
-                    }
-                    DeltaInstruction::LiteralData { data } => {
-                        let data_to_write = if let Some(compressor) = &self.compressor {
-                            compressor.decompress(data)?
-                        } else {
-                            data.clone()
-                        };
-                        writer.write_all(&data_to_write)?;
-                    }
-                }
-            }
-            writer.flush()?;
+            let writer_buffer_size = BufferOptimizer::new().optimal_buffer_for_file(&partial_path);
+            let file = File::create(&partial_path)?;
+            self.preallocate(&file, options)?;
+            let mut writer = SparseWriter::with_capacity(writer_buffer_size, file, options.sparse);
+            self.apply_delta(base_file, delta, &mut writer)?;
+            writer.finish()?;
             Ok(())
         })();
 
         if result.is_ok() {
 
             std::fs::rename(&partial_path, output)?;
+            if basis_is_partial {
+                let _ = std::fs::remove_file(&saved_partial);
+            }
         } else {
 
-            if !options.partial {
+            if !options.partial || basis_is_partial {
                 let _ = std::fs::remove_file(&partial_path);
             }
         }
@@ -129,18 +331,32 @@ impl Receiver {
         result
     }
 
+    pub fn apply_delta<W: Write>(
+        &self,
+        base_file: Option<&Path>,
+        delta: &[DeltaInstruction],
+        writer: &mut W,
+    ) -> Result<()> {
+        let mut output = DeltaWriter::new(self, base_file, writer)?;
+        for instruction in delta {
+            output.apply(instruction)?;
+        }
+        Ok(())
+    }
+
+
     fn reconstruct_file_inplace(
         &self,
         base_file: Option<&Path>,
         delta: &[DeltaInstruction],
         output: &Path,
+        options: &Options,
     ) -> Result<()> {
         let optimizer = BufferOptimizer::new();
         let writer_buffer_size = optimizer.optimal_buffer_for_file(output);
-        let mut writer = BufWriter::with_capacity(
-            writer_buffer_size,
-            OpenOptions::new().write(true).open(output)?
-        );
+        let file = OpenOptions::new().write(true).create(true).truncate(false).open(output)?;
+        self.preallocate(&file, options)?;
+        let mut writer = BufWriter::with_capacity(writer_buffer_size, file);
 
 
         let mut base_reader = if let Some(base_path) = base_file {
@@ -182,6 +398,8 @@ impl Receiver {
             }
         }
         writer.flush()?;
+        let written = writer.stream_position()?;
+        writer.get_ref().set_len(written)?;
         Ok(())
     }
 
@@ -193,6 +411,82 @@ impl Receiver {
     }
 }
 
+
+pub struct DeltaWriter<'r, W: Write> {
+    receiver: &'r Receiver,
+    base: Option<BufReader<File>>,
+    writer: W,
+    block_buffer: Vec<u8>,
+    written: u64,
+}
+
+impl<'r, W: Write> DeltaWriter<'r, W> {
+    pub fn new(receiver: &'r Receiver, base_file: Option<&Path>, writer: W) -> Result<Self> {
+        let base = match base_file {
+            Some(base_path) if base_path.exists() => {
+                let reader_buffer_size = BufferOptimizer::new().optimal_buffer_for_file(base_path);
+                Some(BufReader::with_capacity(reader_buffer_size, File::open(base_path)?))
+            }
+            _ => None,
+        };
+
+        Ok(Self {
+            receiver,
+            base,
+            writer,
+            block_buffer: vec![0u8; receiver.block_size],
+            written: 0,
+        })
+    }
+
+
+    pub fn apply(&mut self, instruction: &DeltaInstruction) -> Result<()> {
+        match instruction {
+            DeltaInstruction::MatchedBlock { index } => self.copy_block(*index),
+            DeltaInstruction::LiteralData { data } => self.write_literal(data),
+        }
+    }
+
+
+    pub fn copy_block(&mut self, index: u32) -> Result<()> {
+        let Some(ref mut reader) = self.base else {
+            return Err(RsyncError::Other(
+                "Matched block reference but no base file provided".to_string(),
+            ));
+        };
+
+        let offset = (index as u64) * (self.receiver.block_size as u64);
+        reader.seek(SeekFrom::Start(offset))?;
+        let bytes_read = reader.read(&mut self.block_buffer)?;
+        self.writer.write_all(&self.block_buffer[..bytes_read])?;
+        self.written += bytes_read as u64;
+        Ok(())
+    }
+
+
+    pub fn write_literal(&mut self, data: &[u8]) -> Result<()> {
+        if let Some(compressor) = &self.receiver.compressor {
+            let decompressed = compressor.decompress(data)?;
+            self.writer.write_all(&decompressed)?;
+            self.written += decompressed.len() as u64;
+        } else {
+            self.writer.write_all(data)?;
+            self.written += data.len() as u64;
+        }
+        Ok(())
+    }
+
+
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -350,4 +644,151 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_fnamecmp_type_codes() -> Result<()> {
+        for code in [0x00, 0x05, 0x80, 0x81, 0x82, 0x83] {
+            assert_eq!(FnameCmpType::from_u8(code)?.as_u8(), code);
+        }
+        assert_eq!(FnameCmpType::from_u8(0x81)?, FnameCmpType::PartialDir);
+        assert!(FnameCmpType::from_u8(0x84).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_basis_file_selection() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let output = temp_dir.path().join("data.txt");
+        let options = Options {
+            partial_dir: Some(temp_dir.path().join(".partial")),
+            suffix: ".bak".to_string(),
+            ..Options::default()
+        };
+
+        fs::create_dir_all(temp_dir.path().join(".partial"))?;
+        fs::write(temp_dir.path().join(".partial").join("data.txt"), b"partial")?;
+        fs::write(temp_dir.path().join("data.txt.bak"), b"backup")?;
+        fs::write(temp_dir.path().join("data-old.txt"), b"fuzzy")?;
+
+        let receiver = Receiver::new(16, &options);
+        let relative = Path::new("data.txt");
+        let partial = receiver.basis_file(FnameCmpType::PartialDir, &output, relative, None, &options)?;
+        assert_eq!(fs::read(partial)?, b"partial");
+        let backup = receiver.basis_file(FnameCmpType::Backup, &output, relative, None, &options)?;
+        assert_eq!(fs::read(backup)?, b"backup");
+        let fuzzy = receiver.basis_file(FnameCmpType::Fuzzy, &output, relative, Some(Path::new("data-old.txt")), &options)?;
+        assert_eq!(fs::read(fuzzy)?, b"fuzzy");
+
+        assert!(receiver.basis_file(FnameCmpType::Fname, &output, relative, None, &options).is_err());
+        assert!(receiver.basis_file(FnameCmpType::Fuzzy, &output, relative, None, &options).is_err());
+        assert!(receiver.basis_file(FnameCmpType::BasisDir(0), &output, relative, None, &options).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_basis_dir() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let dest = temp_dir.path().join("dest");
+        fs::create_dir_all(dest.join("sub"))?;
+        for (dir, content) in [("old", "stale"), ("older", "fresh")] {
+            fs::create_dir_all(temp_dir.path().join(dir).join("sub"))?;
+            fs::write(temp_dir.path().join(dir).join("sub/data.txt"), content)?;
+        }
+        let options = Options {
+            compare_dest: vec![PathBuf::from("../missing"), PathBuf::from("../old"), PathBuf::from("../older")],
+            ..Options::default()
+        };
+
+        let output = dest.join("sub/data.txt");
+        let relative = Path::new("sub/data.txt");
+        let found = find_basis_dir(&output, relative, &options, |path, _| Ok(fs::read(path)? == b"fresh"))?;
+        assert_eq!(found.map(|found| (found.index, found.identical)), Some((2, true)));
+        let found = find_basis_dir(&output, relative, &options, |_, _| Ok(false))?.unwrap();
+        assert_eq!((found.index, found.identical), (1, false));
+
+        let receiver = Receiver::new(16, &options);
+        let basis = receiver.basis_file(FnameCmpType::BasisDir(found.index), &output, relative, None, &options)?;
+        assert_eq!(fs::read(basis)?, b"stale");
+        assert!(receiver.basis_file(FnameCmpType::BasisDir(0), &output, relative, None, &options).is_err());
+
+        assert_eq!(adopt_basis(&found.path, &output, BasisMode::Compare)?, BasisMode::Compare);
+        assert!(!output.exists());
+        assert_eq!(adopt_basis(&found.path, &output, BasisMode::Copy)?, BasisMode::Copy);
+        assert_eq!(fs::read(&output)?, b"stale");
+        Ok(())
+    }
+
+    #[test]
+    fn test_reconstruct_from_partial_basis() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let source = temp_dir.path().join("source.bin");
+        let output = temp_dir.path().join("output.bin");
+        let options = Options { partial: true, ..Options::default() };
+
+        let content: Vec<u8> = (0..4096u32).map(|i| (i % 251) as u8).collect();
+        fs::write(&source, &content)?;
+        let partial = partial_path(&output, &options);
+        assert_eq!(partial, temp_dir.path().join("output.bin.partial"));
+        assert!(is_partial_path(Path::new("sub/output.bin.partial"), &options));
+        assert!(!is_partial_path(Path::new("output.bin"), &options));
+        let in_dir = Options { partial_dir: Some(PathBuf::from(".partial")), ..options.clone() };
+        assert!(is_partial_path(Path::new("sub/.partial/output.bin"), &in_dir));
+        fs::write(&partial, &content[..2048])?;
+
+        let block_size = 256;
+        let receiver = Receiver::new(block_size, &options);
+        let basis = receiver.basis_file(FnameCmpType::PartialDir, &output, Path::new("output.bin"), None, &options)?;
+        let checksums = Generator::new(block_size, ChecksumAlgorithm::Md5).generate_checksums(&basis)?;
+        let delta = Sender::new(block_size, &options).compute_delta(&source, &checksums, &options)?;
+        receiver.reconstruct_file(Some(&basis), &delta, &output, &options)?;
+
+        assert_eq!(fs::read(&output)?, content);
+        assert!(!partial.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_backup_file_keeps_hierarchy() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let file = temp_dir.path().join("dest/sub/data.txt");
+        fs::create_dir_all(file.parent().unwrap())?;
+        fs::write(&file, b"old")?;
+
+        let options = Options { backup_dir: Some(temp_dir.path().join("backups")), ..Options::default() };
+        let backup = backup_file(&file, Path::new("sub/data.txt"), &options)?;
+        assert_eq!(backup, temp_dir.path().join("backups/sub/data.txt"));
+        assert_eq!(fs::read(&backup)?, b"old");
+        assert!(!file.exists());
+
+        fs::write(&file, b"older")?;
+        let options = Options { inplace: true, ..Options::default() };
+        let backup = backup_file(&file, Path::new("sub/data.txt"), &options)?;
+        assert_eq!(backup, temp_dir.path().join("dest/sub/data.txt~"));
+        assert_eq!(fs::read(&backup)?, b"older");
+        assert!(file.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_reconstruct_file_with_preallocate() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let source = temp_dir.path().join("source.bin");
+        let output = temp_dir.path().join("output.bin");
+        let content: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+        fs::write(&source, &content)?;
+        fs::write(&output, &content[..4_000])?;
+
+        for inplace in [false, true] {
+            let options = Options { preallocate: true, inplace, ..Options::default() };
+            let block_size = 256;
+            let receiver = Receiver::new(block_size, &options).with_file_size(content.len() as u64);
+            let basis = temp_dir.path().join("basis.bin");
+            fs::copy(&output, &basis)?;
+            let checksums = Generator::new(block_size, ChecksumAlgorithm::Md5).generate_checksums(&basis)?;
+            let delta = Sender::new(block_size, &options).compute_delta(&source, &checksums, &options)?;
+            receiver.reconstruct_file(Some(&basis), &delta, &output, &options)?;
+            assert_eq!(fs::read(&output)?, content);
+        }
+        Ok(())
+    }
 }
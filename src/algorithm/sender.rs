@@ -3,7 +3,7 @@ use std::fs::File;
 use std::io::{BufReader, Read};
 use std::path::Path;
 use crate::error::Result;
-use crate::algorithm::checksum::RollingChecksum;
+use crate::algorithm::checksum::{compute_seeded_checksum, ChecksumSeed, RollingChecksum};
 use crate::algorithm::generator::BlockChecksum;
 use crate::algorithm::delta::DeltaInstruction;
 use crate::options::Options;
@@ -12,6 +12,17 @@ use crate::algorithm::bwlimit::BandwidthLimiter;
 use crate::filesystem::buffer_optimizer::BufferOptimizer;
 
 
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DeltaStats {
+
+    pub matched_bytes: u64,
+
+    pub literal_bytes: u64,
+
+    pub compressed_bytes: u64,
+}
+
+
 pub struct Sender {
 
     block_size: usize,
@@ -19,6 +30,10 @@ pub struct Sender {
     compressor: Option<Compressor>,
 
     bandwidth_limiter: Option<BandwidthLimiter>,
+
+    seed: ChecksumSeed,
+
+    stats: DeltaStats,
 }
 
 impl Sender {
@@ -34,7 +49,18 @@ impl Sender {
         } else {
             None
         };
-        Self { block_size, compressor, bandwidth_limiter }
+        Self { block_size, compressor, bandwidth_limiter, seed: ChecksumSeed::default(), stats: DeltaStats::default() }
+    }
+
+
+    pub fn seed(mut self, seed: ChecksumSeed) -> Self {
+        self.seed = seed;
+        self
+    }
+
+
+    pub fn stats(&self) -> DeltaStats {
+        self.stats
     }
 
 
@@ -98,9 +124,10 @@ impl Sender {
             let mut matched = false;
             if let Some(candidates) = hash_table.get(&weak) {
                 let block = &buffer[pos..pos + self.block_size];
-                let strong = crate::algorithm::checksum::compute_strong_checksum(
+                let strong = compute_seeded_checksum(
                     block,
                     &options.checksum_choice.unwrap_or_default(),
+                    self.seed,
                 );
 
                 if let Some(matched_block) = candidates.iter().find(|c| c.strong == strong) {
@@ -111,6 +138,7 @@ impl Sender {
                     }
 
                     instructions.push(DeltaInstruction::matched_block(matched_block.index));
+                    self.stats.matched_bytes += self.block_size as u64;
                     pos += self.block_size;
                     rolling_checksum = None;
                     matched = true;
@@ -130,9 +158,10 @@ impl Sender {
             let mut final_match = false;
 
             if let Some(candidates) = hash_table.get(&weak) {
-                let strong = crate::algorithm::checksum::compute_strong_checksum(
+                let strong = compute_seeded_checksum(
                     final_block,
                     &options.checksum_choice.unwrap_or_default(),
+                    self.seed,
                 );
                 if let Some(matched_block) = candidates.iter().find(|c| c.strong == strong) {
                     if !literal_buffer.is_empty() {
@@ -141,6 +170,7 @@ impl Sender {
                         literal_buffer.clear();
                     }
                     instructions.push(DeltaInstruction::matched_block(matched_block.index));
+                    self.stats.matched_bytes += final_block.len() as u64;
                     final_match = true;
                 }
             }
@@ -159,12 +189,52 @@ impl Sender {
         Ok(instructions)
     }
 
+    pub fn compute_append_delta(
+        &mut self,
+        source: &Path,
+        basis_length: u64,
+        checksums: &[BlockChecksum],
+        options: &Options,
+    ) -> Result<Option<Vec<DeltaInstruction>>> {
+        let mut buffer = Vec::new();
+        File::open(source)?.read_to_end(&mut buffer)?;
+        if (buffer.len() as u64) < basis_length {
+            return Ok(None);
+        }
+
+        let (prefix, tail) = buffer.split_at(basis_length as usize);
+        let algorithm = options.checksum_choice.unwrap_or_default();
+        let mut instructions = Vec::new();
+        let mut matched_bytes = 0;
+        for (index, block) in prefix.chunks(self.block_size).enumerate() {
+            if options.append_verify {
+                let strong = compute_seeded_checksum(block, &algorithm, self.seed);
+                if checksums.get(index).map(|c| &c.strong) != Some(&strong) {
+                    return Ok(None);
+                }
+            }
+            instructions.push(DeltaInstruction::matched_block(index as u32));
+            matched_bytes += block.len() as u64;
+        }
+        self.stats.matched_bytes += matched_bytes;
+
+        if !tail.is_empty() {
+            let data_to_send = self.compress_and_limit(&mut tail.to_vec())?;
+            instructions.push(DeltaInstruction::literal_data(data_to_send));
+        }
+
+        Ok(Some(instructions))
+    }
+
     fn compress_and_limit(&mut self, data: &mut Vec<u8>) -> Result<Vec<u8>> {
         let compressed_data = if let Some(compressor) = &self.compressor {
-            compressor.compress(data)?
+            let compressed = compressor.compress(data)?;
+            self.stats.compressed_bytes += compressed.len() as u64;
+            compressed
         } else {
             data.clone()
         };
+        self.stats.literal_bytes += data.len() as u64;
 
         if let Some(limiter) = &mut self.bandwidth_limiter {
             limiter.limit(compressed_data.len() as u64);
@@ -286,6 +356,55 @@ mod tests {
 
         assert!(matched_count > 0, "Should have matched blocks");
         assert!(literal_count > 0, "Should have literal data");
+        assert_eq!(sender.stats().matched_bytes + sender.stats().literal_bytes, source_content.len() as u64);
+        assert_eq!(sender.stats().compressed_bytes, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compute_delta_counts_compressed_literals() -> Result<()> {
+        let options = Options { compress: true, ..Options::default() };
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = temp_dir.path().join("text.txt");
+        let content = "compressible line of text\n".repeat(200);
+        fs::write(&file_path, &content)?;
+
+        let mut sender = Sender::new(700, &options);
+        sender.compute_delta(&file_path, &[], &options)?;
+
+        let stats = sender.stats();
+        assert_eq!(stats.matched_bytes, 0);
+        assert_eq!(stats.literal_bytes, content.len() as u64);
+        assert!(stats.compressed_bytes > 0 && stats.compressed_bytes < stats.literal_bytes);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compute_append_delta() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let base_file = temp_dir.path().join("base.log");
+        let source_file = temp_dir.path().join("source.log");
+        fs::write(&base_file, b"line one\nline two\n")?;
+        fs::write(&source_file, b"line one\nline two\nline three\n")?;
+
+        let options = Options { append: true, ..Options::default() };
+        let mut sender = Sender::new(8, &options);
+        let delta = sender.compute_append_delta(&source_file, 18, &[], &options)?.unwrap();
+        assert_eq!(delta.iter().filter(|i| i.is_matched_block()).count(), 3);
+        assert_eq!(delta.last(), Some(&DeltaInstruction::literal_data(b"line three\n".to_vec())));
+        assert_eq!(sender.stats().matched_bytes, 18);
+
+        let verify = Options { append: true, append_verify: true, ..Options::default() };
+        let checksums = Generator::new(8, ChecksumAlgorithm::Md5).generate_checksums(&base_file)?;
+        let mut sender = Sender::new(8, &verify);
+        assert!(sender.compute_append_delta(&source_file, 18, &checksums, &verify)?.is_some());
+
+        fs::write(&base_file, b"line 1\nline two\n\n\n")?;
+        let checksums = Generator::new(8, ChecksumAlgorithm::Md5).generate_checksums(&base_file)?;
+        assert!(sender.compute_append_delta(&source_file, 18, &checksums, &verify)?.is_none());
+        assert!(Sender::new(8, &options).compute_append_delta(&source_file, 100, &[], &options)?.is_none());
 
         Ok(())
     }
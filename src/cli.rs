@@ -1,8 +1,10 @@
-use clap::{Parser, ArgAction};
+use clap::{Args, ArgAction, ArgMatches, Command, FromArgMatches, Parser};
 use std::path::PathBuf;
-use crate::options::{Options, CompressionAlgorithm, ChecksumAlgorithm};
+use crate::options::{Options, CompressionAlgorithm, ChecksumAlgorithm, FilterRule, PrescanMode};
 use crate::error::{Result, RsyncError};
 use crate::output::VerboseOutput;
+use crate::schedule::Schedule;
+use crate::transport::{SocketOptions, SshAlgorithms, StrictHostKeyChecking};
 
 #[derive(Parser, Debug)]
 #[command(name = "rsync")]
@@ -10,17 +12,15 @@ use crate::output::VerboseOutput;
 #[command(version)]
 #[command(about = "A file synchronization tool for Windows", long_about = None)]
 #[command(disable_help_flag = true)]
+#[command(args_override_self = true)]
+#[command(override_usage = "yarw [OPTIONS] SOURCE... DESTINATION\n       yarw --verify-manifest=FILE [DESTINATION]")]
 pub struct Cli {
 
     #[arg(long = "help", action = ArgAction::Help)]
     pub help: Option<bool>,
 
-    #[arg(required = true)]
-    pub source: Vec<String>,
-
-
-    #[arg(required = true)]
-    pub destination: String,
+    #[arg(value_name = "PATH", required_unless_present_any = ["verify_manifest", "explain"])]
+    pub paths: Vec<String>,
 
 
 
@@ -36,14 +36,38 @@ pub struct Cli {
     pub checksum: bool,
 
 
+    #[arg(long = "cache-file", value_name = "FILE")]
+    pub cache_file: Option<PathBuf>,
+
+
     #[arg(short = 'a', long = "archive")]
     pub archive: bool,
 
 
-    #[arg(short = 'r', long = "recursive")]
+    #[arg(short = 'r', long = "recursive", overrides_with = "no_recursive")]
     pub recursive: bool,
 
 
+    #[arg(long = "no-recursive", visible_alias = "no-r", overrides_with = "recursive")]
+    pub no_recursive: bool,
+
+
+    #[arg(short = 'd', long = "dirs", overrides_with = "no_dirs")]
+    pub dirs: bool,
+
+
+    #[arg(long = "no-dirs", visible_alias = "no-d", overrides_with = "dirs")]
+    pub no_dirs: bool,
+
+
+    #[arg(long = "inc-recursive", visible_alias = "i-r", overrides_with = "no_inc_recursive")]
+    pub inc_recursive: bool,
+
+
+    #[arg(long = "no-inc-recursive", visible_alias = "no-i-r", overrides_with = "inc_recursive")]
+    pub no_inc_recursive: bool,
+
+
     #[arg(short = 'R', long = "relative")]
     pub relative: bool,
 
@@ -52,10 +76,14 @@ pub struct Cli {
     pub update: bool,
 
 
-    #[arg(short = 'l', long = "links")]
+    #[arg(short = 'l', long = "links", overrides_with = "no_links")]
     pub links: bool,
 
 
+    #[arg(long = "no-links", visible_alias = "no-l", overrides_with = "links")]
+    pub no_links: bool,
+
+
     #[arg(short = 'L', long = "copy-links")]
     pub copy_links: bool,
 
@@ -64,27 +92,59 @@ pub struct Cli {
     pub hard_links: bool,
 
 
+    #[arg(short = 'A', long = "acls")]
+    pub acls: bool,
+
+
+    #[arg(long = "ads")]
+    pub ads: bool,
 
-    #[arg(short = 'p', long = "perms")]
+
+    #[arg(long = "no-ads", conflicts_with = "ads")]
+    pub no_ads: bool,
+
+
+
+    #[arg(short = 'p', long = "perms", overrides_with = "no_perms")]
     pub perms: bool,
 
 
-    #[arg(short = 'g', long = "group")]
+    #[arg(long = "no-perms", visible_alias = "no-p", overrides_with = "perms")]
+    pub no_perms: bool,
+
+
+    #[arg(short = 'g', long = "group", overrides_with = "no_group")]
     pub group: bool,
 
 
-    #[arg(short = 'o', long = "owner")]
+    #[arg(long = "no-group", visible_alias = "no-g", overrides_with = "group")]
+    pub no_group: bool,
+
+
+    #[arg(short = 'o', long = "owner", overrides_with = "no_owner")]
     pub owner: bool,
 
 
-    #[arg(short = 't', long = "times")]
+    #[arg(long = "no-owner", visible_alias = "no-o", overrides_with = "owner")]
+    pub no_owner: bool,
+
+
+    #[arg(short = 't', long = "times", overrides_with = "no_times")]
     pub times: bool,
 
 
-    #[arg(short = 'D')]
+    #[arg(long = "no-times", visible_alias = "no-t", overrides_with = "times")]
+    pub no_times: bool,
+
+
+    #[arg(short = 'D', overrides_with = "no_devices_and_specials")]
     pub devices_and_specials: bool,
 
 
+    #[arg(long = "no-D", overrides_with = "devices_and_specials")]
+    pub no_devices_and_specials: bool,
+
+
     #[arg(long = "devices")]
     pub devices: bool,
 
@@ -102,14 +162,34 @@ pub struct Cli {
     pub compress_choice: Option<String>,
 
 
-    #[arg(short = 'W', long = "whole-file")]
+    #[arg(short = 'W', long = "whole-file", overrides_with = "no_whole_file")]
     pub whole_file: bool,
 
 
+    #[arg(long = "no-whole-file", overrides_with = "whole_file")]
+    pub no_whole_file: bool,
+
+
     #[arg(long = "inplace")]
     pub inplace: bool,
 
 
+    #[arg(short = 'S', long = "sparse")]
+    pub sparse: bool,
+
+
+    #[arg(long = "preallocate")]
+    pub preallocate: bool,
+
+
+    #[arg(long = "append")]
+    pub append: bool,
+
+
+    #[arg(long = "append-verify")]
+    pub append_verify: bool,
+
+
     #[arg(long = "partial")]
     pub partial: bool,
 
@@ -118,6 +198,14 @@ pub struct Cli {
     pub partial_dir: Option<PathBuf>,
 
 
+    #[arg(short = 'P')]
+    pub partial_progress: bool,
+
+
+    #[arg(long = "strict-options")]
+    pub strict_options: bool,
+
+
     #[arg(short = 'b', long = "backup")]
     pub backup: bool,
 
@@ -130,10 +218,26 @@ pub struct Cli {
     pub suffix: String,
 
 
+    #[arg(long = "compare-dest", value_name = "DIR", conflicts_with_all = ["copy_dest", "link_dest"])]
+    pub compare_dest: Vec<PathBuf>,
+
+
+    #[arg(long = "copy-dest", value_name = "DIR", conflicts_with = "link_dest")]
+    pub copy_dest: Vec<PathBuf>,
+
+
+    #[arg(long = "link-dest", value_name = "DIR")]
+    pub link_dest: Vec<PathBuf>,
+
+
     #[arg(long = "bwlimit")]
     pub bwlimit: Option<u64>,
 
 
+    #[arg(long = "parallel", value_name = "N")]
+    pub parallel: Option<usize>,
+
+
 
     #[arg(long = "delete")]
     pub delete: bool,
@@ -160,31 +264,43 @@ pub struct Cli {
 
 
 
-    #[arg(long = "exclude", action = ArgAction::Append)]
-    pub exclude: Vec<String>,
+    #[command(flatten)]
+    pub filter: FilterArgs,
 
 
-    #[arg(long = "exclude-from")]
-    pub exclude_from: Option<PathBuf>,
+    #[arg(long = "exclude-if-present", value_name = "FILE", action = ArgAction::Append)]
+    pub exclude_if_present: Vec<String>,
 
 
-    #[arg(long = "include", action = ArgAction::Append)]
-    pub include: Vec<String>,
+    #[arg(long = "protect", value_name = "PATTERN", action = ArgAction::Append)]
+    pub protect: Vec<String>,
 
 
-    #[arg(long = "include-from")]
-    pub include_from: Option<PathBuf>,
+    #[arg(long = "explain", value_name = "PATH", action = ArgAction::Append)]
+    pub explain: Vec<PathBuf>,
 
 
     #[arg(long = "files-from")]
     pub files_from: Option<PathBuf>,
 
 
+    #[arg(short = '0', long = "from0")]
+    pub from0: bool,
+
+
 
     #[arg(long = "progress")]
     pub progress: bool,
 
 
+    #[arg(long = "no-prescan")]
+    pub no_prescan: bool,
+
+
+    #[arg(long = "prescan", conflicts_with = "no_prescan")]
+    pub prescan: bool,
+
+
     #[arg(short = 'i', long = "itemize-changes")]
     pub itemize_changes: bool,
 
@@ -193,6 +309,14 @@ pub struct Cli {
     pub stats: bool,
 
 
+    #[arg(long = "info", value_name = "FLAGS", value_delimiter = ',', action = ArgAction::Append)]
+    pub info: Vec<String>,
+
+
+    #[arg(long = "json")]
+    pub json: bool,
+
+
     #[arg(short = 'h', long = "human-readable")]
     pub human_readable: bool,
 
@@ -210,6 +334,91 @@ pub struct Cli {
     pub rsync_path: Option<String>,
 
 
+    #[arg(short = 'M', long = "remote-option", value_name = "OPTION", allow_hyphen_values = true, action = clap::ArgAction::Append)]
+    pub remote_option: Vec<String>,
+
+
+    #[arg(long = "ssh-ciphers", value_name = "LIST")]
+    pub ssh_ciphers: Option<String>,
+
+
+    #[arg(long = "ssh-kex", value_name = "LIST")]
+    pub ssh_kex: Option<String>,
+
+
+    #[arg(long = "ssh-hostkey-algorithms", value_name = "LIST")]
+    pub ssh_hostkey_algorithms: Option<String>,
+
+
+    #[arg(long = "blocking-io", overrides_with = "no_blocking_io")]
+    pub blocking_io: bool,
+
+
+    #[arg(long = "no-blocking-io", overrides_with = "blocking_io")]
+    pub no_blocking_io: bool,
+
+
+    #[arg(long = "password-prompts", value_name = "N")]
+    pub password_prompts: Option<u32>,
+
+
+    #[arg(long = "no-prompt", visible_alias = "batch-mode")]
+    pub no_prompt: bool,
+
+
+    #[arg(long = "strict-host-key-checking", value_name = "yes|ask|accept-new|no")]
+    pub strict_host_key_checking: Option<String>,
+
+
+    #[arg(long = "use-credential-manager")]
+    pub use_credential_manager: bool,
+
+
+    #[arg(long = "copy-as", value_name = "USER")]
+    pub copy_as: Option<String>,
+
+
+    #[arg(long = "elevate")]
+    pub elevate: bool,
+
+
+    #[arg(long = "lock-file", value_name = "PATH")]
+    pub lock_file: Option<PathBuf>,
+
+
+    #[arg(long = "lock-wait", value_name = "SECS")]
+    pub lock_wait: Option<u64>,
+
+
+    #[arg(long = "schedule", value_name = "WHEN", conflicts_with = "unschedule")]
+    pub schedule: Option<String>,
+
+
+    #[arg(long = "unschedule")]
+    pub unschedule: bool,
+
+
+    #[arg(long = "checksum-seed", value_name = "NUM")]
+    pub checksum_seed: Option<i32>,
+
+
+    #[arg(long = "sockopts")]
+    pub sockopts: Option<String>,
+
+
+    #[arg(long = "net-buffer-size")]
+    pub net_buffer_size: Option<usize>,
+
+
+
+    #[arg(long = "server", hide = true)]
+    pub server: bool,
+
+
+    #[arg(long = "sender", hide = true, requires = "server")]
+    pub sender: bool,
+
+
 
     #[arg(long = "daemon")]
     pub daemon: bool,
@@ -227,6 +436,10 @@ pub struct Cli {
     pub config: Option<PathBuf>,
 
 
+    #[arg(long = "dparam", value_name = "NAME=VALUE", action = ArgAction::Append)]
+    pub dparam: Vec<String>,
+
+
     #[arg(long = "password-file")]
     pub password_file: Option<PathBuf>,
 
@@ -236,6 +449,18 @@ pub struct Cli {
     pub dry_run: bool,
 
 
+    #[arg(long = "export-changes", value_name = "DIR")]
+    pub export_changes: Option<PathBuf>,
+
+
+    #[arg(long = "manifest", value_name = "FILE")]
+    pub manifest: Option<PathBuf>,
+
+
+    #[arg(long = "verify-manifest", value_name = "FILE", conflicts_with = "manifest")]
+    pub verify_manifest: Option<PathBuf>,
+
+
     #[arg(long = "list-only")]
     pub list_only: bool,
 
@@ -244,6 +469,10 @@ pub struct Cli {
     pub size_only: bool,
 
 
+    #[arg(short = 'I', long = "ignore-times")]
+    pub ignore_times: bool,
+
+
     #[arg(long = "timeout")]
     pub timeout: Option<u64>,
 
@@ -253,41 +482,139 @@ pub struct Cli {
     pub checksum_choice: Option<String>,
 }
 
+#[derive(Args, Debug)]
+struct FilterFlags {
+
+    #[arg(long = "exclude", value_name = "PATTERN", action = ArgAction::Append)]
+    exclude: Vec<String>,
+
+
+    #[arg(long = "exclude-from", value_name = "FILE", action = ArgAction::Append)]
+    exclude_from: Vec<PathBuf>,
+
+
+    #[arg(long = "include", value_name = "PATTERN", action = ArgAction::Append)]
+    include: Vec<String>,
+
+
+    #[arg(long = "include-from", value_name = "FILE", action = ArgAction::Append)]
+    include_from: Vec<PathBuf>,
+
+
+    #[arg(short = 'f', long = "filter", value_name = "RULE", action = ArgAction::Append)]
+    filter: Vec<String>,
+
+
+    #[arg(short = 'F', num_args = 0, default_missing_value = "true", action = ArgAction::Append)]
+    filter_files: Vec<bool>,
+}
+
+
+#[derive(Debug, Clone, Default)]
+pub struct FilterArgs {
+    pub rules: Vec<FilterRule>,
+}
+
+impl FromArgMatches for FilterArgs {
+    fn from_arg_matches(matches: &ArgMatches) -> std::result::Result<Self, clap::Error> {
+        let flags = FilterFlags::from_arg_matches(matches)?;
+        let positions = |id: &str| matches.indices_of(id).into_iter().flatten();
+
+        let mut rules: Vec<(usize, FilterRule)> = Vec::new();
+        rules.extend(positions("exclude").zip(flags.exclude.into_iter().map(FilterRule::Exclude)));
+        rules.extend(positions("exclude_from").zip(flags.exclude_from.into_iter().map(FilterRule::ExcludeFrom)));
+        rules.extend(positions("include").zip(flags.include.into_iter().map(FilterRule::Include)));
+        rules.extend(positions("include_from").zip(flags.include_from.into_iter().map(FilterRule::IncludeFrom)));
+        rules.extend(positions("filter").zip(flags.filter.into_iter().map(FilterRule::Rule)));
+        let filter_files = [": /.rsync-filter", "- .rsync-filter"].map(|rule| FilterRule::Rule(rule.to_string()));
+        rules.extend(positions("filter_files").zip(filter_files));
+        rules.sort_by_key(|(position, _)| *position);
+        Ok(FilterArgs { rules: rules.into_iter().map(|(_, rule)| rule).collect() })
+    }
+
+    fn update_from_arg_matches(&mut self, matches: &ArgMatches) -> std::result::Result<(), clap::Error> {
+        *self = Self::from_arg_matches(matches)?;
+        Ok(())
+    }
+}
+
+impl Args for FilterArgs {
+    fn augment_args(cmd: Command) -> Command {
+        FilterFlags::augment_args(cmd)
+    }
+
+    fn augment_args_for_update(cmd: Command) -> Command {
+        FilterFlags::augment_args_for_update(cmd)
+    }
+}
+
+
 impl Cli {
 
+    pub fn split_paths(&self) -> Result<(Vec<String>, String)> {
+        match self.paths.split_last() {
+            Some((destination, sources)) if !sources.is_empty() => Ok((sources.to_vec(), destination.clone())),
+            _ if self.verify_manifest.is_some() || !self.explain.is_empty() => Ok((Vec::new(), self.paths.last().cloned().unwrap_or_default())),
+            _ => Err(RsyncError::InvalidOption("expected at least one SOURCE and a DESTINATION".to_string())),
+        }
+    }
+
+
     pub fn into_options(self) -> Result<Options> {
         let mut options = Options::default();
 
 
         options.verbose = self.verbose;
-        options.quiet = self.quiet;
+        options.quiet = self.quiet || self.json;
         options.checksum = self.checksum;
+        options.cache_file = self.cache_file;
         options.archive = self.archive;
         options.recursive = self.recursive;
+        options.dirs = self.dirs && !self.no_dirs;
+        options.inc_recursive = !self.no_inc_recursive;
         options.relative = self.relative;
         options.update = self.update;
         options.links = self.links;
+        options.times = self.times;
+        options.perms = self.perms;
+        options.owner = self.owner;
+        options.group = self.group;
+        options.devices = self.devices_and_specials;
         options.copy_links = self.copy_links;
         options.hard_links = self.hard_links;
+        options.acls = self.acls;
+        options.ads = !self.no_ads && (self.ads || options.ads);
 
 
         options.compress = self.compress;
         if let Some(algo) = self.compress_choice {
             options.compress_choice = Some(parse_compression_algorithm(&algo)?);
         }
-        options.whole_file = self.whole_file;
+        options.whole_file = self.whole_file && !self.no_whole_file;
         options.inplace = self.inplace;
-        options.partial = self.partial;
+        options.sparse = self.sparse;
+        options.preallocate = self.preallocate;
+        options.append = self.append || self.append_verify;
+        options.append_verify = self.append_verify;
+        options.partial = self.partial || self.partial_progress;
         options.partial_dir = self.partial_dir;
         options.bwlimit = self.bwlimit;
+        options.parallel = match self.parallel {
+            Some(0) => std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+            Some(n) => n,
+            None => 1,
+        };
 
 
         options.backup = self.backup;
         options.backup_dir = self.backup_dir;
         options.suffix = self.suffix;
+        options.compare_dest = self.compare_dest;
+        options.copy_dest = self.copy_dest;
+        options.link_dest = self.link_dest;
 
 
-        options.delete = self.delete;
+        options.delete = self.delete || self.delete_before || self.delete_during || self.delete_after || self.delete_excluded;
         options.delete_before = self.delete_before;
         options.delete_during = self.delete_during;
         options.delete_after = self.delete_after;
@@ -295,22 +622,69 @@ impl Cli {
         options.remove_source_files = self.remove_source_files;
 
 
-        options.exclude = self.exclude;
-        options.include = self.include;
-        options.exclude_from = self.exclude_from.into_iter().collect();
-        options.include_from = self.include_from.into_iter().collect();
+        options.filter_rules = self.filter.rules;
+        options.exclude_if_present = self.exclude_if_present;
+        options.protect = self.protect;
+        options.explain = self.explain;
         options.files_from = self.files_from;
+        options.from0 = self.from0;
 
 
-        options.progress = self.progress;
+        options.progress = self.progress || self.partial_progress;
+        options.prescan = if self.no_prescan {
+            PrescanMode::Skip
+        } else if self.prescan {
+            PrescanMode::Accurate
+        } else {
+            PrescanMode::Estimate
+        };
         options.itemize_changes = self.itemize_changes;
         options.stats = self.stats;
+        options.info_skip = parse_skip_info(&self.info)?;
+        options.json = self.json;
         options.human_readable = self.human_readable;
         options.log_file = self.log_file;
 
 
         options.rsh = self.rsh;
+        options.ssh_algorithms = SshAlgorithms {
+            ciphers: self.ssh_ciphers,
+            kex: self.ssh_kex,
+            host_keys: self.ssh_hostkey_algorithms,
+            macs: None,
+        };
         options.rsync_path = self.rsync_path;
+        options.remote_options = self.remote_option;
+        options.blocking_io = if self.no_blocking_io {
+            Some(false)
+        } else if self.blocking_io {
+            Some(true)
+        } else {
+            None
+        };
+        options.password_prompts = self.password_prompts;
+        options.no_prompt = self.no_prompt;
+        if let Some(ref policy) = self.strict_host_key_checking {
+            options.strict_host_key_checking = Some(parse_strict_host_key_checking(policy)?);
+        }
+        options.use_credential_manager = self.use_credential_manager;
+        options.copy_as = self.copy_as;
+        options.elevate = self.elevate;
+        options.lock_file = self.lock_file;
+        options.lock_wait = self.lock_wait;
+        if let Some(ref spec) = self.schedule {
+            options.schedule = Some(Schedule::parse(spec)?);
+        }
+        options.unschedule = self.unschedule;
+        if let Some(ref spec) = self.sockopts {
+            options.sockopts = Some(SocketOptions::parse(spec)?);
+        }
+        options.net_buffer_size = self.net_buffer_size;
+        options.checksum_seed = self.checksum_seed.filter(|&seed| seed != 0);
+
+
+        options.server = self.server;
+        options.sender = self.sender;
 
 
         options.daemon = self.daemon;
@@ -319,12 +693,17 @@ impl Cli {
             options.port = Some(port);
         }
         options.config = self.config;
+        options.dparam = self.dparam;
         options.password_file = self.password_file;
 
 
-        options.dry_run = self.dry_run;
+        options.dry_run = self.dry_run || self.export_changes.is_some();
+        options.export_changes = self.export_changes;
+        options.manifest = self.manifest;
+        options.verify_manifest = self.verify_manifest;
         options.list_only = self.list_only;
         options.size_only = self.size_only;
+        options.ignore_times = self.ignore_times;
         options.timeout = self.timeout;
 
 
@@ -334,6 +713,16 @@ impl Cli {
 
 
         options.apply_archive_mode();
+        options.recursive &= !self.no_recursive;
+        options.links &= !self.no_links;
+        options.times &= !self.no_times;
+        options.perms &= !self.no_perms;
+        options.owner &= !self.no_owner;
+        options.group &= !self.no_group;
+        options.devices &= !self.no_devices_and_specials;
+        if options.server {
+            return Ok(options);
+        }
 
         let verbose = VerboseOutput::new(1, false);
 
@@ -355,11 +744,11 @@ impl Cli {
                 verbose.print_warning(&warning);
             }
         }
-        if self.times {
-            let warning = options.warn_unsupported_on_windows("times");
-            if !warning.is_empty() {
-                verbose.print_warning(&warning);
-            }
+        if self.acls && !cfg!(windows) {
+            verbose.print_warning("Warning: Option --acls (-A) is only supported on Windows and will be ignored.");
+        }
+        if self.ads && !cfg!(windows) {
+            verbose.print_warning("Warning: Option --ads is only supported on Windows and will be ignored.");
         }
         if self.devices_and_specials || self.devices || self.specials {
             let warning = options.warn_unsupported_on_windows("devices");
@@ -367,6 +756,9 @@ impl Cli {
                 verbose.print_warning(&warning);
             }
         }
+        if options.delete && !options.recursive && !options.dirs {
+            return Err(RsyncError::InvalidOption("--delete does not work without --recursive (-r) or --dirs (-d)".to_string()));
+        }
 
         Ok(options)
     }
@@ -384,15 +776,101 @@ fn parse_compression_algorithm(s: &str) -> Result<CompressionAlgorithm> {
     }
 }
 
-fn parse_checksum_algorithm(s: &str) -> Result<ChecksumAlgorithm> {
-    match s.to_lowercase().as_str() {
-        "md4" => Ok(ChecksumAlgorithm::Md4),
-        "md5" => Ok(ChecksumAlgorithm::Md5),
-        "blake2" => Ok(ChecksumAlgorithm::Blake2),
-        "xxh128" => Ok(ChecksumAlgorithm::Xxh128),
+fn parse_skip_info(flags: &[String]) -> Result<u8> {
+    flags.iter().try_fold(0, |_, flag| match flag.to_lowercase().as_str() {
+        "skip" | "skip1" => Ok(1),
+        "skip0" => Ok(0),
+        "skip2" => Ok(2),
         _ => Err(RsyncError::InvalidOption(format!(
-            "Invalid checksum algorithm: {}. Valid options: md4, md5, blake2, xxh128",
-            s
+            "Invalid --info flag: {}. Valid options: skip0, skip1, skip2",
+            flag
         ))),
+    })
+}
+
+fn parse_strict_host_key_checking(s: &str) -> Result<StrictHostKeyChecking> {
+    StrictHostKeyChecking::from_name(s).ok_or_else(|| RsyncError::InvalidOption(format!(
+        "Invalid host key checking policy: {}. Valid options: yes, ask, accept-new, no",
+        s
+    )))
+}
+
+fn parse_checksum_algorithm(s: &str) -> Result<ChecksumAlgorithm> {
+    ChecksumAlgorithm::from_name(s).ok_or_else(|| RsyncError::InvalidOption(format!(
+        "Invalid checksum algorithm: {}. Valid options: xxh128, xxh3, xxh64, md5, md4, blake2",
+        s
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(argv: &[&str]) -> Options {
+        Cli::try_parse_from(argv).unwrap().into_options().unwrap()
+    }
+
+    #[test]
+    fn test_partial_progress_shorthand() {
+        let options = parse(&["yarw", "-avzP", "src/", "host:dst/"]);
+        assert!(options.archive && options.recursive && options.links && options.times);
+        assert_eq!(options.verbose, 1);
+        assert!(options.compress);
+        assert!(options.partial);
+        assert!(options.progress);
+
+        let options = parse(&["yarw", "-P", "--partial-dir=.partial", "src/", "dst/"]);
+        assert!(options.partial && options.progress);
+        assert_eq!(options.partial_dir, Some(PathBuf::from(".partial")));
+    }
+
+    #[test]
+    fn test_typical_command_lines() {
+        let options = parse(&["yarw", "-avh", "--delete", "--exclude=.git/", "-e", "ssh -p 2222", "src/", "user@host:dst/"]);
+        assert!(options.archive && options.human_readable && options.delete);
+        assert_eq!(options.filter_rules, vec![FilterRule::Exclude(".git/".to_string())]);
+        assert_eq!(options.rsh.as_deref(), Some("ssh -p 2222"));
+
+        let options = parse(&["yarw", "-rtvn", "--stats", "src/", "dst/"]);
+        assert!(options.recursive && options.times && options.dry_run && options.stats);
+        assert!(!options.archive);
+
+        let options = parse(&["yarw", "-vvazP", "--progress", "-a", "src/", "dst/"]);
+        assert_eq!(options.verbose, 2);
+        assert!(options.archive && options.progress && options.partial);
+
+        let options = parse(&["yarw", "-av", "-e", "ssh", "-e", "ssh -i key", "src/", "dst/"]);
+        assert_eq!(options.rsh.as_deref(), Some("ssh -i key"));
+
+        let cli = Cli::try_parse_from(["yarw", "-azP", "--exclude", "*.tmp", "--exclude=*.log", "a/", "b/", "dst/"]).unwrap();
+        assert_eq!(cli.split_paths().unwrap(), (vec!["a/".to_string(), "b/".to_string()], "dst/".to_string()));
+        let options = cli.into_options().unwrap();
+        assert_eq!(options.filter_rules, vec![FilterRule::Exclude("*.tmp".to_string()), FilterRule::Exclude("*.log".to_string())]);
+    }
+
+
+    #[test]
+    fn test_filter_rules_keep_command_line_order() {
+        let options = parse(&[
+            "yarw", "-r", "--filter=+ keep.log", "--exclude=*.log", "--include-from=inc.txt",
+            "--filter=- *.tmp", "--exclude-from=exc.txt", "--include=*.tmp", "src/", "dst/",
+        ]);
+        assert_eq!(options.filter_rules, vec![
+            FilterRule::Rule("+ keep.log".to_string()),
+            FilterRule::Exclude("*.log".to_string()),
+            FilterRule::IncludeFrom(PathBuf::from("inc.txt")),
+            FilterRule::Rule("- *.tmp".to_string()),
+            FilterRule::ExcludeFrom(PathBuf::from("exc.txt")),
+            FilterRule::Include("*.tmp".to_string()),
+        ]);
+
+        let options = parse(&["yarw", "-r", "-F", "--exclude=*.bak", "-F", "src/", "dst/"]);
+        assert_eq!(options.filter_rules, vec![
+            FilterRule::Rule(": /.rsync-filter".to_string()),
+            FilterRule::Exclude("*.bak".to_string()),
+            FilterRule::Rule("- .rsync-filter".to_string()),
+        ]);
+        let options = parse(&["yarw", "-rFF", "src/", "dst/"]);
+        assert_eq!(options.filter_rules.len(), 2);
     }
 }
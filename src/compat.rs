@@ -0,0 +1,435 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+use clap::CommandFactory;
+use crate::cli::Cli;
+use crate::error::{Result, RsyncError};
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Support {
+
+    Native,
+
+    Ignored,
+
+    Unsupported,
+
+    Refused,
+
+    CvsExclude,
+}
+
+
+struct UpstreamOption {
+    long: &'static str,
+    short: Option<char>,
+    takes_value: bool,
+    support: Support,
+}
+
+const fn flag(long: &'static str, short: Option<char>, support: Support) -> UpstreamOption {
+    UpstreamOption { long, short, takes_value: false, support }
+}
+
+const fn value(long: &'static str, short: Option<char>, support: Support) -> UpstreamOption {
+    UpstreamOption { long, short, takes_value: true, support }
+}
+
+
+const UPSTREAM_OPTIONS: &[UpstreamOption] = &[
+    flag("verbose", Some('v'), Support::Native),
+    value("info", None, Support::Native),
+    value("debug", None, Support::Ignored),
+    value("stderr", None, Support::Ignored),
+    flag("msgs2stderr", None, Support::Ignored),
+    flag("quiet", Some('q'), Support::Native),
+    flag("no-motd", None, Support::Ignored),
+    flag("checksum", Some('c'), Support::Native),
+    flag("archive", Some('a'), Support::Native),
+    flag("recursive", Some('r'), Support::Native),
+    flag("relative", Some('R'), Support::Native),
+    flag("implied-dirs", None, Support::Unsupported),
+    flag("backup", Some('b'), Support::Native),
+    value("backup-dir", None, Support::Native),
+    value("suffix", None, Support::Native),
+    flag("update", Some('u'), Support::Native),
+    flag("inplace", None, Support::Native),
+    flag("append", None, Support::Native),
+    flag("append-verify", None, Support::Native),
+    flag("dirs", Some('d'), Support::Native),
+    flag("old-dirs", None, Support::Unsupported),
+    flag("old-d", None, Support::Unsupported),
+    flag("mkpath", None, Support::Unsupported),
+    flag("links", Some('l'), Support::Native),
+    flag("copy-links", Some('L'), Support::Native),
+    flag("copy-unsafe-links", None, Support::Unsupported),
+    flag("safe-links", None, Support::Unsupported),
+    flag("munge-links", None, Support::Unsupported),
+    flag("copy-dirlinks", Some('k'), Support::Unsupported),
+    flag("keep-dirlinks", Some('K'), Support::Refused),
+    flag("hard-links", Some('H'), Support::Native),
+    flag("perms", Some('p'), Support::Native),
+    flag("executability", Some('E'), Support::Unsupported),
+    value("chmod", None, Support::Unsupported),
+    flag("acls", Some('A'), Support::Native),
+    flag("xattrs", Some('X'), Support::Unsupported),
+    flag("owner", Some('o'), Support::Native),
+    flag("group", Some('g'), Support::Native),
+    flag("devices", None, Support::Native),
+    flag("copy-devices", None, Support::Unsupported),
+    flag("write-devices", None, Support::Unsupported),
+    flag("specials", None, Support::Native),
+    flag("times", Some('t'), Support::Native),
+    flag("atimes", Some('U'), Support::Unsupported),
+    flag("open-noatime", None, Support::Ignored),
+    flag("noatime", None, Support::Ignored),
+    flag("crtimes", Some('N'), Support::Unsupported),
+    flag("omit-dir-times", Some('O'), Support::Unsupported),
+    flag("omit-link-times", Some('J'), Support::Unsupported),
+    flag("super", None, Support::Unsupported),
+    flag("fake-super", None, Support::Unsupported),
+    flag("sparse", Some('S'), Support::Native),
+    flag("preallocate", None, Support::Native),
+    flag("dry-run", Some('n'), Support::Native),
+    flag("whole-file", Some('W'), Support::Native),
+    value("checksum-choice", None, Support::Native),
+    value("cc", None, Support::Unsupported),
+    flag("one-file-system", Some('x'), Support::Unsupported),
+    value("block-size", Some('B'), Support::Unsupported),
+    value("rsh", Some('e'), Support::Native),
+    value("rsync-path", None, Support::Native),
+    flag("existing", None, Support::Refused),
+    flag("ignore-non-existing", None, Support::Refused),
+    flag("ignore-existing", None, Support::Refused),
+    flag("remove-source-files", None, Support::Native),
+    flag("del", None, Support::Unsupported),
+    flag("delete", None, Support::Native),
+    flag("delete-before", None, Support::Native),
+    flag("delete-during", None, Support::Native),
+    flag("delete-delay", None, Support::Unsupported),
+    flag("delete-after", None, Support::Native),
+    flag("delete-excluded", None, Support::Native),
+    flag("ignore-missing-args", None, Support::Unsupported),
+    flag("delete-missing-args", None, Support::Unsupported),
+    flag("ignore-errors", None, Support::Unsupported),
+    flag("force", None, Support::Unsupported),
+    value("max-delete", None, Support::Refused),
+    value("max-size", None, Support::Refused),
+    value("min-size", None, Support::Refused),
+    value("max-alloc", None, Support::Ignored),
+    flag("partial", None, Support::Native),
+    value("partial-dir", None, Support::Native),
+    flag("delay-updates", None, Support::Unsupported),
+    flag("prune-empty-dirs", Some('m'), Support::Unsupported),
+    flag("numeric-ids", None, Support::Unsupported),
+    value("usermap", None, Support::Unsupported),
+    value("groupmap", None, Support::Unsupported),
+    value("chown", None, Support::Unsupported),
+    value("timeout", None, Support::Native),
+    value("contimeout", None, Support::Unsupported),
+    flag("ignore-times", Some('I'), Support::Native),
+    flag("size-only", None, Support::Native),
+    value("modify-window", Some('@'), Support::Unsupported),
+    value("temp-dir", Some('T'), Support::Unsupported),
+    flag("fuzzy", Some('y'), Support::Unsupported),
+    value("compare-dest", None, Support::Native),
+    value("copy-dest", None, Support::Native),
+    value("link-dest", None, Support::Native),
+    flag("compress", Some('z'), Support::Native),
+    value("compress-choice", None, Support::Native),
+    value("zc", None, Support::Unsupported),
+    value("compress-level", None, Support::Ignored),
+    value("zl", None, Support::Ignored),
+    value("skip-compress", None, Support::Ignored),
+    flag("cvs-exclude", Some('C'), Support::CvsExclude),
+    value("filter", Some('f'), Support::Native),
+    value("exclude", None, Support::Native),
+    value("exclude-from", None, Support::Native),
+    value("include", None, Support::Native),
+    value("include-from", None, Support::Native),
+    value("files-from", None, Support::Native),
+    flag("from0", Some('0'), Support::Native),
+    flag("old-args", None, Support::Ignored),
+    flag("secluded-args", Some('s'), Support::Ignored),
+    flag("protect-args", None, Support::Ignored),
+    flag("trust-sender", None, Support::Ignored),
+    value("copy-as", None, Support::Native),
+    value("address", None, Support::Native),
+    value("port", None, Support::Native),
+    value("sockopts", None, Support::Native),
+    value("outbuf", None, Support::Ignored),
+    flag("blocking-io", None, Support::Native),
+    flag("stats", None, Support::Native),
+    flag("8-bit-output", Some('8'), Support::Ignored),
+    flag("human-readable", Some('h'), Support::Native),
+    flag("progress", None, Support::Native),
+    flag("itemize-changes", Some('i'), Support::Native),
+    value("remote-option", Some('M'), Support::Native),
+    value("out-format", None, Support::Unsupported),
+    value("log-format", None, Support::Unsupported),
+    value("log-file", None, Support::Native),
+    value("log-file-format", None, Support::Unsupported),
+    value("password-file", None, Support::Native),
+    value("early-input", None, Support::Unsupported),
+    flag("list-only", None, Support::Native),
+    value("bwlimit", None, Support::Native),
+    value("stop-after", None, Support::Unsupported),
+    value("time-limit", None, Support::Unsupported),
+    value("stop-at", None, Support::Unsupported),
+    flag("fsync", None, Support::Unsupported),
+    value("write-batch", None, Support::Unsupported),
+    value("only-write-batch", None, Support::Refused),
+    value("read-batch", None, Support::Unsupported),
+    value("protocol", None, Support::Unsupported),
+    value("iconv", None, Support::Unsupported),
+    value("checksum-seed", None, Support::Native),
+    flag("ipv4", Some('4'), Support::Ignored),
+    flag("ipv6", Some('6'), Support::Ignored),
+    flag("version", Some('V'), Support::Native),
+    flag("daemon", None, Support::Native),
+    value("config", None, Support::Native),
+    value("dparam", None, Support::Native),
+    flag("detach", None, Support::Ignored),
+    flag("qsort", None, Support::Ignored),
+];
+
+
+const CVS_EXCLUDES: &[&str] = &[
+    "RCS", "SCCS", "CVS", "CVS.adm", "RCSLOG", "cvslog.*", "tags", "TAGS", ".make.state", ".nse_depinfo",
+    "*~", "#*", ".#*", ",*", "_$*", "*$", "*.old", "*.bak", "*.BAK", "*.orig", "*.rej", ".del-*", "*.a",
+    "*.olb", "*.o", "*.obj", "*.so", "*.exe", "*.Z", "*.elc", "*.ln", "core", ".svn/", ".git/", ".hg/", ".bzr/",
+];
+
+
+struct KnownOptions {
+    longs: HashMap<String, bool>,
+    shorts: HashMap<char, bool>,
+}
+
+impl KnownOptions {
+    fn from_cli() -> Self {
+        let mut command = Cli::command();
+        command.build();
+        let mut longs = HashMap::new();
+        let mut shorts = HashMap::new();
+        for arg in command.get_arguments() {
+            let takes_value = arg.get_action().takes_values();
+            for long in arg.get_long().into_iter().chain(arg.get_all_aliases().unwrap_or_default()) {
+                longs.insert(long.to_string(), takes_value);
+            }
+            for short in arg.get_short().into_iter().chain(arg.get_all_short_aliases().unwrap_or_default()) {
+                shorts.insert(short, takes_value);
+            }
+        }
+        Self { longs, shorts }
+    }
+}
+
+
+pub fn normalize_args(args: impl IntoIterator<Item = OsString>) -> Result<Vec<OsString>> {
+    let args: Vec<OsString> = args.into_iter().collect();
+    let strict = args.iter().any(|arg| arg == "--strict-options");
+    let known = KnownOptions::from_cli();
+
+    let mut normalized = Vec::with_capacity(args.len());
+    let mut args = args.into_iter();
+    if let Some(program) = args.next() {
+        normalized.push(program);
+    }
+    while let Some(arg) = args.next() {
+        let Some(text) = arg.to_str().map(str::to_string) else {
+            normalized.push(arg);
+            continue;
+        };
+        if text == "--" {
+            normalized.push(arg);
+            normalized.extend(args.by_ref());
+            break;
+        }
+
+        if let Some(body) = text.strip_prefix("--") {
+            let (name, inline_value) = match body.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (body, None),
+            };
+            if let Some(&takes_value) = known.longs.get(name) {
+                normalized.push(arg);
+                if takes_value && inline_value.is_none() {
+                    normalized.extend(args.next());
+                }
+                continue;
+            }
+            let Some((option, negated)) = upstream_long(name) else {
+                normalized.push(arg);
+                continue;
+            };
+            if option.takes_value && inline_value.is_none() && !negated {
+                args.next();
+            }
+            if negated && known.longs.contains_key(option.long) {
+                let negation = format!("no-{}", option.long);
+                if !known.longs.contains_key(&negation) {
+                    return Err(RsyncError::InvalidOption(format!("{} is not supported by yarw", text)));
+                }
+                normalized.push(OsString::from(format!("--{}", negation)));
+                continue;
+            }
+            let support = if negated { Support::Ignored } else { option.support };
+            apply(&text, support, negated, strict, &mut normalized)?;
+            continue;
+        }
+
+        if text.len() > 1 && text.starts_with('-') {
+            let mut kept = String::from("-");
+            for (index, short) in text[1..].char_indices() {
+                let rest = &text[1 + index + short.len_utf8()..];
+                if let Some(&takes_value) = known.shorts.get(&short) {
+                    kept.push(short);
+                    if takes_value {
+                        kept.push_str(rest);
+                        if rest.is_empty() {
+                            normalized.push(OsString::from(std::mem::take(&mut kept)));
+                            normalized.extend(args.next());
+                        }
+                        break;
+                    }
+                    continue;
+                }
+                let Some(option) = UPSTREAM_OPTIONS.iter().find(|option| option.short == Some(short)) else {
+                    kept.push(short);
+                    continue;
+                };
+                apply(&format!("-{}", short), option.support, false, strict, &mut normalized)?;
+                if option.takes_value {
+                    if rest.is_empty() {
+                        args.next();
+                    }
+                    break;
+                }
+            }
+            if kept.len() > 1 {
+                normalized.push(OsString::from(kept));
+            }
+            continue;
+        }
+
+        normalized.push(arg);
+    }
+    Ok(normalized)
+}
+
+
+fn upstream_long(name: &str) -> Option<(&'static UpstreamOption, bool)> {
+    if let Some(option) = UPSTREAM_OPTIONS.iter().find(|option| option.long == name) {
+        return Some((option, false));
+    }
+    let negated = name.strip_prefix("no-")?;
+    UPSTREAM_OPTIONS.iter()
+        .find(|option| option.long == negated || (negated.len() == 1 && option.short == negated.chars().next()))
+        .map(|option| (option, true))
+}
+
+
+fn apply(arg: &str, support: Support, negated: bool, strict: bool, normalized: &mut Vec<OsString>) -> Result<()> {
+    match support {
+        Support::Native | Support::Ignored => Ok(()),
+        Support::Refused => Err(RsyncError::InvalidOption(format!(
+            "{} is not supported by yarw, and running without it could delete or overwrite files it would have kept",
+            arg
+        ))),
+        Support::CvsExclude if !negated => {
+            normalized.extend(CVS_EXCLUDES.iter().map(|pattern| OsString::from(format!("--exclude={}", pattern))));
+            Ok(())
+        }
+        Support::CvsExclude | Support::Unsupported if strict => Err(RsyncError::InvalidOption(format!(
+            "{} is not supported by yarw (remove --strict-options to ignore it)",
+            arg
+        ))),
+        Support::CvsExclude | Support::Unsupported => {
+            eprintln!("Warning: ignoring unsupported option {}", arg);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalize(argv: &[&str]) -> Result<Vec<String>> {
+        let args = normalize_args(argv.iter().map(OsString::from))?;
+        Ok(args.into_iter().map(|arg| arg.into_string().unwrap()).collect())
+    }
+
+    #[test]
+    fn test_ignores_harmless_upstream_options() -> Result<()> {
+        assert_eq!(
+            normalize(&["yarw", "--outbuf=L", "-av8", "--noatime", "--skip-compress", "gz/zip", "src/", "dst/"])?,
+            vec!["yarw", "-av", "src/", "dst/"]
+        );
+        assert_eq!(normalize(&["yarw", "-avs", "--no-motd", "--no-xattrs", "src/", "dst/"])?, vec!["yarw", "-av", "src/", "dst/"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_unsupported_options_warn_or_refuse() -> Result<()> {
+        assert_eq!(normalize(&["yarw", "-axXB", "1024", "--chmod", "a+r", "src/", "dst/"])?, vec!["yarw", "-a", "src/", "dst/"]);
+        assert!(normalize(&["yarw", "--strict-options", "-ax", "src/", "dst/"]).is_err());
+        assert!(normalize(&["yarw", "--strict-options", "--outbuf=L", "src/", "dst/"]).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_known_options_and_values_pass_through() -> Result<()> {
+        let argv = ["yarw", "--server", "-re.LsfxC", "--exclude", "--outbuf", "-e", "ssh -x", ".", "dst/"];
+        assert_eq!(normalize(&argv)?, argv.to_vec());
+        assert_eq!(normalize(&["yarw", "-av", "--", "--noatime", "dst/"])?, vec!["yarw", "-av", "--", "--noatime", "dst/"]);
+        assert_eq!(normalize(&["yarw", "--bogus", "src/", "dst/"])?, vec!["yarw", "--bogus", "src/", "dst/"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_refuses_options_that_limit_deletes_or_overwrites() {
+        for argv in [
+            &["yarw", "-a", "--max-delete=10", "--delete", "src/", "dst/"][..],
+            &["yarw", "-a", "--max-delete", "10", "src/", "dst/"],
+            &["yarw", "-a", "--ignore-existing", "src/", "dst/"],
+            &["yarw", "-a", "--existing", "src/", "dst/"],
+            &["yarw", "-a", "--ignore-non-existing", "src/", "dst/"],
+            &["yarw", "-a", "--max-size=1m", "src/", "dst/"],
+            &["yarw", "-a", "--min-size", "1k", "src/", "dst/"],
+            &["yarw", "-aK", "src/", "dst/"],
+        ] {
+            assert!(matches!(normalize(argv), Err(RsyncError::InvalidOption(_))), "{:?} was accepted", argv);
+        }
+        assert_eq!(normalize(&["yarw", "-a", "--no-ignore-existing", "src/", "dst/"]).unwrap(), vec!["yarw", "-a", "src/", "dst/"]);
+    }
+
+    #[test]
+    fn test_negations_map_to_yarw_flags() -> Result<()> {
+        assert_eq!(
+            normalize(&["yarw", "-a", "--no-W", "--no-perms", "--no-o", "--no-D", "src/", "dst/"])?,
+            vec!["yarw", "-a", "--no-whole-file", "--no-perms", "--no-o", "--no-D", "src/", "dst/"]
+        );
+        assert!(matches!(normalize(&["yarw", "-a", "--no-update", "src/", "dst/"]), Err(RsyncError::InvalidOption(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_native_options_match_cli() {
+        let known = KnownOptions::from_cli();
+        for option in UPSTREAM_OPTIONS {
+            let native = known.longs.contains_key(option.long);
+            assert_eq!(option.support == Support::Native, native, "--{} is listed as {:?}", option.long, option.support);
+        }
+    }
+
+    #[test]
+    fn test_cvs_exclude() -> Result<()> {
+        let args = normalize(&["yarw", "-avC", "src/", "dst/"])?;
+        assert_eq!(&args[..2], ["yarw", "--exclude=RCS"]);
+        assert!(args.contains(&"--exclude=.git/".to_string()));
+        assert_eq!(&args[args.len() - 3..], ["-av", "src/", "dst/"]);
+        Ok(())
+    }
+}
@@ -28,11 +28,16 @@ pub enum RsyncError {
     #[error("Authentication failed: {0}")]
     Auth(String),
 
+    #[error("Insufficient privileges: {0}")]
+    Privilege(String),
+
+    #[error("Host key verification failed: {0}")]
+    HostKey(String),
+
     #[error("Network error: {0}")]
     Network(String),
 
     #[error("Checksum mismatch for file: {0}")]
-    #[allow(dead_code)]
     ChecksumMismatch(String),
 
     #[error("UTF-8 conversion error: {0}")]
@@ -42,6 +47,23 @@ pub enum RsyncError {
     Other(String),
 }
 
+impl RsyncError {
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            RsyncError::Network(_) => true,
+            RsyncError::Io(e) => matches!(
+                e.kind(),
+                std::io::ErrorKind::TimedOut
+                    | std::io::ErrorKind::ConnectionAborted
+                    | std::io::ErrorKind::ConnectionReset
+                    | std::io::ErrorKind::BrokenPipe
+                    | std::io::ErrorKind::UnexpectedEof
+            ),
+            _ => false,
+        }
+    }
+}
+
 impl From<toml::de::Error> for RsyncError {
     fn from(err: toml::de::Error) -> Self {
         RsyncError::Config(err.to_string())
@@ -0,0 +1,161 @@
+use crate::error::Result;
+use std::path::Path;
+
+
+pub fn read_acl(path: &Path) -> Result<Option<String>> {
+    platform::read_acl(path)
+}
+
+
+pub fn acl_differs(source: &Path, destination: &Path) -> Result<bool> {
+    Ok(read_acl(source)? != read_acl(destination)?)
+}
+
+
+pub fn copy_acl(source: &Path, destination: &Path) -> Result<()> {
+    platform::copy_acl(source, destination)
+}
+
+
+#[cfg(windows)]
+mod platform {
+    use crate::error::{Result, RsyncError};
+    use std::path::Path;
+    use std::ptr;
+    use windows::core::{HSTRING, PWSTR};
+    use windows::Win32::Foundation::{LocalFree, HLOCAL, PSID};
+    use windows::Win32::Security::Authorization::{
+        ConvertSecurityDescriptorToStringSecurityDescriptorW, GetNamedSecurityInfoW, SetNamedSecurityInfoW,
+        SDDL_REVISION_1, SE_FILE_OBJECT,
+    };
+    use windows::Win32::Security::{
+        GetSecurityDescriptorControl, ACL, DACL_SECURITY_INFORMATION, PROTECTED_DACL_SECURITY_INFORMATION,
+        PSECURITY_DESCRIPTOR, SE_DACL_PROTECTED, UNPROTECTED_DACL_SECURITY_INFORMATION,
+    };
+
+    struct SecurityDescriptor {
+        descriptor: PSECURITY_DESCRIPTOR,
+        dacl: *mut ACL,
+    }
+
+    impl SecurityDescriptor {
+        fn read(path: &Path) -> Result<Self> {
+            let mut descriptor = PSECURITY_DESCRIPTOR(ptr::null_mut());
+            let mut dacl: *mut ACL = ptr::null_mut();
+            unsafe {
+                GetNamedSecurityInfoW(
+                    &HSTRING::from(path),
+                    SE_FILE_OBJECT,
+                    DACL_SECURITY_INFORMATION,
+                    None,
+                    None,
+                    Some(&mut dacl),
+                    None,
+                    &mut descriptor,
+                )
+            }
+            .map_err(|e| acl_error("read", path, e))?;
+            Ok(Self { descriptor, dacl })
+        }
+
+        fn to_sddl(&self, path: &Path) -> Result<String> {
+            let mut sddl = PWSTR::null();
+            unsafe {
+                ConvertSecurityDescriptorToStringSecurityDescriptorW(
+                    PSECURITY_DESCRIPTOR(self.descriptor.0),
+                    SDDL_REVISION_1,
+                    DACL_SECURITY_INFORMATION,
+                    &mut sddl,
+                    None,
+                )
+            }
+            .map_err(|e| acl_error("read", path, e))?;
+            let text = unsafe { sddl.to_string() };
+            unsafe {
+                let _ = LocalFree(HLOCAL(sddl.0 as *mut _));
+            }
+            text.map_err(|e| RsyncError::Other(format!("Invalid ACL on {}: {}", path.display(), e)))
+        }
+
+        fn is_protected(&self, path: &Path) -> Result<bool> {
+            let mut control = 0u16;
+            let mut revision = 0u32;
+            unsafe { GetSecurityDescriptorControl(PSECURITY_DESCRIPTOR(self.descriptor.0), &mut control, &mut revision) }
+                .map_err(|e| acl_error("read", path, e))?;
+            Ok(control & SE_DACL_PROTECTED.0 != 0)
+        }
+    }
+
+    impl Drop for SecurityDescriptor {
+        fn drop(&mut self) {
+            unsafe {
+                let _ = LocalFree(HLOCAL(self.descriptor.0));
+            }
+        }
+    }
+
+    pub fn read_acl(path: &Path) -> Result<Option<String>> {
+        SecurityDescriptor::read(path)?.to_sddl(path).map(Some)
+    }
+
+    pub fn copy_acl(source: &Path, destination: &Path) -> Result<()> {
+        let descriptor = SecurityDescriptor::read(source)?;
+        let inheritance = if descriptor.is_protected(source)? {
+            PROTECTED_DACL_SECURITY_INFORMATION
+        } else {
+            UNPROTECTED_DACL_SECURITY_INFORMATION
+        };
+        unsafe {
+            SetNamedSecurityInfoW(
+                &HSTRING::from(destination),
+                SE_FILE_OBJECT,
+                DACL_SECURITY_INFORMATION | inheritance,
+                PSID(ptr::null_mut()),
+                PSID(ptr::null_mut()),
+                Some(descriptor.dacl as *const ACL),
+                None,
+            )
+        }
+        .map_err(|e| acl_error("apply", destination, e))
+    }
+
+    fn acl_error(action: &str, path: &Path, e: windows::core::Error) -> RsyncError {
+        RsyncError::Other(format!("Failed to {} ACL of {}: {}", action, path.display(), e))
+    }
+}
+
+
+#[cfg(not(windows))]
+mod platform {
+    use crate::error::Result;
+    use std::path::Path;
+
+    pub fn read_acl(_path: &Path) -> Result<Option<String>> {
+        Ok(None)
+    }
+
+    pub fn copy_acl(_source: &Path, _destination: &Path) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_copy_acl() -> Result<()> {
+        let dir = TempDir::new()?;
+        let source = dir.path().join("source.txt");
+        let destination = dir.path().join("dest.txt");
+        fs::write(&source, b"source")?;
+        fs::write(&destination, b"dest")?;
+
+        copy_acl(&source, &destination)?;
+        assert!(!acl_differs(&source, &destination)?);
+        assert_eq!(read_acl(&source)?.is_some(), cfg!(windows));
+        Ok(())
+    }
+}
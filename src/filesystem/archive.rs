@@ -0,0 +1,290 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use chrono::{Datelike, Local, NaiveDate, TimeZone, Timelike};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+use crate::error::{Result, RsyncError};
+use crate::filesystem::{FileInfo, FileType};
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Tar,
+    Zip,
+}
+
+impl ArchiveFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_string_lossy().to_lowercase();
+        match extension.as_str() {
+            "tar" => Some(Self::Tar),
+            "zip" => Some(Self::Zip),
+            _ => None,
+        }
+    }
+
+
+    pub fn mtime_window(&self) -> Duration {
+        match self {
+            Self::Tar => Duration::from_secs(1),
+            Self::Zip => Duration::from_secs(2),
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveEntry {
+    pub path: String,
+    pub size: u64,
+    pub mtime: SystemTime,
+    pub is_dir: bool,
+    pub is_file: bool,
+}
+
+impl ArchiveEntry {
+    pub fn file_info(&self) -> FileInfo {
+        FileInfo {
+            path: PathBuf::from(&self.path),
+            size: self.size,
+            mtime: self.mtime,
+            file_type: if self.is_dir { FileType::Directory } else { FileType::File },
+            is_symlink: false,
+            symlink_target: None,
+            nlink: 1,
+            file_id: None,
+            streams: Vec::new(),
+        }
+    }
+}
+
+
+pub fn read_index(archive: &Path, format: ArchiveFormat) -> Result<Vec<ArchiveEntry>> {
+    let file = File::open(archive)?;
+    let mut entries = Vec::new();
+    match format {
+        ArchiveFormat::Tar => {
+            for entry in tar::Archive::new(file).entries()? {
+                let entry = entry?;
+                let header = entry.header();
+                entries.push(ArchiveEntry {
+                    path: entry_name(&entry.path()?.to_string_lossy()),
+                    size: entry.size(),
+                    mtime: UNIX_EPOCH + Duration::from_secs(header.mtime()?),
+                    is_dir: header.entry_type().is_dir(),
+                    is_file: header.entry_type().is_file(),
+                });
+            }
+        }
+        ArchiveFormat::Zip => {
+            let mut zip = ZipArchive::new(file).map_err(|e| zip_error(archive, e))?;
+            for index in 0..zip.len() {
+                let entry = zip.by_index(index).map_err(|e| zip_error(archive, e))?;
+                entries.push(ArchiveEntry {
+                    path: entry_name(entry.name()),
+                    size: entry.size(),
+                    mtime: entry.last_modified().and_then(zip_to_system_time).unwrap_or(UNIX_EPOCH),
+                    is_dir: entry.is_dir(),
+                    is_file: entry.is_file(),
+                });
+            }
+        }
+    }
+    Ok(entries)
+}
+
+
+pub fn extract_entries(archive: &Path, format: ArchiveFormat, targets: &HashMap<String, PathBuf>) -> Result<()> {
+    let file = File::open(archive)?;
+    match format {
+        ArchiveFormat::Tar => {
+            for entry in tar::Archive::new(file).entries()? {
+                let mut entry = entry?;
+                if let Some(target) = targets.get(&entry_name(&entry.path()?.to_string_lossy())) {
+                    write_target(target, &mut entry)?;
+                }
+            }
+        }
+        ArchiveFormat::Zip => {
+            let mut zip = ZipArchive::new(file).map_err(|e| zip_error(archive, e))?;
+            for index in 0..zip.len() {
+                let mut entry = zip.by_index(index).map_err(|e| zip_error(archive, e))?;
+                if let Some(target) = targets.get(&entry_name(entry.name())) {
+                    write_target(target, &mut entry)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+
+pub struct ArchiveWriter {
+    inner: Writer,
+}
+
+enum Writer {
+    Tar(tar::Builder<File>),
+    Zip(Box<ZipWriter<File>>),
+}
+
+impl ArchiveWriter {
+    pub fn new(file: File, format: ArchiveFormat) -> Self {
+        let inner = match format {
+            ArchiveFormat::Tar => Writer::Tar(tar::Builder::new(file)),
+            ArchiveFormat::Zip => Writer::Zip(Box::new(ZipWriter::new(file))),
+        };
+        Self { inner }
+    }
+
+
+    pub fn copy_entries(&mut self, archive: &Path, keep: &HashSet<String>) -> Result<()> {
+        let file = File::open(archive)?;
+        match self.inner {
+            Writer::Tar(ref mut builder) => {
+                let mut copied = HashSet::new();
+                for entry in tar::Archive::new(file).entries()? {
+                    let mut entry = entry?;
+                    let path = entry.path()?.into_owned();
+                    let name = entry_name(&path.to_string_lossy());
+                    if keep.contains(&name) && copied.insert(name) {
+                        let mut header = entry.header().clone();
+                        builder.append_data(&mut header, path, &mut entry)?;
+                    }
+                }
+            }
+            Writer::Zip(ref mut writer) => {
+                let mut zip = ZipArchive::new(file).map_err(|e| zip_error(archive, e))?;
+                for index in 0..zip.len() {
+                    let entry = zip.by_index(index).map_err(|e| zip_error(archive, e))?;
+                    if keep.contains(&entry_name(entry.name())) {
+                        writer.raw_copy_file(entry).map_err(|e| zip_error(archive, e))?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+
+    pub fn add_directory(&mut self, name: &str, source: &Path) -> Result<()> {
+        match self.inner {
+            Writer::Tar(ref mut builder) => builder.append_dir(name, source)?,
+            Writer::Zip(ref mut writer) => writer
+                .add_directory(name, zip_options(source)?)
+                .map_err(|e| zip_error(source, e))?,
+        }
+        Ok(())
+    }
+
+
+    pub fn add_file(&mut self, name: &str, source: &Path) -> Result<()> {
+        match self.inner {
+            Writer::Tar(ref mut builder) => builder.append_path_with_name(source, name)?,
+            Writer::Zip(ref mut writer) => {
+                writer.start_file(name, zip_options(source)?).map_err(|e| zip_error(source, e))?;
+                std::io::copy(&mut File::open(source)?, writer)?;
+            }
+        }
+        Ok(())
+    }
+
+
+    pub fn finish(self) -> Result<File> {
+        match self.inner {
+            Writer::Tar(builder) => Ok(builder.into_inner()?),
+            Writer::Zip(writer) => writer.finish().map_err(|e| RsyncError::Other(format!("Failed to finish zip archive: {}", e))),
+        }
+    }
+}
+
+
+fn write_target(target: &Path, reader: &mut impl Read) -> Result<()> {
+    let parent = target.parent().unwrap_or(Path::new("."));
+    fs::create_dir_all(parent)?;
+    let mut temp = tempfile::NamedTempFile::new_in(parent)?;
+    std::io::copy(reader, &mut temp)?;
+    temp.persist(target).map_err(|e| e.error)?;
+    Ok(())
+}
+
+
+fn entry_name(path: &str) -> String {
+    path.replace('\\', "/").trim_end_matches('/').to_string()
+}
+
+
+fn zip_options(source: &Path) -> Result<SimpleFileOptions> {
+    let mtime: chrono::DateTime<Local> = fs::metadata(source)?.modified()?.into();
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    Ok(match zip::DateTime::from_date_and_time(
+        mtime.year() as u16,
+        mtime.month() as u8,
+        mtime.day() as u8,
+        mtime.hour() as u8,
+        mtime.minute() as u8,
+        mtime.second() as u8,
+    ) {
+        Ok(mtime) => options.last_modified_time(mtime),
+        Err(_) => options,
+    })
+}
+
+
+fn zip_to_system_time(mtime: zip::DateTime) -> Option<SystemTime> {
+    let naive = NaiveDate::from_ymd_opt(mtime.year() as i32, mtime.month() as u32, mtime.day() as u32)?
+        .and_hms_opt(mtime.hour() as u32, mtime.minute() as u32, mtime.second() as u32)?;
+    Local.from_local_datetime(&naive).earliest().map(SystemTime::from)
+}
+
+
+fn zip_error(path: &Path, e: zip::result::ZipError) -> RsyncError {
+    RsyncError::Other(format!("Invalid zip archive {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_archive_round_trip() -> Result<()> {
+        let dir = TempDir::new()?;
+        fs::create_dir(dir.path().join("sub"))?;
+        fs::write(dir.path().join("sub/a.txt"), b"alpha")?;
+        fs::write(dir.path().join("b.txt"), b"bravo!")?;
+
+        for (name, format) in [("out.tar", ArchiveFormat::Tar), ("out.zip", ArchiveFormat::Zip)] {
+            let archive = dir.path().join(name);
+            assert_eq!(ArchiveFormat::from_path(&archive), Some(format));
+            let mut writer = ArchiveWriter::new(File::create(&archive)?, format);
+            writer.add_directory("sub", &dir.path().join("sub"))?;
+            writer.add_file("sub/a.txt", &dir.path().join("sub/a.txt"))?;
+            writer.add_file("b.txt", &dir.path().join("b.txt"))?;
+            writer.finish()?;
+
+            let index = read_index(&archive, format)?;
+            let summary: Vec<_> = index.iter().map(|e| (e.path.as_str(), e.size, e.is_dir)).collect();
+            assert_eq!(summary, [("sub", 0, true), ("sub/a.txt", 5, false), ("b.txt", 6, false)]);
+
+            let restored = dir.path().join(format!("restored-{}", name));
+            let targets = HashMap::from([("sub/a.txt".to_string(), restored.join("a.txt"))]);
+            extract_entries(&archive, format, &targets)?;
+            assert_eq!(fs::read(restored.join("a.txt"))?, b"alpha");
+
+            let copy = dir.path().join(format!("copy-{}", name));
+            let mut writer = ArchiveWriter::new(File::create(&copy)?, format);
+            writer.copy_entries(&archive, &HashSet::from(["b.txt".to_string()]))?;
+            writer.finish()?;
+            let index = read_index(&copy, format)?;
+            assert_eq!(index.len(), 1);
+            assert_eq!(index[0].path, "b.txt");
+        }
+        assert_eq!(ArchiveFormat::from_path(Path::new("backup.TAR")), Some(ArchiveFormat::Tar));
+        assert_eq!(ArchiveFormat::from_path(Path::new("backup")), None);
+        Ok(())
+    }
+}
@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use crate::error::{Result, RsyncError};
+use crate::options::ChecksumAlgorithm;
+
+
+const CACHE_MAGIC: &[u8; 8] = b"YARWFSC1";
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct CacheEntry {
+    size: u64,
+    mtime: Duration,
+    checksum: Vec<u8>,
+}
+
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: usize,
+    pub misses: usize,
+}
+
+
+#[derive(Debug)]
+struct CacheState {
+    entries: HashMap<PathBuf, CacheEntry>,
+    stats: CacheStats,
+    dirty: bool,
+}
+
+
+#[derive(Debug)]
+pub struct FileStateCache {
+    path: PathBuf,
+    algorithm: ChecksumAlgorithm,
+    state: Mutex<CacheState>,
+}
+
+impl FileStateCache {
+
+    pub fn load(path: &Path, algorithm: ChecksumAlgorithm) -> Result<Self> {
+        let entries = match fs::File::open(path) {
+            Ok(file) => read_entries(&mut BufReader::new(file), algorithm).map_err(|e| {
+                RsyncError::Config(format!("Invalid cache file {}: {}", path.display(), e))
+            })?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            algorithm,
+            state: Mutex::new(CacheState { entries, stats: CacheStats::default(), dirty: false }),
+        })
+    }
+
+
+    pub fn empty(path: &Path, algorithm: ChecksumAlgorithm) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            algorithm,
+            state: Mutex::new(CacheState { entries: HashMap::new(), stats: CacheStats::default(), dirty: true }),
+        }
+    }
+
+
+    pub fn checksum(&self, path: &Path, compute: impl FnOnce() -> Result<Vec<u8>>) -> Result<Vec<u8>> {
+        let metadata = fs::metadata(path)?;
+        let size = metadata.len();
+        let mtime = metadata.modified()?.duration_since(UNIX_EPOCH).unwrap_or_default();
+
+        {
+            let mut state = self.lock();
+            let cached = state.entries.get(path)
+                .filter(|entry| entry.size == size && entry.mtime == mtime)
+                .map(|entry| entry.checksum.clone());
+            if let Some(checksum) = cached {
+                state.stats.hits += 1;
+                return Ok(checksum);
+            }
+        }
+
+        let checksum = compute()?;
+        let mut state = self.lock();
+        state.stats.misses += 1;
+        if !is_recent(mtime) {
+            state.entries.insert(path.to_path_buf(), CacheEntry { size, mtime, checksum: checksum.clone() });
+            state.dirty = true;
+        }
+        Ok(checksum)
+    }
+
+
+    pub fn stats(&self) -> CacheStats {
+        self.lock().stats
+    }
+
+
+    pub fn save(&self) -> Result<()> {
+        let mut state = self.lock();
+        if !state.dirty {
+            return Ok(());
+        }
+        let parent = match self.path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        fs::create_dir_all(parent)?;
+        let mut temp = tempfile::NamedTempFile::new_in(parent)?;
+        {
+            let mut writer = BufWriter::new(temp.as_file_mut());
+            write_entries(&mut writer, self.algorithm, &state.entries)?;
+            writer.flush()?;
+        }
+        temp.persist(&self.path).map_err(|e| e.error)?;
+        state.dirty = false;
+        Ok(())
+    }
+
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, CacheState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+
+fn is_recent(mtime: Duration) -> bool {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().saturating_sub(mtime) < Duration::from_secs(2)
+}
+
+
+fn read_entries(reader: &mut impl Read, algorithm: ChecksumAlgorithm) -> std::io::Result<HashMap<PathBuf, CacheEntry>> {
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != CACHE_MAGIC {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "not a yarw cache file"));
+    }
+    let name = read_bytes(reader)?;
+    if name != algorithm.name().as_bytes() {
+        return Ok(HashMap::new());
+    }
+
+    let count = reader.read_u64::<LittleEndian>()?;
+    let mut entries = HashMap::new();
+    for _ in 0..count {
+        let path = String::from_utf8(read_bytes(reader)?)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        let size = reader.read_u64::<LittleEndian>()?;
+        let secs = reader.read_u64::<LittleEndian>()?;
+        let nanos = reader.read_u32::<LittleEndian>()?;
+        let checksum = read_bytes(reader)?;
+        entries.insert(PathBuf::from(path), CacheEntry { size, mtime: Duration::new(secs, nanos), checksum });
+    }
+    Ok(entries)
+}
+
+
+fn write_entries(writer: &mut impl Write, algorithm: ChecksumAlgorithm, entries: &HashMap<PathBuf, CacheEntry>) -> std::io::Result<()> {
+    writer.write_all(CACHE_MAGIC)?;
+    write_bytes(writer, algorithm.name().as_bytes())?;
+
+    let entries: Vec<(&str, &CacheEntry)> = entries.iter()
+        .filter_map(|(path, entry)| path.to_str().map(|path| (path, entry)))
+        .collect();
+    writer.write_u64::<LittleEndian>(entries.len() as u64)?;
+    for (path, entry) in entries {
+        write_bytes(writer, path.as_bytes())?;
+        writer.write_u64::<LittleEndian>(entry.size)?;
+        writer.write_u64::<LittleEndian>(entry.mtime.as_secs())?;
+        writer.write_u32::<LittleEndian>(entry.mtime.subsec_nanos())?;
+        write_bytes(writer, &entry.checksum)?;
+    }
+    Ok(())
+}
+
+
+fn read_bytes(reader: &mut impl Read) -> std::io::Result<Vec<u8>> {
+    let len = reader.read_u32::<LittleEndian>()? as usize;
+    let mut bytes = Vec::with_capacity(len.min(4096));
+    reader.take(len as u64).read_to_end(&mut bytes)?;
+    if bytes.len() != len {
+        return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "truncated cache entry"));
+    }
+    Ok(bytes)
+}
+
+
+fn write_bytes(writer: &mut impl Write, bytes: &[u8]) -> std::io::Result<()> {
+    writer.write_u32::<LittleEndian>(bytes.len() as u32)?;
+    writer.write_all(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filesystem::set_file_times;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_cache_reuses_checksums_until_file_changes() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let file = temp_dir.path().join("data.bin");
+        let cache_file = temp_dir.path().join("state/yarw.cache");
+        fs::write(&file, b"first")?;
+        set_file_times(&file, UNIX_EPOCH + Duration::from_secs(1_500_000_000), None)?;
+
+        let cache = FileStateCache::load(&cache_file, ChecksumAlgorithm::Md5)?;
+        assert_eq!(cache.checksum(&file, || Ok(b"sum1".to_vec()))?, b"sum1");
+        cache.save()?;
+
+        let cache = FileStateCache::load(&cache_file, ChecksumAlgorithm::Md5)?;
+        assert_eq!(cache.checksum(&file, || panic!("cached checksum not used"))?, b"sum1");
+        assert_eq!(cache.stats(), CacheStats { hits: 1, misses: 0 });
+
+        fs::write(&file, b"second")?;
+        set_file_times(&file, UNIX_EPOCH + Duration::from_secs(1_600_000_000), None)?;
+        assert_eq!(cache.checksum(&file, || Ok(b"sum2".to_vec()))?, b"sum2");
+        assert_eq!(cache.stats(), CacheStats { hits: 1, misses: 1 });
+
+        let cache = FileStateCache::load(&cache_file, ChecksumAlgorithm::Xxh128)?;
+        assert_eq!(cache.checksum(&file, || Ok(b"xxh".to_vec()))?, b"xxh");
+
+        fs::write(&cache_file, b"garbage")?;
+        assert!(FileStateCache::load(&cache_file, ChecksumAlgorithm::Md5).is_err());
+
+        Ok(())
+    }
+}
@@ -1,5 +1,6 @@
 use std::path::PathBuf;
 use std::time::SystemTime;
+use crate::filesystem::streams::StreamInfo;
 
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -10,6 +11,15 @@ pub enum FileType {
 }
 
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FileId {
+
+    pub device: u64,
+
+    pub index: u64,
+}
+
+
 #[derive(Debug, Clone)]
 pub struct FileInfo {
 
@@ -31,9 +41,13 @@ pub struct FileInfo {
     pub symlink_target: Option<PathBuf>,
 
 
+    pub nlink: u64,
+
 
+    pub file_id: Option<FileId>,
 
 
+    pub streams: Vec<StreamInfo>,
 }
 
 impl FileInfo {
@@ -54,6 +68,8 @@ impl FileInfo {
             None
         };
 
+        let (nlink, file_id) = link_identity(metadata);
+
         Self {
             path,
             size: metadata.len(),
@@ -61,6 +77,21 @@ impl FileInfo {
             file_type,
             is_symlink,
             symlink_target,
+            nlink,
+            file_id,
+            streams: Vec::new(),
+        }
+    }
+
+
+    pub fn load_link_identity(&mut self) {
+        if self.file_id.is_some() || !self.is_file() {
+            return;
+        }
+
+        if let Some((nlink, file_id)) = query_link_identity(&self.path) {
+            self.nlink = nlink;
+            self.file_id = Some(file_id);
         }
     }
 
@@ -70,7 +101,6 @@ impl FileInfo {
     }
 
 
-    #[allow(dead_code)]
     pub fn is_file(&self) -> bool {
         self.file_type == FileType::File
     }
@@ -88,6 +118,70 @@ impl FileInfo {
 }
 
 
+#[cfg(unix)]
+fn link_identity(metadata: &std::fs::Metadata) -> (u64, Option<FileId>) {
+    use std::os::unix::fs::MetadataExt;
+
+    (metadata.nlink(), Some(FileId { device: metadata.dev(), index: metadata.ino() }))
+}
+
+
+#[cfg(not(unix))]
+fn link_identity(_metadata: &std::fs::Metadata) -> (u64, Option<FileId>) {
+    (1, None)
+}
+
+
+#[cfg(windows)]
+fn query_link_identity(path: &std::path::Path) -> Option<(u64, FileId)> {
+    use std::os::windows::io::AsRawHandle;
+    use windows::Win32::Foundation::HANDLE;
+    use windows::Win32::Storage::FileSystem::{GetFileInformationByHandle, BY_HANDLE_FILE_INFORMATION};
+
+    let file = std::fs::File::open(path).ok()?;
+    let mut info: BY_HANDLE_FILE_INFORMATION = unsafe { std::mem::zeroed() };
+    unsafe { GetFileInformationByHandle(HANDLE(file.as_raw_handle() as isize), &mut info) }.ok()?;
+
+    Some((
+        info.nNumberOfLinks as u64,
+        FileId {
+            device: info.dwVolumeSerialNumber as u64,
+            index: ((info.nFileIndexHigh as u64) << 32) | (info.nFileIndexLow as u64),
+        },
+    ))
+}
+
+
+#[cfg(windows)]
+pub fn directory_identity(path: &std::path::Path) -> Option<FileId> {
+    use std::os::windows::fs::OpenOptionsExt;
+    use std::os::windows::io::AsRawHandle;
+    use windows::Win32::Foundation::HANDLE;
+    use windows::Win32::Storage::FileSystem::{GetFileInformationByHandle, BY_HANDLE_FILE_INFORMATION, FILE_FLAG_BACKUP_SEMANTICS};
+
+    let dir = std::fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(FILE_FLAG_BACKUP_SEMANTICS.0)
+        .open(path)
+        .ok()?;
+    let mut info: BY_HANDLE_FILE_INFORMATION = unsafe { std::mem::zeroed() };
+    unsafe { GetFileInformationByHandle(HANDLE(dir.as_raw_handle() as isize), &mut info) }.ok()?;
+
+    Some(FileId {
+        device: info.dwVolumeSerialNumber as u64,
+        index: ((info.nFileIndexHigh as u64) << 32) | (info.nFileIndexLow as u64),
+    })
+}
+
+
+#[cfg(not(windows))]
+fn query_link_identity(path: &std::path::Path) -> Option<(u64, FileId)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    let (nlink, file_id) = link_identity(&metadata);
+    file_id.map(|id| (nlink, id))
+}
+
+
 pub fn human_readable_size(bytes: u64) -> String {
     const UNITS: &[&str] = &["B", "KB", "MB", "GB", "TB"];
 
@@ -133,6 +227,9 @@ mod tests {
             file_type: FileType::File,
             is_symlink: false,
             symlink_target: None,
+            nlink: 1,
+            file_id: None,
+            streams: Vec::new(),
         };
 
         assert!(file_info.is_file());
@@ -145,6 +242,9 @@ mod tests {
             file_type: FileType::Directory,
             is_symlink: false,
             symlink_target: None,
+            nlink: 1,
+            file_id: None,
+            streams: Vec::new(),
         };
 
         assert!(dir_info.is_directory());
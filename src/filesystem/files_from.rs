@@ -1,8 +1,8 @@
-use std::path::{Path, PathBuf};
-use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::path::{Component, Path, PathBuf};
+use std::fs;
+use std::io::Read;
 use crate::error::{Result, RsyncError};
-use crate::output::VerboseOutput;
+use crate::filesystem::path_utils::{is_daemon_path, parse_remote_path};
 
 
 
@@ -17,40 +17,66 @@ use crate::output::VerboseOutput;
 
 
 
-pub fn read_files_from(file_path: &Path) -> Result<Vec<PathBuf>> {
-    let file = File::open(file_path).map_err(|e| {
-        RsyncError::Io(std::io::Error::new(
-            e.kind(),
-            format!("Failed to open files-from file '{}': {}", file_path.display(), e)
-        ))
-    })?;
+pub fn read_files_from(spec: &Path, from0: bool) -> Result<Vec<PathBuf>> {
+    let contents = if spec.as_os_str() == "-" {
+        let mut contents = Vec::new();
+        std::io::stdin().read_to_end(&mut contents)?;
+        contents
+    } else {
+        fs::read(spec).map_err(|e| {
+            RsyncError::Io(std::io::Error::new(
+                e.kind(),
+                format!("Failed to open files-from file '{}': {}", spec.display(), e)
+            ))
+        })?
+    };
 
-    let reader = BufReader::new(file);
-    let mut files = Vec::new();
-    let verbose = VerboseOutput::new(1, false);
-
-    for (line_num, line) in reader.lines().enumerate() {
-        let line = line?;
-
-
-        let trimmed = line.trim();
-        if trimmed.is_empty() || trimmed.starts_with('#') {
-            continue;
-        }
+    Ok(parse_files_from(&String::from_utf8_lossy(&contents), from0))
+}
 
 
-        let path = PathBuf::from(trimmed);
+pub fn parse_files_from(contents: &str, from0: bool) -> Vec<PathBuf> {
+    if from0 {
+        return contents.split('\0')
+            .filter(|entry| !entry.is_empty())
+            .filter_map(files_from_entry)
+            .collect();
+    }
+    contents.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#') && !line.starts_with(';'))
+        .filter_map(files_from_entry)
+        .collect()
+}
 
 
-        if !path.exists() {
-            verbose.print_warning(&format!("File listed in files-from does not exist (line {}): {}",
-                line_num + 1, path.display()));
+pub fn files_from_entry(entry: &str) -> Option<PathBuf> {
+    let mut path = PathBuf::new();
+    for component in Path::new(entry).components() {
+        match component {
+            Component::Normal(name) => path.push(name),
+            Component::ParentDir => return None,
+            _ => {}
         }
+    }
+    Some(path)
+}
+
 
-        files.push(path);
+pub fn remote_files_from(spec: &Path) -> Option<(String, String)> {
+    let spec = spec.to_str()?;
+    if is_daemon_path(spec) {
+        return None;
     }
+    match parse_remote_path(spec) {
+        (Some((_, host)), path) => Some((host, path)),
+        (None, _) => None,
+    }
+}
+
 
-    Ok(files)
+pub fn files_from_allows(allowed: &[PathBuf], rel_path: &Path) -> bool {
+    allowed.iter().any(|entry| rel_path.starts_with(entry) || entry.starts_with(rel_path))
 }
 
 #[cfg(test)]
@@ -70,7 +96,7 @@ mod tests {
         writeln!(temp_file, "# コメント")?;
         writeln!(temp_file, "file3.txt")?;
 
-        let files = read_files_from(temp_file.path())?;
+        let files = read_files_from(temp_file.path(), false)?;
 
         assert_eq!(files.len(), 3);
         assert_eq!(files[0], PathBuf::from("file1.txt"));
@@ -80,9 +106,44 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_remote_files_from() {
+        assert_eq!(remote_files_from(Path::new("backup@nas:lists/photos.txt")), Some(("nas".to_string(), "lists/photos.txt".to_string())));
+        assert_eq!(remote_files_from(Path::new(":photos.txt")), Some((String::new(), "photos.txt".to_string())));
+        assert_eq!(remote_files_from(Path::new("lists/photos.txt")), None);
+        assert_eq!(remote_files_from(Path::new("rsync://nas/lists/photos.txt")), None);
+
+        let allowed = parse_files_from("a/one.txt\n\n# skipped\n  two.txt  \n", false);
+        assert_eq!(allowed, vec![PathBuf::from("a/one.txt"), PathBuf::from("two.txt")]);
+        assert!(files_from_allows(&allowed, Path::new("a/one.txt")));
+        assert!(!files_from_allows(&allowed, Path::new("/src/b/two.txt")));
+        assert!(!files_from_allows(&allowed, Path::new("a/three.txt")));
+    }
+
+    #[test]
+    fn test_files_from_exact_paths() {
+        let allowed = parse_files_from("./docs/guide.md\n/photos\n; comment\n../escape.txt\n", false);
+        assert_eq!(allowed, vec![PathBuf::from("docs/guide.md"), PathBuf::from("photos")]);
+        assert!(files_from_allows(&allowed, Path::new("docs/guide.md")));
+        assert!(files_from_allows(&allowed, Path::new("docs")));
+        assert!(files_from_allows(&allowed, Path::new("")));
+        assert!(files_from_allows(&allowed, Path::new("photos/2024/cat.jpg")));
+        assert!(!files_from_allows(&allowed, Path::new("docs/other.md")));
+        assert!(!files_from_allows(&allowed, Path::new("old/docs/guide.md")));
+        assert!(!files_from_allows(&allowed, Path::new("guide.md")));
+        assert!(!files_from_allows(&allowed, Path::new("photos-old")));
+
+        let allowed = parse_files_from("with space.txt\0# not a comment\0dir/line\nbreak.txt\0", true);
+        assert_eq!(allowed, vec![
+            PathBuf::from("with space.txt"),
+            PathBuf::from("# not a comment"),
+            PathBuf::from("dir/line\nbreak.txt"),
+        ]);
+    }
+
     #[test]
     fn test_read_files_from_nonexistent() {
-        let result = read_files_from(Path::new("nonexistent_file.txt"));
+        let result = read_files_from(Path::new("nonexistent_file.txt"), false);
         assert!(result.is_err());
     }
 }
@@ -0,0 +1,176 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use crate::error::Result;
+use crate::filesystem::file_info::{FileId, FileInfo};
+
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DiskUsage {
+
+    pub logical_bytes: u64,
+
+    pub physical_bytes: u64,
+}
+
+impl DiskUsage {
+
+    pub fn from_files<'a>(files: impl IntoIterator<Item = &'a FileInfo>) -> Self {
+        let mut usage = DiskUsage::default();
+        let mut seen: HashSet<FileId> = HashSet::new();
+
+        for file in files {
+            if !file.is_file() {
+                continue;
+            }
+
+            usage.logical_bytes += file.size;
+
+            match file.file_id {
+                Some(id) if file.nlink > 1 => {
+                    if seen.insert(id) {
+                        usage.physical_bytes += file.size;
+                    }
+                }
+                _ => usage.physical_bytes += file.size,
+            }
+        }
+
+        usage
+    }
+}
+
+
+pub fn load_link_identities<'a>(files: impl IntoIterator<Item = &'a mut FileInfo>) {
+    for file in files {
+        file.load_link_identity();
+    }
+}
+
+
+pub fn is_hard_linked(file: &FileInfo) -> bool {
+    file.is_file() && file.nlink > 1 && file.file_id.is_some()
+}
+
+
+pub fn link_leaders<'a>(files: impl IntoIterator<Item = &'a FileInfo>) -> Vec<Option<usize>> {
+    let mut first: HashMap<FileId, usize> = HashMap::new();
+    files.into_iter()
+        .enumerate()
+        .map(|(index, file)| match file.file_id.filter(|_| is_hard_linked(file)) {
+            Some(id) => first.get(&id).copied().or_else(|| {
+                first.insert(id, index);
+                None
+            }),
+            None => None,
+        })
+        .collect()
+}
+
+
+pub fn is_same_file(a: &Path, b: &Path) -> bool {
+    let identity = |path: &Path| {
+        let mut file = FileInfo::from_metadata(path.to_path_buf(), &fs::symlink_metadata(path).ok()?);
+        file.load_link_identity();
+        file.file_id.filter(|_| file.is_file())
+    };
+    matches!((identity(a), identity(b)), (Some(a), Some(b)) if a == b)
+}
+
+
+pub fn link_to(leader: &Path, dest: &Path) -> Result<bool> {
+    if is_same_file(leader, dest) {
+        return Ok(false);
+    }
+    if fs::symlink_metadata(dest).is_ok() {
+        fs::remove_file(dest)?;
+    }
+    fs::hard_link(leader, dest)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filesystem::FileType;
+    use std::path::PathBuf;
+    use std::time::SystemTime;
+
+    fn file(name: &str, size: u64, nlink: u64, index: Option<u64>) -> FileInfo {
+        FileInfo {
+            path: PathBuf::from(name),
+            size,
+            mtime: SystemTime::now(),
+            file_type: FileType::File,
+            is_symlink: false,
+            symlink_target: None,
+            nlink,
+            file_id: index.map(|index| FileId { device: 1, index }),
+            streams: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_linked_files_counted_once() {
+        let files = vec![
+            file("a.txt", 100, 2, Some(7)),
+            file("b.txt", 100, 2, Some(7)),
+            file("c.txt", 50, 1, Some(8)),
+        ];
+
+        let usage = DiskUsage::from_files(&files);
+        assert_eq!(usage.logical_bytes, 250);
+        assert_eq!(usage.physical_bytes, 150);
+    }
+
+    #[test]
+    fn test_unknown_identity_counted_individually() {
+        let files = vec![
+            file("a.txt", 100, 1, None),
+            file("b.txt", 100, 1, None),
+        ];
+
+        let usage = DiskUsage::from_files(&files);
+        assert_eq!(usage.logical_bytes, 200);
+        assert_eq!(usage.physical_bytes, 200);
+    }
+
+    #[test]
+    fn test_link_leaders() -> Result<()> {
+        let files = vec![
+            file("a.txt", 100, 2, Some(7)),
+            file("b.txt", 50, 1, Some(8)),
+            file("c.txt", 100, 2, Some(7)),
+            file("d.txt", 100, 1, Some(7)),
+        ];
+        assert_eq!(link_leaders(&files), vec![None, None, Some(0), None]);
+
+        let temp_dir = tempfile::TempDir::new()?;
+        let leader = temp_dir.path().join("leader.txt");
+        let dest = temp_dir.path().join("dest.txt");
+        fs::write(&leader, b"shared")?;
+        fs::write(&dest, b"old")?;
+        assert!(link_to(&leader, &dest)?);
+        assert!(is_same_file(&leader, &dest));
+        assert!(!link_to(&leader, &dest)?);
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_real_hard_link() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let original = temp_dir.path().join("original.txt");
+        let link = temp_dir.path().join("link.txt");
+        std::fs::write(&original, b"0123456789").unwrap();
+        std::fs::hard_link(&original, &link).unwrap();
+
+        let files: Vec<FileInfo> = [&original, &link].iter()
+            .map(|p| FileInfo::from_metadata(p.to_path_buf(), &std::fs::metadata(p).unwrap()))
+            .collect();
+
+        let usage = DiskUsage::from_files(&files);
+        assert_eq!(usage.logical_bytes, 20);
+        assert_eq!(usage.physical_bytes, 10);
+    }
+}
@@ -0,0 +1,148 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use path_absolutize::Absolutize;
+use twox_hash::XxHash64;
+use crate::error::{Result, RsyncError};
+use crate::filesystem::path_utils::{is_daemon_path, is_remote_path};
+
+
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+
+#[derive(Debug)]
+pub struct RunLock {
+    _file: File,
+    path: PathBuf,
+}
+
+impl RunLock {
+
+    pub fn acquire(path: &Path, wait: Option<Duration>) -> Result<Self> {
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+
+        let deadline = wait.map(|wait| Instant::now() + wait);
+        loop {
+            match platform::try_lock(&file) {
+                Ok(true) => break,
+                Ok(false) => match deadline {
+                    Some(deadline) if Instant::now() < deadline => std::thread::sleep(LOCK_POLL_INTERVAL),
+                    _ => {
+                        return Err(RsyncError::Other(format!(
+                            "another yarw run is using this destination (lock file {}); {}",
+                            path.display(),
+                            if wait.is_some() { "gave up waiting" } else { "use --lock-wait=SECS to wait for it" }
+                        )));
+                    }
+                },
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        file.set_len(0)?;
+        writeln!(file, "{}", std::process::id())?;
+        file.flush()?;
+        Ok(Self { _file: file, path: path.to_path_buf() })
+    }
+
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+
+pub fn default_lock_path(destination: &str) -> PathBuf {
+    let key = if is_remote_path(destination) || is_daemon_path(destination) {
+        destination.trim_end_matches('/').to_string()
+    } else {
+        let path = Path::new(destination);
+        let absolute = path.absolutize().map(|path| path.into_owned()).unwrap_or_else(|_| path.to_path_buf());
+        let key = absolute.to_string_lossy().trim_end_matches(['/', '\\']).to_string();
+        if cfg!(windows) { key.to_lowercase() } else { key }
+    };
+    let hash = XxHash64::oneshot(0, key.as_bytes());
+    std::env::temp_dir().join("yarw-locks").join(format!("{:016x}.lock", hash))
+}
+
+
+#[cfg(windows)]
+mod platform {
+    use std::fs::File;
+    use std::io;
+    use std::os::windows::io::AsRawHandle;
+    use windows::Win32::Foundation::{ERROR_LOCK_VIOLATION, HANDLE};
+    use windows::Win32::Storage::FileSystem::{LockFileEx, LOCKFILE_EXCLUSIVE_LOCK, LOCKFILE_FAIL_IMMEDIATELY};
+    use windows::Win32::System::IO::OVERLAPPED;
+
+    pub fn try_lock(file: &File) -> io::Result<bool> {
+        let mut overlapped = OVERLAPPED::default();
+        let locked = unsafe {
+            LockFileEx(
+                HANDLE(file.as_raw_handle() as isize),
+                LOCKFILE_EXCLUSIVE_LOCK | LOCKFILE_FAIL_IMMEDIATELY,
+                0,
+                u32::MAX,
+                u32::MAX,
+                &mut overlapped,
+            )
+        };
+        match locked {
+            Ok(()) => Ok(true),
+            Err(e) if e.code() == ERROR_LOCK_VIOLATION.to_hresult() => Ok(false),
+            Err(e) => Err(io::Error::from(e)),
+        }
+    }
+}
+
+
+#[cfg(unix)]
+mod platform {
+    use std::fs::File;
+    use std::io;
+    use std::os::unix::io::AsRawFd;
+
+    pub fn try_lock(file: &File) -> io::Result<bool> {
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+            return Ok(true);
+        }
+        let error = io::Error::last_os_error();
+        if error.kind() == io::ErrorKind::WouldBlock { Ok(false) } else { Err(error) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_run_lock_excludes_second_run() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let path = temp_dir.path().join("locks/dest.lock");
+
+        let first = RunLock::acquire(&path, None)?;
+        assert_eq!(first.path(), path);
+        assert!(RunLock::acquire(&path, None).is_err());
+        let started = Instant::now();
+        assert!(RunLock::acquire(&path, Some(Duration::from_millis(300))).is_err());
+        assert!(started.elapsed() >= Duration::from_millis(300));
+
+        drop(first);
+        let _second = RunLock::acquire(&path, Some(Duration::from_secs(5)))?;
+        assert_eq!(std::fs::read_to_string(&path)?.trim(), std::process::id().to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_default_lock_path() {
+        assert_eq!(default_lock_path("backup/"), default_lock_path("backup"));
+        assert_ne!(default_lock_path("backup"), default_lock_path("archive"));
+        assert_eq!(default_lock_path("host:/srv/backup/"), default_lock_path("host:/srv/backup"));
+        assert!(default_lock_path("backup").starts_with(std::env::temp_dir()));
+    }
+}
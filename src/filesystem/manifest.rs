@@ -0,0 +1,166 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use serde::{Deserialize, Serialize};
+use crate::algorithm::checksum::compute_strong_checksum;
+use crate::error::{Result, RsyncError};
+use crate::filesystem::path_utils::to_unix_separators;
+use crate::filesystem::Scanner;
+use crate::options::ChecksumAlgorithm;
+
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Manifest {
+    pub root: PathBuf,
+    pub algorithm: String,
+    pub files: Vec<ManifestEntry>,
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub path: String,
+    pub size: u64,
+    pub mtime: u64,
+    pub checksum: String,
+}
+
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Drift {
+    Missing(String),
+    Extra(String),
+    Size { path: String, expected: u64, actual: u64 },
+    Content(String),
+    Mtime(String),
+}
+
+impl fmt::Display for Drift {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Drift::Missing(path) => write!(f, "missing: {}", path),
+            Drift::Extra(path) => write!(f, "extra: {}", path),
+            Drift::Size { path, expected, actual } => write!(f, "size changed: {} ({} -> {} bytes)", path, expected, actual),
+            Drift::Content(path) => write!(f, "content changed: {}", path),
+            Drift::Mtime(path) => write!(f, "mtime changed: {}", path),
+        }
+    }
+}
+
+
+impl Manifest {
+    pub fn build(root: &Path, algorithm: ChecksumAlgorithm, skip: Option<&Path>) -> Result<Self> {
+        let base = Scanner::resolve_scan_path(root)?;
+        let skip = skip.and_then(|path| dunce::canonicalize(path).ok());
+        let mut files = Vec::new();
+        for file in Scanner::new().recursive(true).scan(root)? {
+            if !file.is_file() || skip.as_deref() == Some(file.path.as_path()) {
+                continue;
+            }
+            let Some(rel_path) = file.relative_path(&base) else {
+                continue;
+            };
+            let data = fs::read(&file.path)?;
+            files.push(ManifestEntry {
+                path: to_unix_separators(&rel_path.to_string_lossy()),
+                size: file.size,
+                mtime: file.mtime.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default(),
+                checksum: to_hex(compute_strong_checksum(&data, &algorithm).as_bytes()),
+            });
+        }
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+
+        Ok(Self { root: base, algorithm: algorithm.name().to_string(), files })
+    }
+
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        serde_json::from_str(&contents)
+            .map_err(|e| RsyncError::Config(format!("Invalid manifest {}: {}", path.display(), e)))
+    }
+
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let contents = serde_json::to_string_pretty(self)
+            .map_err(|e| RsyncError::Other(format!("Failed to encode manifest: {}", e)))?;
+        fs::write(path, contents + "\n")?;
+        Ok(())
+    }
+
+
+    pub fn verify(&self, root: &Path, skip: Option<&Path>) -> Result<Vec<Drift>> {
+        let algorithm = ChecksumAlgorithm::from_name(&self.algorithm).ok_or_else(|| {
+            RsyncError::Config(format!("Manifest uses unknown checksum algorithm: {}", self.algorithm))
+        })?;
+        let current: BTreeMap<String, ManifestEntry> = Self::build(root, algorithm, skip)?.files.into_iter()
+            .map(|entry| (entry.path.clone(), entry))
+            .collect();
+
+        let mut drift = Vec::new();
+        for expected in &self.files {
+            match current.get(&expected.path) {
+                None => drift.push(Drift::Missing(expected.path.clone())),
+                Some(actual) if actual.size != expected.size => drift.push(Drift::Size {
+                    path: expected.path.clone(),
+                    expected: expected.size,
+                    actual: actual.size,
+                }),
+                Some(actual) if actual.checksum != expected.checksum => drift.push(Drift::Content(expected.path.clone())),
+                Some(actual) if actual.mtime != expected.mtime => drift.push(Drift::Mtime(expected.path.clone())),
+                Some(_) => {}
+            }
+        }
+        let recorded: Vec<&str> = self.files.iter().map(|entry| entry.path.as_str()).collect();
+        drift.extend(current.keys()
+            .filter(|path| !recorded.contains(&path.as_str()))
+            .map(|path| Drift::Extra(path.clone())));
+
+        Ok(drift)
+    }
+}
+
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_manifest_detects_drift() -> Result<()> {
+        let dir = TempDir::new()?;
+        let root = dir.path().join("dest");
+        fs::create_dir_all(root.join("sub"))?;
+        fs::write(root.join("a.txt"), b"alpha")?;
+        fs::write(root.join("sub/b.txt"), b"bravo")?;
+        fs::write(root.join("gone.txt"), b"gone")?;
+
+        let manifest_path = dir.path().join("manifest.json");
+        Manifest::build(&root, ChecksumAlgorithm::Xxh128, None)?.save(&manifest_path)?;
+        let manifest = Manifest::load(&manifest_path)?;
+        assert_eq!(manifest.algorithm, "xxh128");
+        assert_eq!(manifest.files.iter().map(|f| f.path.as_str()).collect::<Vec<_>>(), ["a.txt", "gone.txt", "sub/b.txt"]);
+        assert!(manifest.verify(&root, None)?.is_empty());
+
+        let mtime = fs::metadata(root.join("sub/b.txt"))?.modified()?;
+        fs::write(root.join("sub/b.txt"), b"BRAVO")?;
+        fs::File::options().write(true).open(root.join("sub/b.txt"))?.set_modified(mtime)?;
+        fs::write(root.join("a.txt"), b"alpha, longer")?;
+        fs::remove_file(root.join("gone.txt"))?;
+        fs::write(root.join("new.txt"), b"new")?;
+
+        assert_eq!(manifest.verify(&root, None)?, vec![
+            Drift::Size { path: "a.txt".to_string(), expected: 5, actual: 13 },
+            Drift::Missing("gone.txt".to_string()),
+            Drift::Content("sub/b.txt".to_string()),
+            Drift::Extra("new.txt".to_string()),
+        ]);
+        Ok(())
+    }
+}
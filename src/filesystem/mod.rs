@@ -5,7 +5,20 @@ pub mod symlinks;
 pub mod files_from;
 pub mod windows_scanner;
 pub mod buffer_optimizer;
+pub mod hard_links;
+pub mod times;
+pub mod manifest;
+pub mod acl;
+pub mod archive;
+pub mod streams;
+pub mod cache;
+pub mod sparse;
+pub mod preallocate;
+pub mod privileges;
+pub mod lock;
 
 pub use file_info::{FileInfo, FileType};
 pub use scanner::Scanner;
-pub use files_from::read_files_from;
+pub use files_from::{files_from_allows, files_from_entry, read_files_from};
+pub use times::set_file_times;
+pub use manifest::Manifest;
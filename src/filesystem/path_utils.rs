@@ -77,6 +77,38 @@ pub fn is_daemon_path(path_str: &str) -> bool {
 }
 
 
+pub fn copies_contents(source: &str) -> bool {
+    let trimmed = source.strip_suffix('.').unwrap_or(source);
+    source == "." || trimmed.ends_with(std::path::is_separator)
+}
+
+
+pub fn transfer_name(source: &str) -> Option<String> {
+    if copies_contents(source) {
+        return None;
+    }
+    Path::new(source).file_name().map(|name| name.to_string_lossy().into_owned())
+}
+
+
+pub fn top_dir(source: &str, destination: &str, multiple_sources: bool) -> Option<String> {
+    if is_remote_path(source) {
+        return None;
+    }
+    transfer_name(source)
+        .filter(|_| Path::new(source).is_dir() || multiple_sources || copies_contents(destination))
+}
+
+
+pub fn nested_destination(destination: &str, name: &str) -> String {
+    if destination.is_empty() || destination.ends_with(std::path::is_separator) || destination.ends_with(':') {
+        format!("{}{}", destination, name)
+    } else {
+        format!("{}/{}", destination, name)
+    }
+}
+
+
 
 
 
@@ -134,6 +166,42 @@ mod tests {
         assert!(!is_remote_path("\\\\server\\share"));
     }
 
+    #[test]
+    fn test_trailing_slash_semantics() {
+        assert!(copies_contents("src/"));
+        assert!(copies_contents("src/."));
+        assert!(copies_contents("."));
+        assert!(!copies_contents("src"));
+        assert!(!copies_contents("src.d"));
+        assert_eq!(transfer_name("src/"), None);
+        assert_eq!(transfer_name("path/to/src"), Some("src".to_string()));
+        assert_eq!(transfer_name(".."), None);
+        assert_eq!(nested_destination("dest", "src"), "dest/src");
+        assert_eq!(nested_destination("dest/", "src"), "dest/src");
+        assert_eq!(nested_destination("host:", "src"), "host:src");
+        assert_eq!(nested_destination("host:backup", "src"), "host:backup/src");
+    }
+
+    #[test]
+    fn test_top_dir_for_mixed_sources() -> Result<()> {
+        let temp_dir = tempfile::TempDir::new()?;
+        let dir = temp_dir.path().join("photos");
+        let file = temp_dir.path().join("notes.txt");
+        std::fs::create_dir(&dir)?;
+        std::fs::write(&file, b"notes")?;
+        let dir = dir.to_string_lossy().into_owned();
+        let file = file.to_string_lossy().into_owned();
+
+        assert_eq!(top_dir(&dir, "dest", false), Some("photos".to_string()));
+        assert_eq!(top_dir(&format!("{}/", dir), "dest", true), None);
+        assert_eq!(top_dir(&file, "dest", false), None);
+        assert_eq!(top_dir(&file, "dest/", false), Some("notes.txt".to_string()));
+        assert_eq!(top_dir(&file, "dest", true), Some("notes.txt".to_string()));
+        assert_eq!(top_dir("host:photos", "dest", true), None);
+        assert_eq!(top_dir("rsync://host/module/photos", "dest", true), None);
+        Ok(())
+    }
+
     #[test]
     fn test_parse_remote_path() {
         let (user_host, path) = parse_remote_path("user@host:/path/to/file");
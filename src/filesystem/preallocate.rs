@@ -0,0 +1,66 @@
+use std::fs::File;
+use std::io;
+
+
+pub fn preallocate(file: &File, size: u64) -> io::Result<()> {
+    if size == 0 || file.metadata()?.len() >= size {
+        return Ok(());
+    }
+    platform::reserve(file, size)
+}
+
+
+#[cfg(windows)]
+mod platform {
+    use std::ffi::c_void;
+    use std::fs::File;
+    use std::io;
+    use std::os::windows::io::AsRawHandle;
+    use windows::Win32::Foundation::HANDLE;
+    use windows::Win32::Storage::FileSystem::{FileAllocationInfo, SetFileInformationByHandle, FILE_ALLOCATION_INFO};
+
+    pub fn reserve(file: &File, size: u64) -> io::Result<()> {
+        let info = FILE_ALLOCATION_INFO { AllocationSize: size as i64 };
+        unsafe {
+            SetFileInformationByHandle(
+                HANDLE(file.as_raw_handle() as isize),
+                FileAllocationInfo,
+                &info as *const FILE_ALLOCATION_INFO as *const c_void,
+                std::mem::size_of::<FILE_ALLOCATION_INFO>() as u32,
+            )
+        }
+        .map_err(io::Error::from)
+    }
+}
+
+
+#[cfg(not(windows))]
+mod platform {
+    use std::fs::File;
+    use std::io;
+
+    pub fn reserve(_file: &File, _size: u64) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_preallocate_keeps_file_length() -> io::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let path = temp_dir.path().join("reserved.bin");
+        let mut file = File::create(&path)?;
+        preallocate(&file, 1024 * 1024)?;
+        assert_eq!(file.metadata()?.len(), 0);
+
+        file.write_all(b"partial data")?;
+        drop(file);
+        assert_eq!(std::fs::read(&path)?, b"partial data");
+        Ok(())
+    }
+}
@@ -0,0 +1,303 @@
+use crate::error::{Result, RsyncError};
+use crate::options::Options;
+use crate::output::VerboseOutput;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Privilege {
+    CreateSymlink,
+    Restore,
+}
+
+impl Privilege {
+    pub const ALL: [Privilege; 2] = [Privilege::CreateSymlink, Privilege::Restore];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Privilege::CreateSymlink => "SeCreateSymbolicLinkPrivilege",
+            Privilege::Restore => "SeRestorePrivilege",
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TokenInfo {
+    pub elevated: bool,
+    pub developer_mode: bool,
+    pub privileges: Vec<Privilege>,
+}
+
+impl TokenInfo {
+
+    pub fn current() -> Self {
+        platform::current_token()
+    }
+
+
+    pub fn allows(&self, privilege: Privilege) -> bool {
+        match privilege {
+            Privilege::CreateSymlink => self.developer_mode || self.privileges.contains(&privilege),
+            Privilege::Restore => self.privileges.contains(&privilege),
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MissingPrivilege {
+    pub option: &'static str,
+    pub privilege: Privilege,
+    pub fatal: bool,
+}
+
+impl std::fmt::Display for MissingPrivilege {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.privilege {
+            Privilege::CreateSymlink => write!(
+                f,
+                "{} needs permission to create symbolic links ({}). Run yarw from an elevated prompt, \
+                 enable Developer Mode, add --elevate, or use -L to copy what the links point to",
+                self.option, self.privilege.name()
+            ),
+            Privilege::Restore => write!(
+                f,
+                "{} cannot replace ACLs on destination files owned by other accounts without {}. \
+                 Run yarw from an elevated prompt or add --elevate",
+                self.option, self.privilege.name()
+            ),
+        }
+    }
+}
+
+
+pub fn missing_privileges(options: &Options, token: &TokenInfo) -> Vec<MissingPrivilege> {
+    let mut missing = Vec::new();
+    if options.links && !options.copy_links && !token.allows(Privilege::CreateSymlink) {
+        missing.push(MissingPrivilege {
+            option: if options.archive { "-a (which implies --links)" } else { "--links" },
+            privilege: Privilege::CreateSymlink,
+            fatal: !options.archive,
+        });
+    }
+    if options.acls && !token.allows(Privilege::Restore) {
+        missing.push(MissingPrivilege { option: "--acls", privilege: Privilege::Restore, fatal: false });
+    }
+    missing
+}
+
+
+pub fn check_privileges(options: &Options, verbose: &VerboseOutput) -> Result<()> {
+    let token = TokenInfo::current();
+    let missing = missing_privileges(options, &token);
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    if options.elevate && !token.elevated {
+        verbose.print_basic("Restarting yarw with administrator rights...");
+        std::process::exit(platform::relaunch_elevated()?);
+    }
+
+    for entry in missing.iter().filter(|entry| !entry.fatal) {
+        verbose.print_verbose(format!("Note: {}", entry));
+    }
+    match missing.iter().find(|entry| entry.fatal) {
+        Some(entry) => Err(RsyncError::Privilege(entry.to_string())),
+        None => Ok(()),
+    }
+}
+
+
+pub fn quote_argument(arg: &str) -> String {
+    if !arg.is_empty() && !arg.contains([' ', '\t', '"']) {
+        return arg.to_string();
+    }
+    let mut quoted = String::from("\"");
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        match c {
+            '\\' => backslashes += 1,
+            '"' => {
+                quoted.push_str(&"\\".repeat(backslashes * 2 + 1));
+                backslashes = 0;
+            }
+            _ => {
+                quoted.push_str(&"\\".repeat(backslashes));
+                backslashes = 0;
+            }
+        }
+        if c != '\\' {
+            quoted.push(c);
+        }
+    }
+    quoted.push_str(&"\\".repeat(backslashes * 2));
+    quoted.push('"');
+    quoted
+}
+
+
+#[cfg(windows)]
+mod platform {
+    use super::{quote_argument, Privilege, TokenInfo};
+    use crate::error::{Result, RsyncError};
+    use std::ffi::c_void;
+    use std::mem::size_of;
+    use windows::core::{w, HSTRING, PCWSTR};
+    use windows::Win32::Foundation::{CloseHandle, HANDLE, LUID};
+    use windows::Win32::Security::{
+        GetTokenInformation, LookupPrivilegeValueW, TokenElevation, TokenPrivileges, TOKEN_ELEVATION,
+        TOKEN_PRIVILEGES, TOKEN_QUERY,
+    };
+    use windows::Win32::System::Registry::{RegGetValueW, HKEY_LOCAL_MACHINE, RRF_RT_REG_DWORD};
+    use windows::Win32::System::Threading::{
+        GetCurrentProcess, GetExitCodeProcess, OpenProcessToken, WaitForSingleObject, INFINITE,
+    };
+    use windows::Win32::UI::Shell::{ShellExecuteExW, SEE_MASK_NOCLOSEPROCESS, SHELLEXECUTEINFOW};
+    use windows::Win32::UI::WindowsAndMessaging::SW_SHOWNORMAL;
+
+    pub fn current_token() -> TokenInfo {
+        let developer_mode = developer_mode();
+        let mut token = HANDLE::default();
+        if unsafe { OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token) }.is_err() {
+            return TokenInfo { developer_mode, ..TokenInfo::default() };
+        }
+        let info = TokenInfo { elevated: elevated(token), developer_mode, privileges: privileges(token) };
+        unsafe {
+            let _ = CloseHandle(token);
+        }
+        info
+    }
+
+    fn elevated(token: HANDLE) -> bool {
+        let mut elevation = TOKEN_ELEVATION::default();
+        let mut size = 0u32;
+        let queried = unsafe {
+            GetTokenInformation(
+                token,
+                TokenElevation,
+                Some(&mut elevation as *mut TOKEN_ELEVATION as *mut c_void),
+                size_of::<TOKEN_ELEVATION>() as u32,
+                &mut size,
+            )
+        };
+        queried.is_ok() && elevation.TokenIsElevated != 0
+    }
+
+    fn privileges(token: HANDLE) -> Vec<Privilege> {
+        let mut size = 0u32;
+        let _ = unsafe { GetTokenInformation(token, TokenPrivileges, None, 0, &mut size) };
+        if size == 0 {
+            return Vec::new();
+        }
+        let mut buffer = vec![0u64; size as usize / size_of::<u64>() + 1];
+        let queried = unsafe {
+            GetTokenInformation(token, TokenPrivileges, Some(buffer.as_mut_ptr() as *mut c_void), size, &mut size)
+        };
+        if queried.is_err() {
+            return Vec::new();
+        }
+        let held = unsafe {
+            let list = &*(buffer.as_ptr() as *const TOKEN_PRIVILEGES);
+            std::slice::from_raw_parts(list.Privileges.as_ptr(), list.PrivilegeCount as usize)
+        };
+
+        Privilege::ALL
+            .into_iter()
+            .filter(|privilege| {
+                let mut luid = LUID::default();
+                let found = unsafe { LookupPrivilegeValueW(PCWSTR::null(), &HSTRING::from(privilege.name()), &mut luid) };
+                found.is_ok() && held.iter().any(|entry| entry.Luid == luid)
+            })
+            .collect()
+    }
+
+    fn developer_mode() -> bool {
+        let mut value = 0u32;
+        let mut size = size_of::<u32>() as u32;
+        let read = unsafe {
+            RegGetValueW(
+                HKEY_LOCAL_MACHINE,
+                w!("SOFTWARE\\Microsoft\\Windows\\CurrentVersion\\AppModelUnlock"),
+                w!("AllowDevelopmentWithoutDevLicense"),
+                RRF_RT_REG_DWORD,
+                None,
+                Some(&mut value as *mut u32 as *mut c_void),
+                Some(&mut size),
+            )
+        };
+        read.is_ok() && value != 0
+    }
+
+    pub fn relaunch_elevated() -> Result<i32> {
+        let program = HSTRING::from(std::env::current_exe()?.as_os_str());
+        let directory = HSTRING::from(std::env::current_dir()?.as_os_str());
+        let parameters: Vec<String> = std::env::args().skip(1).map(|arg| quote_argument(&arg)).collect();
+        let parameters = HSTRING::from(parameters.join(" "));
+
+        let mut info = SHELLEXECUTEINFOW {
+            cbSize: size_of::<SHELLEXECUTEINFOW>() as u32,
+            fMask: SEE_MASK_NOCLOSEPROCESS,
+            lpVerb: w!("runas"),
+            lpFile: PCWSTR(program.as_ptr()),
+            lpParameters: PCWSTR(parameters.as_ptr()),
+            lpDirectory: PCWSTR(directory.as_ptr()),
+            nShow: SW_SHOWNORMAL.0,
+            ..Default::default()
+        };
+        unsafe { ShellExecuteExW(&mut info) }
+            .map_err(|e| RsyncError::Privilege(format!("could not restart with administrator rights: {}", e)))?;
+
+        let mut exit_code = 0u32;
+        unsafe {
+            WaitForSingleObject(info.hProcess, INFINITE);
+            let status = GetExitCodeProcess(info.hProcess, &mut exit_code);
+            let _ = CloseHandle(info.hProcess);
+            status.map_err(|e| RsyncError::Privilege(format!("could not read the elevated exit code: {}", e)))?;
+        }
+        Ok(exit_code as i32)
+    }
+}
+
+
+#[cfg(not(windows))]
+mod platform {
+    use super::{Privilege, TokenInfo};
+    use crate::error::{Result, RsyncError};
+
+    pub fn current_token() -> TokenInfo {
+        TokenInfo { elevated: true, developer_mode: false, privileges: Privilege::ALL.to_vec() }
+    }
+
+    pub fn relaunch_elevated() -> Result<i32> {
+        Err(RsyncError::InvalidOption("--elevate is only supported on Windows".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missing_privileges() {
+        let limited = TokenInfo::default();
+        let links = Options { links: true, ..Options::default() };
+        let missing = missing_privileges(&links, &limited);
+        assert_eq!(missing, vec![MissingPrivilege { option: "--links", privilege: Privilege::CreateSymlink, fatal: true }]);
+        assert!(missing[0].to_string().contains("SeCreateSymbolicLinkPrivilege"));
+
+        let developer = TokenInfo { developer_mode: true, ..TokenInfo::default() };
+        assert!(missing_privileges(&links, &developer).is_empty());
+        let copy_links = Options { copy_links: true, ..links.clone() };
+        assert!(missing_privileges(&copy_links, &limited).is_empty());
+
+        let mut archive = Options { archive: true, acls: true, ..Options::default() };
+        archive.apply_archive_mode();
+        let missing = missing_privileges(&archive, &limited);
+        assert_eq!(missing.len(), 2);
+        assert!(missing.iter().all(|entry| !entry.fatal));
+
+        let admin = TokenInfo { elevated: true, developer_mode: false, privileges: Privilege::ALL.to_vec() };
+        assert!(missing_privileges(&archive, &admin).is_empty());
+    }
+}
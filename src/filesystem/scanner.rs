@@ -1,10 +1,15 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use walkdir::WalkDir;
-#[cfg(not(windows))]
-use rayon::prelude::*;
 use crate::error::{Result, RsyncError};
 use crate::filesystem::file_info::FileInfo;
 use crate::filesystem::path_utils::{normalize_path, to_long_path, exceeds_max_path};
+use crate::filesystem::streams::list_streams;
+
+
+pub type ScanIter = Box<dyn Iterator<Item = Result<FileInfo>> + Send>;
+
+
+pub const MAX_SCAN_DEPTH: usize = 1024;
 
 
 pub struct Scanner {
@@ -15,8 +20,13 @@ pub struct Scanner {
     pub follow_symlinks: bool,
 
 
-    #[allow(dead_code)]
-    pub parallel: bool,
+    pub streams: bool,
+
+
+    pub exclude_if_present: Vec<String>,
+
+
+    pub max_depth: usize,
 }
 
 impl Default for Scanner {
@@ -24,7 +34,9 @@ impl Default for Scanner {
         Self {
             recursive: true,
             follow_symlinks: false,
-            parallel: true,
+            streams: false,
+            exclude_if_present: Vec::new(),
+            max_depth: MAX_SCAN_DEPTH,
         }
     }
 }
@@ -47,38 +59,54 @@ impl Scanner {
     }
 
 
+    pub fn streams(mut self, streams: bool) -> Self {
+        self.streams = streams;
+        self
+    }
+
+
+    pub fn exclude_if_present(mut self, markers: &[String]) -> Self {
+        self.exclude_if_present = markers.to_vec();
+        self
+    }
+
+
     #[allow(dead_code)]
-    pub fn parallel(mut self, parallel: bool) -> Self {
-        self.parallel = parallel;
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
         self
     }
 
 
     pub fn scan(&self, path: &Path) -> Result<Vec<FileInfo>> {
+        self.scan_iter(path)?.collect()
+    }
 
-        let normalized = if path.exists() {
-            normalize_path(path)?
-        } else {
-            path.to_path_buf()
-        };
 
+    pub fn scan_iter(&self, path: &Path) -> Result<ScanIter> {
+        let files = self.scan_entries(path)?;
+        if !self.streams {
+            return Ok(files);
+        }
 
-        let scan_path = if exceeds_max_path(&normalized) {
-            to_long_path(&normalized)?
-        } else {
-            normalized
-        };
+        Ok(Box::new(files.map(|file_info| {
+            let mut file_info = file_info?;
+            if file_info.is_file() {
+                file_info.streams = list_streams(&file_info.path)?;
+            }
+            Ok(file_info)
+        })))
+    }
 
 
-        if !scan_path.exists() {
-            return Err(RsyncError::InvalidPath(path.to_path_buf()));
-        }
+    fn scan_entries(&self, path: &Path) -> Result<ScanIter> {
+        let scan_path = Self::resolve_scan_path(path)?;
 
 
         if scan_path.is_file() {
             let metadata = std::fs::metadata(&scan_path)
-                .map_err(|e| RsyncError::Io(e))?;
-            return Ok(vec![FileInfo::from_metadata(scan_path, &metadata)]);
+                .map_err(RsyncError::Io)?;
+            return Ok(Box::new(std::iter::once(Ok(FileInfo::from_metadata(scan_path, &metadata)))));
         }
 
 
@@ -92,96 +120,116 @@ impl Scanner {
     }
 
 
-    fn scan_directory_non_recursive(&self, path: &Path) -> Result<Vec<FileInfo>> {
+    pub fn resolve_scan_path(path: &Path) -> Result<PathBuf> {
+        let normalized = if path.exists() {
+            normalize_path(path)?
+        } else {
+            path.to_path_buf()
+        };
+
+
+        let scan_path = if exceeds_max_path(&normalized) {
+            to_long_path(&normalized)?
+        } else {
+            normalized
+        };
+
+
+        if !scan_path.exists() {
+            return Err(RsyncError::InvalidPath(path.to_path_buf()));
+        }
+
+        Ok(scan_path)
+    }
+
+
+    fn scan_directory_non_recursive(&self, path: &Path) -> Result<ScanIter> {
 
         #[cfg(windows)]
         {
             use crate::filesystem::windows_scanner::WindowsScanner;
             let scanner = WindowsScanner::new()
                 .recursive(false)
-                .follow_symlinks(self.follow_symlinks);
-            return scanner.scan(path);
+                .follow_symlinks(self.follow_symlinks)
+                .exclude_if_present(&self.exclude_if_present);
+            return Ok(Box::new(scanner.scan_iter(path)));
         }
 
 
         #[cfg(not(windows))]
         {
-            let mut files = Vec::new();
-
+            let follow_symlinks = self.follow_symlinks;
+            let markers = self.exclude_if_present.clone();
             let entries = std::fs::read_dir(path)
-                .map_err(|e| RsyncError::Io(e))?;
-
-            for entry in entries {
-                let entry = entry.map_err(|e| RsyncError::Io(e))?;
-                let entry_path = entry.path();
+                .map_err(RsyncError::Io)?;
 
-                let metadata = if self.follow_symlinks {
-                    std::fs::metadata(&entry_path)
-                } else {
-                    std::fs::symlink_metadata(&entry_path)
-                }.map_err(|e| RsyncError::Io(e))?;
+            Ok(Box::new(entries
+                .map(move |entry| {
+                    let entry_path = entry.map_err(RsyncError::Io)?.path();
 
-                files.push(FileInfo::from_metadata(entry_path, &metadata));
-            }
-
-            Ok(files)
+                    let metadata = if follow_symlinks {
+                        std::fs::metadata(&entry_path)
+                    } else {
+                        std::fs::symlink_metadata(&entry_path)
+                    }.map_err(RsyncError::Io)?;
+
+                    Ok(FileInfo::from_metadata(entry_path, &metadata))
+                })
+                .filter(move |file_info| {
+                    !matches!(file_info, Ok(file_info) if file_info.is_directory() && has_marker(&file_info.path, &markers))
+                })))
         }
     }
 
 
-    fn scan_directory_recursive(&self, path: &Path) -> Result<Vec<FileInfo>> {
+    fn scan_directory_recursive(&self, path: &Path) -> Result<ScanIter> {
 
         #[cfg(windows)]
         {
             use crate::filesystem::windows_scanner::WindowsScanner;
             let scanner = WindowsScanner::new()
                 .recursive(true)
-                .follow_symlinks(self.follow_symlinks);
-            return scanner.scan(path);
+                .follow_symlinks(self.follow_symlinks)
+                .exclude_if_present(&self.exclude_if_present)
+                .max_depth(self.max_depth);
+            return Ok(Box::new(scanner.scan_iter(path)));
         }
 
 
         #[cfg(not(windows))]
         {
+            let follow_symlinks = self.follow_symlinks;
+            let markers = self.exclude_if_present.clone();
+            let max_depth = self.max_depth;
             let walker = WalkDir::new(path)
-                .follow_links(self.follow_symlinks)
+                .follow_links(follow_symlinks)
+                .max_depth(max_depth)
                 .into_iter()
-                .filter_map(|e| e.ok());
-
-            if self.parallel {
-
-                let entries: Vec<_> = walker.collect();
-
-                let files: Result<Vec<FileInfo>> = entries
-                    .par_iter()
-                    .map(|entry| {
-                        let metadata = if self.follow_symlinks {
-                            entry.metadata().map_err(|e| RsyncError::Io(std::io::Error::from(e)))?
-                        } else {
-                            entry.path().symlink_metadata().map_err(|e| RsyncError::Io(e))?
-                        };
-
-                        Ok(FileInfo::from_metadata(entry.path().to_path_buf(), &metadata))
-                    })
-                    .collect();
-
-                files
-            } else {
-
-                let mut files = Vec::new();
-
-                for entry in walker {
-                    let metadata = if self.follow_symlinks {
-                        entry.metadata().map_err(|e| RsyncError::Io(std::io::Error::from(e)))?
-                    } else {
-                        entry.path().symlink_metadata().map_err(|e| RsyncError::Io(e))?
-                    };
-
-                    files.push(FileInfo::from_metadata(entry.path().to_path_buf(), &metadata));
-                }
+                .filter_entry(move |entry| {
+                    if entry.depth() == max_depth && entry.file_type().is_dir() {
+                        eprintln!("Warning: not descending into {}: more than {} levels deep", entry.path().display(), max_depth);
+                    }
+                    entry.depth() == 0 || !entry.file_type().is_dir() || !has_marker(entry.path(), &markers)
+                })
+                .filter_map(|entry| match entry {
+                    Ok(entry) => Some(entry),
+                    Err(e) => {
+                        if let (Some(path), Some(ancestor)) = (e.path(), e.loop_ancestor()) {
+                            eprintln!("Warning: skipping symlink loop {} -> {}", path.display(), ancestor.display());
+                        }
+                        None
+                    }
+                });
+
+            Ok(Box::new(walker.map(move |entry| {
+                let metadata = if follow_symlinks {
+                    entry.metadata().map_err(|e| RsyncError::Io(std::io::Error::from(e)))?
+                } else {
+                    entry.path().symlink_metadata().map_err(RsyncError::Io)?
+                };
 
-                Ok(files)
-            }
+                Ok(FileInfo::from_metadata(entry.into_path(), &metadata))
+            })))
         }
     }
 
@@ -212,6 +260,11 @@ impl Scanner {
     }
 }
 
+
+pub fn has_marker(dir: &Path, markers: &[String]) -> bool {
+    markers.iter().any(|marker| dir.join(marker).exists())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -266,6 +319,72 @@ mod tests {
         assert!(files.len() >= 3);
     }
 
+    #[test]
+    fn test_scan_iter_matches_scan() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir_path = temp_dir.path();
+
+        fs::write(dir_path.join("file1.txt"), "content1").unwrap();
+        fs::create_dir(dir_path.join("subdir")).unwrap();
+        fs::write(dir_path.join("subdir").join("file2.txt"), "content2").unwrap();
+
+        let scanner = Scanner::new().recursive(true);
+        let mut streamed: Vec<_> = scanner.scan_iter(dir_path).unwrap()
+            .map(|f| f.unwrap().path)
+            .collect();
+        let mut collected: Vec<_> = scanner.scan(dir_path).unwrap()
+            .into_iter()
+            .map(|f| f.path)
+            .collect();
+
+        streamed.sort();
+        collected.sort();
+        assert_eq!(streamed, collected);
+        assert!(streamed.iter().any(|p| p.ends_with("file2.txt")));
+    }
+
+    #[test]
+    fn test_exclude_if_present() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir_path = temp_dir.path();
+
+        fs::write(dir_path.join(".nobackup"), "").unwrap();
+        fs::write(dir_path.join("keep.txt"), "keep").unwrap();
+        fs::create_dir_all(dir_path.join("cache/deep")).unwrap();
+        fs::write(dir_path.join("cache/.nobackup"), "").unwrap();
+        fs::write(dir_path.join("cache/deep/blob.bin"), "blob").unwrap();
+
+        let markers = [".nobackup".to_string()];
+        for recursive in [true, false] {
+            let files = Scanner::new().recursive(recursive).exclude_if_present(&markers).scan(dir_path).unwrap();
+            assert!(files.iter().any(|f| f.path.ends_with("keep.txt")));
+            assert!(!files.iter().any(|f| f.path.starts_with(dir_path.join("cache"))));
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_follow_symlinks_stops_at_loops() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir_path = temp_dir.path();
+
+        fs::create_dir_all(dir_path.join("a/deep")).unwrap();
+        fs::create_dir(dir_path.join("b")).unwrap();
+        fs::write(dir_path.join("a/file.txt"), "content").unwrap();
+        std::os::unix::fs::symlink("..", dir_path.join("a/up")).unwrap();
+        std::os::unix::fs::symlink("../b", dir_path.join("a/to_b")).unwrap();
+        std::os::unix::fs::symlink("../a", dir_path.join("b/to_a")).unwrap();
+
+        let files = Scanner::new().follow_symlinks(true).scan(dir_path).unwrap();
+        assert!(files.iter().any(|f| f.path.ends_with("b/to_a/file.txt")));
+        assert!(!files.iter().any(|f| f.path.starts_with(dir_path.join("a/up"))));
+        assert!(!files.iter().any(|f| f.path.starts_with(dir_path.join("b/to_a/to_b"))));
+
+        let files = Scanner::new().max_depth(2).scan(dir_path).unwrap();
+        assert!(files.iter().any(|f| f.path.ends_with("a/deep")));
+        assert!(files.iter().all(|f| f.path.strip_prefix(dir_path).unwrap().components().count() <= 2));
+    }
+
     #[test]
     fn test_count_files() {
         let temp_dir = TempDir::new().unwrap();
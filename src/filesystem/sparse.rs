@@ -0,0 +1,152 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+
+
+pub const SPARSE_BLOCK_SIZE: u64 = 4096;
+
+
+pub struct SparseWriter {
+    writer: BufWriter<File>,
+    sparse: bool,
+    position: u64,
+    hole: u64,
+}
+
+impl SparseWriter {
+
+    pub fn new(file: File, sparse: bool) -> Self {
+        Self::with_capacity(64 * 1024, file, sparse)
+    }
+
+
+    pub fn with_capacity(capacity: usize, file: File, sparse: bool) -> Self {
+        if sparse {
+            let _ = platform::mark_sparse(&file);
+        }
+        Self { writer: BufWriter::with_capacity(capacity, file), sparse, position: 0, hole: 0 }
+    }
+
+
+    pub fn finish(mut self) -> io::Result<File> {
+        self.flush()?;
+        let file = self.writer.into_inner().map_err(|e| e.into_error())?;
+        if self.hole > 0 {
+            file.set_len(self.position)?;
+        }
+        Ok(file)
+    }
+
+
+    fn skip_hole(&mut self) -> io::Result<()> {
+        if self.hole > 0 {
+            self.writer.seek(SeekFrom::Current(self.hole as i64))?;
+            self.hole = 0;
+        }
+        Ok(())
+    }
+}
+
+impl Write for SparseWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !self.sparse {
+            let written = self.writer.write(buf)?;
+            self.position += written as u64;
+            return Ok(written);
+        }
+
+        let mut remaining = buf;
+        while !remaining.is_empty() {
+            let to_boundary = (SPARSE_BLOCK_SIZE - self.position % SPARSE_BLOCK_SIZE) as usize;
+            let (chunk, rest) = remaining.split_at(to_boundary.min(remaining.len()));
+            if chunk.iter().all(|&byte| byte == 0) {
+                self.hole += chunk.len() as u64;
+            } else {
+                self.skip_hole()?;
+                self.writer.write_all(chunk)?;
+            }
+            self.position += chunk.len() as u64;
+            remaining = rest;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+
+#[cfg(windows)]
+mod platform {
+    use std::fs::File;
+    use std::io;
+    use std::os::windows::io::AsRawHandle;
+    use windows::Win32::Foundation::HANDLE;
+    use windows::Win32::System::IO::DeviceIoControl;
+    use windows::Win32::System::Ioctl::FSCTL_SET_SPARSE;
+
+    pub fn mark_sparse(file: &File) -> io::Result<()> {
+        let mut returned = 0u32;
+        unsafe {
+            DeviceIoControl(
+                HANDLE(file.as_raw_handle() as isize),
+                FSCTL_SET_SPARSE,
+                None,
+                0,
+                None,
+                0,
+                Some(&mut returned),
+                None,
+            )
+        }
+        .map_err(io::Error::from)
+    }
+}
+
+
+#[cfg(not(windows))]
+mod platform {
+    use std::fs::File;
+    use std::io;
+
+    pub fn mark_sparse(_file: &File) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_sparse_writer_skips_zero_blocks() -> io::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let path = temp_dir.path().join("disk.img");
+        let mut data = vec![0u8; 64 * 1024];
+        data[..5].copy_from_slice(b"head\n");
+        data[40_000..40_005].copy_from_slice(b"body\n");
+        data.extend(vec![0u8; 32 * 1024]);
+
+        let mut writer = SparseWriter::new(File::create(&path)?, true);
+        for chunk in data.chunks(1000) {
+            writer.write_all(chunk)?;
+        }
+        writer.finish()?;
+        assert_eq!(std::fs::read(&path)?, data);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            let metadata = std::fs::metadata(&path)?;
+            assert!(metadata.blocks() * 512 < metadata.len());
+        }
+
+        let plain = temp_dir.path().join("plain.img");
+        let mut writer = SparseWriter::new(File::create(&plain)?, false);
+        writer.write_all(&data)?;
+        writer.finish()?;
+        assert_eq!(std::fs::read(&plain)?, data);
+        Ok(())
+    }
+}
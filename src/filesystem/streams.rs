@@ -0,0 +1,135 @@
+use crate::error::Result;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamInfo {
+
+    pub name: String,
+
+    pub size: u64,
+}
+
+
+pub fn list_streams(path: &Path) -> Result<Vec<StreamInfo>> {
+    platform::list_streams(path)
+}
+
+
+pub fn stream_path(path: &Path, name: &str) -> PathBuf {
+    let mut stream = OsString::from(path.as_os_str());
+    stream.push(":");
+    stream.push(name);
+    PathBuf::from(stream)
+}
+
+
+pub fn copy_streams(source: &Path, destination: &Path, streams: &[StreamInfo]) -> Result<()> {
+    for existing in list_streams(destination)? {
+        if !streams.iter().any(|stream| stream.name == existing.name) {
+            fs::remove_file(stream_path(destination, &existing.name))?;
+        }
+    }
+    for stream in streams {
+        let mut reader = File::open(stream_path(source, &stream.name))?;
+        let mut writer = File::create(stream_path(destination, &stream.name))?;
+        std::io::copy(&mut reader, &mut writer)?;
+    }
+    Ok(())
+}
+
+
+#[cfg(windows)]
+mod platform {
+    use super::StreamInfo;
+    use crate::error::{Result, RsyncError};
+    use std::path::Path;
+    use windows::core::HSTRING;
+    use windows::Win32::Foundation::ERROR_HANDLE_EOF;
+    use windows::Win32::Storage::FileSystem::{
+        FindClose, FindFirstStreamW, FindNextStreamW, FindStreamInfoStandard, WIN32_FIND_STREAM_DATA,
+    };
+
+    const DATA_SUFFIX: &str = ":$DATA";
+
+    pub fn list_streams(path: &Path) -> Result<Vec<StreamInfo>> {
+        let mut data: WIN32_FIND_STREAM_DATA = unsafe { std::mem::zeroed() };
+        let handle = match unsafe {
+            FindFirstStreamW(&HSTRING::from(path), FindStreamInfoStandard, &mut data as *mut _ as *mut _, 0)
+        } {
+            Ok(handle) => handle,
+            Err(e) if e.code() == ERROR_HANDLE_EOF.to_hresult() => return Ok(Vec::new()),
+            Err(e) => return Err(RsyncError::Other(format!(
+                "Failed to list streams of {}: {}",
+                path.display(),
+                e
+            ))),
+        };
+
+        let mut streams = Vec::new();
+        loop {
+            let len = data.cStreamName.iter().position(|&c| c == 0).unwrap_or(data.cStreamName.len());
+            let name = String::from_utf16_lossy(&data.cStreamName[..len]);
+            let name = name.strip_prefix(':').unwrap_or(&name);
+            let name = name.strip_suffix(DATA_SUFFIX).unwrap_or(name);
+            if !name.is_empty() {
+                streams.push(StreamInfo { name: name.to_string(), size: data.StreamSize as u64 });
+            }
+            if unsafe { FindNextStreamW(handle, &mut data as *mut _ as *mut _) }.is_err() {
+                break;
+            }
+        }
+        unsafe {
+            let _ = FindClose(handle);
+        }
+        Ok(streams)
+    }
+}
+
+
+#[cfg(not(windows))]
+mod platform {
+    use super::StreamInfo;
+    use crate::error::Result;
+    use std::path::Path;
+
+    pub fn list_streams(_path: &Path) -> Result<Vec<StreamInfo>> {
+        Ok(Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_stream_path() {
+        assert_eq!(
+            stream_path(Path::new("dir/file.txt"), "Zone.Identifier"),
+            PathBuf::from("dir/file.txt:Zone.Identifier")
+        );
+    }
+
+    #[test]
+    fn test_copy_streams() -> Result<()> {
+        let dir = TempDir::new()?;
+        let source = dir.path().join("source.txt");
+        let destination = dir.path().join("dest.txt");
+        fs::write(&source, b"main")?;
+        fs::write(&destination, b"main")?;
+        if cfg!(windows) {
+            fs::write(stream_path(&source, "Zone.Identifier"), b"[ZoneTransfer]\r\nZoneId=3\r\n")?;
+            fs::write(stream_path(&destination, "stale"), b"stale")?;
+        }
+
+        let streams = list_streams(&source)?;
+        assert_eq!(streams.len(), usize::from(cfg!(windows)));
+        copy_streams(&source, &destination, &streams)?;
+        assert_eq!(list_streams(&destination)?, streams);
+        assert_eq!(fs::read(&destination)?, b"main");
+        Ok(())
+    }
+}
@@ -5,6 +5,7 @@ use std::collections::HashSet;
 use std::fs;
 use std::path::{Path, PathBuf};
 use anyhow::{Result, Context};
+use crate::filesystem::file_info::FileId;
 
 
 #[derive(Debug, Clone)]
@@ -111,6 +112,34 @@ pub fn detect_symlink_loop(start_path: &Path, max_depth: usize) -> Result<bool>
 
 
 
+pub const MAX_LINK_CHAIN: usize = 40;
+
+
+#[derive(Debug, Clone, Default)]
+pub struct LinkLoopGuard {
+    ancestors: Vec<FileId>,
+}
+
+impl LinkLoopGuard {
+
+    pub fn depth(&self) -> usize {
+        self.ancestors.len()
+    }
+
+
+    pub fn descend(&self, id: Option<FileId>) -> Option<Self> {
+        if id.is_some_and(|id| self.ancestors.contains(&id)) {
+            return None;
+        }
+        let mut ancestors = self.ancestors.clone();
+        ancestors.extend(id);
+        Some(Self { ancestors })
+    }
+}
+
+
+
+
 pub fn resolve_symlink(path: &Path, max_depth: usize) -> Result<PathBuf> {
     if detect_symlink_loop(path, max_depth)? {
         anyhow::bail!("Symlink loop detected at: {}", path.display());
@@ -149,7 +178,7 @@ pub fn copy_symlink(src: &Path, dst: &Path) -> Result<()> {
 
 
 pub fn copy_symlink_content(src: &Path, dst: &Path) -> Result<()> {
-    let resolved = resolve_symlink(src, 40)?;
+    let resolved = resolve_symlink(src, MAX_LINK_CHAIN)?;
 
     if resolved.is_dir() {
 
@@ -202,6 +231,18 @@ mod tests {
         assert!(!is_symlink(&file_path));
     }
 
+    #[test]
+    fn test_link_loop_guard() {
+        let id = |index| Some(FileId { device: 1, index });
+        let root = LinkLoopGuard::default().descend(id(1)).unwrap();
+        let child = root.descend(id(2)).unwrap();
+        assert_eq!(child.depth(), 2);
+        assert!(child.descend(id(1)).is_none());
+        assert!(child.descend(id(2)).is_none());
+        assert_eq!(child.descend(None).unwrap().depth(), 2);
+        assert!(root.descend(id(3)).unwrap().descend(id(2)).is_some());
+    }
+
     #[test]
     fn test_symlink_info() {
         let temp = TempDir::new().unwrap();
@@ -0,0 +1,116 @@
+use crate::error::Result;
+use std::path::Path;
+use std::time::SystemTime;
+
+
+pub fn set_file_times(path: &Path, mtime: SystemTime, atime: Option<SystemTime>) -> Result<()> {
+    platform::set_file_times(path, mtime, atime)?;
+    Ok(())
+}
+
+
+#[cfg(windows)]
+mod platform {
+    use std::fs::OpenOptions;
+    use std::io;
+    use std::os::windows::fs::OpenOptionsExt;
+    use std::os::windows::io::AsRawHandle;
+    use std::path::Path;
+    use std::time::{SystemTime, UNIX_EPOCH};
+    use windows::Win32::Foundation::{FILETIME, HANDLE};
+    use windows::Win32::Storage::FileSystem::{SetFileTime, FILE_FLAG_BACKUP_SEMANTICS, FILE_WRITE_ATTRIBUTES};
+
+    const TICKS_PER_SECOND: u64 = 10_000_000;
+    const EPOCH_DIFF_SECONDS: u64 = 11_644_473_600;
+
+    pub fn set_file_times(path: &Path, mtime: SystemTime, atime: Option<SystemTime>) -> io::Result<()> {
+        let file = OpenOptions::new()
+            .access_mode(FILE_WRITE_ATTRIBUTES.0)
+            .custom_flags(FILE_FLAG_BACKUP_SEMANTICS.0)
+            .open(path)?;
+
+        let mtime = systemtime_to_filetime(mtime);
+        let atime = atime.map(systemtime_to_filetime);
+        unsafe {
+            SetFileTime(
+                HANDLE(file.as_raw_handle() as isize),
+                None,
+                atime.as_ref().map(|atime| atime as *const FILETIME),
+                Some(&mtime as *const FILETIME),
+            )
+        }
+        .map_err(io::Error::from)
+    }
+
+    fn systemtime_to_filetime(time: SystemTime) -> FILETIME {
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let ticks = (since_epoch.as_secs() + EPOCH_DIFF_SECONDS) * TICKS_PER_SECOND
+            + since_epoch.subsec_nanos() as u64 / 100;
+        FILETIME {
+            dwLowDateTime: ticks as u32,
+            dwHighDateTime: (ticks >> 32) as u32,
+        }
+    }
+}
+
+
+#[cfg(unix)]
+mod platform {
+    use std::ffi::CString;
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    pub fn set_file_times(path: &Path, mtime: SystemTime, atime: Option<SystemTime>) -> io::Result<()> {
+        let path = CString::new(path.as_os_str().as_bytes())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "path contains a NUL byte"))?;
+        let omit = libc::timespec { tv_sec: 0, tv_nsec: libc::UTIME_OMIT };
+        let times = [atime.map_or(omit, systemtime_to_timespec), systemtime_to_timespec(mtime)];
+        if unsafe { libc::utimensat(libc::AT_FDCWD, path.as_ptr(), times.as_ptr(), 0) } == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+
+    fn systemtime_to_timespec(time: SystemTime) -> libc::timespec {
+        let (seconds, nanos) = match time.duration_since(UNIX_EPOCH) {
+            Ok(since_epoch) => (since_epoch.as_secs() as i64, since_epoch.subsec_nanos()),
+            Err(e) => {
+                let before_epoch = e.duration();
+                match before_epoch.subsec_nanos() {
+                    0 => (-(before_epoch.as_secs() as i64), 0),
+                    nanos => (-(before_epoch.as_secs() as i64) - 1, 1_000_000_000 - nanos),
+                }
+            }
+        };
+        libc::timespec { tv_sec: seconds as libc::time_t, tv_nsec: nanos as libc::c_long }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::time::{Duration, UNIX_EPOCH};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_set_file_times() -> Result<()> {
+        let dir = TempDir::new()?;
+        let file = dir.path().join("file.txt");
+        fs::write(&file, b"content")?;
+
+        let mtime = UNIX_EPOCH + Duration::new(1_600_000_000, 500_000_000);
+        let atime = UNIX_EPOCH + Duration::from_secs(1_650_000_000);
+        set_file_times(&file, mtime, Some(atime))?;
+        let metadata = fs::metadata(&file)?;
+        assert_eq!(metadata.modified()?, mtime);
+        assert_eq!(metadata.accessed()?, atime);
+
+        set_file_times(dir.path(), mtime, None)?;
+        assert_eq!(fs::metadata(dir.path())?.modified()?, mtime);
+        Ok(())
+    }
+}
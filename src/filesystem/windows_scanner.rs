@@ -14,9 +14,17 @@ use windows::Win32::Storage::FileSystem::{
     FIND_FIRST_EX_LARGE_FETCH,
 };
 use std::path::Path;
+#[cfg(windows)]
+use std::path::PathBuf;
 use std::time::SystemTime;
 use crate::error::{Result, RsyncError};
 use crate::filesystem::FileInfo;
+#[cfg(windows)]
+use crate::filesystem::file_info::directory_identity;
+#[cfg(windows)]
+use crate::filesystem::scanner::{has_marker, MAX_SCAN_DEPTH};
+#[cfg(windows)]
+use crate::filesystem::symlinks::{detect_symlink_loop, LinkLoopGuard, MAX_LINK_CHAIN};
 
 
 
@@ -25,6 +33,8 @@ use crate::filesystem::FileInfo;
 pub struct WindowsScanner {
     recursive: bool,
     follow_symlinks: bool,
+    exclude_if_present: Vec<String>,
+    max_depth: usize,
 }
 
 #[cfg(windows)]
@@ -34,6 +44,8 @@ impl WindowsScanner {
         Self {
             recursive: false,
             follow_symlinks: false,
+            exclude_if_present: Vec::new(),
+            max_depth: MAX_SCAN_DEPTH,
         }
     }
 
@@ -50,97 +62,184 @@ impl WindowsScanner {
     }
 
 
-    pub fn scan(&self, path: &Path) -> Result<Vec<FileInfo>> {
-        let mut results = Vec::new();
-        self.scan_internal(path, path, &mut results)?;
-        Ok(results)
+    pub fn exclude_if_present(mut self, markers: &[String]) -> Self {
+        self.exclude_if_present = markers.to_vec();
+        self
     }
 
 
-    fn scan_internal(
-        &self,
-        base_path: &Path,
-        current_path: &Path,
-        results: &mut Vec<FileInfo>,
-    ) -> Result<()> {
-
-        let search_pattern = current_path.join("*");
-        let search_pattern_wide = to_wide_string(search_pattern.to_str().unwrap());
-
-        let mut find_data: WIN32_FIND_DATAW = unsafe { std::mem::zeroed() };
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
 
 
+    #[allow(dead_code)]
+    pub fn scan(&self, path: &Path) -> Result<Vec<FileInfo>> {
+        self.scan_iter(path).collect()
+    }
 
-        let handle = unsafe {
-            FindFirstFileExW(
-                windows::core::PCWSTR(search_pattern_wide.as_ptr()),
-                FindExInfoBasic,
-                &mut find_data as *mut _ as *mut _,
-                FindExSearchNameMatch,
-                None,
-                FIND_FIRST_EX_LARGE_FETCH,
-            )
-        }.map_err(|_| RsyncError::Io(std::io::Error::last_os_error()))?;
 
-        if handle == INVALID_HANDLE_VALUE {
-            return Err(RsyncError::Io(std::io::Error::last_os_error()));
+    pub fn scan_iter(&self, path: &Path) -> WindowsScanIter {
+        let guard = if self.follow_symlinks {
+            LinkLoopGuard::default().descend(directory_identity(path)).unwrap_or_default()
+        } else {
+            LinkLoopGuard::default()
+        };
+        WindowsScanIter {
+            recursive: self.recursive,
+            follow_symlinks: self.follow_symlinks,
+            exclude_if_present: self.exclude_if_present.clone(),
+            max_depth: self.max_depth,
+            pending_dirs: vec![(path.to_path_buf(), 0, guard)],
+            current: Vec::new().into_iter(),
+            current_depth: 0,
+            current_guard: LinkLoopGuard::default(),
         }
+    }
+}
 
 
-        let _guard = HandleGuard(handle);
+#[cfg(windows)]
+pub struct WindowsScanIter {
+    recursive: bool,
+    follow_symlinks: bool,
+    exclude_if_present: Vec<String>,
+    max_depth: usize,
+    pending_dirs: Vec<(PathBuf, usize, LinkLoopGuard)>,
+    current: std::vec::IntoIter<FileInfo>,
+    current_depth: usize,
+    current_guard: LinkLoopGuard,
+}
 
+#[cfg(windows)]
+impl Iterator for WindowsScanIter {
+    type Item = Result<FileInfo>;
+
+    fn next(&mut self) -> Option<Self::Item> {
         loop {
-            let file_name = from_wide_string(&find_data.cFileName);
-
-
-            if file_name != "." && file_name != ".." {
-                let full_path = current_path.join(&file_name);
-                let is_directory = (find_data.dwFileAttributes & FILE_ATTRIBUTE_DIRECTORY.0) != 0;
-                let is_symlink = (find_data.dwFileAttributes & FILE_ATTRIBUTE_REPARSE_POINT.0) != 0;
-
-
-                let file_info = FileInfo {
-                    path: full_path.clone(),
-                    size: if is_directory {
-                        0
-                    } else {
-                        ((find_data.nFileSizeHigh as u64) << 32) | (find_data.nFileSizeLow as u64)
-                    },
-                    mtime: filetime_to_systemtime(&find_data.ftLastWriteTime),
-                    file_type: if is_directory {
-                        crate::filesystem::FileType::Directory
-                    } else if is_symlink {
-                        crate::filesystem::FileType::Symlink
-                    } else {
-                        crate::filesystem::FileType::File
-                    },
-                    is_symlink,
-                    symlink_target: None,
-                };
-
-                results.push(file_info);
-
-
-                if is_directory && self.recursive && (!is_symlink || self.follow_symlinks) {
-                    self.scan_internal(base_path, &full_path, results)?;
+            if let Some(file_info) = self.current.next() {
+                if file_info.is_directory() && has_marker(&file_info.path, &self.exclude_if_present) {
+                    continue;
+                }
+                if file_info.is_directory() && self.recursive && (!file_info.is_symlink || self.follow_symlinks) {
+                    self.descend(&file_info);
                 }
+                return Some(Ok(file_info));
+            }
+
+            let (dir, depth, guard) = self.pending_dirs.pop()?;
+            self.current_depth = depth;
+            self.current_guard = guard;
+            match read_directory(&dir) {
+                Ok(entries) => self.current = entries.into_iter(),
+                Err(e) => return Some(Err(e)),
             }
+        }
+    }
+}
+
+
+#[cfg(windows)]
+impl WindowsScanIter {
+    fn descend(&mut self, dir: &FileInfo) {
+        let depth = self.current_depth + 1;
+        if depth >= self.max_depth {
+            eprintln!("Warning: not descending into {}: more than {} levels deep", dir.path.display(), self.max_depth);
+            return;
+        }
+        if !self.follow_symlinks {
+            self.pending_dirs.push((dir.path.clone(), depth, LinkLoopGuard::default()));
+            return;
+        }
+        if dir.is_symlink && detect_symlink_loop(&dir.path, MAX_LINK_CHAIN).unwrap_or(false) {
+            eprintln!("Warning: skipping symlink loop at {}", dir.path.display());
+            return;
+        }
+        match self.current_guard.descend(directory_identity(&dir.path)) {
+            Some(guard) => self.pending_dirs.push((dir.path.clone(), depth, guard)),
+            None => eprintln!("Warning: skipping symlink loop {} -> an ancestor directory", dir.path.display()),
+        }
+    }
+}
 
 
-            let result = unsafe { FindNextFileW(handle, &mut find_data) };
-            if result.is_err() {
+#[cfg(windows)]
+fn read_directory(current_path: &Path) -> Result<Vec<FileInfo>> {
+    let mut results = Vec::new();
+
+    let search_pattern = current_path.join("*");
+    let search_pattern_wide = to_wide_string(search_pattern.to_str().unwrap());
+
+    let mut find_data: WIN32_FIND_DATAW = unsafe { std::mem::zeroed() };
+
+
+
+    let handle = unsafe {
+        FindFirstFileExW(
+            windows::core::PCWSTR(search_pattern_wide.as_ptr()),
+            FindExInfoBasic,
+            &mut find_data as *mut _ as *mut _,
+            FindExSearchNameMatch,
+            None,
+            FIND_FIRST_EX_LARGE_FETCH,
+        )
+    }.map_err(|_| RsyncError::Io(std::io::Error::last_os_error()))?;
+
+    if handle == INVALID_HANDLE_VALUE {
+        return Err(RsyncError::Io(std::io::Error::last_os_error()));
+    }
+
 
-                let last_error = std::io::Error::last_os_error();
-                if last_error.raw_os_error() == Some(18) {
-                    break;
+    let _guard = HandleGuard(handle);
+
+    loop {
+        let file_name = from_wide_string(&find_data.cFileName);
+
+
+        if file_name != "." && file_name != ".." {
+            let full_path = current_path.join(&file_name);
+            let is_directory = (find_data.dwFileAttributes & FILE_ATTRIBUTE_DIRECTORY.0) != 0;
+            let is_symlink = (find_data.dwFileAttributes & FILE_ATTRIBUTE_REPARSE_POINT.0) != 0;
+
+
+            results.push(FileInfo {
+                path: full_path,
+                size: if is_directory {
+                    0
                 } else {
-                    return Err(RsyncError::Io(last_error));
-                }
-            }
+                    ((find_data.nFileSizeHigh as u64) << 32) | (find_data.nFileSizeLow as u64)
+                },
+                mtime: filetime_to_systemtime(&find_data.ftLastWriteTime),
+                file_type: if is_directory {
+                    crate::filesystem::FileType::Directory
+                } else if is_symlink {
+                    crate::filesystem::FileType::Symlink
+                } else {
+                    crate::filesystem::FileType::File
+                },
+                is_symlink,
+                symlink_target: None,
+                nlink: 1,
+                file_id: None,
+                streams: Vec::new(),
+            });
         }
 
-        Ok(())
+
+        let result = unsafe { FindNextFileW(handle, &mut find_data) };
+        if result.is_err() {
+
+            let last_error = std::io::Error::last_os_error();
+            if last_error.raw_os_error() == Some(18) {
+                break;
+            } else {
+                return Err(RsyncError::Io(last_error));
+            }
+        }
     }
+
+    Ok(results)
 }
 
 #[cfg(windows)]
@@ -1,38 +1,166 @@
-use std::path::Path;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::fs::File;
 use std::io::{BufRead, BufReader};
-use crate::error::Result;
-use super::pattern::{FilterPattern, PatternType};
+use std::sync::{Arc, Mutex};
+use crate::error::{Result, RsyncError};
+use crate::options::{FilterRule, Options};
+use crate::output::VerboseOutput;
+use super::merge::DirMerge;
+use super::pattern::{match_path, FilterPattern, PatternType};
+
+
+#[derive(Debug, Clone)]
+enum Rule {
+    Pattern(FilterPattern),
+    DirMerge(DirMerge),
+}
+
+
+type MergeCache = HashMap<usize, HashMap<PathBuf, Arc<Vec<FilterPattern>>>>;
 
 
 #[derive(Debug, Default)]
 pub struct FilterEngine {
-    patterns: Vec<FilterPattern>,
+    rules: Vec<Rule>,
+    protect: Vec<FilterPattern>,
+    root: Option<PathBuf>,
+    merged: Mutex<MergeCache>,
+    trace: Option<VerboseOutput>,
 }
 
 impl FilterEngine {
 
     pub fn new() -> Self {
+        Self::default()
+    }
+
+
+    pub fn trace(mut self, verbose: VerboseOutput) -> Self {
+        self.trace = Some(verbose);
+        self
+    }
+
+
+    pub fn for_root(&self, root: &Path) -> Self {
         Self {
-            patterns: Vec::new(),
+            rules: self.rules.clone(),
+            protect: self.protect.clone(),
+            root: Some(root.to_path_buf()),
+            merged: Mutex::default(),
+            trace: self.trace,
         }
     }
 
 
+    #[allow(dead_code)]
     pub fn add_exclude(&mut self, pattern: &str) -> Result<()> {
-        let filter = FilterPattern::new(pattern, PatternType::Exclude)?;
-        self.patterns.push(filter);
-        Ok(())
+        self.add_pattern(pattern, PatternType::Exclude, "--exclude")
     }
 
 
+    #[allow(dead_code)]
     pub fn add_include(&mut self, pattern: &str) -> Result<()> {
-        let filter = FilterPattern::new(pattern, PatternType::Include)?;
-        self.patterns.push(filter);
+        self.add_pattern(pattern, PatternType::Include, "--include")
+    }
+
+
+    fn add_pattern(&mut self, pattern: &str, pattern_type: PatternType, origin: impl Into<String>) -> Result<()> {
+        let filter = FilterPattern::new(pattern, pattern_type)?.with_origin(origin);
+        if filter.pattern_type == PatternType::Protect {
+            self.protect.push(filter);
+        } else {
+            self.rules.push(Rule::Pattern(filter));
+        }
         Ok(())
     }
 
 
+    pub fn from_options(options: &Options) -> Result<Self> {
+        let mut engine = Self::new();
+        for (index, pattern) in options.protect.iter().enumerate() {
+            engine.add_pattern(pattern, PatternType::Protect, format!("--protect #{}", index + 1))?;
+        }
+        let (mut excludes, mut includes, mut filters) = (0, 0, 0);
+        for rule in &options.filter_rules {
+            match rule {
+                FilterRule::Exclude(pattern) => {
+                    excludes += 1;
+                    engine.add_pattern(pattern, PatternType::Exclude, format!("--exclude #{}", excludes))?;
+                }
+                FilterRule::Include(pattern) => {
+                    includes += 1;
+                    engine.add_pattern(pattern, PatternType::Include, format!("--include #{}", includes))?;
+                }
+                FilterRule::Rule(rule) => {
+                    filters += 1;
+                    if !engine.add_rule_from(rule, &format!("--filter #{}", filters))? {
+                        return Err(RsyncError::InvalidPattern(format!("Unsupported filter rule '{}'", rule)));
+                    }
+                }
+                FilterRule::ExcludeFrom(file_path) => engine.add_exclude_from(file_path)?,
+                FilterRule::IncludeFrom(file_path) => engine.add_include_from(file_path)?,
+            }
+        }
+        Ok(engine)
+    }
+
+
+    pub fn protection(options: &Options) -> Result<Self> {
+        let mut engine = Self::new();
+        for (index, pattern) in options.protect.iter().enumerate() {
+            engine.add_pattern(pattern, PatternType::Protect, format!("--protect #{}", index + 1))?;
+        }
+        let filters = options.filter_rules.iter().filter_map(|rule| match rule {
+            FilterRule::Rule(rule) => Some(rule),
+            _ => None,
+        });
+        for (index, rule) in filters.enumerate() {
+            if let Some(pattern) = rule.strip_prefix("P ") {
+                engine.add_pattern(pattern, PatternType::Protect, format!("--filter #{}", index + 1))?;
+            }
+        }
+        Ok(engine)
+    }
+
+
+    pub fn protect_patterns(&self) -> Vec<String> {
+        self.protect.iter().map(|pattern| pattern.pattern.clone()).collect()
+    }
+
+
+    pub fn add_rule(&mut self, rule: &str) -> Result<bool> {
+        self.add_rule_from(rule, "peer filter list")
+    }
+
+
+    fn add_rule_from(&mut self, rule: &str, origin: &str) -> Result<bool> {
+        if let Some(pattern) = rule.strip_prefix("- ").or_else(|| rule.strip_prefix("H ")) {
+            self.add_pattern(pattern, PatternType::Exclude, origin)?;
+        } else if let Some(pattern) = rule.strip_prefix("+ ").or_else(|| rule.strip_prefix("S ")) {
+            self.add_pattern(pattern, PatternType::Include, origin)?;
+        } else if let Some(pattern) = rule.strip_prefix("P ") {
+            self.add_pattern(pattern, PatternType::Protect, origin)?;
+        } else if let Some(merge) = DirMerge::parse(rule, origin) {
+            self.rules.push(Rule::DirMerge(merge?));
+        } else {
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+
+    pub fn rules(&self) -> Vec<String> {
+        self.rules.iter()
+            .map(|rule| match rule {
+                Rule::Pattern(pattern) => pattern.rule(),
+                Rule::DirMerge(merge) => merge.rule(),
+            })
+            .chain(self.protect.iter().map(FilterPattern::rule))
+            .collect()
+    }
+
+
     pub fn add_exclude_from(&mut self, file_path: &Path) -> Result<()> {
         self.load_patterns_from_file(file_path, PatternType::Exclude)
     }
@@ -47,7 +175,7 @@ impl FilterEngine {
         let file = File::open(file_path)?;
         let reader = BufReader::new(file);
 
-        for line in reader.lines() {
+        for (index, line) in reader.lines().enumerate() {
             let line = line?;
             let line = line.trim();
 
@@ -57,8 +185,7 @@ impl FilterEngine {
             }
 
 
-            let filter = FilterPattern::new(line, pattern_type.clone())?;
-            self.patterns.push(filter);
+            self.add_pattern(line, pattern_type.clone(), format!("{}:{}", file_path.display(), index + 1))?;
         }
 
         Ok(())
@@ -73,29 +200,131 @@ impl FilterEngine {
 
 
     pub fn should_include(&self, path: &Path) -> bool {
+        let included = self.with_matching_rule(path, |pattern| {
+            match pattern {
+                Some(pattern) => pattern.pattern_type == PatternType::Include,
+                None => true,
+            }
+        });
+        if let Some(trace) = self.trace.filter(|_| !included) {
+            trace.print_debug(self.explain(path));
+        }
+        included
+    }
+
+
+    pub fn is_protected(&self, path: &Path) -> bool {
+        self.protecting_rule(path).is_some()
+    }
+
 
-        if self.patterns.is_empty() {
-            return true;
+    pub fn protecting_rule(&self, path: &Path) -> Option<&FilterPattern> {
+        if self.protect.is_empty() {
+            return None;
         }
+        let path_str = match_path(path);
+        let rule = path_str.match_indices('/')
+            .map(|(index, _)| &path_str[..index])
+            .chain([path_str.as_ref()])
+            .find_map(|prefix| self.protect.iter().find(|pattern| pattern.matches_str(prefix)));
+        rule
+    }
+
+
+    pub fn explain_protection(&self, path: &Path) -> Option<String> {
+        self.protecting_rule(path).map(|pattern| format!(
+            "protecting {} because of rule '{}' from {}",
+            path.display(),
+            pattern.rule(),
+            pattern.origin
+        ))
+    }
+
 
+    pub fn has_protect_rules(&self) -> bool {
+        !self.protect.is_empty()
+    }
 
-        for pattern in &self.patterns {
-            if pattern.matches(path) {
 
-                return match pattern.pattern_type {
-                    PatternType::Include => true,
-                    PatternType::Exclude => false,
+    pub fn explicitly_includes(&self, path: &Path) -> bool {
+        self.with_matching_rule(path, |pattern| {
+            pattern.is_some_and(|pattern| pattern.pattern_type == PatternType::Include)
+        })
+    }
+
+
+    pub fn matching_rule(&self, path: &Path) -> Option<FilterPattern> {
+        self.with_matching_rule(path, |pattern| pattern.cloned())
+    }
+
+
+    fn with_matching_rule<R>(&self, path: &Path, found: impl FnOnce(Option<&FilterPattern>) -> R) -> R {
+        let path_str = match_path(path);
+        for (index, rule) in self.rules.iter().enumerate() {
+            let merge = match rule {
+                Rule::Pattern(pattern) if pattern.matches_str(&path_str) => return found(Some(pattern)),
+                Rule::Pattern(_) => continue,
+                Rule::DirMerge(merge) => merge,
+            };
+            let Some(ref root) = self.root else {
+                continue;
+            };
+            if let Some(pattern) = merge.exclude_self.as_ref().filter(|pattern| pattern.matches_str(&path_str)) {
+                return found(Some(pattern));
+            }
+            for dir in merge.directories(path) {
+                let patterns = self.merged_patterns(index, merge, root, dir);
+                let dir_str = match_path(dir);
+                let local = match dir_str.as_ref() {
+                    "" => &path_str,
+                    dir_str => path_str.strip_prefix(dir_str)
+                        .and_then(|local| local.strip_prefix('/'))
+                        .unwrap_or(&path_str),
                 };
+                if let Some(pattern) = patterns.iter().find(|pattern| pattern.matches_str(local)) {
+                    return found(Some(pattern));
+                }
             }
         }
+        found(None)
+    }
+
+
+    fn merged_patterns(&self, index: usize, merge: &DirMerge, root: &Path, dir: &Path) -> Arc<Vec<FilterPattern>> {
+        let mut merged = self.merged.lock().unwrap_or_else(|e| e.into_inner());
+        let merged = merged.entry(index).or_default();
+        if let Some(patterns) = merged.get(dir) {
+            return patterns.clone();
+        }
+        merged.entry(dir.to_path_buf())
+            .or_insert_with(|| {
+                Arc::new(merge.load(root, dir).unwrap_or_else(|e| {
+                    if let Some(trace) = self.trace {
+                        trace.print_warning(format!("ignoring {} in {} ({}): {}", merge.file, dir.display(), merge.origin, e));
+                    }
+                    Vec::new()
+                }))
+            })
+            .clone()
+    }
 
 
-        true
+    pub fn explain(&self, path: &Path) -> String {
+        match self.matching_rule(path) {
+            Some(pattern) => format!(
+                "{} is {} by rule '{}' from {}",
+                path.display(),
+                if pattern.pattern_type == PatternType::Include { "included" } else { "excluded" },
+                pattern.rule(),
+                pattern.origin
+            ),
+            None => format!("{} is included: no filter rule matches", path.display()),
+        }
     }
 
 
     pub fn pattern_count(&self) -> usize {
-        self.patterns.len()
+        self.rules.len() + self.protect.len()
     }
 }
 
@@ -168,6 +397,157 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_rules_round_trip() -> Result<()> {
+        let mut engine = FilterEngine::new();
+        engine.add_include("keep.log")?;
+        engine.add_exclude("*.log")?;
+        assert_eq!(engine.rules(), vec!["+ keep.log", "- *.log"]);
+
+        let mut received = FilterEngine::new();
+        for rule in engine.rules() {
+            assert!(received.add_rule(&rule)?);
+        }
+        assert!(!received.add_rule("R risky")?);
+        assert!(received.should_include(&PathBuf::from("keep.log")));
+        assert!(!received.should_include(&PathBuf::from("logs/other.log")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_protect_rules() -> Result<()> {
+        let options = Options {
+            protect: vec!["/etc/machine.conf".to_string(), "secrets/".to_string()],
+            filter_rules: vec![FilterRule::Rule("P *.local".to_string())],
+            ..Options::default()
+        };
+        let engine = FilterEngine::from_options(&options)?;
+        assert!(engine.should_include(Path::new("etc/machine.conf")));
+        assert!(engine.is_protected(Path::new("etc/machine.conf")));
+        assert!(engine.is_protected(Path::new("secrets/keys/id")));
+        assert!(engine.is_protected(Path::new("app/settings.local")));
+        assert!(!engine.is_protected(Path::new("etc/other.conf")));
+        assert_eq!(engine.rules(), vec!["P /etc/machine.conf", "P secrets/", "P *.local"]);
+        assert_eq!(
+            engine.explain_protection(Path::new("etc/machine.conf")),
+            Some("protecting etc/machine.conf because of rule 'P /etc/machine.conf' from --protect #1".to_string())
+        );
+
+        let mut received = FilterEngine::new();
+        for rule in engine.rules() {
+            assert!(received.add_rule(&rule)?);
+        }
+        assert_eq!(received.protect_patterns(), vec!["/etc/machine.conf", "secrets/", "*.local"]);
+        assert_eq!(FilterEngine::protection(&options)?.pattern_count(), 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_explain() -> Result<()> {
+        let mut rules = NamedTempFile::new()?;
+        writeln!(rules, "# keep build logs")?;
+        writeln!(rules, "build/*.log")?;
+        rules.flush()?;
+
+        let options = Options {
+            filter_rules: vec![
+                FilterRule::Exclude("*.tmp".to_string()),
+                FilterRule::Exclude("*.log".to_string()),
+                FilterRule::IncludeFrom(rules.path().to_path_buf()),
+            ],
+            ..Options::default()
+        };
+        let engine = FilterEngine::from_options(&options)?;
+
+        assert_eq!(
+            engine.explain(Path::new("logs/app.log")),
+            "logs/app.log is excluded by rule '- *.log' from --exclude #2"
+        );
+        assert_eq!(
+            engine.explain(Path::new("src/main.rs")),
+            "src/main.rs is included: no filter rule matches"
+        );
+        assert_eq!(engine.matching_rule(Path::new("a.tmp")).map(|rule| rule.origin), Some("--exclude #1".to_string()));
+
+        let mut received = FilterEngine::new();
+        received.add_rule("+ keep.txt")?;
+        let expected = format!("{}:2", rules.path().display());
+        let mut engine = FilterEngine::new();
+        engine.add_include_from(rules.path())?;
+        assert_eq!(engine.matching_rule(Path::new("build/x.log")).map(|rule| rule.origin), Some(expected));
+        assert_eq!(received.matching_rule(Path::new("keep.txt")).map(|rule| rule.origin), Some("peer filter list".to_string()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_explain_include_before_exclude() -> Result<()> {
+        let options = Options {
+            filter_rules: vec![
+                FilterRule::Include("*.txt".to_string()),
+                FilterRule::Rule("- notes.*".to_string()),
+                FilterRule::Exclude("*".to_string()),
+            ],
+            ..Options::default()
+        };
+        let engine = FilterEngine::from_options(&options)?;
+
+        assert_eq!(engine.rules(), vec!["+ *.txt", "- notes.*", "- *"]);
+        assert_eq!(
+            engine.explain(Path::new("docs/notes.txt")),
+            "docs/notes.txt is included by rule '+ *.txt' from --include #1"
+        );
+        assert_eq!(
+            engine.explain(Path::new("notes.md")),
+            "notes.md is excluded by rule '- notes.*' from --filter #1"
+        );
+        assert_eq!(
+            engine.explain(Path::new("image.png")),
+            "image.png is excluded by rule '- *' from --exclude #1"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dir_merge() -> Result<()> {
+        let dir = tempfile::TempDir::new()?;
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("a/b"))?;
+        std::fs::write(root.join(".rules"), "- *.log\n- /top.txt\n")?;
+        std::fs::write(root.join("a/.rules"), "+ keep.log\n- top.txt\n")?;
+        std::fs::write(root.join("a/.skip"), "*.tmp\n")?;
+
+        let options = Options {
+            filter_rules: vec![FilterRule::Rule(": .rules".to_string()), FilterRule::Rule("dir-merge,n- .skip".to_string())],
+            ..Options::default()
+        };
+        let engine = FilterEngine::from_options(&options)?.for_root(root);
+
+        assert!(!engine.should_include(Path::new("app.log")));
+        assert!(!engine.should_include(Path::new("a/b/app.log")));
+        assert!(engine.should_include(Path::new("a/keep.log")));
+        assert!(engine.should_include(Path::new("a/b/keep.log")));
+        assert!(!engine.should_include(Path::new("top.txt")));
+        assert!(!engine.should_include(Path::new("a/b/top.txt")));
+        assert!(engine.should_include(Path::new("b/top.txt")));
+        assert!(!engine.should_include(Path::new("a/x.tmp")));
+        assert!(engine.should_include(Path::new("a/b/x.tmp")));
+        assert_eq!(
+            engine.explain(Path::new("a/b/keep.log")),
+            format!("a/b/keep.log is included by rule '+ keep.log' from {}:1", Path::new("a").join(".rules").display())
+        );
+        assert_eq!(engine.rules(), vec![": .rules", ":n- .skip"]);
+
+        let unrooted = FilterEngine::from_options(&options)?;
+        assert!(unrooted.should_include(Path::new("app.log")));
+        assert!(FilterEngine::new().add_rule(":x .rules").is_err());
+
+        Ok(())
+    }
+
     #[test]
     fn test_load_from_file() -> Result<()> {
 
@@ -206,7 +586,31 @@ mod tests {
         assert!(!engine.should_include(&PathBuf::from("node_modules")));
         assert!(!engine.should_include(&PathBuf::from("node_modules/package/index.js")));
         assert!(engine.should_include(&PathBuf::from("src/main.rs")));
+        assert!(!engine.explicitly_includes(&PathBuf::from("src/main.rs")));
+
+        engine.add_include("*.md")?;
+        assert!(engine.explicitly_includes(&PathBuf::from("docs/README.md")));
 
         Ok(())
     }
+
+    #[test]
+    #[ignore]
+    fn bench_should_include_1m_paths() -> Result<()> {
+        let mut engine = FilterEngine::new();
+        for pattern in ["*.tmp", "*.log", ".git/", "node_modules/", "/build/", "target/**/*.rlib", "cache*"] {
+            engine.add_exclude(pattern)?;
+        }
+        engine.add_include("*.rs")?;
+        let paths: Vec<PathBuf> = (0..1_000_000)
+            .map(|i| PathBuf::from(format!("src/module{}/sub{}/file{}.{}", i % 97, i % 13, i, ["rs", "txt", "log"][i % 3])))
+            .collect();
+
+        let started = std::time::Instant::now();
+        let included = paths.iter().filter(|path| engine.should_include(path)).count();
+        let elapsed = started.elapsed();
+        println!("{} of {} paths included in {:?} ({:.0} paths/s)", included, paths.len(), elapsed, paths.len() as f64 / elapsed.as_secs_f64());
+        assert_eq!(included, 666_667);
+        Ok(())
+    }
 }
@@ -0,0 +1,116 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, ErrorKind};
+use std::path::{Path, PathBuf};
+use crate::error::{Result, RsyncError};
+use super::pattern::{FilterPattern, PatternType};
+
+
+#[derive(Debug, Clone)]
+pub struct DirMerge {
+
+    pub file: String,
+
+    pub modifier: Option<PatternType>,
+
+    pub inherit: bool,
+
+    pub exclude_self: Option<FilterPattern>,
+
+    pub origin: String,
+}
+
+impl DirMerge {
+
+    pub fn parse(rule: &str, origin: &str) -> Option<Result<Self>> {
+        let rest = rule.strip_prefix("dir-merge")
+            .map(|rest| rest.strip_prefix(',').unwrap_or(rest))
+            .or_else(|| rule.strip_prefix(':'))?;
+        let (modifiers, file) = rest.split_once(' ').unwrap_or(("", rest));
+        let file = file.trim().trim_start_matches('/');
+        if file.is_empty() {
+            return Some(Err(RsyncError::InvalidPattern(format!("Missing file name in merge rule '{}'", rule))));
+        }
+
+        let mut merge = Self {
+            file: file.to_string(),
+            modifier: None,
+            inherit: true,
+            exclude_self: None,
+            origin: origin.to_string(),
+        };
+        for modifier in modifiers.chars() {
+            match modifier {
+                '-' => merge.modifier = Some(PatternType::Exclude),
+                '+' => merge.modifier = Some(PatternType::Include),
+                'n' => merge.inherit = false,
+                'e' => match FilterPattern::new(file, PatternType::Exclude) {
+                    Ok(pattern) => merge.exclude_self = Some(pattern.with_origin(origin)),
+                    Err(e) => return Some(Err(e)),
+                },
+                other => return Some(Err(RsyncError::InvalidPattern(
+                    format!("Unknown modifier '{}' in merge rule '{}'", other, rule)
+                ))),
+            }
+        }
+        Some(Ok(merge))
+    }
+
+
+    pub fn rule(&self) -> String {
+        let mut modifiers = String::new();
+        if !self.inherit {
+            modifiers.push('n');
+        }
+        if self.exclude_self.is_some() {
+            modifiers.push('e');
+        }
+        match self.modifier {
+            Some(PatternType::Exclude) => modifiers.push('-'),
+            Some(PatternType::Include) => modifiers.push('+'),
+            Some(PatternType::Protect) | None => {}
+        }
+        format!(":{} {}", modifiers, self.file)
+    }
+
+
+    pub fn directories<'a>(&self, path: &'a Path) -> impl Iterator<Item = &'a Path> {
+        let parent = path.parent().unwrap_or(Path::new(""));
+        parent.ancestors().take(if self.inherit { usize::MAX } else { 1 })
+    }
+
+
+    pub fn load(&self, root: &Path, dir: &Path) -> Result<Vec<FilterPattern>> {
+        let file_path = root.join(dir).join(&self.file);
+        let file = match File::open(&file_path) {
+            Ok(file) => file,
+            Err(e) if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::NotADirectory) => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let display_path = PathBuf::from(dir).join(&self.file);
+
+        let mut patterns = Vec::new();
+        for (index, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (pattern_type, pattern) = match self.modifier {
+                Some(ref pattern_type) => (pattern_type.clone(), line),
+                None => match line.get(..2).map(|prefix| (prefix, &line[2..])) {
+                    Some(("- ", pattern)) => (PatternType::Exclude, pattern),
+                    Some(("+ ", pattern)) => (PatternType::Include, pattern),
+                    _ => return Err(RsyncError::InvalidPattern(format!(
+                        "Unsupported rule '{}' in {}:{}",
+                        line,
+                        display_path.display(),
+                        index + 1
+                    ))),
+                },
+            };
+            let origin = format!("{}:{}", display_path.display(), index + 1);
+            patterns.push(FilterPattern::new(pattern, pattern_type)?.with_origin(origin));
+        }
+        Ok(patterns)
+    }
+}
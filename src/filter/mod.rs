@@ -1,4 +1,6 @@
 mod pattern;
+mod wildmatch;
 mod engine;
+mod merge;
 
 pub use engine::FilterEngine;
@@ -1,12 +1,14 @@
+use std::borrow::Cow;
 use std::path::Path;
-use globset::{Glob, GlobMatcher};
-use crate::error::{Result, RsyncError};
+use crate::error::Result;
+use super::wildmatch::Wildmatch;
 
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum PatternType {
     Include,
     Exclude,
+    Protect,
 }
 
 
@@ -30,134 +32,84 @@ pub struct FilterPattern {
 
     pub match_type: MatchType,
 
-    matcher: GlobMatcher,
+    pub origin: String,
 
-    normalized_pattern: String,
+    matcher: Wildmatch,
+
+    anchored: bool,
+
+    full_path: bool,
 }
 
 impl FilterPattern {
 
     pub fn new(pattern: &str, pattern_type: PatternType) -> Result<Self> {
+        let trimmed = pattern.trim();
+        let match_type = if trimmed.ends_with('/') {
+            MatchType::Directory
+        } else if trimmed.starts_with('/') {
+            MatchType::Absolute
+        } else {
+            MatchType::Wildcard
+        };
 
-        let (normalized_pattern, match_type) = Self::parse_pattern(pattern);
-
-
-        let glob = Glob::new(&normalized_pattern)
-            .map_err(|e| RsyncError::InvalidPattern(format!("Invalid pattern '{}': {}", pattern, e)))?;
+        let anchored = trimmed.starts_with('/');
+        let body = trimmed.trim_start_matches('/').trim_end_matches('/');
 
         Ok(Self {
             pattern: pattern.to_string(),
             pattern_type,
             match_type,
-            matcher: glob.compile_matcher(),
-            normalized_pattern: normalized_pattern.clone(),
+            origin: String::new(),
+            matcher: Wildmatch::new(body)?,
+            anchored,
+            full_path: body.contains('/') || body.contains("**"),
         })
     }
 
 
-    fn parse_pattern(pattern: &str) -> (String, MatchType) {
-        let pattern = pattern.trim();
-
-
-        if pattern.ends_with('/') {
-            let dir_name = pattern.trim_end_matches('/');
-            let dir_pattern = if pattern.starts_with('/') {
-
-                let abs_dir = dir_name.trim_start_matches('/');
-                format!("{}/**", abs_dir)
-            } else {
-
-                format!("**/{}{}", dir_name, "/**")
-            };
-            return (dir_pattern, MatchType::Directory);
-        }
-
-
-        if pattern.starts_with('/') {
-            let abs_pattern = pattern.trim_start_matches('/');
-            return (abs_pattern.to_string(), MatchType::Absolute);
-        }
-
-
-
-
-
-        let wildcard_pattern = if pattern.contains('/') {
-
-
-
-            format!("{{,**/}}{}", pattern)
-        } else {
-
-            format!("**/{}", pattern)
-        };
-
-        (wildcard_pattern, MatchType::Wildcard)
-    }
-
-
+    #[allow(dead_code)]
     pub fn matches(&self, path: &Path) -> bool {
-
-        let path_str = path.to_string_lossy().replace('\\', "/");
-
+        self.matches_str(&match_path(path))
+    }
 
 
+    pub fn matches_str(&self, path_str: &str) -> bool {
         if self.match_type == MatchType::Directory {
-
-
-            let pattern_str = &self.normalized_pattern;
-            if let Some(dir_name) = pattern_str.strip_prefix("**/").and_then(|s| s.strip_suffix("/**")) {
-
-                if path_str == dir_name || path_str.starts_with(&format!("{}/", dir_name)) {
-                    return true;
-                }
-
-                if path_str.contains(&format!("/{}", dir_name)) {
-                    let parts: Vec<&str> = path_str.split('/').collect();
-                    if let Some(_pos) = parts.iter().position(|&p| p == dir_name) {
-
-                        return true;
-                    }
-                }
-            }
+            return path_str.match_indices('/')
+                .map(|(index, _)| &path_str[..index])
+                .chain([path_str])
+                .any(|prefix| self.matches_name(prefix));
         }
+        self.matches_name(path_str)
+    }
 
 
+    fn matches_name(&self, path: &str) -> bool {
+        if self.anchored {
+            return self.matcher.is_match(path);
+        }
+        if !self.full_path {
+            return self.matcher.is_match(path.rsplit('/').next().unwrap_or(path));
+        }
+        [0].into_iter()
+            .chain(path.match_indices('/').map(|(index, _)| index + 1))
+            .any(|start| self.matcher.is_match(&path[start..]))
+    }
 
 
-        if self.pattern.contains('/') && !self.pattern.ends_with('/') && !self.pattern.starts_with('/') {
-
-            let pattern_parts: Vec<&str> = self.pattern.split('/').collect();
-            let path_parts: Vec<&str> = path_str.split('/').collect();
-
-
-            if path_parts.len() >= pattern_parts.len() {
-                for i in 0..=path_parts.len() - pattern_parts.len() {
-                    let mut matches = true;
-                    for (j, pattern_part) in pattern_parts.iter().enumerate() {
-                        let path_part = path_parts[i + j];
-
-
-                        if !glob_match_simple(pattern_part, path_part) {
-                            matches = false;
-                            break;
-                        }
-                    }
-
-                    if matches {
-
+    pub fn with_origin(mut self, origin: impl Into<String>) -> Self {
+        self.origin = origin.into();
+        self
+    }
 
-                        if i + pattern_parts.len() == path_parts.len() {
-                            return true;
-                        }
-                    }
-                }
-            }
 
-            return false;
+    pub fn rule(&self) -> String {
+        match self.pattern_type {
+            PatternType::Include => format!("+ {}", self.pattern),
+            PatternType::Exclude => format!("- {}", self.pattern),
+            PatternType::Protect => format!("P {}", self.pattern),
         }
-
-        self.matcher.is_match(&path_str)
     }
 
 
@@ -168,14 +120,15 @@ impl FilterPattern {
 }
 
 
-fn glob_match_simple(pattern: &str, text: &str) -> bool {
-
-    let glob = match Glob::new(pattern) {
-        Ok(g) => g,
-        Err(_) => return false,
-    };
-    let matcher = glob.compile_matcher();
-    matcher.is_match(text)
+pub fn match_path(path: &Path) -> Cow<'_, str> {
+    let path_str = path.to_string_lossy();
+    if path_str.contains('\\') {
+        return Cow::Owned(path_str.replace('\\', "/").trim_end_matches('/').to_string());
+    }
+    match path_str {
+        Cow::Borrowed(path_str) => Cow::Borrowed(path_str.trim_end_matches('/')),
+        Cow::Owned(path_str) => Cow::Owned(path_str.trim_end_matches('/').to_string()),
+    }
 }
 
 #[cfg(test)]
@@ -228,6 +181,29 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_rsync_wildcards() -> Result<()> {
+        let pattern = FilterPattern::new("src/**/*.rs", PatternType::Exclude)?;
+        assert!(pattern.matches(&PathBuf::from("src/a/b/main.rs")));
+        assert!(pattern.matches(&PathBuf::from("crate/src/a/main.rs")));
+        assert!(!pattern.matches(&PathBuf::from("src/main.rs")));
+
+        let pattern = FilterPattern::new("/cache/***", PatternType::Exclude)?;
+        assert!(pattern.matches(&PathBuf::from("cache")));
+        assert!(pattern.matches(&PathBuf::from("cache/x/y.bin")));
+        assert!(!pattern.matches(&PathBuf::from("sub/cache/y.bin")));
+
+        let pattern = FilterPattern::new("report[0-9]-\\*.txt", PatternType::Exclude)?;
+        assert!(pattern.matches(&PathBuf::from("out/report7-*.txt")));
+        assert!(!pattern.matches(&PathBuf::from("out/report7-final.txt")));
+
+        let pattern = FilterPattern::new("build*/", PatternType::Exclude)?;
+        assert!(pattern.matches(&PathBuf::from("a/build-x/obj/main.o")));
+        assert!(!pattern.matches(&PathBuf::from("a/rebuild/main.o")));
+
+        Ok(())
+    }
+
     #[test]
     fn test_doc_pattern() -> Result<()> {
         let pattern = FilterPattern::new("*.doc", PatternType::Exclude)?;
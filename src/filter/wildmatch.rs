@@ -0,0 +1,284 @@
+use crate::error::{Result, RsyncError};
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Literal(char),
+
+    AnyChar,
+
+    Star,
+
+    DoubleStar,
+
+    Class { negated: bool, ranges: Vec<(char, char)> },
+}
+
+impl Token {
+    fn matches(&self, ch: char) -> bool {
+        match self {
+            Token::Literal(literal) => *literal == ch,
+            Token::AnyChar => ch != '/',
+            Token::Class { negated, ranges } => {
+                ch != '/' && ranges.iter().any(|&(low, high)| low <= ch && ch <= high) != *negated
+            }
+            Token::Star | Token::DoubleStar => false,
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Compiled {
+    Literal(String),
+
+    Prefix { literal: String, cross_dirs: bool },
+
+    Suffix { literal: String, cross_dirs: bool },
+
+    Tokens { tokens: Vec<Token>, tail: String },
+}
+
+impl Compiled {
+    fn new(tokens: Vec<Token>) -> Self {
+        if let Some(literal) = literal(&tokens) {
+            return Compiled::Literal(literal);
+        }
+        if let Some((star @ (Token::Star | Token::DoubleStar), rest)) = tokens.split_last() {
+            if let Some(literal) = literal(rest) {
+                return Compiled::Prefix { literal, cross_dirs: *star == Token::DoubleStar };
+            }
+        }
+        if let Some((star @ (Token::Star | Token::DoubleStar), rest)) = tokens.split_first() {
+            if let Some(literal) = literal(rest) {
+                return Compiled::Suffix { literal, cross_dirs: *star == Token::DoubleStar };
+            }
+        }
+        let tail_len = tokens.iter().rev().take_while(|token| matches!(token, Token::Literal(_))).count();
+        let tail = literal(&tokens[tokens.len() - tail_len..]).unwrap_or_default();
+        Compiled::Tokens { tokens, tail }
+    }
+
+
+    fn is_match(&self, text: &str) -> bool {
+        match self {
+            Compiled::Literal(literal) => text == literal,
+            Compiled::Prefix { literal, cross_dirs } => text
+                .strip_prefix(literal.as_str())
+                .is_some_and(|rest| *cross_dirs || !rest.contains('/')),
+            Compiled::Suffix { literal, cross_dirs } => text
+                .strip_suffix(literal.as_str())
+                .is_some_and(|rest| *cross_dirs || !rest.contains('/')),
+            Compiled::Tokens { tokens, tail } => text.ends_with(tail.as_str()) && match_tokens(tokens, text),
+        }
+    }
+}
+
+
+#[derive(Debug, Clone)]
+pub struct Wildmatch {
+    alternatives: Vec<Compiled>,
+}
+
+impl Wildmatch {
+
+    pub fn new(pattern: &str) -> Result<Self> {
+        let alternatives = match pattern.strip_suffix("/***") {
+            Some(base) => {
+                let base = tokenize(base)?;
+                let mut contents = base.clone();
+                contents.extend([Token::Literal('/'), Token::DoubleStar]);
+                vec![Compiled::new(base), Compiled::new(contents)]
+            }
+            None => vec![Compiled::new(tokenize(pattern)?)],
+        };
+        Ok(Self { alternatives })
+    }
+
+
+    pub fn is_match(&self, text: &str) -> bool {
+        self.alternatives.iter().any(|compiled| compiled.is_match(text))
+    }
+}
+
+
+fn literal(tokens: &[Token]) -> Option<String> {
+    tokens.iter()
+        .map(|token| match token {
+            Token::Literal(ch) => Some(*ch),
+            _ => None,
+        })
+        .collect()
+}
+
+
+pub fn has_wildcards(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '['])
+}
+
+
+fn tokenize(pattern: &str) -> Result<Vec<Token>> {
+    let escapes = has_wildcards(pattern);
+    let mut tokens = Vec::new();
+    let mut chars = pattern.chars().peekable();
+    while let Some(ch) = chars.next() {
+        let token = match ch {
+            '\\' if escapes => Token::Literal(chars.next().unwrap_or('\\')),
+            '?' => Token::AnyChar,
+            '*' => {
+                if chars.peek() == Some(&'*') {
+                    while chars.peek() == Some(&'*') {
+                        chars.next();
+                    }
+                    Token::DoubleStar
+                } else {
+                    Token::Star
+                }
+            }
+            '[' => parse_class(&mut chars).ok_or_else(|| {
+                RsyncError::InvalidPattern(format!("Unterminated character class in '{}'", pattern))
+            })?,
+            other => Token::Literal(other),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+
+fn parse_class(chars: &mut std::iter::Peekable<std::str::Chars>) -> Option<Token> {
+    let negated = matches!(chars.peek(), Some('!') | Some('^'));
+    if negated {
+        chars.next();
+    }
+    let mut ranges = Vec::new();
+    let mut first = true;
+    loop {
+        let low = match chars.next()? {
+            ']' if !first => return Some(Token::Class { negated, ranges }),
+            '[' if chars.peek() == Some(&':') => {
+                chars.next();
+                let name: String = chars.by_ref().take_while(|&c| c != ':').collect();
+                if chars.next()? != ']' {
+                    return None;
+                }
+                ranges.extend_from_slice(posix_class(&name)?);
+                first = false;
+                continue;
+            }
+            '\\' => chars.next()?,
+            other => other,
+        };
+        first = false;
+        let mut lookahead = chars.clone();
+        if lookahead.next() == Some('-') && lookahead.peek().is_some_and(|&c| c != ']') {
+            chars.next();
+            let high = match chars.next()? {
+                '\\' => chars.next()?,
+                other => other,
+            };
+            ranges.push((low, high));
+        } else {
+            ranges.push((low, low));
+        }
+    }
+}
+
+
+fn posix_class(name: &str) -> Option<&'static [(char, char)]> {
+    Some(match name {
+        "alpha" => &[('a', 'z'), ('A', 'Z')],
+        "digit" => &[('0', '9')],
+        "alnum" => &[('a', 'z'), ('A', 'Z'), ('0', '9')],
+        "upper" => &[('A', 'Z')],
+        "lower" => &[('a', 'z')],
+        "xdigit" => &[('0', '9'), ('a', 'f'), ('A', 'F')],
+        "space" => &[(' ', ' '), ('\t', '\r')],
+        "blank" => &[(' ', ' '), ('\t', '\t')],
+        "punct" => &[('!', '/'), (':', '@'), ('[', '`'), ('{', '~')],
+        _ => return None,
+    })
+}
+
+
+fn match_tokens(tokens: &[Token], text: &str) -> bool {
+    let Some((token, rest)) = tokens.split_first() else {
+        return text.is_empty();
+    };
+    match token {
+        Token::Star | Token::DoubleStar => {
+            for (index, ch) in text.char_indices() {
+                if match_tokens(rest, &text[index..]) {
+                    return true;
+                }
+                if ch == '/' && *token == Token::Star {
+                    return false;
+                }
+            }
+            match_tokens(rest, "")
+        }
+        single => {
+            let mut chars = text.chars();
+            chars.next().is_some_and(|ch| single.matches(ch)) && match_tokens(rest, chars.as_str())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, text: &str) -> bool {
+        Wildmatch::new(pattern).unwrap().is_match(text)
+    }
+
+    #[test]
+    fn test_stars() {
+        assert!(matches("*.txt", "notes.txt"));
+        assert!(!matches("*.txt", "dir/notes.txt"));
+        assert!(matches("**.txt", "dir/notes.txt"));
+        assert!(matches("src/**/mod.rs", "src/a/b/mod.rs"));
+        assert!(!matches("src/**/mod.rs", "src/mod.rs"));
+        assert!(matches("cache/***", "cache"));
+        assert!(matches("cache/***", "cache/a/b"));
+        assert!(!matches("cache/***", "cached"));
+    }
+
+    #[test]
+    fn test_character_classes() {
+        assert!(matches("file[0-9].log", "file7.log"));
+        assert!(!matches("file[0-9].log", "fileA.log"));
+        assert!(matches("file[!0-9].log", "fileA.log"));
+        assert!(matches("[]x]", "]"));
+        assert!(matches("[a-]", "-"));
+        assert!(matches("[[:upper:]]*", "Readme"));
+        assert!(!matches("[[:upper:]]*", "readme"));
+        assert!(!matches("a[/]b", "a/b"));
+        assert!(Wildmatch::new("[abc").is_err());
+    }
+
+    #[test]
+    fn test_compiled_shortcuts() -> Result<()> {
+        assert_eq!(Compiled::new(tokenize("*.txt")?), Compiled::Suffix { literal: ".txt".to_string(), cross_dirs: false });
+        assert_eq!(Compiled::new(tokenize("build**")?), Compiled::Prefix { literal: "build".to_string(), cross_dirs: true });
+        assert_eq!(Compiled::new(tokenize("Thumbs.db")?), Compiled::Literal("Thumbs.db".to_string()));
+        assert!(matches!(Compiled::new(tokenize("*.t?t")?), Compiled::Tokens { tail, .. } if tail == "t"));
+
+        assert!(matches("build*", "build-x"));
+        assert!(!matches("build*", "build/x"));
+        assert!(matches("build**", "build/x"));
+        assert!(!matches("*.txt", "a/b.txt"));
+        assert!(matches("**.txt", "a/b.txt"));
+        assert!(matches("*", ""));
+        assert!(!matches("?", ""));
+        Ok(())
+    }
+
+    #[test]
+    fn test_escaping() {
+        assert!(matches(r"\*.txt", "*.txt"));
+        assert!(!matches(r"\*.txt", "a.txt"));
+        assert!(matches(r"what\?*", "what?.md"));
+        assert!(matches(r"back\slash", r"back\slash"));
+    }
+}
@@ -1,4 +1,5 @@
 mod cli;
+mod compat;
 mod error;
 mod options;
 mod filesystem;
@@ -7,12 +8,14 @@ mod transport;
 mod filter;
 mod output;
 mod protocol;
+mod schedule;
 
 use clap::Parser;
 use cli::Cli;
 use error::Result;
-use filesystem::path_utils::{is_remote_path, is_daemon_path, parse_remote_path};
-use transport::{AuthMethod, DaemonClient, DaemonConfig, RemoteTransport, RsyncDaemon};
+use filesystem::path_utils::{is_remote_path, is_daemon_path, nested_destination, parse_remote_path, top_dir};
+use filesystem::files_from::remote_files_from;
+use transport::{start_copy_as, AuthMethod, DaemonClient, DaemonConfig, RemoteTransport, RsyncDaemon, RsyncServer};
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -20,96 +23,228 @@ async fn main() -> Result<()> {
     env_logger::init();
 
 
-    let cli = Cli::parse();
+    let cli = Cli::parse_from(compat::normalize_args(std::env::args_os())?);
 
 
-    let sources = cli.source.clone();
-    let destination = cli.destination.clone();
+    let (sources, destination) = cli.split_paths()?;
 
 
     let options = cli.into_options()?;
 
+
+    if options.server {
+        let server = RsyncServer::new(options);
+        server.run(std::path::Path::new(&destination))?;
+        return Ok(());
+    }
+
     let verbose = options.verbose_output();
+    if options.json {
+        output::json::enable();
+    }
 
     if let Some(ref log_file_path) = options.log_file {
         match output::init_logger(log_file_path) {
             Ok(_) => {
-                verbose.print_basic(&format!("Logging to file: {}", log_file_path.display()));
+                verbose.print_basic(format!("Logging to file: {}", log_file_path.display()));
                 output::log_with_timestamp(&format!("YARW (Yet Another Rsync for Windows) v{} started", env!("CARGO_PKG_VERSION")));
                 output::log(&format!("Command: rsync {} {}", sources.join(" "), destination));
             }
             Err(e) => {
-                verbose.print_warning(&format!("Failed to initialize log file: {}", e));
+                verbose.print_warning(format!("Failed to initialize log file: {}", e));
             }
         }
     }
 
 
-    verbose.print_basic(&format!("YARW (Yet Another Rsync for Windows) v{}", env!("CARGO_PKG_VERSION")));
-    verbose.print_basic(&format!("Verbose level: {}", options.verbose));
+    verbose.print_basic(format!("YARW (Yet Another Rsync for Windows) v{}", env!("CARGO_PKG_VERSION")));
+    verbose.print_basic(format!("Verbose level: {}", options.verbose));
+
+
+    if let Some(ref when) = options.schedule {
+        let name = schedule::register(when)?;
+        println!("Scheduled task {} to run {}", name, when);
+        return Ok(());
+    }
+    if options.unschedule {
+        println!("Removed scheduled task {}", schedule::unregister()?);
+        return Ok(());
+    }
 
 
     if options.daemon {
         let config_path = options.config.clone().unwrap_or_else(|| "rsyncd.conf".into());
-        let config_str = std::fs::read_to_string(config_path)?;
-        let config: DaemonConfig = toml::from_str(&config_str)?;
-        let daemon = RsyncDaemon::new(config);
+        let config = DaemonConfig::load_with(&config_path, &options.dparam)?;
+        let daemon = RsyncDaemon::new(config)
+            .config_path(config_path)
+            .dparams(options.dparam.clone())
+            .default_bwlimit(options.bwlimit)
+            .socket_options(Some(options.socket_options()));
         daemon.start().await?;
         return Ok(());
     }
 
 
+    if let Some(ref manifest_path) = options.verify_manifest {
+        let manifest = filesystem::Manifest::load(manifest_path)?;
+        let root = if destination.is_empty() { manifest.root.clone() } else { std::path::PathBuf::from(&destination) };
+        verbose.print_basic(format!("Verifying {} against {}", root.display(), manifest_path.display()));
+        let drift = manifest.verify(&root, Some(manifest_path))?;
+        for change in &drift {
+            verbose.print_warning(change.to_string());
+        }
+        if !drift.is_empty() {
+            return Err(error::RsyncError::Other(format!(
+                "{} difference(s) from manifest {}",
+                drift.len(),
+                manifest_path.display()
+            )));
+        }
+        verbose.print_basic(format!("All {} files match the manifest", manifest.files.len()));
+        return Ok(());
+    }
+
+
+    if !options.explain.is_empty() {
+        let filter = filter::FilterEngine::from_options(&options)?.for_root(std::path::Path::new("."));
+        for path in &options.explain {
+            println!("{}", filter.explain(path));
+        }
+        return Ok(());
+    }
+
+
+    filesystem::privileges::check_privileges(&options, &verbose)?;
+    let lock_path = match options.lock_file {
+        Some(ref path) => Some(path.clone()),
+        None if !options.dry_run => Some(filesystem::lock::default_lock_path(&destination)),
+        None => None,
+    };
+    let _run_lock = match lock_path {
+        Some(ref path) => {
+            let lock = filesystem::lock::RunLock::acquire(path, options.lock_wait.map(std::time::Duration::from_secs))?;
+            verbose.print_debug(format!("Holding run lock {}", lock.path().display()));
+            Some(lock)
+        }
+        None => None,
+    };
+    if let Some(ref account) = options.copy_as {
+        start_copy_as(account, &options)?;
+    }
+
+
     let local_transport = transport::LocalTransport::new(options.clone());
+    let daemon_compress = if options.compress {
+        Some(options.compress_choice.unwrap_or_default())
+    } else {
+        None
+    };
 
+    let multiple_sources = sources.len() > 1;
     for source_str in &sources {
         let source = std::path::PathBuf::from(source_str);
-        let dest = std::path::PathBuf::from(&destination);
-
-
         let is_remote_source = is_remote_path(source_str);
-        let is_remote_dest = is_remote_path(&destination);
         let is_daemon_source = is_daemon_path(source_str);
         let is_daemon_dest = is_daemon_path(&destination);
 
+
+        let top_dir = top_dir(source_str, &destination, multiple_sources);
+        let destination = match top_dir {
+            Some(ref name) if !is_daemon_dest => nested_destination(&destination, name),
+            _ => destination.clone(),
+        };
+        let dest = std::path::PathBuf::from(&destination);
+        let is_remote_dest = is_remote_path(&destination);
+
+        if options.export_changes.is_some() && (is_remote_source || is_remote_dest || is_daemon_source || is_daemon_dest) {
+            verbose.print_error("--export-changes only works for local transfers");
+            continue;
+        }
+        if options.copy_as.is_some() && (is_daemon_source || is_daemon_dest) {
+            verbose.print_error("--copy-as only works for local and SSH transfers");
+            continue;
+        }
+
         if is_daemon_source || is_daemon_dest {
+            let files_from = match options.files_from {
+                Some(ref spec) if remote_files_from(spec).is_some() => {
+                    verbose.print_error(format!(
+                        "--files-from={} names a remote list, which only works for SSH transfers",
+                        spec.display()
+                    ));
+                    continue;
+                }
+                Some(ref spec) => Some(filesystem::read_files_from(spec, options.from0)?),
+                None => None,
+            };
+            let filter = filter::FilterEngine::from_options(&options)?;
 
             if is_daemon_source {
 
                 match DaemonClient::parse_daemon_url(source_str) {
-                    Ok((host, port, module, remote_path)) => {
-                        verbose.print_basic(&format!("Downloading from rsync daemon: {}:{}/{}", host, port, module));
-                        let client = DaemonClient::new(host, port);
+                    Ok((user, host, port, module, remote_path)) => {
+                        verbose.print_basic(format!("Downloading from rsync daemon: {}:{}/{}", host, port, module));
+                        let client = DaemonClient::new(host, port)
+                            .compress(daemon_compress)
+                            .socket_options(Some(options.socket_options()))
+                            .checksum_seed(options.checksum_seed)
+                            .user(user)
+                            .use_credential_manager(options.use_credential_manager)
+                            .prompt(!options.no_prompt)
+                            .password_file(options.password_file.clone())
+                            .files_from(files_from)
+                            .filter(filter);
                         match client.download(&module, &remote_path, &dest).await {
                             Ok(stats) => {
-                                verbose.print_basic(&format!("Download completed: {} files", stats.scanned_files));
+                                output::json::stats(&stats);
+                                if options.stats {
+                                    stats.display(options.human_readable, &verbose);
+                                }
+                                verbose.print_basic(format!("Download completed: {} files", stats.scanned_files));
                             }
                             Err(e) => {
-                                verbose.print_error(&format!("downloading from daemon: {}", e));
+                                verbose.print_error(format!("downloading from daemon: {}", e));
                             }
                         }
                     }
                     Err(e) => {
-                        verbose.print_error(&format!("parsing daemon URL: {}", e));
+                        verbose.print_error(format!("parsing daemon URL: {}", e));
                     }
                 }
             } else {
 
                 match DaemonClient::parse_daemon_url(&destination) {
-                    Ok((host, port, module, remote_path)) => {
-                        verbose.print_basic(&format!("Uploading to rsync daemon: {}:{}/{}", host, port, module));
-                        let client = DaemonClient::new(host, port);
+                    Ok((user, host, port, module, remote_path)) => {
+                        verbose.print_basic(format!("Uploading to rsync daemon: {}:{}/{}", host, port, module));
+                        let client = DaemonClient::new(host, port)
+                            .compress(daemon_compress)
+                            .socket_options(Some(options.socket_options()))
+                            .checksum_seed(options.checksum_seed)
+                            .progress(options.progress && !options.quiet)
+                            .user(user)
+                            .use_credential_manager(options.use_credential_manager)
+                            .prompt(!options.no_prompt)
+                            .password_file(options.password_file.clone())
+                            .files_from(files_from)
+                            .filter(filter)
+                            .exclude_if_present(&options.exclude_if_present)
+                            .top_dir(top_dir);
                         match client.upload(&module, &source, &remote_path).await {
                             Ok(stats) => {
-                                verbose.print_basic(&format!("Upload completed: {} files, {} bytes",
+                                output::json::stats(&stats);
+                                if options.stats {
+                                    stats.display(options.human_readable, &verbose);
+                                }
+                                verbose.print_basic(format!("Upload completed: {} files, {} bytes",
                                     stats.transferred_files, stats.transferred_bytes));
                             }
                             Err(e) => {
-                                verbose.print_error(&format!("uploading to daemon: {}", e));
+                                verbose.print_error(format!("uploading to daemon: {}", e));
                             }
                         }
                     }
                     Err(e) => {
-                        verbose.print_error(&format!("parsing daemon URL: {}", e));
+                        verbose.print_error(format!("parsing daemon URL: {}", e));
                     }
                 }
             }
@@ -127,23 +262,25 @@ async fn main() -> Result<()> {
                 } else {
                     user
                 };
-                verbose.print_basic(&format!("Connecting to {}@{}...", username, host));
+                verbose.print_basic(format!("Connecting to {}@{}...", username, host));
 
 
                 let _auth_method = AuthMethod::Agent;
 
                 let remote_transport = RemoteTransport::new(options.clone());
-                let result = if is_remote_source {
-                    remote_transport.sync(source_str, &destination)
-                } else {
-                    remote_transport.sync(&sources[0], &destination)
-                };
+                let remote_source = if is_remote_source { source_str } else { &sources[0] };
+                let result = remote_transport.run(remote_source.clone(), destination.clone()).await;
                 match result {
-                    Ok(_) => {
-                        verbose.print_basic(&format!("\nRemote sync for {} completed successfully!", source.display()));
+                    Ok(stats) => {
+                        output::json::stats(&stats);
+                        verbose.print_basic(format!("\nRemote sync for {} completed successfully!", source.display()));
                     }
                     Err(e) => {
-                        verbose.print_error(&format!("in remote sync for {}: {}", source.display(), e));
+                        verbose.print_error(format!("in remote sync for {}: {}", source.display(), e));
+                        if e.is_retryable() {
+                            let hint = if options.partial { "" } else { " (add --partial to resume interrupted files)" };
+                            verbose.print_warning(format!("The connection was lost; run the command again to retry{}", hint));
+                        }
                     }
                 }
             } else {
@@ -152,17 +289,30 @@ async fn main() -> Result<()> {
         } else {
             match local_transport.sync(&source, &dest) {
                 Ok(stats) => {
+                    output::json::stats(&stats);
                     if options.stats {
                         stats.display(options.human_readable, &verbose);
                     }
-                    verbose.print_basic(&format!("\nSync for {} completed successfully!", source.display()));
+                    verbose.print_basic(format!("\nSync for {} completed successfully!", source.display()));
                 }
                 Err(e) => {
-                    verbose.print_error(&format!("syncing {}: {}", source.display(), e));
+                    verbose.print_error(format!("syncing {}: {}", source.display(), e));
                 }
             }
         }
     }
 
+
+    if let Some(ref manifest_path) = options.manifest {
+        if is_remote_path(&destination) || is_daemon_path(&destination) {
+            verbose.print_error("--manifest needs a local destination");
+        } else if !options.dry_run {
+            let algorithm = options.checksum_choice.unwrap_or_default();
+            let manifest = filesystem::Manifest::build(std::path::Path::new(&destination), algorithm, Some(manifest_path))?;
+            manifest.save(manifest_path)?;
+            verbose.print_basic(format!("Wrote manifest of {} files to {}", manifest.files.len(), manifest_path.display()));
+        }
+    }
+
     Ok(())
 }
@@ -1,5 +1,7 @@
 use std::path::PathBuf;
 use crate::output::VerboseOutput;
+use crate::schedule::Schedule;
+use crate::transport::{SocketOptions, SshAlgorithms, StrictHostKeyChecking, DEFAULT_NET_BUFFER_SIZE};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CompressionAlgorithm {
@@ -14,20 +16,92 @@ impl Default for CompressionAlgorithm {
     }
 }
 
+impl CompressionAlgorithm {
+    pub fn name(&self) -> &'static str {
+        match self {
+            CompressionAlgorithm::Zstd => "zstd",
+            CompressionAlgorithm::Lz4 => "lz4",
+            CompressionAlgorithm::Zlib => "zlib",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "zstd" => Some(CompressionAlgorithm::Zstd),
+            "lz4" => Some(CompressionAlgorithm::Lz4),
+            "zlib" => Some(CompressionAlgorithm::Zlib),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ChecksumAlgorithm {
     Md4,
     Md5,
     Blake2,
+    Xxh64,
+    Xxh3,
     Xxh128,
 }
 
+impl ChecksumAlgorithm {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChecksumAlgorithm::Md4 => "md4",
+            ChecksumAlgorithm::Md5 => "md5",
+            ChecksumAlgorithm::Blake2 => "blake2",
+            ChecksumAlgorithm::Xxh64 => "xxh64",
+            ChecksumAlgorithm::Xxh3 => "xxh3",
+            ChecksumAlgorithm::Xxh128 => "xxh128",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "md4" => Some(ChecksumAlgorithm::Md4),
+            "md5" => Some(ChecksumAlgorithm::Md5),
+            "blake2" => Some(ChecksumAlgorithm::Blake2),
+            "xxh64" | "xxhash" => Some(ChecksumAlgorithm::Xxh64),
+            "xxh3" => Some(ChecksumAlgorithm::Xxh3),
+            "xxh128" => Some(ChecksumAlgorithm::Xxh128),
+            _ => None,
+        }
+    }
+}
+
 impl Default for ChecksumAlgorithm {
     fn default() -> Self {
         ChecksumAlgorithm::Md5
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BasisMode {
+    Compare,
+    Copy,
+    Link,
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FilterRule {
+    Exclude(String),
+    Include(String),
+    Rule(String),
+    ExcludeFrom(PathBuf),
+    IncludeFrom(PathBuf),
+}
+
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PrescanMode {
+    Skip,
+    #[default]
+    Estimate,
+    Accurate,
+}
+
 
 #[derive(Debug, Clone)]
 pub struct Options {
@@ -35,27 +109,45 @@ pub struct Options {
     pub verbose: u8,
     pub quiet: bool,
     pub checksum: bool,
+    pub cache_file: Option<PathBuf>,
     pub archive: bool,
     pub recursive: bool,
+    pub dirs: bool,
+    pub inc_recursive: bool,
     pub relative: bool,
     pub update: bool,
     pub links: bool,
+    pub times: bool,
+    pub perms: bool,
+    pub owner: bool,
+    pub group: bool,
+    pub devices: bool,
     pub copy_links: bool,
     pub hard_links: bool,
+    pub acls: bool,
+    pub ads: bool,
 
 
     pub compress: bool,
     pub compress_choice: Option<CompressionAlgorithm>,
     pub whole_file: bool,
     pub inplace: bool,
+    pub sparse: bool,
+    pub preallocate: bool,
+    pub append: bool,
+    pub append_verify: bool,
     pub partial: bool,
     pub partial_dir: Option<PathBuf>,
     pub bwlimit: Option<u64>,
+    pub parallel: usize,
 
 
     pub backup: bool,
     pub backup_dir: Option<PathBuf>,
     pub suffix: String,
+    pub compare_dest: Vec<PathBuf>,
+    pub copy_dest: Vec<PathBuf>,
+    pub link_dest: Vec<PathBuf>,
 
 
     pub delete: bool,
@@ -66,34 +158,63 @@ pub struct Options {
     pub remove_source_files: bool,
 
 
-    pub exclude: Vec<String>,
-    pub include: Vec<String>,
-    pub exclude_from: Vec<PathBuf>,
-    pub include_from: Vec<PathBuf>,
+    pub filter_rules: Vec<FilterRule>,
+    pub exclude_if_present: Vec<String>,
+    pub protect: Vec<String>,
+    pub explain: Vec<PathBuf>,
     pub files_from: Option<PathBuf>,
+    pub from0: bool,
 
 
     pub progress: bool,
+    pub prescan: PrescanMode,
     pub itemize_changes: bool,
     pub stats: bool,
+    pub info_skip: u8,
+    pub json: bool,
     pub human_readable: bool,
     pub log_file: Option<PathBuf>,
 
 
     pub rsh: Option<String>,
     pub rsync_path: Option<String>,
+    pub remote_options: Vec<String>,
+    pub ssh_algorithms: SshAlgorithms,
+    pub blocking_io: Option<bool>,
+    pub password_prompts: Option<u32>,
+    pub no_prompt: bool,
+    pub strict_host_key_checking: Option<StrictHostKeyChecking>,
+    pub use_credential_manager: bool,
+    pub copy_as: Option<String>,
+    pub elevate: bool,
+    pub lock_file: Option<PathBuf>,
+    pub lock_wait: Option<u64>,
+    pub schedule: Option<Schedule>,
+    pub unschedule: bool,
+    pub sockopts: Option<SocketOptions>,
+    pub net_buffer_size: Option<usize>,
+    pub checksum_seed: Option<i32>,
+
+
+    pub server: bool,
+    pub sender: bool,
 
 
     pub daemon: bool,
     pub address: Option<String>,
     pub port: Option<u16>,
     pub config: Option<PathBuf>,
+    pub dparam: Vec<String>,
     pub password_file: Option<PathBuf>,
 
 
     pub dry_run: bool,
+    pub export_changes: Option<PathBuf>,
+    pub manifest: Option<PathBuf>,
+    pub verify_manifest: Option<PathBuf>,
     pub list_only: bool,
     pub size_only: bool,
+    pub ignore_times: bool,
     pub timeout: Option<u64>,
 
 
@@ -107,27 +228,45 @@ impl Default for Options {
             verbose: 0,
             quiet: false,
             checksum: false,
+            cache_file: None,
             archive: false,
             recursive: false,
+            dirs: false,
+            inc_recursive: true,
             relative: false,
             update: false,
             links: false,
+            times: false,
+            perms: false,
+            owner: false,
+            group: false,
+            devices: false,
             copy_links: false,
             hard_links: false,
+            acls: false,
+            ads: cfg!(windows),
 
 
             compress: false,
             compress_choice: None,
             whole_file: false,
             inplace: false,
+            sparse: false,
+            preallocate: false,
+            append: false,
+            append_verify: false,
             partial: false,
             partial_dir: None,
             bwlimit: None,
+            parallel: 1,
 
 
             backup: false,
             backup_dir: None,
             suffix: "~".to_string(),
+            compare_dest: Vec::new(),
+            copy_dest: Vec::new(),
+            link_dest: Vec::new(),
 
 
             delete: false,
@@ -138,34 +277,63 @@ impl Default for Options {
             remove_source_files: false,
 
 
-            exclude: Vec::new(),
-            include: Vec::new(),
-            exclude_from: Vec::new(),
-            include_from: Vec::new(),
+            filter_rules: Vec::new(),
+            exclude_if_present: Vec::new(),
+            protect: Vec::new(),
+            explain: Vec::new(),
             files_from: None,
+            from0: false,
 
 
             progress: false,
+            prescan: PrescanMode::Estimate,
             itemize_changes: false,
             stats: false,
+            info_skip: 0,
+            json: false,
             human_readable: false,
             log_file: None,
 
 
             rsh: None,
             rsync_path: None,
+            remote_options: Vec::new(),
+            ssh_algorithms: SshAlgorithms::default(),
+            blocking_io: None,
+            password_prompts: None,
+            no_prompt: false,
+            strict_host_key_checking: None,
+            use_credential_manager: false,
+            copy_as: None,
+            elevate: false,
+            lock_file: None,
+            lock_wait: None,
+            schedule: None,
+            unschedule: false,
+            sockopts: None,
+            net_buffer_size: None,
+            checksum_seed: None,
+
+
+            server: false,
+            sender: false,
 
 
             daemon: false,
             address: None,
             port: Some(873),
             config: None,
+            dparam: Vec::new(),
             password_file: None,
 
 
             dry_run: false,
+            export_changes: None,
+            manifest: None,
+            verify_manifest: None,
             list_only: false,
             size_only: false,
+            ignore_times: false,
             timeout: None,
 
 
@@ -177,13 +345,34 @@ impl Default for Options {
 impl Options {
 
     pub fn verbose_output(&self) -> VerboseOutput {
-        VerboseOutput::new(self.verbose, self.quiet)
+        VerboseOutput::new(self.verbose, self.quiet).skip_info(self.info_skip)
+    }
+
+    pub fn socket_options(&self) -> SocketOptions {
+        let buffer_size = self.net_buffer_size.unwrap_or(DEFAULT_NET_BUFFER_SIZE);
+        self.sockopts.unwrap_or_default().or(SocketOptions::tuned(buffer_size))
+    }
+
+    pub fn basis_dirs(&self) -> Option<(BasisMode, &[PathBuf])> {
+        [
+            (BasisMode::Compare, &self.compare_dest),
+            (BasisMode::Copy, &self.copy_dest),
+            (BasisMode::Link, &self.link_dest),
+        ]
+        .into_iter()
+        .find(|(_, dirs)| !dirs.is_empty())
+        .map(|(mode, dirs)| (mode, dirs.as_slice()))
     }
 
     pub fn apply_archive_mode(&mut self) {
         if self.archive {
             self.recursive = true;
             self.links = true;
+            self.times = true;
+            self.perms = true;
+            self.owner = true;
+            self.group = true;
+            self.devices = true;
         }
     }
 
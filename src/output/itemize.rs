@@ -1,3 +1,4 @@
+use crate::protocol::iflags::{ITEM_IS_NEW, ITEM_LOCAL_CHANGE, ITEM_REPORT_ACL, ITEM_REPORT_CHANGE, ITEM_REPORT_SIZE, ITEM_REPORT_TIME, ITEM_TRANSFER};
 use std::path::Path;
 
 
@@ -44,6 +45,10 @@ pub struct ItemizeChange {
 
     pub time_diff: bool,
 
+    pub acl_diff: bool,
+
+    pub is_new: bool,
+
     pub path: String,
 }
 
@@ -56,6 +61,8 @@ impl ItemizeChange {
             checksum_diff: false,
             size_diff: true,
             time_diff: true,
+            acl_diff: false,
+            is_new: true,
             path: path.to_string_lossy().to_string(),
         }
     }
@@ -68,6 +75,8 @@ impl ItemizeChange {
             checksum_diff: size_diff || time_diff,
             size_diff,
             time_diff,
+            acl_diff: false,
+            is_new: false,
             path: path.to_string_lossy().to_string(),
         }
     }
@@ -80,6 +89,22 @@ impl ItemizeChange {
             checksum_diff: false,
             size_diff: false,
             time_diff: false,
+            acl_diff: false,
+            is_new: true,
+            path: path.to_string_lossy().to_string(),
+        }
+    }
+
+
+    pub fn update_acl(path: &Path, is_dir: bool) -> Self {
+        Self {
+            update_type: ChangeType::NoUpdate,
+            file_type: if is_dir { FileType::Directory } else { FileType::File },
+            checksum_diff: false,
+            size_diff: false,
+            time_diff: false,
+            acl_diff: true,
+            is_new: false,
             path: path.to_string_lossy().to_string(),
         }
     }
@@ -92,11 +117,34 @@ impl ItemizeChange {
             checksum_diff: false,
             size_diff: false,
             time_diff: false,
+            acl_diff: false,
+            is_new: false,
             path: path.to_string_lossy().to_string(),
         }
     }
 
 
+    pub fn from_iflags(path: &Path, is_dir: bool, iflags: u16, sending: bool) -> Self {
+        let update_type = if iflags & ITEM_TRANSFER != 0 {
+            if sending { ChangeType::Send } else { ChangeType::Receive }
+        } else if iflags & ITEM_LOCAL_CHANGE != 0 {
+            ChangeType::LocalChange
+        } else {
+            ChangeType::NoUpdate
+        };
+
+        Self {
+            update_type,
+            file_type: if is_dir { FileType::Directory } else { FileType::File },
+            checksum_diff: iflags & ITEM_REPORT_CHANGE != 0,
+            size_diff: iflags & ITEM_REPORT_SIZE != 0,
+            time_diff: iflags & ITEM_REPORT_TIME != 0,
+            acl_diff: iflags & ITEM_REPORT_ACL != 0,
+            is_new: iflags & ITEM_IS_NEW != 0,
+            path: path.to_string_lossy().to_string(),
+        }
+    }
+
 
     pub fn format(&self) -> String {
         let update_char = match self.update_type {
@@ -115,6 +163,10 @@ impl ItemizeChange {
             FileType::Special => 'S',
         };
 
+        if self.is_new {
+            return format!("{}{}+++++++++ {}", update_char, file_type_char, self.path);
+        }
+
         let checksum_char = if self.checksum_diff { 'c' } else { '.' };
         let size_char = if self.size_diff { 's' } else { '.' };
         let time_char = if self.time_diff { 't' } else { '.' };
@@ -123,9 +175,12 @@ impl ItemizeChange {
         let perms_char = '.';
         let owner_char = '.';
         let group_char = '.';
+        let atime_char = '.';
+        let acl_char = if self.acl_diff { 'a' } else { '.' };
+        let xattr_char = '.';
 
         format!(
-            "{}{}{}{}{}{}{}{} {}",
+            "{}{}{}{}{}{}{}{}{}{}{} {}",
             update_char,
             file_type_char,
             checksum_char,
@@ -134,6 +189,9 @@ impl ItemizeChange {
             perms_char,
             owner_char,
             group_char,
+            atime_char,
+            acl_char,
+            xattr_char,
             self.path
         )
     }
@@ -162,6 +220,26 @@ mod tests {
         assert!(formatted.contains("test/dir"));
     }
 
+    #[test]
+    fn test_from_iflags_format() {
+        let path = PathBuf::from("data.bin");
+        assert_eq!(
+            ItemizeChange::from_iflags(&path, false, ITEM_IS_NEW | ITEM_TRANSFER, false).format(),
+            ">f+++++++++ data.bin"
+        );
+        assert_eq!(
+            ItemizeChange::from_iflags(&path, false, ITEM_REPORT_SIZE | ITEM_REPORT_TIME | ITEM_TRANSFER, true).format(),
+            "<f.st...... data.bin"
+        );
+        assert_eq!(
+            ItemizeChange::from_iflags(&path, true, ITEM_IS_NEW | ITEM_LOCAL_CHANGE, false).format(),
+            "cd+++++++++ data.bin"
+        );
+        assert_eq!(ItemizeChange::from_iflags(&path, false, 0, false).format(), ".f......... data.bin");
+        assert_eq!(ItemizeChange::from_iflags(&path, true, ITEM_REPORT_ACL, false).format(), ".d.......a. data.bin");
+        assert_eq!(ItemizeChange::update_acl(&path, false).format(), ".f.......a. data.bin");
+    }
+
     #[test]
     fn test_delete_format() {
         let change = ItemizeChange::delete_file(&PathBuf::from("test/old.txt"));
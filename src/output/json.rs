@@ -0,0 +1,80 @@
+use serde::Serialize;
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use crate::transport::SyncStats;
+
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    Transferred { path: String, bytes: u64 },
+    Deleted { path: String, bytes: u64 },
+    Skipped { path: String, reason: &'a str },
+    Stats(&'a SyncStats),
+}
+
+impl Event<'_> {
+    pub fn to_line(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+
+pub fn emit(event: &Event) {
+    if is_enabled() {
+        let mut stdout = std::io::stdout().lock();
+        let _ = writeln!(stdout, "{}", event.to_line());
+    }
+}
+
+
+pub fn transferred(path: &Path, bytes: u64) {
+    emit(&Event::Transferred { path: path.to_string_lossy().into_owned(), bytes });
+}
+
+
+pub fn deleted(path: &Path, bytes: u64) {
+    emit(&Event::Deleted { path: path.to_string_lossy().into_owned(), bytes });
+}
+
+
+pub fn skipped(path: &Path, reason: &str) {
+    emit(&Event::Skipped { path: path.to_string_lossy().into_owned(), reason });
+}
+
+
+pub fn stats(stats: &SyncStats) {
+    emit(&Event::Stats(stats));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_lines() {
+        let event = Event::Transferred { path: "dir/\"quoted\".txt".to_string(), bytes: 42 };
+        assert_eq!(event.to_line(), r#"{"event":"transferred","path":"dir/\"quoted\".txt","bytes":42}"#);
+
+        let event = Event::Skipped { path: "a.txt".to_string(), reason: "uptodate" };
+        assert_eq!(event.to_line(), r#"{"event":"skipped","path":"a.txt","reason":"uptodate"}"#);
+
+        let stats = SyncStats { transferred_files: 3, deleted_files: 1, ..SyncStats::default() };
+        let line = Event::Stats(&stats).to_line();
+        assert!(line.starts_with(r#"{"event":"stats","scanned_files":0,"transferred_files":3,"deleted_files":1,"#));
+    }
+}
@@ -2,6 +2,7 @@ pub mod progress;
 pub mod itemize;
 pub mod verbose;
 pub mod logger;
+pub mod json;
 
 pub use progress::ProgressDisplay;
 pub use itemize::ItemizeChange;
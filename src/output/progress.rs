@@ -1,10 +1,25 @@
-use indicatif::{ProgressBar, ProgressStyle};
+use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+
+struct FileProgress {
+    name: String,
+    size: u64,
+    done: u64,
+    started: Instant,
+}
 
 
 pub struct ProgressDisplay {
     bar: ProgressBar,
     #[allow(dead_code)]
     total_bytes: u64,
+    wire_bytes: AtomicU64,
+    started: Instant,
+    current: Mutex<Option<FileProgress>>,
+    finished_files: AtomicUsize,
 }
 
 impl ProgressDisplay {
@@ -24,6 +39,52 @@ impl ProgressDisplay {
         Self {
             bar,
             total_bytes,
+            wire_bytes: AtomicU64::new(0),
+            started: Instant::now(),
+            current: Mutex::new(None),
+            finished_files: AtomicUsize::new(0),
+        }
+    }
+
+
+    pub fn network() -> Self {
+        let bar = ProgressBar::new(0);
+
+        bar.set_style(
+            ProgressStyle::default_bar()
+                .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({percent}%) {prefix} {msg}")
+                .expect("Invalid progress bar template")
+                .progress_chars("#>-")
+        );
+        bar.enable_steady_tick(Duration::from_millis(200));
+
+        Self {
+            bar,
+            total_bytes: 0,
+            wire_bytes: AtomicU64::new(0),
+            started: Instant::now(),
+            current: Mutex::new(None),
+            finished_files: AtomicUsize::new(0),
+        }
+    }
+
+
+    pub fn without_totals() -> Self {
+        let bar = ProgressBar::new_spinner();
+
+        bar.set_style(
+            ProgressStyle::default_spinner()
+                .template("{spinner:.green} [{elapsed_precise}] {bytes} ({bytes_per_sec}) {msg}")
+                .expect("Invalid progress bar template")
+        );
+
+        Self {
+            bar,
+            total_bytes: 0,
+            wire_bytes: AtomicU64::new(0),
+            started: Instant::now(),
+            current: Mutex::new(None),
+            finished_files: AtomicUsize::new(0),
         }
     }
 
@@ -34,6 +95,61 @@ impl ProgressDisplay {
     }
 
 
+    pub fn set_total(&self, total_bytes: u64) {
+        self.bar.set_length(total_bytes);
+    }
+
+
+    pub fn add_total(&self, total_bytes: u64) {
+        self.bar.inc_length(total_bytes);
+    }
+
+
+    pub fn start_file(&self, current_file: &str, size: u64) {
+        let file = FileProgress { name: current_file.to_string(), size, done: 0, started: Instant::now() };
+        self.bar.set_message(format!("{} {}", file.name, progress_line(0, size, Duration::ZERO)));
+        *self.current.lock().unwrap_or_else(|e| e.into_inner()) = Some(file);
+    }
+
+
+    pub fn advance(&self, file_bytes: u64, wire_bytes: u64) {
+        self.bar.inc(file_bytes);
+        let total = self.wire_bytes.fetch_add(wire_bytes, Ordering::Relaxed) + wire_bytes;
+        self.bar.set_prefix(format!("{}/s", HumanBytes(self.wire_rate(total))));
+
+        if let Some(ref mut file) = *self.current.lock().unwrap_or_else(|e| e.into_inner()) {
+            file.done += file_bytes;
+            self.bar.set_message(format!("{} {}", file.name, progress_line(file.done, file.size, file.started.elapsed())));
+        }
+    }
+
+
+    pub fn finish_file(&self) {
+        let Some(file) = self.current.lock().unwrap_or_else(|e| e.into_inner()).take() else {
+            return;
+        };
+        let finished = self.finished_files.fetch_add(1, Ordering::Relaxed) + 1;
+        self.bar.println(&file.name);
+        self.bar.println(format!("{} (xfr#{})", progress_line(file.done, file.done, file.started.elapsed()), finished));
+    }
+
+
+    #[allow(dead_code)]
+    pub fn wire_bytes(&self) -> u64 {
+        self.wire_bytes.load(Ordering::Relaxed)
+    }
+
+
+    fn wire_rate(&self, wire_bytes: u64) -> u64 {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        if elapsed > 0.0 {
+            (wire_bytes as f64 / elapsed) as u64
+        } else {
+            0
+        }
+    }
+
+
     pub fn finish(&self) {
         self.bar.finish_with_message("Transfer complete");
     }
@@ -45,6 +161,56 @@ impl ProgressDisplay {
     }
 }
 
+
+
+pub fn progress_line(done: u64, size: u64, elapsed: Duration) -> String {
+    let percent = (done.min(size) * 100).checked_div(size).unwrap_or(100);
+    let seconds = elapsed.as_secs_f64();
+    let rate = if seconds > 0.0 { done as f64 / seconds } else { 0.0 };
+    let shown = if done >= size || rate <= 0.0 {
+        elapsed.as_secs()
+    } else {
+        ((size - done) as f64 / rate) as u64
+    };
+    format!(
+        "{:>15} {:>3}% {} {:>4}:{:02}:{:02}",
+        group_digits(done),
+        percent,
+        format_rate(rate),
+        shown / 3600,
+        shown / 60 % 60,
+        shown % 60
+    )
+}
+
+
+fn format_rate(bytes_per_sec: f64) -> String {
+    let mut rate = bytes_per_sec / 1024.0;
+    let mut unit = "kB/s";
+    for next in ["MB/s", "GB/s"] {
+        if rate < 1024.0 {
+            break;
+        }
+        rate /= 1024.0;
+        unit = next;
+    }
+    format!("{:>7.2}{}", rate, unit)
+}
+
+
+fn group_digits(value: u64) -> String {
+    let digits = value.to_string();
+    let mut grouped = String::with_capacity(digits.len() + digits.len() / 3);
+    let offset = digits.len() % 3;
+    for (index, digit) in digits.chars().enumerate() {
+        if index > 0 && index % 3 == offset {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
+
 impl Drop for ProgressDisplay {
     fn drop(&mut self) {
         if !self.bar.is_finished() {
@@ -52,3 +218,55 @@ impl Drop for ProgressDisplay {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_network_progress_counts_wire_bytes() {
+        let progress = ProgressDisplay::network();
+        progress.hide();
+        progress.set_total(3000);
+        progress.start_file("big.bin", 3000);
+
+        progress.advance(2048, 4);
+        assert!(progress.bar.message().starts_with("big.bin           2,048  68%"));
+        progress.advance(952, 956);
+
+        assert_eq!(progress.bar.position(), 3000);
+        assert_eq!(progress.bar.length(), Some(3000));
+        assert_eq!(progress.wire_bytes(), 960);
+
+        progress.finish_file();
+        assert_eq!(progress.finished_files.load(Ordering::Relaxed), 1);
+        assert!(progress.current.lock().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_progress_without_totals() {
+        let progress = ProgressDisplay::without_totals();
+        progress.hide();
+        progress.start_file("log.txt", 100);
+        progress.advance(100, 100);
+        assert_eq!(progress.bar.length(), None);
+        assert_eq!(progress.bar.position(), 100);
+
+        let progress = ProgressDisplay::new(500, 2);
+        progress.hide();
+        assert_eq!(progress.bar.length(), Some(500));
+    }
+
+    #[test]
+    fn test_progress_line() {
+        assert_eq!(
+            progress_line(1_234_567, 3_631_079, Duration::from_secs(1)),
+            "      1,234,567  34%    1.18MB/s    0:00:01"
+        );
+        assert_eq!(
+            progress_line(32_768, 32_768, Duration::from_millis(500)),
+            "         32,768 100%   64.00kB/s    0:00:00"
+        );
+        assert_eq!(progress_line(0, 0, Duration::ZERO), "              0 100%    0.00kB/s    0:00:00");
+    }
+}
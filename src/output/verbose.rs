@@ -9,17 +9,26 @@ use std::path::Path;
 use crate::filesystem::FileInfo;
 
 
+#[derive(Debug, Clone, Copy)]
 pub struct VerboseOutput {
 
     level: u8,
 
     quiet: bool,
+
+    skip: u8,
 }
 
 impl VerboseOutput {
 
     pub fn new(level: u8, quiet: bool) -> Self {
-        VerboseOutput { level, quiet }
+        VerboseOutput { level, quiet, skip: 0 }
+    }
+
+
+    pub fn skip_info(mut self, skip: u8) -> Self {
+        self.skip = skip;
+        self
     }
 
 
@@ -104,9 +113,8 @@ impl VerboseOutput {
     }
 
 
-    #[allow(dead_code)]
-    pub fn print_skip(&self, path: &Path, reason: &str) {
-        if !self.quiet && self.level >= 2 {
+    pub fn print_skip(&self, path: &Path, reason: &str, info_level: u8) {
+        if !self.quiet && self.skip >= info_level {
             println!("skipping {} ({})", path.display(), reason);
         }
     }
@@ -232,6 +240,7 @@ impl Default for VerboseOutput {
         VerboseOutput {
             level: 0,
             quiet: false,
+            skip: 0,
         }
     }
 }
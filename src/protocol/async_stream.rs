@@ -1,6 +1,7 @@
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use crate::error::{Result, RsyncError};
+use crate::protocol::codec;
 use std::io::Cursor;
 
 
@@ -8,14 +9,30 @@ use std::io::Cursor;
 
 pub struct AsyncProtocolStream<S> {
     stream: S,
-    #[allow(dead_code)]
     protocol_version: i32,
+    checksum_seed: i32,
 }
 
 impl<S: AsyncRead + AsyncWrite + Unpin> AsyncProtocolStream<S> {
 
     pub fn new(stream: S, protocol_version: i32) -> Self {
-        Self { stream, protocol_version }
+        Self { stream, protocol_version, checksum_seed: 0 }
+    }
+
+    pub fn checksum_seed(&self) -> i32 {
+        self.checksum_seed
+    }
+
+    pub fn set_checksum_seed(&mut self, seed: i32) {
+        self.checksum_seed = seed;
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.stream
+    }
+
+    pub fn into_inner(self) -> S {
+        self.stream
     }
 
 
@@ -50,59 +67,74 @@ impl<S: AsyncRead + AsyncWrite + Unpin> AsyncProtocolStream<S> {
         Ok(self.stream.write_u8(val).await?)
     }
 
+    pub async fn read_u16(&mut self) -> Result<u16> {
+        Ok(self.stream.read_u16_le().await?)
+    }
 
+    pub async fn write_u16(&mut self, val: u16) -> Result<()> {
+        Ok(self.stream.write_u16_le(val).await?)
+    }
 
-    pub async fn read_varint(&mut self) -> Result<i64> {
-        let first = self.read_u8().await?;
 
-        match first {
-            0..=250 => Ok(first as i64),
-            251 => {
-                let mut buf = [0u8; 2];
-                self.stream.read_exact(&mut buf).await?;
-                let mut cursor = Cursor::new(buf);
-                Ok(ReadBytesExt::read_i16::<LittleEndian>(&mut cursor)? as i64)
-            }
-            252 => {
-                let mut buf = [0u8; 4];
-                self.stream.read_exact(&mut buf).await?;
-                let mut cursor = Cursor::new(buf);
-                Ok(ReadBytesExt::read_i32::<LittleEndian>(&mut cursor)? as i64)
-            }
-            253 => {
-                let mut buf = [0u8; 8];
-                self.stream.read_exact(&mut buf).await?;
-                let mut cursor = Cursor::new(buf);
-                Ok(ReadBytesExt::read_i64::<LittleEndian>(&mut cursor)?)
-            }
-            254 => Ok(self.read_i8().await? as i64),
-            255 => Err(RsyncError::Other("Invalid varint tag 255".to_string())),
+
+    pub async fn read_varint(&mut self) -> Result<i32> {
+        let tag = self.read_u8().await?;
+        let mut extra = [0u8; 4];
+        let extra_len = codec::varint_extra(tag)?;
+        self.stream.read_exact(&mut extra[..extra_len]).await?;
+        Ok(codec::decode_varint(tag, &extra[..extra_len]))
+    }
+
+    pub async fn write_varint(&mut self, val: i32) -> Result<()> {
+        self.stream.write_all(codec::encode_varint(val).as_slice()).await?;
+        Ok(())
+    }
+
+    pub async fn read_varlong(&mut self, min_bytes: u8) -> Result<i64> {
+        let mut head = [0u8; 8];
+        let head = &mut head[..min_bytes as usize];
+        self.stream.read_exact(head).await?;
+
+        let mut extra = [0u8; 8];
+        let extra_len = codec::varlong_extra(head[0], min_bytes)?;
+        self.stream.read_exact(&mut extra[..extra_len]).await?;
+        Ok(codec::decode_varlong(head, &extra[..extra_len]))
+    }
+
+    pub async fn write_varlong(&mut self, val: i64, min_bytes: u8) -> Result<()> {
+        self.stream.write_all(codec::encode_varlong(val, min_bytes).as_slice()).await?;
+        Ok(())
+    }
+
+    pub async fn read_longint(&mut self) -> Result<i64> {
+        let num = self.read_i32().await?;
+        if num != -1 {
+            return Ok(num as i64);
         }
+        let mut buf = [0u8; 8];
+        self.stream.read_exact(&mut buf).await?;
+        Ok(i64::from_le_bytes(buf))
+    }
+
+    pub async fn write_longint(&mut self, val: i64) -> Result<()> {
+        self.stream.write_all(codec::encode_longint(val).as_slice()).await?;
+        Ok(())
     }
 
-    pub async fn write_varint(&mut self, val: i64) -> Result<()> {
-        if val >= 0 && val <= 250 {
-            self.write_u8(val as u8).await?;
-        } else if val >= -128 && val <= -1 {
-            self.write_u8(254).await?;
-            self.write_i8(val as i8).await?;
-        } else if val >= 251 && val <= 32767 {
-            self.write_u8(251).await?;
-            let mut buf = Vec::new();
-            WriteBytesExt::write_i16::<LittleEndian>(&mut buf, val as i16)?;
-            self.stream.write_all(&buf).await?;
-        } else if (val >= 32768 && val <= i32::MAX as i64) || (val >= i32::MIN as i64 && val <= -129) {
-            self.write_u8(252).await?;
-            let mut buf = Vec::new();
-            WriteBytesExt::write_i32::<LittleEndian>(&mut buf, val as i32)?;
-            self.stream.write_all(&buf).await?;
+    pub async fn read_varlong30(&mut self, min_bytes: u8) -> Result<i64> {
+        if self.protocol_version >= 30 {
+            self.read_varlong(min_bytes).await
         } else {
-            self.write_u8(253).await?;
-            let mut buf = Vec::new();
-            WriteBytesExt::write_i64::<LittleEndian>(&mut buf, val)?;
-            self.stream.write_all(&buf).await?;
+            self.read_longint().await
+        }
+    }
+
+    pub async fn write_varlong30(&mut self, val: i64, min_bytes: u8) -> Result<()> {
+        if self.protocol_version >= 30 {
+            self.write_varlong(val, min_bytes).await
+        } else {
+            self.write_longint(val).await
         }
-        Ok(())
     }
 
 
@@ -148,3 +180,53 @@ impl<S: AsyncRead + AsyncWrite + Unpin> AsyncProtocolStream<S> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::ProtocolStream;
+
+    #[tokio::test]
+    async fn test_sync_and_async_share_wire_format() -> Result<()> {
+        let mut buffer = std::io::Cursor::new(Vec::new());
+        {
+            let mut stream = ProtocolStream::new(&mut buffer, 31);
+            stream.write_varint(16384)?;
+            stream.write_varint(-1)?;
+            stream.write_varlong30(4294967296, 3)?;
+            stream.write_string("module")?;
+        }
+
+        let bytes = buffer.into_inner();
+        let mut stream = AsyncProtocolStream::new(std::io::Cursor::new(bytes.clone()), 31);
+        assert_eq!(stream.read_varint().await?, 16384);
+        assert_eq!(stream.read_varint().await?, -1);
+        assert_eq!(stream.read_varlong30(3).await?, 4294967296);
+        assert_eq!(stream.read_string(64).await?, "module");
+
+        let mut stream = AsyncProtocolStream::new(std::io::Cursor::new(Vec::new()), 31);
+        stream.write_varint(16384).await?;
+        stream.write_varint(-1).await?;
+        stream.write_varlong30(4294967296, 3).await?;
+        stream.write_string("module").await?;
+        assert_eq!(stream.stream.into_inner(), bytes);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_async_longint_before_protocol_30() -> Result<()> {
+        let mut stream = AsyncProtocolStream::new(std::io::Cursor::new(Vec::new()), 29);
+        stream.write_varlong30(4294967296, 3).await?;
+        stream.write_varlong30(7, 3).await?;
+
+        let bytes = stream.stream.into_inner();
+        assert_eq!(bytes.len(), 16);
+
+        let mut stream = AsyncProtocolStream::new(std::io::Cursor::new(bytes), 29);
+        assert_eq!(stream.read_varlong30(3).await?, 4294967296);
+        assert_eq!(stream.read_varlong30(3).await?, 7);
+
+        Ok(())
+    }
+}
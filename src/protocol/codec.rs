@@ -0,0 +1,190 @@
+use crate::error::{Result, RsyncError};
+
+
+const INT_BYTE_EXTRA: [u8; 64] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    2, 2, 2, 2, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 5, 6,
+];
+
+
+#[derive(Debug, Clone, Copy)]
+pub struct Encoded {
+    buf: [u8; 16],
+    len: usize,
+}
+
+impl Encoded {
+    pub fn as_slice(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+
+pub fn varint_extra(tag: u8) -> Result<usize> {
+    let extra = INT_BYTE_EXTRA[(tag / 4) as usize] as usize;
+    if extra >= 5 {
+        return Err(RsyncError::Other("Overflow in read_varint".to_string()));
+    }
+    Ok(extra)
+}
+
+
+pub fn decode_varint(tag: u8, extra: &[u8]) -> i32 {
+    let mut buf = [0u8; 5];
+
+    if extra.is_empty() {
+        buf[0] = tag;
+    } else {
+        let bit = 1u8 << (8 - extra.len());
+        buf[..extra.len()].copy_from_slice(extra);
+        buf[extra.len()] = tag & (bit - 1);
+    }
+
+    i32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]])
+}
+
+
+pub fn encode_varint(val: i32) -> Encoded {
+    let mut buf = [0u8; 16];
+    buf[1..5].copy_from_slice(&val.to_le_bytes());
+
+    let mut cnt = 4;
+    while cnt > 1 && buf[cnt] == 0 {
+        cnt -= 1;
+    }
+
+    let bit = 1u8 << (8 - cnt);
+    if buf[cnt] >= bit {
+        cnt += 1;
+        buf[0] = !(bit - 1);
+    } else if cnt > 1 {
+        buf[0] = buf[cnt] | !(bit * 2 - 1);
+    } else {
+        buf[0] = buf[cnt];
+    }
+
+    Encoded { buf, len: cnt }
+}
+
+
+pub fn varlong_extra(tag: u8, min_bytes: u8) -> Result<usize> {
+    let extra = INT_BYTE_EXTRA[(tag / 4) as usize] as usize;
+    if min_bytes as usize + extra > 9 {
+        return Err(RsyncError::Other("Overflow in read_varlong".to_string()));
+    }
+    Ok(extra)
+}
+
+
+pub fn decode_varlong(head: &[u8], extra: &[u8]) -> i64 {
+    let min_bytes = head.len();
+    let mut buf = [0u8; 9];
+    buf[..min_bytes - 1].copy_from_slice(&head[1..]);
+    let tag = head[0];
+
+    if extra.is_empty() {
+        buf[min_bytes - 1] = tag;
+    } else {
+        let bit = 1u8 << (8 - extra.len());
+        buf[min_bytes - 1..min_bytes - 1 + extra.len()].copy_from_slice(extra);
+        buf[min_bytes + extra.len() - 1] = tag & (bit - 1);
+    }
+
+    let mut val = [0u8; 8];
+    val.copy_from_slice(&buf[..8]);
+    i64::from_le_bytes(val)
+}
+
+
+pub fn encode_varlong(val: i64, min_bytes: u8) -> Encoded {
+    let min_bytes = min_bytes as usize;
+    let mut buf = [0u8; 16];
+    buf[1..9].copy_from_slice(&val.to_le_bytes());
+
+    let mut cnt = 8;
+    while cnt > min_bytes && buf[cnt] == 0 {
+        cnt -= 1;
+    }
+
+    let bit = 1u8 << (7 + min_bytes - cnt);
+    if buf[cnt] >= bit {
+        cnt += 1;
+        buf[0] = !(bit - 1);
+    } else if cnt > min_bytes {
+        buf[0] = buf[cnt] | !(bit * 2 - 1);
+    } else {
+        buf[0] = buf[cnt];
+    }
+
+    Encoded { buf, len: cnt }
+}
+
+
+pub fn encode_longint(val: i64) -> Encoded {
+    let mut buf = [0u8; 16];
+
+    if (0..=i32::MAX as i64).contains(&val) {
+        buf[..4].copy_from_slice(&(val as i32).to_le_bytes());
+        return Encoded { buf, len: 4 };
+    }
+
+    buf[..4].copy_from_slice(&(-1i32).to_le_bytes());
+    buf[4..12].copy_from_slice(&val.to_le_bytes());
+    Encoded { buf, len: 12 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_varint_bytes(bytes: &[u8]) -> Result<i32> {
+        let extra = varint_extra(bytes[0])?;
+        assert_eq!(bytes.len(), 1 + extra);
+        Ok(decode_varint(bytes[0], &bytes[1..]))
+    }
+
+    fn decode_varlong_bytes(bytes: &[u8], min_bytes: u8) -> Result<i64> {
+        let min_bytes = min_bytes as usize;
+        let extra = varlong_extra(bytes[0], min_bytes as u8)?;
+        assert_eq!(bytes.len(), min_bytes + extra);
+        Ok(decode_varlong(&bytes[..min_bytes], &bytes[min_bytes..]))
+    }
+
+    #[test]
+    fn test_varint_codec_round_trip() -> Result<()> {
+        for val in [0, 1, 127, 128, 16383, 16384, 1000000, i32::MAX, -1, i32::MIN] {
+            let encoded = encode_varint(val);
+            assert_eq!(decode_varint_bytes(encoded.as_slice())?, val);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_varlong_codec_round_trip() -> Result<()> {
+        for min_bytes in 3..=8 {
+            for val in [0, 1, 0x7fffff, 0x800000, 1700000000, 4294967296, i64::MAX, -1, i64::MIN] {
+                let encoded = encode_varlong(val, min_bytes);
+                assert_eq!(decode_varlong_bytes(encoded.as_slice(), min_bytes)?, val);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_longint_encoding() {
+        assert_eq!(encode_longint(5).as_slice(), &[5, 0, 0, 0]);
+        assert_eq!(
+            encode_longint(4294967296).as_slice(),
+            &[0xff, 0xff, 0xff, 0xff, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00]
+        );
+    }
+
+    #[test]
+    fn test_overflow_tags_rejected() {
+        assert!(varint_extra(0xf8).is_err());
+        assert!(varlong_extra(0xfc, 4).is_err());
+        assert!(varlong_extra(0xfc, 3).is_ok());
+    }
+}
@@ -1,11 +1,23 @@
 use crate::filesystem::{FileInfo, FileType};
+use crate::filesystem::file_info::FileId;
+use crate::filesystem::hard_links::{is_hard_linked, link_leaders};
+use crate::filesystem::path_utils::to_unix_separators;
 use crate::protocol::stream::ProtocolStream;
-use crate::error::Result;
+use crate::error::{Result, RsyncError};
 use std::io::{Read, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::time::UNIX_EPOCH;
 
 
+pub const NDX_FLIST_EOF: i32 = -2;
+pub const NDX_FLIST_OFFSET: i32 = -101;
+
+
+const XMIT_TYPE_MASK: i8 = 0x0f;
+const XMIT_HLINKED: i8 = 1 << 4;
+const XMIT_HLINK_FIRST: i8 = 1 << 5;
+
+
 pub struct FileList;
 
 impl FileList {
@@ -16,22 +28,23 @@ impl FileList {
 
     pub fn encode<S: Read + Write>(stream: &mut ProtocolStream<S>, files: &[FileInfo]) -> Result<()> {
 
-        stream.write_varint(files.len() as i64)?;
+        stream.write_varint(files.len() as i32)?;
 
 
-        for file in files {
+        let leaders = link_leaders(files);
+        for (file, leader) in files.iter().zip(leaders) {
 
             let path_str = file.path.to_string_lossy();
             stream.write_string(&path_str)?;
 
 
-            stream.write_varint(file.size as i64)?;
+            stream.write_varlong30(file.size as i64, 3)?;
 
 
             let mtime_secs = file.mtime.duration_since(UNIX_EPOCH)
                 .unwrap_or_default()
                 .as_secs();
-            stream.write_varint(mtime_secs as i64)?;
+            stream.write_varlong30(mtime_secs as i64, 4)?;
 
 
             let file_type_code = match file.file_type {
@@ -39,7 +52,14 @@ impl FileList {
                 FileType::Directory => 1i8,
                 FileType::Symlink => 2i8,
             };
-            stream.write_i8(file_type_code)?;
+            match leader {
+                Some(leader) => {
+                    stream.write_i8(file_type_code | XMIT_HLINKED)?;
+                    stream.write_varint(leader as i32)?;
+                }
+                None if is_hard_linked(file) => stream.write_i8(file_type_code | XMIT_HLINKED | XMIT_HLINK_FIRST)?,
+                None => stream.write_i8(file_type_code)?,
+            }
 
 
             if file.is_symlink {
@@ -65,24 +85,24 @@ impl FileList {
     pub fn decode<S: Read + Write>(stream: &mut ProtocolStream<S>) -> Result<Vec<FileInfo>> {
 
         let num_files = stream.read_varint()? as usize;
-        let mut files = Vec::with_capacity(num_files);
+        let mut files: Vec<FileInfo> = Vec::with_capacity(num_files);
 
 
-        for _ in 0..num_files {
+        for index in 0..num_files {
 
             let path_str = stream.read_string(4096)?;
             let path = PathBuf::from(path_str);
 
 
-            let size = stream.read_varint()? as u64;
+            let size = stream.read_varlong30(3)? as u64;
 
 
-            let mtime_secs = stream.read_varint()? as u64;
+            let mtime_secs = stream.read_varlong30(4)? as u64;
             let mtime = UNIX_EPOCH + std::time::Duration::from_secs(mtime_secs);
 
 
             let file_type_code = stream.read_i8()?;
-            let file_type = match file_type_code {
+            let file_type = match file_type_code & XMIT_TYPE_MASK {
                 0 => FileType::File,
                 1 => FileType::Directory,
                 2 => FileType::Symlink,
@@ -102,6 +122,23 @@ impl FileList {
                 None
             };
 
+
+            let file_id = match file_type_code & (XMIT_HLINKED | XMIT_HLINK_FIRST) {
+                0 => None,
+                XMIT_HLINKED => {
+                    let leader = stream.read_varint()? as usize;
+                    let leader_id = files.get(leader).and_then(|leader| leader.file_id);
+                    if leader_id.is_none() {
+                        return Err(RsyncError::Other(format!(
+                            "Invalid hard link in file list: entry {} points to entry {}",
+                            index, leader
+                        )));
+                    }
+                    leader_id
+                }
+                _ => Some(FileId { device: 0, index: index as u64 }),
+            };
+
             files.push(FileInfo {
                 path,
                 size,
@@ -109,11 +146,122 @@ impl FileList {
                 file_type,
                 is_symlink,
                 symlink_target,
+                nlink: if file_id.is_some() { 2 } else { 1 },
+                file_id,
+                streams: Vec::new(),
             });
         }
 
         Ok(files)
     }
+
+
+    pub fn relative_to(files: &[FileInfo], base: &Path) -> Vec<FileInfo> {
+        files.iter()
+            .filter_map(|file| {
+                let relative = file.path.strip_prefix(base).ok()?;
+                let relative = if relative.as_os_str().is_empty() {
+                    if file.is_directory() {
+                        return None;
+                    }
+                    Path::new(file.path.file_name()?)
+                } else {
+                    relative
+                };
+
+                let mut file = file.clone();
+                file.path = PathBuf::from(to_unix_separators(&relative.to_string_lossy()));
+                Some(file)
+            })
+            .collect()
+    }
+}
+
+
+
+#[derive(Debug, Clone)]
+pub struct Flist {
+    pub ndx_start: i32,
+    pub parent_ndx: Option<i32>,
+    pub files: Vec<FileInfo>,
+}
+
+impl Flist {
+    pub fn directories(&self) -> impl Iterator<Item = (i32, PathBuf)> + '_ {
+        (self.ndx_start..)
+            .zip(&self.files)
+            .filter(|(_, file)| file.is_directory())
+            .map(|(ndx, file)| (ndx, file.path.clone()))
+    }
+}
+
+
+#[derive(Debug, Default)]
+pub struct FlistManager {
+    flists: Vec<Flist>,
+    next_ndx: i32,
+}
+
+impl FlistManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+
+    pub fn add(&mut self, parent_ndx: Option<i32>, files: Vec<FileInfo>) -> &Flist {
+        let ndx_start = self.next_ndx;
+        self.next_ndx += files.len() as i32;
+        self.flists.push(Flist { ndx_start, parent_ndx, files });
+        &self.flists[self.flists.len() - 1]
+    }
+
+
+    pub fn write_segment<S: Read + Write>(
+        &mut self,
+        stream: &mut ProtocolStream<S>,
+        parent_ndx: i32,
+        files: Vec<FileInfo>,
+    ) -> Result<&Flist> {
+        stream.write_i32(NDX_FLIST_OFFSET - parent_ndx)?;
+        FileList::encode(stream, &files)?;
+        Ok(self.add(Some(parent_ndx), files))
+    }
+
+
+    pub fn write_eof<S: Read + Write>(&self, stream: &mut ProtocolStream<S>) -> Result<()> {
+        stream.write_i32(NDX_FLIST_EOF)?;
+        stream.flush()
+    }
+
+
+    pub fn read_segment<S: Read + Write>(&mut self, stream: &mut ProtocolStream<S>) -> Result<Option<&Flist>> {
+        let ndx = stream.read_i32()?;
+        if ndx == NDX_FLIST_EOF {
+            return Ok(None);
+        }
+
+        let parent_ndx = NDX_FLIST_OFFSET - ndx;
+        if ndx > NDX_FLIST_OFFSET || !self.file(parent_ndx).is_some_and(|dir| dir.is_directory()) {
+            return Err(RsyncError::Other(format!(
+                "Invalid file-list segment: index {} is not a directory in the transfer",
+                parent_ndx
+            )));
+        }
+        let files = FileList::decode(stream)?;
+        Ok(Some(self.add(Some(parent_ndx), files)))
+    }
+
+
+    pub fn file(&self, ndx: i32) -> Option<&FileInfo> {
+        let position = self.flists.partition_point(|flist| flist.ndx_start <= ndx).checked_sub(1)?;
+        let flist = &self.flists[position];
+        flist.files.get((ndx - flist.ndx_start) as usize)
+    }
+
+
+    pub fn len(&self) -> usize {
+        self.next_ndx as usize
+    }
 }
 
 #[cfg(test)]
@@ -135,6 +283,9 @@ mod tests {
                 file_type: FileType::File,
                 is_symlink: false,
                 symlink_target: None,
+                nlink: 1,
+                file_id: None,
+                streams: Vec::new(),
             },
             FileInfo {
                 path: PathBuf::from("dir1"),
@@ -143,6 +294,9 @@ mod tests {
                 file_type: FileType::Directory,
                 is_symlink: false,
                 symlink_target: None,
+                nlink: 1,
+                file_id: None,
+                streams: Vec::new(),
             },
         ];
 
@@ -186,6 +340,9 @@ mod tests {
                 file_type: FileType::Symlink,
                 is_symlink: true,
                 symlink_target: Some(PathBuf::from("/target/path")),
+                nlink: 1,
+                file_id: None,
+                streams: Vec::new(),
             },
         ];
 
@@ -206,4 +363,84 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_encode_decode_hard_links() -> Result<()> {
+        let entry = |path: &str, index: Option<u64>| FileInfo {
+            path: PathBuf::from(path),
+            size: 10,
+            mtime: UNIX_EPOCH,
+            file_type: FileType::File,
+            is_symlink: false,
+            symlink_target: None,
+            nlink: if index.is_some() { 3 } else { 1 },
+            file_id: index.map(|index| FileId { device: 9, index }),
+            streams: Vec::new(),
+        };
+        let files = vec![entry("a", Some(40)), entry("b", None), entry("c", Some(40)), entry("d", Some(41))];
+
+        let mut buffer = Cursor::new(Vec::new());
+        let mut stream = ProtocolStream::new(&mut buffer, 31);
+        FileList::encode(&mut stream, &files)?;
+
+        stream.get_mut().set_position(0);
+        let decoded = FileList::decode(&mut stream)?;
+        assert_eq!(link_leaders(&decoded), vec![None, None, Some(0), None]);
+        assert!(is_hard_linked(&decoded[3]));
+        assert!(!is_hard_linked(&decoded[1]));
+
+        let mut buffer = Cursor::new(Vec::new());
+        let mut stream = ProtocolStream::new(&mut buffer, 31);
+        stream.write_varint(1)?;
+        stream.write_string("x")?;
+        stream.write_varlong30(0, 3)?;
+        stream.write_varlong30(0, 4)?;
+        stream.write_i8(XMIT_HLINKED)?;
+        stream.write_varint(5)?;
+        stream.get_mut().set_position(0);
+        assert!(FileList::decode(&mut stream).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_flist_segments() -> Result<()> {
+        let entry = |path: &str, file_type: FileType| FileInfo {
+            path: PathBuf::from(path),
+            size: 0,
+            mtime: UNIX_EPOCH,
+            file_type,
+            is_symlink: false,
+            symlink_target: None,
+            nlink: 1,
+            file_id: None,
+            streams: Vec::new(),
+        };
+
+        let mut buffer = Cursor::new(Vec::new());
+        let mut stream = ProtocolStream::new(&mut buffer, 31);
+        let mut sender = FlistManager::new();
+        let top = vec![entry("a.txt", FileType::File), entry("dir", FileType::Directory)];
+        FileList::encode(&mut stream, &top)?;
+        let dirs: Vec<_> = sender.add(None, top).directories().collect();
+        assert_eq!(dirs, vec![(1, PathBuf::from("dir"))]);
+        sender.write_segment(&mut stream, 1, vec![entry("dir/b.txt", FileType::File)])?;
+        sender.write_eof(&mut stream)?;
+
+        stream.get_mut().set_position(0);
+        let mut receiver = FlistManager::new();
+        receiver.add(None, FileList::decode(&mut stream)?);
+        let segment = receiver.read_segment(&mut stream)?.unwrap();
+        assert_eq!((segment.ndx_start, segment.parent_ndx), (2, Some(1)));
+        assert!(receiver.read_segment(&mut stream)?.is_none());
+        assert_eq!(receiver.len(), 3);
+        assert_eq!(receiver.file(2).unwrap().path, PathBuf::from("dir/b.txt"));
+        assert!(receiver.file(3).is_none());
+
+        stream.get_mut().set_position(0);
+        stream.get_mut().get_mut().clear();
+        stream.write_i32(NDX_FLIST_OFFSET)?;
+        stream.get_mut().set_position(0);
+        assert!(receiver.read_segment(&mut stream).is_err());
+        Ok(())
+    }
 }
@@ -0,0 +1,144 @@
+use crate::filesystem::{FileInfo, FileType};
+use crate::options::Options;
+
+
+#[allow(dead_code)]
+pub const ITEM_REPORT_ATIME: u16 = 1 << 0;
+pub const ITEM_REPORT_CHANGE: u16 = 1 << 1;
+pub const ITEM_REPORT_SIZE: u16 = 1 << 2;
+pub const ITEM_REPORT_TIME: u16 = 1 << 3;
+#[allow(dead_code)]
+pub const ITEM_REPORT_PERMS: u16 = 1 << 4;
+#[allow(dead_code)]
+pub const ITEM_REPORT_OWNER: u16 = 1 << 5;
+#[allow(dead_code)]
+pub const ITEM_REPORT_GROUP: u16 = 1 << 6;
+pub const ITEM_REPORT_ACL: u16 = 1 << 7;
+#[allow(dead_code)]
+pub const ITEM_BASIS_TYPE_FOLLOWS: u16 = 1 << 11;
+pub const ITEM_XNAME_FOLLOWS: u16 = 1 << 12;
+pub const ITEM_IS_NEW: u16 = 1 << 13;
+pub const ITEM_LOCAL_CHANGE: u16 = 1 << 14;
+pub const ITEM_TRANSFER: u16 = 1 << 15;
+
+
+pub fn compute_iflags(remote: &FileInfo, local: Option<&FileInfo>, options: &Options) -> u16 {
+    let is_dir = remote.file_type == FileType::Directory;
+
+    let Some(local) = local.filter(|l| l.file_type == remote.file_type) else {
+        return if is_dir {
+            ITEM_IS_NEW | ITEM_LOCAL_CHANGE
+        } else {
+            ITEM_IS_NEW | ITEM_TRANSFER
+        };
+    };
+
+    if is_dir {
+        return 0;
+    }
+
+    let mut iflags = 0;
+    if local.size != remote.size {
+        iflags |= ITEM_REPORT_SIZE;
+    }
+    if !options.size_only && mtime_secs(local) != mtime_secs(remote) {
+        iflags |= ITEM_REPORT_TIME;
+    }
+
+    let changed = iflags & (ITEM_REPORT_SIZE | ITEM_REPORT_TIME) != 0;
+    let local_is_newer = options.update && mtime_secs(local) > mtime_secs(remote);
+    let nothing_to_append = options.append && local.size >= remote.size;
+    if !local_is_newer && !nothing_to_append && (changed || options.ignore_times) {
+        iflags |= ITEM_TRANSFER;
+    }
+
+    iflags
+}
+
+
+pub fn quick_check_matches(remote: &FileInfo, local: &FileInfo, options: &Options) -> bool {
+    !options.ignore_times
+        && local.size == remote.size
+        && (options.size_only || mtime_secs(local) == mtime_secs(remote))
+}
+
+
+fn mtime_secs(file: &FileInfo) -> u64 {
+    file.mtime
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn entry(size: u64, mtime: u64, file_type: FileType) -> FileInfo {
+        FileInfo {
+            path: "f".into(),
+            size,
+            mtime: UNIX_EPOCH + Duration::from_secs(mtime),
+            file_type,
+            is_symlink: false,
+            symlink_target: None,
+            nlink: 1,
+            file_id: None,
+            streams: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_compute_iflags() {
+        let options = Options::default();
+        let remote = entry(10, 100, FileType::File);
+
+        assert_eq!(compute_iflags(&remote, None, &options), ITEM_IS_NEW | ITEM_TRANSFER);
+        assert_eq!(compute_iflags(&remote, Some(&entry(10, 100, FileType::File)), &options), 0);
+        assert_eq!(
+            compute_iflags(&remote, Some(&entry(12, 100, FileType::File)), &options),
+            ITEM_REPORT_SIZE | ITEM_TRANSFER
+        );
+        assert_eq!(
+            compute_iflags(&remote, Some(&entry(10, 50, FileType::File)), &options),
+            ITEM_REPORT_TIME | ITEM_TRANSFER
+        );
+        assert_eq!(
+            compute_iflags(&remote, Some(&entry(0, 0, FileType::Directory)), &options),
+            ITEM_IS_NEW | ITEM_TRANSFER
+        );
+
+        let dir = entry(0, 100, FileType::Directory);
+        assert_eq!(compute_iflags(&dir, None, &options), ITEM_IS_NEW | ITEM_LOCAL_CHANGE);
+        assert_eq!(compute_iflags(&dir, Some(&entry(0, 5, FileType::Directory)), &options), 0);
+    }
+
+    #[test]
+    fn test_compute_iflags_respects_options() {
+        let remote = entry(10, 100, FileType::File);
+        let same = entry(10, 100, FileType::File);
+        let newer = entry(10, 200, FileType::File);
+
+        let ignore_times = Options { ignore_times: true, ..Options::default() };
+        assert_eq!(compute_iflags(&remote, Some(&same), &ignore_times), ITEM_TRANSFER);
+
+        let size_only = Options { size_only: true, ..Options::default() };
+        assert_eq!(compute_iflags(&remote, Some(&newer), &size_only), 0);
+
+        let update = Options { update: true, ..Options::default() };
+        assert_eq!(compute_iflags(&remote, Some(&newer), &update), ITEM_REPORT_TIME);
+
+        let append = Options { append: true, ..Options::default() };
+        assert_eq!(
+            compute_iflags(&remote, Some(&entry(4, 100, FileType::File)), &append),
+            ITEM_REPORT_SIZE | ITEM_TRANSFER
+        );
+        assert_eq!(compute_iflags(&remote, Some(&entry(12, 50, FileType::File)), &append), ITEM_REPORT_SIZE | ITEM_REPORT_TIME);
+
+        assert!(quick_check_matches(&remote, &same, &Options::default()));
+        assert!(!quick_check_matches(&remote, &newer, &Options::default()));
+        assert!(quick_check_matches(&remote, &newer, &size_only));
+        assert!(!quick_check_matches(&remote, &same, &ignore_times));
+    }
+}
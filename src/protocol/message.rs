@@ -5,31 +5,67 @@ pub enum MessageTag {
 
     Data = 0,
 
-    Error = 1,
+    ErrorXfer = 1,
 
     Info = 2,
 
-    Warning = 3,
+    Error = 3,
 
-    FileListStart = 4,
+    Warning = 4,
 
-    FileListEnd = 5,
+    ErrorSocket = 5,
 
-    ProtocolVersion = 7,
+    Log = 6,
 
-    FileIndex = 8,
+    Client = 7,
 
-    FileChecksum = 9,
+    ErrorUtf8 = 8,
 
-    FileBlock = 10,
+    Redo = 9,
 
-    FileUpdated = 11,
+    Stats = 10,
 
-    IoTimeout = 12,
+    IoError = 22,
 
-    IoError = 13,
+    IoTimeout = 33,
 
-    Other = 100,
+    Noop = 42,
+
+    ErrorExit = 86,
+
+    Success = 100,
+
+    Deleted = 101,
+
+    NoSend = 102,
+}
+
+impl MessageTag {
+
+    pub fn from_u8(code: u8) -> Option<Self> {
+        let tag = match code {
+            0 => MessageTag::Data,
+            1 => MessageTag::ErrorXfer,
+            2 => MessageTag::Info,
+            3 => MessageTag::Error,
+            4 => MessageTag::Warning,
+            5 => MessageTag::ErrorSocket,
+            6 => MessageTag::Log,
+            7 => MessageTag::Client,
+            8 => MessageTag::ErrorUtf8,
+            9 => MessageTag::Redo,
+            10 => MessageTag::Stats,
+            22 => MessageTag::IoError,
+            33 => MessageTag::IoTimeout,
+            42 => MessageTag::Noop,
+            86 => MessageTag::ErrorExit,
+            100 => MessageTag::Success,
+            101 => MessageTag::Deleted,
+            102 => MessageTag::NoSend,
+            _ => return None,
+        };
+        Some(tag)
+    }
 }
 
 
@@ -58,8 +94,8 @@ impl Message {
             Message::Error(_) => MessageTag::Error,
             Message::Info(_) => MessageTag::Info,
             Message::Warning(_) => MessageTag::Warning,
-            Message::FileList(_) => MessageTag::FileListStart,
-            Message::Done => MessageTag::FileListEnd,
+            Message::FileList(_) => MessageTag::Data,
+            Message::Done => MessageTag::Success,
         }
     }
 }
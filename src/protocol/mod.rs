@@ -6,12 +6,20 @@
 
 
 pub mod version;
+pub mod codec;
 pub mod stream;
 pub mod async_stream;
 pub mod message;
+pub mod multiplex;
 pub mod file_list;
+pub mod iflags;
+pub mod sums;
 
-pub use version::PROTOCOL_VERSION_MAX;
+pub use version::{
+    choose_checksum_seed, negotiate_checksum, negotiate_compression, CF_CHKSUM_SEED_FIX, CF_INC_RECURSE, CHECKSUM_NEGOTIATION,
+    COMPRESSION_NEGOTIATION, PROTOCOL_VERSION_MAX,
+};
 pub use stream::ProtocolStream;
 pub use async_stream::AsyncProtocolStream;
-pub use file_list::FileList;
+pub use file_list::{FileList, FlistManager};
+pub use multiplex::{MultiplexReader, MultiplexWriter};
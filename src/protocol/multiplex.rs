@@ -0,0 +1,423 @@
+use crate::protocol::message::MessageTag;
+use crate::output::VerboseOutput;
+use std::future::poll_fn;
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+
+pub const MPLEX_BASE: u8 = 7;
+
+
+pub const MAX_PACKET_LEN: usize = 0xFF_FFFF;
+
+
+pub const DEFAULT_BUFFER_SIZE: usize = 32 * 1024;
+
+
+fn encode_header(tag: MessageTag, len: usize) -> [u8; 4] {
+    let header = ((MPLEX_BASE as u32 + tag as u32) << 24) | len as u32;
+    header.to_le_bytes()
+}
+
+
+fn decode_header(header: [u8; 4]) -> io::Result<(MessageTag, usize)> {
+    let header = u32::from_le_bytes(header);
+    let code = (header >> 24) as u8;
+    let len = (header & MAX_PACKET_LEN as u32) as usize;
+
+    code.checked_sub(MPLEX_BASE)
+        .and_then(MessageTag::from_u8)
+        .map(|tag| (tag, len))
+        .ok_or_else(|| io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Unexpected multiplex tag: {}", code),
+        ))
+}
+
+
+pub struct MultiplexWriter<W> {
+    inner: W,
+    enabled: bool,
+    buffer: Vec<u8>,
+    buffer_size: usize,
+    pending: Vec<u8>,
+    pending_pos: usize,
+    last_write: Instant,
+}
+
+impl<W: AsyncWrite + Unpin> MultiplexWriter<W> {
+
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            enabled: true,
+            buffer: Vec::with_capacity(DEFAULT_BUFFER_SIZE),
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            pending: Vec::new(),
+            pending_pos: 0,
+            last_write: Instant::now(),
+        }
+    }
+
+
+    pub fn enabled(mut self, enabled: bool) -> Self {
+        self.enabled = enabled;
+        self
+    }
+
+
+    #[allow(dead_code)]
+    pub fn buffer_size(mut self, size: usize) -> Self {
+        self.buffer_size = size.max(1);
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+
+    pub async fn write_message(&mut self, tag: MessageTag, payload: &[u8]) -> io::Result<()> {
+        if !self.enabled {
+            return Ok(());
+        }
+
+        self.queue_buffered_data();
+        self.queue_packets(tag, payload);
+        poll_fn(|cx| self.poll_drain(cx)).await
+    }
+
+
+    pub async fn keepalive(&mut self) -> io::Result<()> {
+        if !self.enabled {
+            return Ok(());
+        }
+
+        self.write_message(MessageTag::Noop, &[]).await?;
+        poll_fn(|cx| Pin::new(&mut self.inner).poll_flush(cx)).await
+    }
+
+
+    pub async fn maybe_keepalive(&mut self, interval: Duration) -> io::Result<()> {
+        if self.last_write.elapsed() < interval {
+            return Ok(());
+        }
+        self.keepalive().await
+    }
+
+    fn queue_packets(&mut self, tag: MessageTag, payload: &[u8]) {
+        if payload.is_empty() {
+            self.pending.extend_from_slice(&encode_header(tag, 0));
+            return;
+        }
+
+        for chunk in payload.chunks(MAX_PACKET_LEN) {
+            self.pending.extend_from_slice(&encode_header(tag, chunk.len()));
+            self.pending.extend_from_slice(chunk);
+        }
+    }
+
+    fn queue_buffered_data(&mut self) {
+        if self.buffer.is_empty() {
+            return;
+        }
+
+        let buffer = std::mem::take(&mut self.buffer);
+        self.queue_packets(MessageTag::Data, &buffer);
+        self.buffer = buffer;
+        self.buffer.clear();
+    }
+
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.pending_pos < self.pending.len() {
+            let n = ready!(Pin::new(&mut self.inner).poll_write(cx, &self.pending[self.pending_pos..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.pending_pos += n;
+            self.last_write = Instant::now();
+        }
+
+        self.pending.clear();
+        self.pending_pos = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for MultiplexWriter<W> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if !this.enabled {
+            return Pin::new(&mut this.inner).poll_write(cx, buf);
+        }
+
+        ready!(this.poll_drain(cx))?;
+
+        if this.buffer.len() >= this.buffer_size {
+            this.queue_buffered_data();
+            ready!(this.poll_drain(cx))?;
+        }
+
+        let n = buf.len().min(this.buffer_size - this.buffer.len());
+        this.buffer.extend_from_slice(&buf[..n]);
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        this.queue_buffered_data();
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        this.queue_buffered_data();
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+
+pub struct MultiplexReader<R> {
+    inner: R,
+    enabled: bool,
+    header: [u8; 4],
+    header_len: usize,
+    data_remaining: usize,
+    message: Option<(MessageTag, usize)>,
+    payload: Vec<u8>,
+    error: Option<String>,
+}
+
+impl<R: AsyncRead + Unpin> MultiplexReader<R> {
+
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            enabled: true,
+            header: [0u8; 4],
+            header_len: 0,
+            data_remaining: 0,
+            message: None,
+            payload: Vec::new(),
+            error: None,
+        }
+    }
+
+
+    pub fn enabled(mut self, enabled: bool) -> Self {
+        self.enabled = enabled;
+        self
+    }
+
+
+    pub fn take_error(&mut self) -> Option<String> {
+        self.error.take()
+    }
+
+    fn dispatch_message(tag: MessageTag, payload: &[u8]) -> Option<String> {
+        let verbose = VerboseOutput::new(1, false);
+        let text = String::from_utf8_lossy(payload);
+        let text = text.trim_end();
+
+        match tag {
+            MessageTag::Info | MessageTag::Log | MessageTag::Client => verbose.print_basic(text),
+            MessageTag::Warning => verbose.print_warning(text),
+            MessageTag::Error | MessageTag::ErrorXfer | MessageTag::ErrorSocket | MessageTag::ErrorUtf8 => {
+                verbose.print_error(text);
+                return Some(text.to_string());
+            }
+            _ => {}
+        }
+        None
+    }
+
+    fn poll_data(&mut self, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let limit = buf.remaining().min(self.data_remaining);
+        let mut limited = buf.take(limit);
+        ready!(Pin::new(&mut self.inner).poll_read(cx, &mut limited))?;
+        let n = limited.filled().len();
+        if n == 0 && limit > 0 {
+            return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+        }
+
+        unsafe { buf.assume_init(n) };
+        buf.advance(n);
+        self.data_remaining -= n;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for MultiplexReader<R> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if !this.enabled {
+            return Pin::new(&mut this.inner).poll_read(cx, buf);
+        }
+
+        loop {
+            if this.data_remaining > 0 {
+                return this.poll_data(cx, buf);
+            }
+
+            if let Some((tag, filled)) = this.message.as_mut() {
+                while *filled < this.payload.len() {
+                    let mut read_buf = ReadBuf::new(&mut this.payload[*filled..]);
+                    ready!(Pin::new(&mut this.inner).poll_read(cx, &mut read_buf))?;
+                    let n = read_buf.filled().len();
+                    if n == 0 {
+                        return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+                    }
+                    *filled += n;
+                }
+                if let Some(error) = Self::dispatch_message(*tag, &this.payload) {
+                    this.error = Some(error);
+                }
+                this.message = None;
+                continue;
+            }
+
+            while this.header_len < this.header.len() {
+                let mut read_buf = ReadBuf::new(&mut this.header[this.header_len..]);
+                ready!(Pin::new(&mut this.inner).poll_read(cx, &mut read_buf))?;
+                let n = read_buf.filled().len();
+                if n == 0 {
+                    if this.header_len == 0 {
+                        return Poll::Ready(Ok(()));
+                    }
+                    return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+                }
+                this.header_len += n;
+            }
+            this.header_len = 0;
+
+            let (tag, len) = decode_header(this.header)?;
+            if tag == MessageTag::Data {
+                this.data_remaining = len;
+                if len > 0 && buf.remaining() == 0 {
+                    return Poll::Ready(Ok(()));
+                }
+            } else {
+                this.payload.clear();
+                this.payload.resize(len, 0);
+                this.message = Some((tag, 0));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_data_is_tagged_and_buffered() -> io::Result<()> {
+        let mut writer = MultiplexWriter::new(Vec::new());
+        writer.write_all(b"hello").await?;
+        writer.write_all(b" world").await?;
+        writer.flush().await?;
+
+        let bytes = writer.into_inner();
+        assert_eq!(&bytes[..4], &encode_header(MessageTag::Data, 11));
+        assert_eq!(bytes[3], MPLEX_BASE);
+        assert_eq!(&bytes[4..], b"hello world");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_large_payload_is_split_at_max_packet() -> io::Result<()> {
+        let payload = vec![0xABu8; MAX_PACKET_LEN + 10];
+        let mut writer = MultiplexWriter::new(Vec::new());
+        writer.write_message(MessageTag::Data, &payload).await?;
+
+        let bytes = writer.into_inner();
+        assert_eq!(bytes.len(), payload.len() + 8);
+        assert_eq!(decode_header(bytes[..4].try_into().unwrap())?, (MessageTag::Data, MAX_PACKET_LEN));
+        let second = 4 + MAX_PACKET_LEN;
+        assert_eq!(decode_header(bytes[second..second + 4].try_into().unwrap())?, (MessageTag::Data, 10));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_messages_keep_order_with_buffered_data() -> io::Result<()> {
+        let mut writer = MultiplexWriter::new(Vec::new()).buffer_size(4);
+        writer.write_all(b"abcdef").await?;
+        writer.write_message(MessageTag::Info, b"note\n").await?;
+        writer.keepalive().await?;
+        writer.write_all(b"gh").await?;
+        writer.flush().await?;
+
+        let bytes = writer.into_inner();
+        let mut reader = MultiplexReader::new(bytes.as_slice());
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).await?;
+        assert_eq!(data, b"abcdefgh");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reader_fills_small_buffers_across_packets() -> io::Result<()> {
+        let mut writer = MultiplexWriter::new(Vec::new()).buffer_size(5);
+        writer.write_all(b"0123456789").await?;
+        writer.write_message(MessageTag::Warning, b"a long warning message\n").await?;
+        writer.write_message(MessageTag::Info, b"hi\n").await?;
+        writer.write_all(b"abcdefg").await?;
+        writer.flush().await?;
+
+        let bytes = writer.into_inner();
+        let mut reader = MultiplexReader::new(bytes.as_slice());
+        let mut data = Vec::new();
+        let mut chunk = [0u8; 3];
+        loop {
+            let n = reader.read(&mut chunk).await?;
+            if n == 0 {
+                break;
+            }
+            data.extend_from_slice(&chunk[..n]);
+        }
+        assert_eq!(data, b"0123456789abcdefg");
+        assert!(reader.payload.capacity() >= b"a long warning message\n".len());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reader_keeps_last_error() -> io::Result<()> {
+        let mut writer = MultiplexWriter::new(Vec::new());
+        writer.write_all(b"data").await?;
+        writer.write_message(MessageTag::Error, b"quota exceeded\n").await?;
+        writer.flush().await?;
+
+        let bytes = writer.into_inner();
+        let mut reader = MultiplexReader::new(bytes.as_slice());
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).await?;
+        assert_eq!(data, b"data");
+        assert_eq!(reader.take_error().as_deref(), Some("quota exceeded"));
+        assert_eq!(reader.take_error(), None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_disabled_is_passthrough() -> io::Result<()> {
+        let mut writer = MultiplexWriter::new(Vec::new()).enabled(false);
+        writer.write_all(b"raw").await?;
+        writer.keepalive().await?;
+        writer.flush().await?;
+        assert_eq!(writer.into_inner(), b"raw");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reader_rejects_unknown_tag() {
+        let bytes = [0u8, 0, 0, MPLEX_BASE + 200];
+        let mut reader = MultiplexReader::new(&bytes[..]);
+        let mut data = Vec::new();
+        assert!(reader.read_to_end(&mut data).await.is_err());
+    }
+}
@@ -1,6 +1,9 @@
 use std::io::{Read, Write};
-use byteorder::{ReadBytesExt, WriteBytesExt, BigEndian, LittleEndian};
+use byteorder::{ReadBytesExt, WriteBytesExt, LittleEndian};
 use crate::error::{Result, RsyncError};
+use crate::algorithm::checksum::ChecksumSeed;
+use crate::options::CompressionAlgorithm;
+use crate::protocol::codec;
 
 
 
@@ -8,14 +11,43 @@ use crate::error::{Result, RsyncError};
 pub struct ProtocolStream<S: Read + Write> {
     stream: S,
 
-    #[allow(dead_code)]
     protocol_version: i32,
+
+    checksum_seed: i32,
+
+    seed_first: bool,
+
+    compression: Option<CompressionAlgorithm>,
 }
 
 impl<S: Read + Write + ReadBytesExt + WriteBytesExt> ProtocolStream<S> {
 
     pub fn new(stream: S, protocol_version: i32) -> Self {
-        Self { stream, protocol_version }
+        Self { stream, protocol_version, checksum_seed: 0, seed_first: false, compression: None }
+    }
+
+    pub fn checksum_seed(&self) -> i32 {
+        self.checksum_seed
+    }
+
+    pub fn set_checksum_seed(&mut self, seed: i32) {
+        self.checksum_seed = seed;
+    }
+
+    pub fn set_seed_first(&mut self, seed_first: bool) {
+        self.seed_first = seed_first;
+    }
+
+    pub fn block_seed(&self) -> ChecksumSeed {
+        ChecksumSeed::new(self.checksum_seed, self.seed_first)
+    }
+
+    pub fn compression(&self) -> Option<CompressionAlgorithm> {
+        self.compression
+    }
+
+    pub fn set_compression(&mut self, algorithm: Option<CompressionAlgorithm>) {
+        self.compression = algorithm;
     }
 
 
@@ -28,6 +60,14 @@ impl<S: Read + Write + ReadBytesExt + WriteBytesExt> ProtocolStream<S> {
         Ok(self.stream.write_i8(val)?)
     }
 
+    pub fn read_u16(&mut self) -> Result<u16> {
+        Ok(self.stream.read_u16::<LittleEndian>()?)
+    }
+
+    pub fn write_u16(&mut self, val: u16) -> Result<()> {
+        Ok(self.stream.write_u16::<LittleEndian>(val)?)
+    }
+
     pub fn read_i32(&mut self) -> Result<i32> {
         Ok(self.stream.read_i32::<LittleEndian>()?)
     }
@@ -36,7 +76,6 @@ impl<S: Read + Write + ReadBytesExt + WriteBytesExt> ProtocolStream<S> {
         Ok(self.stream.write_i32::<LittleEndian>(val)?)
     }
 
-    #[allow(dead_code)]
     pub fn read_i64(&mut self) -> Result<i64> {
         Ok(self.stream.read_i64::<LittleEndian>()?)
     }
@@ -61,63 +100,65 @@ impl<S: Read + Write + ReadBytesExt + WriteBytesExt> ProtocolStream<S> {
 
 
 
-    pub fn read_varint(&mut self) -> Result<i64> {
-        let first_byte = self.read_i8()? as u8;
-
-        match first_byte {
+    pub fn read_varint(&mut self) -> Result<i32> {
+        let tag = self.stream.read_u8()?;
+        let mut extra = [0u8; 4];
+        let extra_len = codec::varint_extra(tag)?;
+        self.read_all(&mut extra[..extra_len])?;
+        Ok(codec::decode_varint(tag, &extra[..extra_len]))
+    }
 
-            0..=250 => Ok(first_byte as i64),
 
-            251 => Ok(self.stream.read_i16::<BigEndian>()? as i64),
+    pub fn write_varint(&mut self, val: i32) -> Result<()> {
+        self.write_all(codec::encode_varint(val).as_slice())
+    }
 
-            252 => Ok(self.stream.read_i32::<BigEndian>()? as i64),
 
-            253 => Ok(self.stream.read_i64::<BigEndian>()? as i64),
+    pub fn read_varlong(&mut self, min_bytes: u8) -> Result<i64> {
+        let mut head = [0u8; 8];
+        let head = &mut head[..min_bytes as usize];
+        self.read_all(head)?;
 
-            254 => Ok(self.read_i8()? as i64),
+        let mut extra = [0u8; 8];
+        let extra_len = codec::varlong_extra(head[0], min_bytes)?;
+        self.read_all(&mut extra[..extra_len])?;
+        Ok(codec::decode_varlong(head, &extra[..extra_len]))
+    }
 
-            255 => Ok(self.stream.read_i16::<BigEndian>()? as i64),
 
-        }
+    pub fn write_varlong(&mut self, val: i64, min_bytes: u8) -> Result<()> {
+        self.write_all(codec::encode_varlong(val, min_bytes).as_slice())
     }
 
 
-    pub fn write_varint(&mut self, val: i64) -> Result<()> {
-        match val {
+    pub fn read_longint(&mut self) -> Result<i64> {
+        let num = self.read_i32()?;
+        if num != -1 {
+            return Ok(num as i64);
+        }
+        self.read_i64()
+    }
 
-            0..=250 => {
-                self.stream.write_u8(val as u8)?;
-                Ok(())
-            }
 
-            -128..=-1 => {
-                self.stream.write_u8(254)?;
-                self.write_i8(val as i8)
-            }
+    pub fn write_longint(&mut self, val: i64) -> Result<()> {
+        self.write_all(codec::encode_longint(val).as_slice())
+    }
 
-            251..=32767 => {
-                self.stream.write_u8(251)?;
-                self.stream.write_i16::<BigEndian>(val as i16)?;
-                Ok(())
-            }
 
-            -32768..=-129 => {
-                self.stream.write_u8(255)?;
-                self.stream.write_i16::<BigEndian>(val as i16)?;
-                Ok(())
-            }
+    pub fn read_varlong30(&mut self, min_bytes: u8) -> Result<i64> {
+        if self.protocol_version >= 30 {
+            self.read_varlong(min_bytes)
+        } else {
+            self.read_longint()
+        }
+    }
 
-            -2147483648..=2147483647 => {
-                self.stream.write_u8(252)?;
-                self.stream.write_i32::<BigEndian>(val as i32)?;
-                Ok(())
-            }
 
-            _ => {
-                self.stream.write_u8(253)?;
-                self.stream.write_i64::<BigEndian>(val)?;
-                Ok(())
-            }
+    pub fn write_varlong30(&mut self, val: i64, min_bytes: u8) -> Result<()> {
+        if self.protocol_version >= 30 {
+            self.write_varlong(val, min_bytes)
+        } else {
+            self.write_longint(val)
         }
     }
 
@@ -217,14 +258,11 @@ mod tests {
     #[test]
     fn test_varint_round_trip() -> Result<()> {
         let test_values = vec![
-            0, 1, 100, 250,
+            0, 1, 100, 127, 128, 250, 255, 256,
             -1, -50, -128,
-            251, 1000, 32767,
-            -129, -1000, -32768,
-            32768, 1000000,
-            -32769, -1000000,
-            2147483648,
-            -2147483649,
+            16383, 16384, 32767, 32768, 1000000,
+            -129, -1000, -32768, -1000000,
+            i32::MAX, i32::MIN,
         ];
 
         for &val in &test_values {
@@ -241,6 +279,157 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_varint_round_trip_sweep() -> Result<()> {
+        let mut seed = 0x2545_f491_u32;
+        let mut values = Vec::new();
+        for shift in 0..32 {
+            values.push(1i32.wrapping_shl(shift));
+            values.push(1i32.wrapping_shl(shift).wrapping_sub(1));
+        }
+        for _ in 0..2000 {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            values.push(seed as i32);
+        }
+
+        let mut buffer = Cursor::new(Vec::new());
+        let mut stream = ProtocolStream::new(&mut buffer, 31);
+        for &val in &values {
+            stream.write_varint(val)?;
+        }
+
+        stream.get_mut().set_position(0);
+        for &val in &values {
+            assert_eq!(stream.read_varint()?, val, "Failed for value: {}", val);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_varint_matches_rsync_bytes() -> Result<()> {
+        let vectors: Vec<(i32, Vec<u8>)> = vec![
+            (0, vec![0x00]),
+            (1, vec![0x01]),
+            (127, vec![0x7f]),
+            (128, vec![0x80, 0x80]),
+            (255, vec![0x80, 0xff]),
+            (256, vec![0x81, 0x00]),
+            (16383, vec![0xbf, 0xff]),
+            (16384, vec![0xc0, 0x00, 0x40]),
+            (0x12345, vec![0xc1, 0x45, 0x23]),
+            (1000000, vec![0xcf, 0x40, 0x42]),
+            (i32::MAX, vec![0xf0, 0xff, 0xff, 0xff, 0x7f]),
+            (-1, vec![0xf0, 0xff, 0xff, 0xff, 0xff]),
+            (-128, vec![0xf0, 0x80, 0xff, 0xff, 0xff]),
+            (-1000000, vec![0xf0, 0xc0, 0xbd, 0xf0, 0xff]),
+        ];
+
+        for (val, expected) in vectors {
+            let mut buffer = Cursor::new(Vec::new());
+            let mut stream = ProtocolStream::new(&mut buffer, 31);
+            stream.write_varint(val)?;
+            assert_eq!(stream.get_ref().get_ref(), &expected, "Encoding mismatch for {}", val);
+
+            let mut reader = Cursor::new(expected);
+            let mut stream = ProtocolStream::new(&mut reader, 31);
+            assert_eq!(stream.read_varint()?, val);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_varint_rejects_overflow_tag() {
+        let mut buffer = Cursor::new(vec![0xf8, 0, 0, 0, 0, 0]);
+        let mut stream = ProtocolStream::new(&mut buffer, 31);
+        assert!(stream.read_varint().is_err());
+    }
+
+    #[test]
+    fn test_varlong_matches_rsync_bytes() -> Result<()> {
+        let vectors: Vec<(i64, u8, Vec<u8>)> = vec![
+            (0, 3, vec![0x00, 0x00, 0x00]),
+            (1, 3, vec![0x00, 0x01, 0x00]),
+            (1000, 3, vec![0x00, 0xe8, 0x03]),
+            (0x7fffff, 3, vec![0x7f, 0xff, 0xff]),
+            (0x800000, 3, vec![0x80, 0x00, 0x00, 0x80]),
+            (1700000000, 3, vec![0xc0, 0x00, 0xf1, 0x53, 0x65]),
+            (4294967296, 3, vec![0xc1, 0x00, 0x00, 0x00, 0x00]),
+            (0x123456789a, 3, vec![0xd2, 0x9a, 0x78, 0x56, 0x34]),
+            (-1, 3, vec![0xfc, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]),
+            (0, 4, vec![0x00, 0x00, 0x00, 0x00]),
+            (1000, 4, vec![0x00, 0xe8, 0x03, 0x00]),
+            (0x800000, 4, vec![0x00, 0x00, 0x00, 0x80]),
+            (1700000000, 4, vec![0x65, 0x00, 0xf1, 0x53]),
+            (4294967296, 4, vec![0x81, 0x00, 0x00, 0x00, 0x00]),
+            (0x123456789a, 4, vec![0x92, 0x9a, 0x78, 0x56, 0x34]),
+            (-1, 4, vec![0xf8, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]),
+        ];
+
+        for (val, min_bytes, expected) in vectors {
+            let mut buffer = Cursor::new(Vec::new());
+            let mut stream = ProtocolStream::new(&mut buffer, 31);
+            stream.write_varlong(val, min_bytes)?;
+            assert_eq!(stream.get_ref().get_ref(), &expected, "Encoding mismatch for {} (min_bytes {})", val, min_bytes);
+
+            let mut reader = Cursor::new(expected);
+            let mut stream = ProtocolStream::new(&mut reader, 31);
+            assert_eq!(stream.read_varlong(min_bytes)?, val);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_varlong_round_trip() -> Result<()> {
+        let mut values = vec![i64::MIN, i64::MAX];
+        for shift in 0..64 {
+            values.push(1i64.wrapping_shl(shift));
+            values.push(1i64.wrapping_shl(shift).wrapping_sub(1));
+            values.push(1i64.wrapping_shl(shift).wrapping_neg());
+        }
+
+        for min_bytes in 3..=8 {
+            let mut buffer = Cursor::new(Vec::new());
+            let mut stream = ProtocolStream::new(&mut buffer, 31);
+            for &val in &values {
+                stream.write_varlong(val, min_bytes)?;
+            }
+
+            stream.get_mut().set_position(0);
+            for &val in &values {
+                assert_eq!(stream.read_varlong(min_bytes)?, val, "Failed for {} (min_bytes {})", val, min_bytes);
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_varlong30_uses_longint_before_protocol_30() -> Result<()> {
+        let mut buffer = Cursor::new(Vec::new());
+        let mut stream = ProtocolStream::new(&mut buffer, 29);
+
+        stream.write_varlong30(0x7fffffff, 3)?;
+        stream.write_varlong30(4294967296, 3)?;
+        assert_eq!(
+            stream.get_ref().get_ref(),
+            &vec![
+                0xff, 0xff, 0xff, 0x7f,
+                0xff, 0xff, 0xff, 0xff, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00,
+            ]
+        );
+
+        stream.get_mut().set_position(0);
+        assert_eq!(stream.read_varlong30(3)?, 0x7fffffff);
+        assert_eq!(stream.read_varlong30(3)?, 4294967296);
+
+        Ok(())
+    }
+
     #[test]
     fn test_varint_encoding_size() -> Result<()> {
         let mut buffer = Cursor::new(Vec::new());
@@ -254,7 +443,13 @@ mod tests {
         stream.get_mut().get_mut().clear();
         stream.get_mut().set_position(0);
         stream.write_varint(1000)?;
-        assert_eq!(stream.get_ref().get_ref().len(), 3);
+        assert_eq!(stream.get_ref().get_ref().len(), 2);
+
+
+        stream.get_mut().get_mut().clear();
+        stream.get_mut().set_position(0);
+        stream.write_varint(-1)?;
+        assert_eq!(stream.get_ref().get_ref().len(), 5);
 
         Ok(())
     }
@@ -0,0 +1,426 @@
+use crate::algorithm::checksum::StrongChecksum;
+use crate::algorithm::Compressor;
+use crate::algorithm::delta::DeltaInstruction;
+use crate::algorithm::generator::BlockChecksum;
+use crate::error::{Result, RsyncError};
+use crate::options::ChecksumAlgorithm;
+use crate::protocol::stream::ProtocolStream;
+use std::io::{Read, Write};
+
+
+pub const CHUNK_SIZE: usize = 32 * 1024;
+
+
+const MAX_CHECKSUM_LENGTH: i32 = 64;
+
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SumHead {
+
+    pub count: i32,
+
+    pub block_length: i32,
+
+    pub checksum_length: i32,
+
+    pub remainder: i32,
+}
+
+impl SumHead {
+    pub fn new(file_size: u64, block_length: usize, checksums: &[BlockChecksum]) -> Self {
+        if checksums.is_empty() || block_length == 0 {
+            return Self::default();
+        }
+        Self {
+            count: checksums.len() as i32,
+            block_length: block_length as i32,
+            checksum_length: checksums[0].strong.as_bytes().len() as i32,
+            remainder: (file_size % block_length as u64) as i32,
+        }
+    }
+
+
+    pub fn for_length(length: u64, block_length: usize) -> Self {
+        if length == 0 || block_length == 0 {
+            return Self::default();
+        }
+        let block_length = block_length as u64;
+        let remainder = length % block_length;
+        Self {
+            count: (length / block_length + u64::from(remainder > 0)) as i32,
+            block_length: block_length as i32,
+            checksum_length: 0,
+            remainder: remainder as i32,
+        }
+    }
+
+
+    pub fn basis_length(&self) -> u64 {
+        if self.count <= 0 {
+            return 0;
+        }
+        let full_blocks = (self.count - 1) as u64 * self.block_length as u64;
+        if self.remainder > 0 {
+            full_blocks + self.remainder as u64
+        } else {
+            full_blocks + self.block_length as u64
+        }
+    }
+
+
+    pub fn write<S: Read + Write>(&self, stream: &mut ProtocolStream<S>) -> Result<()> {
+        stream.write_i32(self.count)?;
+        stream.write_i32(self.block_length)?;
+        stream.write_i32(self.checksum_length)?;
+        stream.write_i32(self.remainder)
+    }
+
+
+    pub fn read<S: Read + Write>(stream: &mut ProtocolStream<S>) -> Result<Self> {
+        let head = Self {
+            count: stream.read_i32()?,
+            block_length: stream.read_i32()?,
+            checksum_length: stream.read_i32()?,
+            remainder: stream.read_i32()?,
+        };
+
+        let valid = head.count >= 0
+            && (0..=MAX_CHECKSUM_LENGTH).contains(&head.checksum_length)
+            && (0..=head.block_length).contains(&head.remainder)
+            && (head.count == 0 || head.block_length > 0);
+        if !valid {
+            return Err(RsyncError::Other(format!("Invalid checksum header: {:?}", head)));
+        }
+        Ok(head)
+    }
+
+
+    pub fn block_size(&self, index: u32) -> u64 {
+        if index as i32 == self.count - 1 && self.remainder > 0 {
+            self.remainder as u64
+        } else {
+            self.block_length as u64
+        }
+    }
+
+}
+
+
+pub enum Token<'a> {
+    Literal(&'a [u8]),
+    Block(u32),
+}
+
+
+pub struct DeltaCodec;
+
+impl DeltaCodec {
+    pub fn write_sums<S: Read + Write>(
+        stream: &mut ProtocolStream<S>,
+        head: &SumHead,
+        checksums: &[BlockChecksum],
+    ) -> Result<()> {
+        head.write(stream)?;
+        for checksum in checksums {
+            stream.write_i32(checksum.weak as i32)?;
+            stream.write_all(checksum.strong.as_bytes())?;
+        }
+        stream.flush()
+    }
+
+
+    pub fn read_sums<S: Read + Write>(
+        stream: &mut ProtocolStream<S>,
+        algorithm: &ChecksumAlgorithm,
+    ) -> Result<(SumHead, Vec<BlockChecksum>)> {
+        let head = SumHead::read(stream)?;
+        if head.checksum_length == 0 {
+            return Ok((head, Vec::new()));
+        }
+        if head.count > 0 && head.checksum_length as usize != strong_length(algorithm) {
+            return Err(RsyncError::Other(format!(
+                "Peer sent {}-byte block checksums but {:?} uses {} bytes; use the same --checksum-choice on both sides",
+                head.checksum_length,
+                algorithm,
+                strong_length(algorithm)
+            )));
+        }
+
+        let mut checksums = Vec::with_capacity(head.count as usize);
+        let mut strong = vec![0u8; head.checksum_length as usize];
+        for index in 0..head.count as u32 {
+            let weak = stream.read_i32()? as u32;
+            stream.read_all(&mut strong)?;
+            checksums.push(BlockChecksum {
+                index,
+                weak,
+                strong: strong_from_bytes(&strong, algorithm),
+            });
+        }
+
+        Ok((head, checksums))
+    }
+
+
+    pub fn write_tokens<S, F>(
+        stream: &mut ProtocolStream<S>,
+        instructions: &[DeltaInstruction],
+        mut on_token: F,
+    ) -> Result<()>
+    where
+        S: Read + Write,
+        F: FnMut(Token<'_>, u64),
+    {
+        let compressor = stream.compression().map(Compressor::new);
+        for instruction in instructions {
+            match instruction {
+                DeltaInstruction::MatchedBlock { index } => {
+                    stream.write_i32(-(*index as i32 + 1))?;
+                    on_token(Token::Block(*index), 4);
+                }
+                DeltaInstruction::LiteralData { data } => {
+                    for chunk in data.chunks(CHUNK_SIZE) {
+                        let wire_size = match compressor {
+                            Some(ref compressor) => write_compressed_literal(stream, compressor, chunk)?,
+                            None => {
+                                stream.write_i32(chunk.len() as i32)?;
+                                stream.write_all(chunk)?;
+                                4 + chunk.len() as u64
+                            }
+                        };
+                        on_token(Token::Literal(chunk), wire_size);
+                    }
+                }
+            }
+        }
+        stream.write_i32(0)
+    }
+
+
+    pub fn for_each_token<S, F>(stream: &mut ProtocolStream<S>, mut f: F) -> Result<()>
+    where
+        S: Read + Write,
+        F: FnMut(Token<'_>, u64) -> Result<()>,
+    {
+        let compressor = stream.compression().map(Compressor::new);
+        let mut data = Vec::with_capacity(CHUNK_SIZE);
+        loop {
+            let token = stream.read_i32()?;
+            if token == 0 {
+                return Ok(());
+            }
+
+            if token < 0 {
+                f(Token::Block((-(token as i64) - 1) as u32), 4)?;
+                continue;
+            }
+
+            if token as usize > CHUNK_SIZE {
+                return Err(RsyncError::Other(format!("Literal token of {} bytes exceeds chunk size", token)));
+            }
+            let packed = match compressor {
+                Some(_) => stream.read_i8()? != 0,
+                None => false,
+            };
+            data.resize(token as usize, 0);
+            stream.read_all(&mut data)?;
+            let wire_size = if compressor.is_some() { 5 } else { 4 } + token as u64;
+            match compressor {
+                Some(ref compressor) if packed => {
+                    let literal = compressor.decompress(&data)
+                        .map_err(|e| RsyncError::Other(format!("Failed to decompress literal token: {}", e)))?;
+                    if literal.len() > CHUNK_SIZE {
+                        return Err(RsyncError::Other(format!(
+                            "Compressed token expands to {} bytes, more than the chunk size",
+                            literal.len()
+                        )));
+                    }
+                    f(Token::Literal(&literal), wire_size)?;
+                }
+                _ => f(Token::Literal(&data), wire_size)?,
+            }
+        }
+    }
+
+
+    #[allow(dead_code)]
+    pub fn read_tokens<S: Read + Write>(stream: &mut ProtocolStream<S>) -> Result<Vec<DeltaInstruction>> {
+        let mut instructions = Vec::new();
+        Self::for_each_token(stream, |token, _| {
+            match (token, instructions.last_mut()) {
+                (Token::Block(index), _) => instructions.push(DeltaInstruction::matched_block(index)),
+                (Token::Literal(data), Some(DeltaInstruction::LiteralData { data: previous })) => {
+                    previous.extend_from_slice(data)
+                }
+                (Token::Literal(data), _) => instructions.push(DeltaInstruction::literal_data(data.to_vec())),
+            }
+            Ok(())
+        })?;
+        Ok(instructions)
+    }
+}
+
+
+fn write_compressed_literal<S: Read + Write>(
+    stream: &mut ProtocolStream<S>,
+    compressor: &Compressor,
+    chunk: &[u8],
+) -> Result<u64> {
+    let packed = compressor.compress(chunk)
+        .map_err(|e| RsyncError::Other(format!("Failed to compress literal token: {}", e)))?;
+    let (flag, payload) = if packed.len() < chunk.len() { (1, packed.as_slice()) } else { (0, chunk) };
+    stream.write_i32(payload.len() as i32)?;
+    stream.write_i8(flag)?;
+    stream.write_all(payload)?;
+    Ok(5 + payload.len() as u64)
+}
+
+
+fn strong_length(algorithm: &ChecksumAlgorithm) -> usize {
+    match algorithm {
+        ChecksumAlgorithm::Blake2 => 64,
+        ChecksumAlgorithm::Md4 | ChecksumAlgorithm::Md5 | ChecksumAlgorithm::Xxh128 => 16,
+        ChecksumAlgorithm::Xxh64 | ChecksumAlgorithm::Xxh3 => 8,
+    }
+}
+
+
+fn strong_from_bytes(bytes: &[u8], algorithm: &ChecksumAlgorithm) -> StrongChecksum {
+    match algorithm {
+        ChecksumAlgorithm::Blake2 => {
+            let mut strong = [0u8; 64];
+            strong.copy_from_slice(bytes);
+            StrongChecksum::Blake2(strong)
+        }
+        ChecksumAlgorithm::Md4 => {
+            let mut strong = [0u8; 16];
+            strong.copy_from_slice(bytes);
+            StrongChecksum::Md4(strong)
+        }
+        ChecksumAlgorithm::Md5 => {
+            let mut strong = [0u8; 16];
+            strong.copy_from_slice(bytes);
+            StrongChecksum::Md5(strong)
+        }
+        ChecksumAlgorithm::Xxh64 => {
+            let mut strong = [0u8; 8];
+            strong.copy_from_slice(bytes);
+            StrongChecksum::Xxh64(strong)
+        }
+        ChecksumAlgorithm::Xxh3 => {
+            let mut strong = [0u8; 8];
+            strong.copy_from_slice(bytes);
+            StrongChecksum::Xxh3(strong)
+        }
+        ChecksumAlgorithm::Xxh128 => {
+            let mut strong = [0u8; 16];
+            strong.copy_from_slice(bytes);
+            StrongChecksum::Xxh128(strong)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algorithm::generator::Generator;
+    use crate::options::CompressionAlgorithm;
+    use std::io::Cursor;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_sums_round_trip() -> Result<()> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join("basis.bin");
+        let content: Vec<u8> = (0..1000u32).map(|i| (i % 253) as u8).collect();
+        std::fs::write(&path, &content)?;
+
+        let checksums = Generator::new(300, ChecksumAlgorithm::Md5).generate_checksums(&path)?;
+        let head = SumHead::new(content.len() as u64, 300, &checksums);
+        assert_eq!(head, SumHead { count: 4, block_length: 300, checksum_length: 16, remainder: 100 });
+        assert_eq!(head.block_size(0), 300);
+        assert_eq!(head.block_size(3), 100);
+
+        let mut buffer = Cursor::new(Vec::new());
+        let mut stream = ProtocolStream::new(&mut buffer, 31);
+        DeltaCodec::write_sums(&mut stream, &head, &checksums)?;
+
+        stream.get_mut().set_position(0);
+        let (decoded_head, decoded) = DeltaCodec::read_sums(&mut stream, &ChecksumAlgorithm::Md5)?;
+        assert_eq!(decoded_head, head);
+        assert_eq!(decoded.len(), checksums.len());
+        for (original, decoded) in checksums.iter().zip(&decoded) {
+            assert_eq!(original.weak, decoded.weak);
+            assert_eq!(original.strong, decoded.strong);
+        }
+
+        stream.get_mut().set_position(0);
+        assert!(DeltaCodec::read_sums(&mut stream, &ChecksumAlgorithm::Blake2).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_length_only_head() -> Result<()> {
+        let head = SumHead::for_length(1000, 300);
+        assert_eq!(head, SumHead { count: 4, block_length: 300, checksum_length: 0, remainder: 100 });
+        assert_eq!(head.basis_length(), 1000);
+        assert_eq!(SumHead::for_length(900, 300).basis_length(), 900);
+        assert_eq!(SumHead::default().basis_length(), 0);
+
+        let mut buffer = Cursor::new(Vec::new());
+        let mut stream = ProtocolStream::new(&mut buffer, 31);
+        DeltaCodec::write_sums(&mut stream, &head, &[])?;
+        stream.write_i32(0x1234)?;
+
+        stream.get_mut().set_position(0);
+        let (decoded, checksums) = DeltaCodec::read_sums(&mut stream, &ChecksumAlgorithm::Md5)?;
+        assert_eq!(decoded, head);
+        assert!(checksums.is_empty());
+        assert_eq!(stream.read_i32()?, 0x1234);
+        Ok(())
+    }
+
+    #[test]
+    fn test_tokens_round_trip() -> Result<()> {
+        let instructions = vec![
+            DeltaInstruction::matched_block(0),
+            DeltaInstruction::literal_data(vec![7u8; CHUNK_SIZE + 10]),
+            DeltaInstruction::matched_block(3),
+        ];
+
+        let mut buffer = Cursor::new(Vec::new());
+        let mut stream = ProtocolStream::new(&mut buffer, 31);
+        let mut wire_bytes = 0;
+        DeltaCodec::write_tokens(&mut stream, &instructions, |_, wire_size| wire_bytes += wire_size)?;
+        DeltaCodec::write_tokens(&mut stream, &[], |_, _| {})?;
+        assert_eq!(wire_bytes, 4 + (4 + CHUNK_SIZE as u64) + (4 + 10) + 4);
+
+        stream.get_mut().set_position(0);
+        assert_eq!(DeltaCodec::read_tokens(&mut stream)?, instructions);
+        assert!(DeltaCodec::read_tokens(&mut stream)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_compressed_tokens_round_trip() -> Result<()> {
+        let random: Vec<u8> = (0..4096u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8).collect();
+        let instructions = vec![
+            DeltaInstruction::literal_data(b"compressible ".repeat(4000)),
+            DeltaInstruction::matched_block(1),
+            DeltaInstruction::literal_data(random),
+        ];
+
+        for algorithm in [CompressionAlgorithm::Zstd, CompressionAlgorithm::Lz4, CompressionAlgorithm::Zlib] {
+            let mut buffer = Cursor::new(Vec::new());
+            let mut stream = ProtocolStream::new(&mut buffer, 31);
+            stream.set_compression(Some(algorithm));
+            let mut wire_bytes = 0;
+            DeltaCodec::write_tokens(&mut stream, &instructions, |_, wire_size| wire_bytes += wire_size)?;
+            assert!(wire_bytes < 13 * 4000);
+
+            stream.get_mut().set_position(0);
+            assert_eq!(DeltaCodec::read_tokens(&mut stream)?, instructions);
+        }
+        Ok(())
+    }
+}
@@ -1,10 +1,24 @@
 use crate::error::{Result, RsyncError};
+use crate::options::{ChecksumAlgorithm, CompressionAlgorithm};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 
 pub const PROTOCOL_VERSION_MIN: i32 = 27;
 pub const PROTOCOL_VERSION_MAX: i32 = 31;
 
 
+pub const CF_INC_RECURSE: i32 = 1 << 0;
+
+
+pub const CF_CHKSUM_SEED_FIX: i32 = 1 << 5;
+
+
+pub const CHECKSUM_NEGOTIATION: &str = "xxh128 xxh3 xxh64 md5 md4";
+
+
+pub const COMPRESSION_NEGOTIATION: &str = "zstd lz4 zlibx zlib";
+
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct ProtocolVersion {
     pub version: i32,
@@ -42,10 +56,64 @@ impl ProtocolVersion {
     }
 }
 
+
+
+fn first_common<'a>(preferred: &'a str, supported: &str) -> Option<&'a str> {
+    preferred.split_whitespace()
+        .find(|name| supported.split_whitespace().any(|other| other == *name))
+}
+
+
+pub fn negotiate_checksum(preferred: &str, supported: &str) -> Option<ChecksumAlgorithm> {
+    first_common(preferred, supported).and_then(ChecksumAlgorithm::from_name)
+}
+
+
+pub fn negotiate_compression(preferred: &str, supported: &str) -> Option<CompressionAlgorithm> {
+    match first_common(preferred, supported)? {
+        "zlibx" => Some(CompressionAlgorithm::Zlib),
+        name => CompressionAlgorithm::from_name(name),
+    }
+}
+
+
+pub fn choose_checksum_seed(requested: Option<i32>) -> i32 {
+    match requested {
+        Some(seed) if seed != 0 => seed,
+        _ => {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+            (now as i32) ^ ((std::process::id() as i32) << 6)
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_choose_checksum_seed() {
+        assert_eq!(choose_checksum_seed(Some(12345)), 12345);
+        assert_eq!(choose_checksum_seed(Some(-7)), -7);
+        assert_ne!(choose_checksum_seed(None), 0);
+    }
+
+    #[test]
+    fn test_negotiate_checksum() {
+        assert_eq!(negotiate_checksum(CHECKSUM_NEGOTIATION, CHECKSUM_NEGOTIATION), Some(ChecksumAlgorithm::Xxh128));
+        assert_eq!(negotiate_checksum(CHECKSUM_NEGOTIATION, "md5 md4"), Some(ChecksumAlgorithm::Md5));
+        assert_eq!(negotiate_checksum("xxh3", CHECKSUM_NEGOTIATION), Some(ChecksumAlgorithm::Xxh3));
+        assert_eq!(negotiate_checksum("blake2", CHECKSUM_NEGOTIATION), None);
+    }
+
+    #[test]
+    fn test_negotiate_compression() {
+        assert_eq!(negotiate_compression(COMPRESSION_NEGOTIATION, COMPRESSION_NEGOTIATION), Some(CompressionAlgorithm::Zstd));
+        assert_eq!(negotiate_compression(COMPRESSION_NEGOTIATION, "zlibx zlib"), Some(CompressionAlgorithm::Zlib));
+        assert_eq!(negotiate_compression("lz4", COMPRESSION_NEGOTIATION), Some(CompressionAlgorithm::Lz4));
+        assert_eq!(negotiate_compression("zstd", "zlib"), None);
+    }
+
     #[test]
     fn test_negotiate_success() {
 
@@ -0,0 +1,306 @@
+use std::path::Path;
+use chrono::{NaiveTime, Weekday};
+use twox_hash::XxHash64;
+use crate::error::{Result, RsyncError};
+use crate::filesystem::privileges::quote_argument;
+
+
+const TASK_FOLDER: &str = "\\YARW\\";
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Schedule {
+    Hourly,
+    Daily(NaiveTime),
+    Weekly(Vec<Weekday>, NaiveTime),
+    Logon,
+}
+
+impl Schedule {
+
+    pub fn parse(spec: &str) -> Result<Self> {
+        let words: Vec<String> = spec.split_whitespace().map(str::to_lowercase).collect();
+        let words: Vec<&str> = words.iter().map(String::as_str).collect();
+        let schedule = match words.as_slice() {
+            ["hourly"] => Some(Schedule::Hourly),
+            ["daily", time] => parse_time(time).map(Schedule::Daily),
+            ["weekly", days, time] => days
+                .split(',')
+                .map(|day| day.trim().parse::<Weekday>().ok())
+                .collect::<Option<Vec<_>>>()
+                .zip(parse_time(time))
+                .map(|(days, time)| Schedule::Weekly(days, time)),
+            ["logon"] | ["onlogon"] => Some(Schedule::Logon),
+            _ => None,
+        };
+        schedule.ok_or_else(|| RsyncError::InvalidOption(format!(
+            "Invalid --schedule: {}. Use \"hourly\", \"daily HH:MM\", \"weekly DAY[,DAY...] HH:MM\" or \"logon\"",
+            spec
+        )))
+    }
+
+
+    fn trigger(&self, date: &str, user: Option<&str>) -> String {
+        match self {
+            Schedule::Hourly => format!(
+                "<TimeTrigger><Repetition><Interval>PT1H</Interval><StopAtDurationEnd>false</StopAtDurationEnd>\
+                 </Repetition><StartBoundary>{}T00:00:00</StartBoundary></TimeTrigger>",
+                date
+            ),
+            Schedule::Daily(time) => format!(
+                "<CalendarTrigger><StartBoundary>{}T{}</StartBoundary>\
+                 <ScheduleByDay><DaysInterval>1</DaysInterval></ScheduleByDay></CalendarTrigger>",
+                date, time.format("%H:%M:%S")
+            ),
+            Schedule::Weekly(days, time) => {
+                let days: String = days.iter().map(|day| format!("<{} />", day_name(*day))).collect();
+                format!(
+                    "<CalendarTrigger><StartBoundary>{}T{}</StartBoundary><ScheduleByWeek>\
+                     <DaysOfWeek>{}</DaysOfWeek><WeeksInterval>1</WeeksInterval></ScheduleByWeek></CalendarTrigger>",
+                    date, time.format("%H:%M:%S"), days
+                )
+            }
+            Schedule::Logon => match user {
+                Some(user) => format!("<LogonTrigger><UserId>{}</UserId></LogonTrigger>", escape_xml(user)),
+                None => "<LogonTrigger />".to_string(),
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for Schedule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Schedule::Hourly => write!(f, "every hour"),
+            Schedule::Daily(time) => write!(f, "daily at {}", time.format("%H:%M")),
+            Schedule::Weekly(days, time) => {
+                let days: Vec<&str> = days.iter().map(|day| day_name(*day)).collect();
+                write!(f, "every {} at {}", days.join(", "), time.format("%H:%M"))
+            }
+            Schedule::Logon => write!(f, "at logon"),
+        }
+    }
+}
+
+
+fn parse_time(time: &str) -> Option<NaiveTime> {
+    NaiveTime::parse_from_str(time, "%H:%M").ok()
+}
+
+
+fn day_name(day: Weekday) -> &'static str {
+    match day {
+        Weekday::Mon => "Monday",
+        Weekday::Tue => "Tuesday",
+        Weekday::Wed => "Wednesday",
+        Weekday::Thu => "Thursday",
+        Weekday::Fri => "Friday",
+        Weekday::Sat => "Saturday",
+        Weekday::Sun => "Sunday",
+    }
+}
+
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+
+pub fn task_args<I: IntoIterator<Item = String>>(args: I) -> Vec<String> {
+    let mut kept = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--schedule" => {
+                args.next();
+            }
+            "--unschedule" => {}
+            _ if arg.starts_with("--schedule=") => {}
+            _ => kept.push(arg),
+        }
+    }
+    kept
+}
+
+
+pub fn task_name(args: &[String], working_dir: &Path) -> String {
+    let mut key = working_dir.to_string_lossy().into_owned();
+    for arg in args {
+        key.push('\0');
+        key.push_str(arg);
+    }
+    format!("{}yarw-{:016x}", TASK_FOLDER, XxHash64::oneshot(0, key.as_bytes()))
+}
+
+
+pub fn task_xml(schedule: &Schedule, program: &Path, args: &[String], working_dir: &Path, date: &str, user: Option<&str>) -> String {
+    let mut args = args.to_vec();
+    if !args.iter().any(|arg| arg == "--no-prompt" || arg == "--batch-mode") {
+        args.push("--no-prompt".to_string());
+    }
+    let arguments: Vec<String> = args.iter().map(|arg| quote_argument(arg)).collect();
+    let arguments = escape_xml(&arguments.join(" "));
+
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-16\"?>\n\
+         <Task version=\"1.2\" xmlns=\"http://schemas.microsoft.com/windows/2004/02/mit/task\">\n\
+         <RegistrationInfo><Description>yarw {}</Description></RegistrationInfo>\n\
+         <Triggers>{}</Triggers>\n\
+         <Settings><MultipleInstancesPolicy>IgnoreNew</MultipleInstancesPolicy>\
+         <DisallowStartIfOnBatteries>false</DisallowStartIfOnBatteries>\
+         <StopIfGoingOnBatteries>false</StopIfGoingOnBatteries>\
+         <StartWhenAvailable>true</StartWhenAvailable>\
+         <ExecutionTimeLimit>PT0S</ExecutionTimeLimit></Settings>\n\
+         <Actions Context=\"Author\"><Exec><Command>{}</Command><Arguments>{}</Arguments>\
+         <WorkingDirectory>{}</WorkingDirectory></Exec></Actions>\n\
+         </Task>\n",
+        arguments,
+        schedule.trigger(date, user),
+        escape_xml(&program.to_string_lossy()),
+        arguments,
+        escape_xml(&working_dir.to_string_lossy()),
+    )
+}
+
+
+pub fn register(schedule: &Schedule) -> Result<String> {
+    let args = task_args(std::env::args().skip(1));
+    let working_dir = std::env::current_dir()?;
+    let name = task_name(&args, &working_dir);
+    let user = match (std::env::var("USERDOMAIN"), std::env::var("USERNAME")) {
+        (Ok(domain), Ok(user)) => Some(format!("{}\\{}", domain, user)),
+        _ => None,
+    };
+    let date = chrono::Local::now().format("%Y-%m-%d").to_string();
+    let xml = task_xml(schedule, &std::env::current_exe()?, &args, &working_dir, &date, user.as_deref());
+    platform::create_task(&name, &xml)?;
+    Ok(name)
+}
+
+
+pub fn unregister() -> Result<String> {
+    let args = task_args(std::env::args().skip(1));
+    let name = task_name(&args, &std::env::current_dir()?);
+    platform::delete_task(&name)?;
+    Ok(name)
+}
+
+
+#[cfg(windows)]
+mod platform {
+    use std::io::Write;
+    use std::process::{Command, Output};
+    use crate::error::{Result, RsyncError};
+
+    pub fn create_task(name: &str, xml: &str) -> Result<()> {
+        let mut file = tempfile::Builder::new().prefix("yarw-task").suffix(".xml").tempfile()?;
+        let mut encoded = vec![0xFF, 0xFE];
+        encoded.extend(xml.encode_utf16().flat_map(u16::to_le_bytes));
+        file.write_all(&encoded)?;
+        file.flush()?;
+
+        let output = Command::new("schtasks")
+            .args(["/Create", "/F", "/TN", name, "/XML"])
+            .arg(file.path())
+            .output()?;
+        check("register", name, output)
+    }
+
+    pub fn delete_task(name: &str) -> Result<()> {
+        let output = Command::new("schtasks").args(["/Delete", "/F", "/TN", name]).output()?;
+        check("remove", name, output)
+    }
+
+    fn check(action: &str, name: &str, output: Output) -> Result<()> {
+        if output.status.success() {
+            return Ok(());
+        }
+        Err(RsyncError::Other(format!(
+            "could not {} scheduled task {}: {}",
+            action,
+            name,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )))
+    }
+}
+
+
+#[cfg(not(windows))]
+mod platform {
+    use crate::error::{Result, RsyncError};
+
+    pub fn create_task(_name: &str, _xml: &str) -> Result<()> {
+        Err(RsyncError::InvalidOption("--schedule is only supported on Windows; use cron or a systemd timer".to_string()))
+    }
+
+    pub fn delete_task(_name: &str) -> Result<()> {
+        Err(RsyncError::InvalidOption("--unschedule is only supported on Windows".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_schedule() -> Result<()> {
+        let two_am = NaiveTime::from_hms_opt(2, 0, 0).unwrap();
+        assert_eq!(Schedule::parse("daily 02:00")?, Schedule::Daily(two_am));
+        assert_eq!(Schedule::parse("Weekly mon,fri 02:00")?, Schedule::Weekly(vec![Weekday::Mon, Weekday::Fri], two_am));
+        assert_eq!(Schedule::parse("hourly")?, Schedule::Hourly);
+        assert_eq!(Schedule::parse("logon")?, Schedule::Logon);
+        assert_eq!(Schedule::parse("weekly sat 23:30")?.to_string(), "every Saturday at 23:30");
+
+        assert!(Schedule::parse("daily 25:00").is_err());
+        assert!(Schedule::parse("weekly someday 02:00").is_err());
+        assert!(Schedule::parse("daily").is_err());
+        assert!(Schedule::parse("").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_task_args_and_name() {
+        let scheduled = task_args(args(&["-av", "--schedule", "daily 02:00", "src/", "D:/Backup/"]));
+        assert_eq!(scheduled, args(&["-av", "src/", "D:/Backup/"]));
+        let removed = task_args(args(&["-av", "--unschedule", "src/", "D:/Backup/"]));
+        assert_eq!(removed, scheduled);
+        assert_eq!(task_args(args(&["--schedule=hourly", "a", "b"])), args(&["a", "b"]));
+
+        let cwd = Path::new("C:/Users/alice");
+        let name = task_name(&scheduled, cwd);
+        assert!(name.starts_with("\\YARW\\yarw-"));
+        assert_eq!(name, task_name(&removed, cwd));
+        assert_ne!(name, task_name(&scheduled, Path::new("C:/Users/bob")));
+        assert_ne!(name, task_name(&args(&["-av", "src/", "E:/Backup/"]), cwd));
+    }
+
+    #[test]
+    fn test_task_xml() -> Result<()> {
+        let xml = task_xml(
+            &Schedule::parse("weekly mon,thu 02:00")?,
+            Path::new("C:/Tools/yarw.exe"),
+            &args(&["-av", "My Documents/", "//nas/backup & archive/"]),
+            Path::new("C:/Users/alice"),
+            "2026-10-16",
+            Some("CORP\\alice"),
+        );
+        assert!(xml.contains("<StartBoundary>2026-10-16T02:00:00</StartBoundary>"));
+        assert!(xml.contains("<DaysOfWeek><Monday /><Thursday /></DaysOfWeek>"));
+        assert!(xml.contains("<Command>C:/Tools/yarw.exe</Command>"));
+        assert!(xml.contains("<Arguments>-av &quot;My Documents/&quot; &quot;//nas/backup &amp; archive/&quot; --no-prompt</Arguments>"));
+        assert!(xml.contains("<WorkingDirectory>C:/Users/alice</WorkingDirectory>"));
+
+        let logon = task_xml(&Schedule::Logon, Path::new("yarw.exe"), &args(&["--batch-mode", "a", "b"]), Path::new("."), "2026-10-16", Some("CORP\\alice"));
+        assert!(logon.contains("<LogonTrigger><UserId>CORP\\alice</UserId></LogonTrigger>"));
+        assert!(logon.contains("<Arguments>--batch-mode a b</Arguments>"));
+        Ok(())
+    }
+}
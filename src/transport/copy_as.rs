@@ -0,0 +1,152 @@
+use std::io::Write;
+use std::sync::OnceLock;
+use crate::error::{Result, RsyncError};
+use crate::options::Options;
+use crate::output::VerboseOutput;
+use super::credentials;
+
+
+const PASSWORD_ENV: &str = "YARW_COPY_AS_PASSWORD";
+
+
+static TOKEN: OnceLock<platform::Token> = OnceLock::new();
+
+
+pub fn start_copy_as(account: &str, options: &Options) -> Result<()> {
+    if !cfg!(windows) {
+        return Err(RsyncError::InvalidOption("--copy-as is only supported on Windows".to_string()));
+    }
+    let verbose = options.verbose_output();
+    let (domain, user) = split_account(account);
+    let target = credentials::copy_as_target(account);
+    let (password, prompted) = password(account, &target, options, &verbose)?;
+
+    let token = platform::logon(domain, user, &password)?;
+    platform::impersonate(&token)?;
+    if TOKEN.set(token).is_err() {
+        return Err(RsyncError::Other("--copy-as identity is already active".to_string()));
+    }
+    rayon::ThreadPoolBuilder::new()
+        .start_handler(|_| {
+            let _ = impersonate_current_thread();
+        })
+        .build_global()
+        .map_err(|e| RsyncError::Other(format!("Failed to start --copy-as workers: {}", e)))?;
+
+    if prompted && options.use_credential_manager {
+        match credentials::store(&target, account, &password) {
+            Ok(()) => verbose.print_verbose(format!("Saved password to Credential Manager as {}", target)),
+            Err(e) => verbose.print_warning(e.to_string()),
+        }
+    }
+    verbose.print_verbose(format!("Writing to network destinations as {}", account));
+    Ok(())
+}
+
+
+pub fn impersonate_current_thread() -> Result<()> {
+    match TOKEN.get() {
+        Some(token) => platform::impersonate(token),
+        None => Ok(()),
+    }
+}
+
+
+fn split_account(account: &str) -> (Option<&str>, &str) {
+    match account.split_once('\\') {
+        Some((domain, user)) => (Some(domain), user),
+        None => (None, account),
+    }
+}
+
+
+fn password(account: &str, target: &str, options: &Options, verbose: &VerboseOutput) -> Result<(String, bool)> {
+    if options.use_credential_manager {
+        match credentials::load(target) {
+            Ok(Some(password)) => return Ok((password, false)),
+            Ok(None) => verbose.print_verbose(format!("No password stored in Credential Manager as {}", target)),
+            Err(e) => verbose.print_warning(e.to_string()),
+        }
+    }
+    if let Ok(password) = std::env::var(PASSWORD_ENV) {
+        return Ok((password, false));
+    }
+    if options.no_prompt {
+        return Err(RsyncError::Auth(format!(
+            "--copy-as={} needs a password and prompts are disabled; \
+             store one with --use-credential-manager or set {}",
+            account, PASSWORD_ENV
+        )));
+    }
+
+    print!("Password for {}: ", account);
+    std::io::stdout().flush()?;
+    let password = rpassword::read_password()
+        .map_err(|e| RsyncError::Auth(format!("Failed to read password: {}", e)))?;
+    Ok((password, true))
+}
+
+
+#[cfg(windows)]
+mod platform {
+    use crate::error::{Result, RsyncError};
+    use windows::core::{HSTRING, PCWSTR};
+    use windows::Win32::Foundation::HANDLE;
+    use windows::Win32::Security::{
+        ImpersonateLoggedOnUser, LogonUserW, LOGON32_LOGON_NEW_CREDENTIALS, LOGON32_PROVIDER_WINNT50,
+    };
+
+    pub struct Token(HANDLE);
+
+    pub fn logon(domain: Option<&str>, user: &str, password: &str) -> Result<Token> {
+        let domain = domain.map(HSTRING::from);
+        let domain = domain.as_ref().map_or(PCWSTR::null(), |domain| PCWSTR(domain.as_ptr()));
+        let mut token = HANDLE::default();
+        unsafe {
+            LogonUserW(
+                &HSTRING::from(user),
+                domain,
+                &HSTRING::from(password),
+                LOGON32_LOGON_NEW_CREDENTIALS,
+                LOGON32_PROVIDER_WINNT50,
+                &mut token,
+            )
+        }
+        .map_err(|e| RsyncError::Auth(format!("Failed to log on as {}: {}", user, e)))?;
+        Ok(Token(token))
+    }
+
+    pub fn impersonate(token: &Token) -> Result<()> {
+        unsafe { ImpersonateLoggedOnUser(token.0) }
+            .map_err(|e| RsyncError::Auth(format!("Failed to impersonate --copy-as user: {}", e)))
+    }
+}
+
+
+#[cfg(not(windows))]
+mod platform {
+    use crate::error::{Result, RsyncError};
+
+    pub struct Token;
+
+    pub fn logon(_domain: Option<&str>, _user: &str, _password: &str) -> Result<Token> {
+        Err(RsyncError::InvalidOption("--copy-as is only supported on Windows".to_string()))
+    }
+
+    pub fn impersonate(_token: &Token) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_account() {
+        assert_eq!(split_account("CORP\\backup"), (Some("CORP"), "backup"));
+        assert_eq!(split_account(".\\svc-sync"), (Some("."), "svc-sync"));
+        assert_eq!(split_account("backup@corp.example.com"), (None, "backup@corp.example.com"));
+        assert_eq!(split_account("backup"), (None, "backup"));
+    }
+}
@@ -0,0 +1,139 @@
+use crate::error::Result;
+
+
+const SSH_PORT: u16 = 22;
+const DAEMON_PORT: u16 = 873;
+
+
+pub fn ssh_target(user: &str, host: &str, port: u16) -> String {
+    match port {
+        SSH_PORT => format!("yarw/ssh:{}@{}", user, host),
+        port => format!("yarw/ssh:{}@{}:{}", user, host, port),
+    }
+}
+
+
+pub fn daemon_target(user: &str, host: &str, port: u16, module: &str) -> String {
+    match port {
+        DAEMON_PORT => format!("yarw/rsync:{}@{}/{}", user, host, module),
+        port => format!("yarw/rsync:{}@{}:{}/{}", user, host, port, module),
+    }
+}
+
+
+pub fn copy_as_target(account: &str) -> String {
+    format!("yarw/copy-as:{}", account)
+}
+
+
+pub fn load(target: &str) -> Result<Option<String>> {
+    platform::load(target)
+}
+
+
+pub fn store(target: &str, user: &str, password: &str) -> Result<()> {
+    platform::store(target, user, password)
+}
+
+
+#[cfg(windows)]
+mod platform {
+    use crate::error::{Result, RsyncError};
+    use std::ptr;
+    use windows::core::{HSTRING, PWSTR};
+    use windows::Win32::Foundation::ERROR_NOT_FOUND;
+    use windows::Win32::Security::Credentials::{
+        CredFree, CredReadW, CredWriteW, CREDENTIALW, CRED_PERSIST_LOCAL_MACHINE, CRED_TYPE_GENERIC,
+    };
+
+    pub fn load(target: &str) -> Result<Option<String>> {
+        let mut credential: *mut CREDENTIALW = ptr::null_mut();
+        match unsafe { CredReadW(&HSTRING::from(target), CRED_TYPE_GENERIC, 0, &mut credential) } {
+            Ok(()) => {}
+            Err(e) if e.code() == ERROR_NOT_FOUND.to_hresult() => return Ok(None),
+            Err(e) => return Err(RsyncError::Auth(format!(
+                "Failed to read {} from Credential Manager: {}",
+                target, e
+            ))),
+        }
+
+        let blob = unsafe {
+            let blob = &*credential;
+            if blob.CredentialBlob.is_null() {
+                Vec::new()
+            } else {
+                std::slice::from_raw_parts(blob.CredentialBlob, blob.CredentialBlobSize as usize).to_vec()
+            }
+        };
+        unsafe { CredFree(credential as *const _) };
+        Ok(Some(decode_blob(&blob)))
+    }
+
+    pub fn store(target: &str, user: &str, password: &str) -> Result<()> {
+        let mut target_name = wide(target);
+        let mut user_name = wide(user);
+        let mut blob = encode_blob(password);
+        let credential = CREDENTIALW {
+            Type: CRED_TYPE_GENERIC,
+            TargetName: PWSTR(target_name.as_mut_ptr()),
+            UserName: PWSTR(user_name.as_mut_ptr()),
+            CredentialBlobSize: blob.len() as u32,
+            CredentialBlob: blob.as_mut_ptr(),
+            Persist: CRED_PERSIST_LOCAL_MACHINE,
+            ..Default::default()
+        };
+        unsafe { CredWriteW(&credential, 0) }.map_err(|e| RsyncError::Auth(format!(
+            "Failed to save {} to Credential Manager: {}",
+            target, e
+        )))
+    }
+
+    fn wide(s: &str) -> Vec<u16> {
+        s.encode_utf16().chain(std::iter::once(0)).collect()
+    }
+
+    fn encode_blob(password: &str) -> Vec<u8> {
+        password.encode_utf16().flat_map(u16::to_le_bytes).collect()
+    }
+
+    fn decode_blob(blob: &[u8]) -> String {
+        let pairs = blob.chunks_exact(2);
+        if !pairs.remainder().is_empty() {
+            return String::from_utf8_lossy(blob).into_owned();
+        }
+        let units: Vec<u16> = pairs.map(|pair| u16::from_le_bytes([pair[0], pair[1]])).collect();
+        String::from_utf16_lossy(&units)
+    }
+}
+
+
+#[cfg(not(windows))]
+mod platform {
+    use crate::error::{Result, RsyncError};
+
+    pub fn load(_target: &str) -> Result<Option<String>> {
+        Err(unavailable())
+    }
+
+    pub fn store(_target: &str, _user: &str, _password: &str) -> Result<()> {
+        Err(unavailable())
+    }
+
+    fn unavailable() -> RsyncError {
+        RsyncError::Auth("Windows Credential Manager is not available on this platform".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_target_names() {
+        assert_eq!(ssh_target("backup", "nas", 22), "yarw/ssh:backup@nas");
+        assert_eq!(ssh_target("backup", "nas", 2222), "yarw/ssh:backup@nas:2222");
+        assert_eq!(daemon_target("backup", "nas", 873, "photos"), "yarw/rsync:backup@nas/photos");
+        assert_eq!(daemon_target("backup", "nas", 8873, "photos"), "yarw/rsync:backup@nas:8873/photos");
+        assert_eq!(copy_as_target("CORP\\backup"), "yarw/copy-as:CORP\\backup");
+    }
+}
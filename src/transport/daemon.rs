@@ -1,141 +1,561 @@
 use crate::transport::daemon_config::{DaemonConfig, ModuleConfig};
-use crate::protocol::{AsyncProtocolStream, PROTOCOL_VERSION_MAX};
+use crate::transport::daemon_hosts::{self, HostAccess};
+use crate::transport::daemon_metrics::{DaemonMetrics, Metered, SessionCounters};
+use crate::transport::daemon_log::{DaemonLogger, SessionLog};
+use crate::transport::{SocketOptions, DEFAULT_NET_BUFFER_SIZE};
+use crate::transport::daemon_protocol::{
+    auth_challenge, check_auth, module_listing, parse_auth_user, AuthAccess, read_args, read_line, resolve_source, serve_sender, Greeting,
+    ModuleRequest, ServerArgs, GREETING_PREFIX, MIN_RSYNC_PROTOCOL,
+};
+use crate::protocol::{choose_checksum_seed, AsyncProtocolStream, MultiplexReader, MultiplexWriter, PROTOCOL_VERSION_MAX};
+use crate::protocol::message::MessageTag;
+use crate::algorithm::{Compressor, DontCompress, ThrottledWriter, TokenBucket, DEFAULT_DONT_COMPRESS};
+use crate::options::CompressionAlgorithm;
 use crate::filesystem::Scanner;
-use crate::output::VerboseOutput;
+use tokio::io::{AsyncWriteExt, Join, ReadHalf, WriteHalf};
 use tokio::net::{TcpListener, TcpStream};
 use anyhow::{Result, Context, bail};
 use std::fs;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(30);
+
+
+const ERROR_DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+
+type DaemonSocket = Metered<TcpStream>;
+
+
+struct UploadQuota {
+    max_size: Option<u64>,
+    max_files: Option<usize>,
+    bytes: u64,
+    files: usize,
+}
+
+impl UploadQuota {
+    fn new(module_config: &ModuleConfig) -> Self {
+        UploadQuota {
+            max_size: module_config.max_session_size,
+            max_files: module_config.max_session_files,
+            bytes: 0,
+            files: 0,
+        }
+    }
+
+    fn check_count(&self, num_files: usize) -> Result<()> {
+        if let Some(max_files) = self.max_files {
+            if num_files > max_files {
+                bail!("Upload rejected: {} files exceeds the module limit of {} files per session", num_files, max_files);
+            }
+        }
+        Ok(())
+    }
+
+    fn admit(&mut self, file_path: &str, file_size: u64) -> Result<()> {
+        self.check_count(self.files + 1)?;
+        if let Some(max_size) = self.max_size {
+            if self.bytes + file_size > max_size {
+                bail!(
+                    "Upload rejected at {}: session would reach {} bytes, exceeding the module limit of {} bytes",
+                    file_path,
+                    self.bytes + file_size,
+                    max_size
+                );
+            }
+        }
+        self.files += 1;
+        self.bytes += file_size;
+        Ok(())
+    }
+}
+
+
+type ServerStream = AsyncProtocolStream<Join<MultiplexReader<ReadHalf<DaemonSocket>>, MultiplexWriter<ThrottledWriter<WriteHalf<DaemonSocket>>>>>;
+
+struct DaemonState {
+    config: Arc<DaemonConfig>,
+    bwlimit_kb: Option<u64>,
+    bwlimit: Option<Arc<TokenBucket>>,
+    socket_options: SocketOptions,
+}
+
+impl DaemonState {
+    fn new(config: DaemonConfig, defaults: &RsyncDaemon, previous: Option<&DaemonState>) -> Result<Self> {
+        let bwlimit_kb = config.bwlimit.or(defaults.default_bwlimit);
+        let bwlimit = match previous {
+            Some(previous) if previous.bwlimit_kb == bwlimit_kb => previous.bwlimit.clone(),
+            _ => bwlimit_kb.map(|kb| Arc::new(TokenBucket::new(kb * 1024))),
+        };
+        daemon_hosts::validate(&config)?;
+        for (name, module) in &config.modules {
+            for entry in module.auth_users.iter().flatten() {
+                parse_auth_user(entry).with_context(|| format!("module [{}]", name))?;
+            }
+        }
+        let fallback = defaults.socket_options
+            .unwrap_or_else(|| SocketOptions::tuned(DEFAULT_NET_BUFFER_SIZE));
+        let socket_options = match config.socket_options {
+            Some(ref spec) => SocketOptions::parse(spec)?.or(fallback),
+            None => fallback,
+        };
+        Ok(DaemonState { config: Arc::new(config), bwlimit_kb, bwlimit, socket_options })
+    }
+}
+
+
+#[cfg(unix)]
+fn reload_signal() -> std::io::Result<tokio::signal::unix::Signal> {
+    tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+}
+
+
+#[cfg(windows)]
+fn reload_signal() -> std::io::Result<tokio::signal::windows::CtrlBreak> {
+    tokio::signal::windows::ctrl_break()
+}
+
+
+fn describe_module_changes(old: &DaemonConfig, new: &DaemonConfig) -> String {
+    let mut added: Vec<&str> = new.modules.keys()
+        .filter(|name| !old.modules.contains_key(*name))
+        .map(String::as_str)
+        .collect();
+    let mut removed: Vec<&str> = old.modules.keys()
+        .filter(|name| !new.modules.contains_key(*name))
+        .map(String::as_str)
+        .collect();
+    let mut changed: Vec<&str> = new.modules.iter()
+        .filter(|(name, module)| old.modules.get(*name).is_some_and(|old| old != *module))
+        .map(|(name, _)| name.as_str())
+        .collect();
+    added.sort_unstable();
+    removed.sort_unstable();
+    changed.sort_unstable();
+
+    let mut parts = Vec::new();
+    for (label, names) in [("added", added), ("removed", removed), ("changed", changed)] {
+        if !names.is_empty() {
+            parts.push(format!("{}: {}", label, names.join(", ")));
+        }
+    }
+    if parts.is_empty() {
+        "no module changes".to_string()
+    } else {
+        parts.join("; ")
+    }
+}
 
 pub struct RsyncDaemon {
     config: DaemonConfig,
+    config_path: Option<PathBuf>,
+    dparams: Vec<String>,
+    default_bwlimit: Option<u64>,
+    socket_options: Option<SocketOptions>,
+    metrics: Arc<DaemonMetrics>,
 }
 
 impl RsyncDaemon {
     pub fn new(config: DaemonConfig) -> Self {
-        RsyncDaemon { config }
+        RsyncDaemon {
+            config,
+            config_path: None,
+            dparams: Vec::new(),
+            default_bwlimit: None,
+            socket_options: None,
+            metrics: Arc::new(DaemonMetrics::new()),
+        }
+    }
+
+
+    pub fn config_path(mut self, path: PathBuf) -> Self {
+        self.config_path = Some(path);
+        self
+    }
+
+
+    pub fn dparams(mut self, dparams: Vec<String>) -> Self {
+        self.dparams = dparams;
+        self
+    }
+
+
+    pub fn default_bwlimit(mut self, kb: Option<u64>) -> Self {
+        self.default_bwlimit = kb;
+        self
+    }
+
+
+    pub fn socket_options(mut self, options: Option<SocketOptions>) -> Self {
+        self.socket_options = options;
+        self
     }
 
     pub async fn start(&self) -> Result<()> {
-        let verbose = VerboseOutput::new(1, false);
+        let logger = Arc::new(DaemonLogger::from_config(&self.config)?);
+        let mut state = DaemonState::new(self.config.clone(), self, None)?;
+        let mut reload = reload_signal().context("Failed to install reload handler")?;
         let addr = format!("{}:{}", self.config.address, self.config.port);
         let listener = TcpListener::bind(&addr).await.context(format!("Failed to bind to {}", addr))?;
-        verbose.print_basic(&format!("Rsync daemon listening on {}", addr));
+        logger.info(&format!("Rsync daemon listening on {}", addr));
+
+        if let Some(metrics_port) = self.config.metrics_port {
+            let metrics_addr = format!("{}:{}", self.config.address, metrics_port);
+            let metrics = self.metrics.clone();
+            let logger = logger.clone();
+            tokio::spawn(async move {
+                if let Err(e) = metrics.serve(metrics_addr).await {
+                    logger.error(&format!("metrics endpoint: {}", e));
+                }
+            });
+        }
 
         loop {
-            let (socket, peer_addr) = listener.accept().await?;
-            verbose.print_basic(&format!("Client connected from: {}", peer_addr));
-            let config_clone = self.config.clone();
+            let (socket, peer_addr) = tokio::select! {
+                accepted = listener.accept() => accepted?,
+                Some(()) = reload.recv() => {
+                    match self.reload(&state, &logger) {
+                        Ok(reloaded) => state = reloaded,
+                        Err(e) => logger.error(&format!("reload failed, keeping current configuration: {:#}", e)),
+                    }
+                    continue;
+                }
+            };
+            let log = logger.session();
+            log.info(&format!("Client connected from: {}", peer_addr));
+            if let Err(e) = state.socket_options.apply(&socket) {
+                log.warn(&format!("Failed to apply socket options: {}", e));
+            }
+            let config_clone = state.config.clone();
+            let bwlimit = state.bwlimit.clone();
+            let metrics = self.metrics.clone();
             tokio::spawn(async move {
-                let verbose = VerboseOutput::new(1, false);
-                if let Err(e) = Self::handle_client(socket, &config_clone).await {
-                    verbose.print_error(&format!("handling client {}: {}", peer_addr, e));
+                let session = metrics.session_started();
+                let socket = Metered::new(socket, metrics.clone(), session.clone());
+                let result = Self::handle_client(socket, peer_addr.ip(), &config_clone, bwlimit, &session, &log).await;
+                metrics.session_finished(&session, result.is_ok());
+                if let Err(e) = result {
+                    log.error(&format!("handling client {}: {}", peer_addr, e));
                 }
             });
         }
     }
 
-    async fn handle_client(socket: TcpStream, config: &DaemonConfig) -> Result<()> {
-        let verbose = VerboseOutput::new(1, false);
-        let mut stream = AsyncProtocolStream::new(socket, PROTOCOL_VERSION_MAX);
+    fn reload(&self, current: &DaemonState, logger: &DaemonLogger) -> Result<DaemonState> {
+        let path = self.config_path.as_ref().context("daemon was started without a config file")?;
+        logger.info(&format!("Reloading configuration from {}", path.display()));
+        let config = DaemonConfig::load_with(path, &self.dparams)?;
 
+        let old = &current.config;
+        if config.address != old.address || config.port != old.port || config.metrics_port != old.metrics_port {
+            logger.warn("address, port and metrics_port changes take effect after a restart");
+        }
+        if config.log_file != old.log_file || config.log_level != old.log_level || config.event_log != old.event_log {
+            logger.warn("logging changes take effect after a restart");
+        }
 
-        verbose.print_verbose("Negotiating protocol version...");
-        let client_version = stream.read_i32().await?;
-        verbose.print_verbose(&format!("Client version: {}", client_version));
+        let summary = describe_module_changes(old, &config);
+        let state = DaemonState::new(config, self, Some(current))?;
+        logger.info(&format!(
+            "Configuration reloaded ({} modules, {}); active sessions keep their settings",
+            state.config.modules.len(),
+            summary
+        ));
+        Ok(state)
+    }
+
+    async fn handle_client(
+        socket: DaemonSocket,
+        peer: IpAddr,
+        config: &DaemonConfig,
+        bwlimit: Option<Arc<TokenBucket>>,
+        session: &SessionCounters,
+        log: &SessionLog,
+    ) -> Result<()> {
+        let mut stream = AsyncProtocolStream::new(socket, PROTOCOL_VERSION_MAX);
 
 
-        stream.write_i32(PROTOCOL_VERSION_MAX).await?;
+        log.debug("Negotiating protocol version...");
+        stream.write_all(Greeting::new(PROTOCOL_VERSION_MAX).line().as_bytes()).await?;
         stream.flush().await?;
+        let greeting = Greeting::parse(&read_line(&mut stream).await?)?;
+        log.debug(&format!("Client version: {}", greeting.version));
+        let protocol_version = greeting.version.min(PROTOCOL_VERSION_MAX);
+
+        if greeting.is_yarw() {
+            Self::handle_yarw_client(stream, peer, protocol_version, config, bwlimit, session, log).await
+        } else {
+            Self::handle_rsync_client(stream, peer, protocol_version, config, bwlimit, session, log).await
+        }
+    }
 
+    async fn handle_rsync_client(
+        mut stream: AsyncProtocolStream<DaemonSocket>,
+        peer: IpAddr,
+        protocol_version: i32,
+        config: &DaemonConfig,
+        bwlimit: Option<Arc<TokenBucket>>,
+        session: &SessionCounters,
+        log: &SessionLog,
+    ) -> Result<()> {
+        if protocol_version < MIN_RSYNC_PROTOCOL {
+            return Self::refuse(&mut stream, &format!(
+                "protocol version {} is too old; this daemon needs rsync 3.0 (protocol {}) or newer",
+                protocol_version, MIN_RSYNC_PROTOCOL
+            )).await;
+        }
+
+        let module_name = match ModuleRequest::parse(&read_line(&mut stream).await?) {
+            ModuleRequest::List => {
+                stream.write_all(module_listing(config).as_bytes()).await?;
+                stream.flush().await?;
+                log.info("Sent module list");
+                return Ok(());
+            }
+            ModuleRequest::Module(name) => name,
+        };
+        log.debug(&format!("Client requested module: {}", module_name));
+
+        let Some(module_config) = config.modules.get(&module_name) else {
+            return Self::refuse(&mut stream, &format!("Unknown module '{}'", module_name)).await;
+        };
+        session.set_module(&module_name);
+        if !Self::host_allowed(peer, &module_name, config, module_config, log).await? {
+            return Self::refuse(&mut stream, &format!("access denied to {} from {}", module_name, peer)).await;
+        }
+        let mut read_only = module_config.read_only;
+        if let Some(ref auth_users) = module_config.auth_users {
+            log.debug(&format!("Authentication required for module '{}'", module_name));
+            let challenge = auth_challenge(&module_name);
+            stream.write_all(format!("{}AUTHREQD {}\n", GREETING_PREFIX, challenge).as_bytes()).await?;
+            stream.flush().await?;
+            let reply = read_line(&mut stream).await?;
+            let (user, response) = reply.split_once(' ').unwrap_or((reply.as_str(), ""));
+            let Some(access) = Self::verify_user(auth_users, module_config, user, response, &challenge, log) else {
+                return Self::refuse(&mut stream, &format!("auth failed on module {}", module_name)).await;
+            };
+            read_only = access.read_only(read_only);
+            log.debug(&format!("User '{}' authenticated", user));
+        }
+
+        stream.write_all(format!("{}OK\n", GREETING_PREFIX).as_bytes()).await?;
+        stream.flush().await?;
+        let args = read_args(&mut stream).await?;
+        log.debug(&format!("Client arguments: {}", args.join(" ")));
+        let args = ServerArgs::parse(&args)?;
 
-        stream.write_i32(PROTOCOL_VERSION_MAX).await?;
+        let seed = choose_checksum_seed(args.checksum_seed);
+        stream.write_varint(args.compat_flags()).await?;
+        stream.write_i32(seed).await?;
         stream.flush().await?;
-        let _client_version_ack = stream.read_i32().await?;
+        log.debug(&format!("Checksum seed: {}", seed));
 
+        let mut stream = Self::server_stream(stream, bwlimit, module_config, protocol_version, true, log);
+        stream.set_checksum_seed(seed);
 
-        verbose.print_verbose("Waiting for module name...");
+        let result = async {
+            if !args.sender && read_only {
+                bail!("module {} is read only", module_name);
+            }
+            if !args.sender {
+                bail!("uploads from rsync clients are not supported yet; module '{}' can only be downloaded", module_name);
+            }
+            if let Some(option) = args.unsupported.first() {
+                bail!("{} is not supported by this daemon", option);
+            }
+            let sources = args.paths.iter()
+                .map(|path| resolve_source(&module_name, &module_config.path, path))
+                .collect::<Result<Vec<_>>>()?;
+            let dont_compress = Self::dont_compress(module_config)?;
+            serve_sender(&mut stream, &sources, &args, &dont_compress, protocol_version, log).await
+        }.await;
+        Self::finish(stream, result).await?;
+
+        log.info(&format!("Client session completed successfully (module '{}')", module_name));
+        Ok(())
+    }
+
+    async fn handle_yarw_client(
+        mut stream: AsyncProtocolStream<DaemonSocket>,
+        peer: IpAddr,
+        protocol_version: i32,
+        config: &DaemonConfig,
+        bwlimit: Option<Arc<TokenBucket>>,
+        session: &SessionCounters,
+        log: &SessionLog,
+    ) -> Result<()> {
+        log.debug("Waiting for module name...");
         let module_name = stream.read_string(256).await?;
-        verbose.print_verbose(&format!("Client requested module: {}", module_name));
+        log.debug(&format!("Client requested module: {}", module_name));
 
 
         let module_config = config.modules.get(&module_name)
             .ok_or_else(|| anyhow::anyhow!("Module '{}' not found", module_name))?;
+        session.set_module(&module_name);
+        if !Self::host_allowed(peer, &module_name, config, module_config, log).await? {
+            bail!("access denied to {} from {}", module_name, peer);
+        }
+
+        let requested_seed = stream.read_i32().await?;
+        let seed = choose_checksum_seed(Some(requested_seed));
+        stream.write_i32(seed).await?;
+        stream.flush().await?;
+        log.debug(&format!("Checksum seed: {}", seed));
 
 
+        let mut read_only = module_config.read_only;
         if let Some(ref auth_users) = module_config.auth_users {
-            verbose.print_verbose(&format!("Authentication required for module '{}'", module_name));
-            if !Self::authenticate(&mut stream, auth_users, &module_config).await? {
+            log.debug(&format!("Authentication required for module '{}'", module_name));
+            let Some(access) = Self::authenticate(&mut stream, auth_users, module_config, log).await? else {
                 bail!("Authentication failed");
-            }
-            verbose.print_verbose("Authentication successful");
+            };
+            read_only = access.read_only(read_only);
+            log.debug("Authentication successful");
         }
+        stream.write_string("@RSYNCD: OK").await?;
+        stream.flush().await?;
 
+        let mut stream = Self::server_stream(stream, bwlimit, module_config, protocol_version, false, log);
+        stream.set_checksum_seed(seed);
 
-        Self::handle_file_transfer(&mut stream, module_config).await?;
+        let result = Self::handle_file_transfer(&mut stream, &module_name, module_config, read_only, log).await;
+        Self::finish(stream, result).await?;
 
-        verbose.print_basic("Client session completed successfully");
+        log.info(&format!("Client session completed successfully (module '{}')", module_name));
         Ok(())
     }
 
-    async fn authenticate(
-        stream: &mut AsyncProtocolStream<TcpStream>,
-        _auth_users: &[String],
+    async fn host_allowed(
+        peer: IpAddr,
+        module_name: &str,
+        config: &DaemonConfig,
         module_config: &ModuleConfig,
+        log: &SessionLog,
     ) -> Result<bool> {
-        let verbose = VerboseOutput::new(1, false);
+        if HostAccess::for_module(config, module_config)?.permits(peer).await {
+            return Ok(true);
+        }
+        log.warn(&format!("Refused {} access to module '{}' (hosts_allow/hosts_deny)", peer, module_name));
+        Ok(false)
+    }
 
-        stream.write_string("@RSYNCD: AUTHREQD").await?;
-        stream.flush().await?;
+    fn server_stream(
+        stream: AsyncProtocolStream<DaemonSocket>,
+        bwlimit: Option<Arc<TokenBucket>>,
+        module_config: &ModuleConfig,
+        protocol_version: i32,
+        rsync_client: bool,
+        log: &SessionLog,
+    ) -> ServerStream {
+        let mut buckets: Vec<Arc<TokenBucket>> = bwlimit.into_iter().collect();
+        if let Some(kb) = module_config.bwlimit {
+            log.debug(&format!("Module bandwidth limit: {} KB/s", kb));
+            buckets.push(Arc::new(TokenBucket::new(kb * 1024)));
+        }
 
+        let (reader, writer) = tokio::io::split(stream.into_inner());
+        let reader = MultiplexReader::new(reader).enabled(rsync_client);
+        let writer = ThrottledWriter::new(writer, buckets);
+        let writer = MultiplexWriter::new(writer).enabled(rsync_client || protocol_version >= 31);
+        AsyncProtocolStream::new(tokio::io::join(reader, writer), protocol_version)
+    }
 
-        let username = stream.read_string(256).await?;
-        verbose.print_verbose(&format!("Authentication attempt for user: {}", username));
+    async fn refuse(stream: &mut AsyncProtocolStream<DaemonSocket>, message: &str) -> Result<()> {
+        stream.write_all(format!("@ERROR: {}\n", message).as_bytes()).await?;
+        stream.flush().await?;
+        bail!("{}", message)
+    }
 
+    async fn finish(mut stream: ServerStream, result: Result<()>) -> Result<()> {
+        if let Err(e) = result {
+            let message = format!("{}\n", e);
+            let _ = stream.get_mut().writer_mut().write_message(MessageTag::Error, message.as_bytes()).await;
+            let _ = stream.flush().await;
+            let _ = stream.get_mut().writer_mut().shutdown().await;
+            let _ = tokio::time::timeout(
+                ERROR_DRAIN_TIMEOUT,
+                tokio::io::copy(stream.get_mut().reader_mut(), &mut tokio::io::sink()),
+            ).await;
+            return Err(e);
+        }
+        stream.flush().await?;
+        Ok(())
+    }
 
-        let password_hash = stream.read_string(512).await?;
+    async fn authenticate(
+        stream: &mut AsyncProtocolStream<DaemonSocket>,
+        auth_users: &[String],
+        module_config: &ModuleConfig,
+        log: &SessionLog,
+    ) -> Result<Option<AuthAccess>> {
+        let challenge = auth_challenge(&module_config.path.to_string_lossy());
+        stream.write_string(&format!("{}AUTHREQD {}", GREETING_PREFIX, challenge)).await?;
+        stream.flush().await?;
 
+        let username = stream.read_string(256).await?;
+        let response = stream.read_string(512).await?;
+        let access = Self::verify_user(auth_users, module_config, &username, &response, &challenge, log);
+        if access.is_none() {
+            stream.write_string("@RSYNCD: AUTH FAILED").await?;
+            stream.flush().await?;
+        }
+        Ok(access)
+    }
 
-        if let Some(ref secrets_file) = module_config.secrets_file {
-            if secrets_file.exists() {
-                let contents = fs::read_to_string(secrets_file)?;
-                for line in contents.lines() {
-                    if line.trim().is_empty() || line.starts_with('#') {
-                        continue;
-                    }
-                    let parts: Vec<&str> = line.splitn(2, ':').collect();
-                    if parts.len() == 2 {
-                        let (file_user, file_pass) = (parts[0].trim(), parts[1].trim());
-                        if file_user == username {
-
-                            if password_hash == file_pass {
-                                return Ok(true);
-                            }
-                        }
-                    }
+    fn verify_user(
+        auth_users: &[String],
+        module_config: &ModuleConfig,
+        user: &str,
+        response: &str,
+        challenge: &str,
+        log: &SessionLog,
+    ) -> Option<AuthAccess> {
+        log.debug(&format!("Authentication attempt for user: {}", user));
+        let secrets = match module_config.secrets_file {
+            Some(ref secrets_file) => match fs::read_to_string(secrets_file) {
+                Ok(contents) => contents,
+                Err(e) => {
+                    log.warn(&format!("Cannot read secrets file {}: {}", secrets_file.display(), e));
+                    return None;
                 }
+            },
+            None => {
+                log.warn("Module has auth_users but no secrets_file; refusing every user");
+                return None;
             }
+        };
+        match check_auth(auth_users, &secrets, user, response, challenge) {
+            Ok(Some(access)) => return Some(access),
+            Ok(None) => {}
+            Err(e) => log.warn(&e.to_string()),
         }
-
-
-        stream.write_string("@RSYNCD: AUTH FAILED").await?;
-        stream.flush().await?;
-        Ok(false)
+        log.warn(&format!("Authentication failed for user: {}", user));
+        None
     }
 
     async fn handle_file_transfer(
-        stream: &mut AsyncProtocolStream<TcpStream>,
+        stream: &mut ServerStream,
+        module_name: &str,
         module_config: &ModuleConfig,
+        read_only: bool,
+        log: &SessionLog,
     ) -> Result<()> {
-        let verbose = VerboseOutput::new(1, false);
-        verbose.print_verbose(&format!("Starting file transfer for path: {:?}", module_config.path));
+        log.debug(&format!("Starting file transfer for path: {:?}", module_config.path));
+
+        let compressor = Self::negotiate_compression(stream, module_config, log).await?;
 
 
         let scanner = Scanner::new().recursive(true);
         let files = scanner.scan(&module_config.path)?;
-        verbose.print_verbose(&format!("Scanned {} files", files.len()));
+        log.debug(&format!("Scanned {} files", files.len()));
 
 
-        stream.write_varint(files.len() as i64).await?;
+        stream.write_varint(files.len() as i32).await?;
 
 
         for file in &files {
@@ -145,13 +565,13 @@ impl RsyncDaemon {
             stream.write_string(&relative_path.to_string_lossy()).await?;
 
 
-            stream.write_varint(file.size as i64).await?;
+            stream.write_varlong30(file.size as i64, 3).await?;
 
 
             let mtime_secs = file.mtime.duration_since(std::time::UNIX_EPOCH)
                 .unwrap_or_default()
                 .as_secs();
-            stream.write_varint(mtime_secs as i64).await?;
+            stream.write_varlong30(mtime_secs as i64, 4).await?;
 
 
             let file_type_code = if file.is_directory() { 1i8 } else { 0i8 };
@@ -159,20 +579,30 @@ impl RsyncDaemon {
         }
 
         stream.flush().await?;
-        verbose.print_verbose("File list sent");
+        log.debug("File list sent");
 
 
-        if !module_config.read_only {
-            verbose.print_verbose("Receiving files from client...");
+        if read_only {
+            if let Ok(num_files) = stream.read_varint().await {
+                if num_files > 0 {
+                    bail!("module {} is read only", module_name);
+                }
+            }
+        } else {
+            log.debug("Receiving files from client...");
 
             let num_files = stream.read_varint().await? as usize;
-            verbose.print_verbose(&format!("Client sending {} files", num_files));
+            log.debug(&format!("Client sending {} files", num_files));
+
+            let mut quota = UploadQuota::new(module_config);
+            quota.check_count(num_files)?;
 
             for i in 0..num_files {
                 let file_path = stream.read_string(4096).await?;
-                let file_size = stream.read_varint().await? as usize;
+                let file_size = stream.read_varlong30(3).await? as usize;
+                quota.admit(&file_path, file_size as u64)?;
 
-                verbose.print_verbose(&format!("Receiving file {}: {} ({} bytes)", i + 1, file_path, file_size));
+                log.debug(&format!("Receiving file {}: {} ({} bytes)", i + 1, file_path, file_size));
 
                 let dest_path = module_config.path.join(&file_path);
 
@@ -182,15 +612,229 @@ impl RsyncDaemon {
                 }
 
 
-                let mut file_data = vec![0u8; file_size];
-                stream.read_all(&mut file_data).await?;
+                let compressed = compressor.is_some() && stream.read_i8().await? != 0;
+                let file_data = match compressor {
+                    Some(ref compressor) if compressed => {
+                        let packed_size = stream.read_varlong30(3).await? as usize;
+                        let mut packed = vec![0u8; packed_size];
+                        stream.read_all(&mut packed).await?;
+                        let file_data = compressor.decompress(&packed)?;
+                        if file_data.len() != file_size {
+                            bail!("Decompressed size mismatch for {}: expected {}, got {}", file_path, file_size, file_data.len());
+                        }
+                        file_data
+                    }
+                    _ => {
+                        let mut file_data = vec![0u8; file_size];
+                        stream.read_all(&mut file_data).await?;
+                        file_data
+                    }
+                };
                 fs::write(&dest_path, &file_data)?;
 
-                verbose.print_verbose(&format!("Saved file: {:?}", dest_path));
+                log.debug(&format!("Saved file: {:?}", dest_path));
+
+                stream.get_mut().writer_mut().maybe_keepalive(KEEPALIVE_INTERVAL).await?;
             }
         }
 
-        verbose.print_basic("File transfer completed");
+        log.info("File transfer completed");
+        Ok(())
+    }
+
+    async fn negotiate_compression(
+        stream: &mut ServerStream,
+        module_config: &ModuleConfig,
+        log: &SessionLog,
+    ) -> Result<Option<Compressor>> {
+        let requested = stream.read_string(32).await?;
+        let algorithm = CompressionAlgorithm::from_name(&requested);
+
+        stream.write_string(algorithm.map(|a| a.name()).unwrap_or("")).await?;
+
+        let algorithm = match algorithm {
+            Some(algorithm) => algorithm,
+            None => {
+                stream.flush().await?;
+                return Ok(None);
+            }
+        };
+
+        let dont_compress = Self::dont_compress(module_config)?;
+        stream.write_varint(dont_compress.patterns().len() as i32).await?;
+        for pattern in dont_compress.patterns() {
+            stream.write_string(pattern).await?;
+        }
+        stream.flush().await?;
+
+        log.debug(&format!("Compression enabled: {}", algorithm.name()));
+        Ok(Some(Compressor::new(algorithm)))
+    }
+
+
+    fn dont_compress(module_config: &ModuleConfig) -> Result<DontCompress> {
+        match module_config.dont_compress {
+            Some(ref patterns) => DontCompress::new(patterns),
+            None => DontCompress::new(DEFAULT_DONT_COMPRESS),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::daemon_log::LogLevel;
+    use crate::transport::DaemonClient;
+    use tempfile::TempDir;
+
+    fn config(toml: &str) -> DaemonConfig {
+        toml::from_str(toml).unwrap()
+    }
+
+    async fn serve_once(config: DaemonConfig) -> Result<(u16, tokio::task::JoinHandle<Result<()>>)> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let port = listener.local_addr()?.port();
+        let server = tokio::spawn(async move {
+            let (socket, peer) = listener.accept().await?;
+            let metrics = Arc::new(DaemonMetrics::new());
+            let session = metrics.session_started();
+            let socket = Metered::new(socket, metrics, session.clone());
+            let log = Arc::new(DaemonLogger::new(LogLevel::Error).console(false)).session();
+            RsyncDaemon::handle_client(socket, peer.ip(), &config, None, &session, &log).await
+        });
+        Ok((port, server))
+    }
+
+    #[test]
+    fn test_describe_module_changes() {
+        let old = config(r#"
+            address = "0.0.0.0"
+            port = 873
+
+            [backup]
+            path = "/srv/backup"
+
+            [public]
+            path = "/srv/public"
+        "#);
+        let new = config(r#"
+            address = "0.0.0.0"
+            port = 873
+
+            [backup]
+            path = "/srv/backup"
+            read_only = true
+
+            [media]
+            path = "/srv/media"
+        "#);
+
+        assert_eq!(describe_module_changes(&old, &new), "added: media; removed: public; changed: backup");
+        assert_eq!(describe_module_changes(&new, &new), "no module changes");
+    }
+
+    #[tokio::test]
+    async fn test_read_only_user_cannot_upload() -> Result<()> {
+        let dir = TempDir::new()?;
+        let module = dir.path().join("module");
+        let source = dir.path().join("source");
+        fs::create_dir_all(&module)?;
+        fs::create_dir_all(&source)?;
+        fs::write(source.join("report.txt"), b"report")?;
+        let secrets = dir.path().join("rsyncd.secrets");
+        fs::write(&secrets, "alice:secret\nbob:secret\n")?;
+        let password = dir.path().join("password");
+        fs::write(&password, "secret\n")?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&password, fs::Permissions::from_mode(0o600))?;
+        }
+        let config = config(&format!(r#"
+            address = "127.0.0.1"
+            port = 873
+
+            [backup]
+            path = '{}'
+            auth_users = ["alice", "bob:ro"]
+            secrets_file = '{}'
+        "#, module.display(), secrets.display()));
+
+        for (user, allowed) in [("bob", false), ("alice", true)] {
+            let (port, server) = serve_once(config.clone()).await?;
+            let client = DaemonClient::new("127.0.0.1".to_string(), port)
+                .user(Some(user.to_string()))
+                .password_file(Some(password.clone()));
+            let uploaded = client.upload("backup", &source, "").await;
+            let served = server.await?;
+            assert_eq!(uploaded.is_ok(), allowed, "{}: {:?}", user, uploaded.err());
+            assert_eq!(served.as_ref().err().map(|e| e.to_string()), (!allowed).then(|| "module backup is read only".to_string()));
+            assert_eq!(module.join("report.txt").exists(), allowed);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_upload_over_quota_reports_error_to_client() -> Result<()> {
+        let dir = TempDir::new()?;
+        let module = dir.path().join("module");
+        let source = dir.path().join("source");
+        fs::create_dir_all(&module)?;
+        fs::create_dir_all(&source)?;
+        fs::write(source.join("a.txt"), vec![b'a'; 60])?;
+        fs::write(source.join("b.txt"), vec![b'b'; 60])?;
+        let config = config(&format!(r#"
+            address = "127.0.0.1"
+            port = 873
+
+            [dropbox]
+            path = '{}'
+            max_session_size = 100
+        "#, module.display()));
+
+        let (port, server) = serve_once(config).await?;
+        let uploaded = DaemonClient::new("127.0.0.1".to_string(), port).upload("dropbox", &source, "").await;
+        let served = server.await?;
+        let message = uploaded.err().map(|e| e.to_string()).unwrap_or_default();
+        assert!(message.starts_with("server rejected upload: Upload rejected at "), "{}", message);
+        assert!(message.contains("exceeding the module limit of 100 bytes"), "{}", message);
+        assert!(served.is_err());
         Ok(())
     }
+
+    #[test]
+    fn test_upload_quota() {
+        let module: ModuleConfig = toml::from_str(r#"
+            path = "/srv/dropbox"
+            max_session_size = 100
+            max_session_files = 2
+        "#).unwrap();
+
+        let mut quota = UploadQuota::new(&module);
+        assert!(quota.check_count(3).is_err());
+        assert!(quota.admit("a", 60).is_ok());
+        assert!(quota.admit("b", 50).is_err());
+        assert!(quota.admit("b", 40).is_ok());
+        assert!(quota.admit("c", 0).is_err());
+    }
+
+    #[test]
+    fn test_reload_keeps_unchanged_bwlimit_bucket() {
+        let limited = r#"
+            address = "0.0.0.0"
+            port = 873
+            bwlimit = 100
+        "#;
+        let daemon = RsyncDaemon::new(config(limited));
+        let first = DaemonState::new(config(limited), &daemon, None).unwrap();
+        let second = DaemonState::new(config(limited), &daemon, Some(&first)).unwrap();
+        assert!(Arc::ptr_eq(first.bwlimit.as_ref().unwrap(), second.bwlimit.as_ref().unwrap()));
+
+        let unlimited = DaemonState::new(config("address = \"0.0.0.0\"\nport = 873\n"), &daemon, Some(&second)).unwrap();
+        assert!(unlimited.bwlimit.is_none());
+
+        let daemon = daemon.default_bwlimit(Some(50));
+        let fallback = DaemonState::new(config("address = \"0.0.0.0\"\nport = 873\n"), &daemon, None).unwrap();
+        assert_eq!(fallback.bwlimit_kb, Some(50));
+    }
 }
@@ -1,26 +1,158 @@
-use crate::protocol::{AsyncProtocolStream, PROTOCOL_VERSION_MAX};
-use crate::filesystem::{Scanner, FileInfo, FileType};
-use crate::transport::SyncStats;
-use crate::output::VerboseOutput;
+use crate::protocol::{AsyncProtocolStream, FileList, MultiplexReader, PROTOCOL_VERSION_MAX};
+use crate::filesystem::{files_from_allows, Scanner, FileInfo, FileType};
+use crate::algorithm::{Compressor, DontCompress};
+use crate::filter::FilterEngine;
+use crate::options::CompressionAlgorithm;
+use crate::transport::{prompt_for_password, SocketOptions, SyncStats};
+use crate::transport::credentials;
+use crate::transport::daemon_protocol::{auth_response, read_line, Greeting, GREETING_PREFIX, YARW_DIALECT};
+use crate::output::{json, ProgressDisplay, VerboseOutput};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, Join};
 use tokio::net::TcpStream;
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
 use anyhow::{Result, Context, bail};
 use std::path::{Path, PathBuf};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use std::fs;
 
 
+const FINISH_TIMEOUT: Duration = Duration::from_secs(30);
+
+
+const PASSWORD_ENV: &str = "RSYNC_PASSWORD";
+
+
+const UPLOAD_CHUNK_SIZE: usize = 64 * 1024;
+
+
+const MAX_DONT_COMPRESS_PATTERNS: i32 = 1024;
+
+
+type ClientStream = AsyncProtocolStream<Join<MultiplexReader<OwnedReadHalf>, OwnedWriteHalf>>;
+
+
 pub struct DaemonClient {
     host: String,
     port: u16,
+    compress: Option<CompressionAlgorithm>,
+    socket_options: Option<SocketOptions>,
+    checksum_seed: Option<i32>,
+    progress: bool,
+    user: Option<String>,
+    use_credential_manager: bool,
+    prompt: bool,
+    password_file: Option<PathBuf>,
+    files_from: Option<Vec<PathBuf>>,
+    filter: FilterEngine,
+    exclude_if_present: Vec<String>,
+    top_dir: Option<String>,
 }
 
 impl DaemonClient {
     pub fn new(host: String, port: u16) -> Self {
-        Self { host, port }
+        Self {
+            host,
+            port,
+            compress: None,
+            socket_options: None,
+            checksum_seed: None,
+            progress: false,
+            user: None,
+            use_credential_manager: false,
+            prompt: true,
+            password_file: None,
+            files_from: None,
+            filter: FilterEngine::new(),
+            exclude_if_present: Vec::new(),
+            top_dir: None,
+        }
+    }
+
+
+    pub fn compress(mut self, algorithm: Option<CompressionAlgorithm>) -> Self {
+        self.compress = algorithm;
+        self
+    }
+
+
+    pub fn socket_options(mut self, options: Option<SocketOptions>) -> Self {
+        self.socket_options = options;
+        self
+    }
+
+
+    pub fn checksum_seed(mut self, seed: Option<i32>) -> Self {
+        self.checksum_seed = seed;
+        self
+    }
+
+
+    pub fn progress(mut self, enabled: bool) -> Self {
+        self.progress = enabled;
+        self
+    }
+
+
+    pub fn user(mut self, user: Option<String>) -> Self {
+        self.user = user;
+        self
+    }
+
+
+    pub fn use_credential_manager(mut self, enabled: bool) -> Self {
+        self.use_credential_manager = enabled;
+        self
+    }
+
+
+    pub fn prompt(mut self, enabled: bool) -> Self {
+        self.prompt = enabled;
+        self
+    }
+
+
+    pub fn password_file(mut self, path: Option<PathBuf>) -> Self {
+        self.password_file = path;
+        self
+    }
+
+
+    pub fn files_from(mut self, files: Option<Vec<PathBuf>>) -> Self {
+        self.files_from = files;
+        self
+    }
+
+
+    pub fn filter(mut self, filter: FilterEngine) -> Self {
+        self.filter = filter;
+        self
+    }
+
+
+    pub fn exclude_if_present(mut self, markers: &[String]) -> Self {
+        self.exclude_if_present = markers.to_vec();
+        self
     }
 
 
-    pub fn parse_daemon_url(url: &str) -> Result<(String, u16, String, String)> {
+    pub fn top_dir(mut self, name: Option<String>) -> Self {
+        self.top_dir = name;
+        self
+    }
+
+
+    async fn connect(&self) -> Result<(TcpStream, String)> {
+        let addr = format!("{}:{}", self.host, self.port);
+        let socket = TcpStream::connect(&addr).await
+            .context(format!("Failed to connect to {}", addr))?;
+        if let Some(ref options) = self.socket_options {
+            options.apply(&socket).context("Failed to apply socket options")?;
+        }
+        Ok((socket, addr))
+    }
+
+
+    pub fn parse_daemon_url(url: &str) -> Result<(Option<String>, String, u16, String, String)> {
 
         if !url.starts_with("rsync://") {
             bail!("Invalid daemon URL: must start with rsync://");
@@ -33,7 +165,10 @@ impl DaemonClient {
             bail!("Invalid daemon URL: missing module");
         }
 
-        let host_port = parts[0];
+        let (user, host_port) = match parts[0].rsplit_once('@') {
+            Some((user, host_port)) => (Some(user.to_string()), host_port),
+            None => (None, parts[0]),
+        };
         let module_and_path = parts[1];
 
 
@@ -53,7 +188,136 @@ impl DaemonClient {
             String::new()
         };
 
-        Ok((host, port, module, path))
+        Ok((user, host, port, module, path))
+    }
+
+
+    async fn handshake(stream: &mut AsyncProtocolStream<TcpStream>) -> Result<i32> {
+        let greeting = Greeting::parse(&read_line(stream).await?)?;
+        let ours = Greeting::new(PROTOCOL_VERSION_MAX).with_extension(YARW_DIALECT);
+        stream.write_all(ours.line().as_bytes()).await?;
+        stream.flush().await?;
+        Ok(greeting.version)
+    }
+
+
+    async fn exchange_checksum_seed(&self, stream: &mut AsyncProtocolStream<TcpStream>) -> Result<()> {
+        stream.write_i32(self.checksum_seed.unwrap_or(0)).await?;
+        stream.flush().await?;
+        let seed = stream.read_i32().await?;
+        stream.set_checksum_seed(seed);
+        VerboseOutput::new(1, false).print_debug(format!("Checksum seed: {}", seed));
+        Ok(())
+    }
+
+
+    async fn authenticate(&self, stream: &mut AsyncProtocolStream<TcpStream>, module: &str) -> Result<()> {
+        let status = stream.read_string(256).await?;
+        let Some(challenge) = status.strip_prefix(GREETING_PREFIX).and_then(|rest| rest.strip_prefix("AUTHREQD ")) else {
+            return Ok(());
+        };
+
+        let verbose = VerboseOutput::new(1, false);
+        let user = self.user.clone().unwrap_or_else(whoami::username);
+        let target = credentials::daemon_target(&user, &self.host, self.port, module);
+        let (password, prompted) = self.password(&user, &target, module)?;
+
+        stream.write_string(&user).await?;
+        stream.write_string(&auth_response(&password, challenge)).await?;
+        stream.flush().await?;
+        if stream.read_string(256).await? != "@RSYNCD: OK" {
+            bail!("authentication failed for {}@{} (module '{}')", user, self.host, module);
+        }
+
+        if prompted && self.use_credential_manager {
+            match credentials::store(&target, &user, &password) {
+                Ok(()) => verbose.print_verbose(format!("Saved password to Credential Manager as {}", target)),
+                Err(e) => verbose.print_warning(e.to_string()),
+            }
+        }
+        Ok(())
+    }
+
+
+    fn password(&self, user: &str, target: &str, module: &str) -> Result<(String, bool)> {
+        let verbose = VerboseOutput::new(1, false);
+        if let Some(ref path) = self.password_file {
+            return Ok((read_password_file(path)?, false));
+        }
+        if self.use_credential_manager {
+            match credentials::load(target) {
+                Ok(Some(password)) => return Ok((password, false)),
+                Ok(None) => verbose.print_verbose(format!("No password stored in Credential Manager as {}", target)),
+                Err(e) => verbose.print_warning(e.to_string()),
+            }
+        }
+        if let Ok(password) = std::env::var(PASSWORD_ENV) {
+            return Ok((password, false));
+        }
+        if !self.prompt {
+            bail!(
+                "module '{}' requires a password and prompts are disabled (--batch-mode or --no-prompt); \
+                 use --password-file, store one with --use-credential-manager or set {}",
+                module, PASSWORD_ENV
+            );
+        }
+        Ok((prompt_for_password(user, &self.host)?, true))
+    }
+
+
+    fn demultiplex(
+        stream: AsyncProtocolStream<TcpStream>,
+        server_version: i32,
+    ) -> ClientStream {
+        let protocol_version = server_version.min(PROTOCOL_VERSION_MAX);
+        let seed = stream.checksum_seed();
+        let (reader, writer) = stream.into_inner().into_split();
+        let reader = MultiplexReader::new(reader).enabled(protocol_version >= 31);
+        let mut stream = AsyncProtocolStream::new(tokio::io::join(reader, writer), protocol_version);
+        stream.set_checksum_seed(seed);
+        stream
+    }
+
+
+    async fn finish_session(stream: &mut ClientStream) -> Option<String> {
+        let _ = stream.get_mut().writer_mut().shutdown().await;
+        let mut rest = Vec::new();
+        let _ = tokio::time::timeout(FINISH_TIMEOUT, stream.get_mut().reader_mut().read_to_end(&mut rest)).await;
+        stream.get_mut().reader_mut().take_error()
+    }
+
+
+    async fn negotiate_compression(
+        &self,
+        stream: &mut ClientStream,
+    ) -> Result<Option<(Compressor, DontCompress)>> {
+        let verbose = VerboseOutput::new(1, false);
+
+        stream.write_string(self.compress.map(|a| a.name()).unwrap_or("")).await?;
+        stream.flush().await?;
+
+        let accepted = stream.read_string(32).await?;
+        let algorithm = match CompressionAlgorithm::from_name(&accepted) {
+            Some(algorithm) => algorithm,
+            None => {
+                if self.compress.is_some() {
+                    verbose.print_warning("Server declined compression, sending uncompressed");
+                }
+                return Ok(None);
+            }
+        };
+
+        let num_patterns = stream.read_varint().await?;
+        if !(0..=MAX_DONT_COMPRESS_PATTERNS).contains(&num_patterns) {
+            bail!("Invalid dont compress pattern count from server: {}", num_patterns);
+        }
+        let mut patterns = Vec::new();
+        for _ in 0..num_patterns {
+            patterns.push(stream.read_string(256).await?);
+        }
+
+        verbose.print_basic(format!("Compression: {}", algorithm.name()));
+        Ok(Some((Compressor::new(algorithm), DontCompress::new(&patterns)?)))
     }
 
 
@@ -68,29 +332,20 @@ impl DaemonClient {
 
         let verbose = VerboseOutput::new(1, false);
 
-        let addr = format!("{}:{}", self.host, self.port);
-        let socket = TcpStream::connect(&addr).await
-            .context(format!("Failed to connect to {}", addr))?;
-        verbose.print_basic(&format!("Connected to rsync daemon at {}", addr));
+        let (socket, addr) = self.connect().await?;
+        verbose.print_basic(format!("Connected to rsync daemon at {}", addr));
 
         let mut stream = AsyncProtocolStream::new(socket, PROTOCOL_VERSION_MAX);
-
-
-        stream.write_i32(PROTOCOL_VERSION_MAX).await?;
-        stream.flush().await?;
-
-        let server_version = stream.read_i32().await?;
-        verbose.print_basic(&format!("Server version: {}", server_version));
-
-
-        let _server_version_ack = stream.read_i32().await?;
-        stream.write_i32(PROTOCOL_VERSION_MAX).await?;
-        stream.flush().await?;
+        let server_version = Self::handshake(&mut stream).await?;
+        verbose.print_basic(format!("Server version: {}", server_version));
 
 
         stream.write_string(module).await?;
-        stream.flush().await?;
-        verbose.print_basic(&format!("Requested module: {}", module));
+        self.exchange_checksum_seed(&mut stream).await?;
+        self.authenticate(&mut stream, module).await?;
+        let mut stream = Self::demultiplex(stream, server_version);
+        self.negotiate_compression(&mut stream).await?;
+        verbose.print_basic(format!("Requested module: {}", module));
 
 
 
@@ -98,13 +353,13 @@ impl DaemonClient {
 
 
         let num_files = stream.read_varint().await? as usize;
-        verbose.print_basic(&format!("Receiving {} files from server", num_files));
+        verbose.print_basic(format!("Receiving {} files from server", num_files));
 
         let mut files = Vec::with_capacity(num_files);
         for _ in 0..num_files {
             let file_path = stream.read_string(4096).await?;
-            let file_size = stream.read_varint().await? as u64;
-            let mtime_secs = stream.read_varint().await? as u64;
+            let file_size = stream.read_varlong30(3).await? as u64;
+            let mtime_secs = stream.read_varlong30(4).await? as u64;
             let file_type_code = stream.read_i8().await?;
 
             let file_type = match file_type_code {
@@ -122,12 +377,19 @@ impl DaemonClient {
                 file_type,
                 is_symlink: false,
                 symlink_target: None,
+                nlink: 1,
+                file_id: None,
+                streams: Vec::new(),
             };
 
             files.push(file_info);
         }
+        if let Some(ref allowed) = self.files_from {
+            files.retain(|file| files_from_allows(allowed, &file.path));
+        }
+        files.retain(|file| self.filter.should_include(&file.path));
 
-        verbose.print_basic(&format!("Received file list with {} entries", files.len()));
+        verbose.print_basic(format!("Received file list with {} entries", files.len()));
         stats.scanned_files = files.len();
 
 
@@ -135,7 +397,7 @@ impl DaemonClient {
 
 
         stats.execution_time_secs = start_time.elapsed().as_secs_f64();
-        verbose.print_basic(&format!("Download completed in {:.2}s", stats.execution_time_secs));
+        verbose.print_basic(format!("Download completed in {:.2}s", stats.execution_time_secs));
 
         Ok(stats)
     }
@@ -152,85 +414,149 @@ impl DaemonClient {
 
         let verbose = VerboseOutput::new(1, false);
 
-        let addr = format!("{}:{}", self.host, self.port);
-        let socket = TcpStream::connect(&addr).await
-            .context(format!("Failed to connect to {}", addr))?;
-        verbose.print_basic(&format!("Connected to rsync daemon at {}", addr));
+        let (socket, addr) = self.connect().await?;
+        verbose.print_basic(format!("Connected to rsync daemon at {}", addr));
 
         let mut stream = AsyncProtocolStream::new(socket, PROTOCOL_VERSION_MAX);
-
-
-        stream.write_i32(PROTOCOL_VERSION_MAX).await?;
-        stream.flush().await?;
-
-        let server_version = stream.read_i32().await?;
-        verbose.print_basic(&format!("Server version: {}", server_version));
-
-
-        let _server_version_ack = stream.read_i32().await?;
-        stream.write_i32(PROTOCOL_VERSION_MAX).await?;
-        stream.flush().await?;
+        let server_version = Self::handshake(&mut stream).await?;
+        verbose.print_basic(format!("Server version: {}", server_version));
 
 
         stream.write_string(module).await?;
-        stream.flush().await?;
+        self.exchange_checksum_seed(&mut stream).await?;
+        self.authenticate(&mut stream, module).await?;
+        let mut stream = Self::demultiplex(stream, server_version);
+        let compression = self.negotiate_compression(&mut stream).await?;
 
 
         let num_server_files = stream.read_varint().await? as usize;
-        verbose.print_basic(&format!("Server has {} files", num_server_files));
+        verbose.print_basic(format!("Server has {} files", num_server_files));
 
 
         for _ in 0..num_server_files {
             let _file_path = stream.read_string(4096).await?;
-            let _file_size = stream.read_varint().await?;
-            let _mtime = stream.read_varint().await?;
+            let _file_size = stream.read_varlong30(3).await?;
+            let _mtime = stream.read_varlong30(4).await?;
             let _file_type = stream.read_i8().await?;
         }
 
 
-        let scanner = Scanner::new().recursive(true);
-        let local_files = scanner.scan(local_path)?;
-        verbose.print_basic(&format!("Uploading {} files to server", local_files.len()));
-
-
-        stream.write_varint(local_files.len() as i64).await?;
+        let scanner = Scanner::new().recursive(true).exclude_if_present(&self.exclude_if_present);
+        let base = Scanner::resolve_scan_path(local_path)?;
+        let local_files = FileList::relative_to(&scanner.scan(local_path)?, &base);
+        let root = if base.is_file() { base.parent().unwrap_or(&base) } else { &base };
+        let upload_files: Vec<&FileInfo> = local_files.iter()
+            .filter(|f| !f.is_directory())
+            .filter(|f| match &self.files_from {
+                Some(allowed) => files_from_allows(allowed, &f.path),
+                None => true,
+            })
+            .filter(|f| self.filter.should_include(&f.path))
+            .collect();
+        verbose.print_basic(format!("Uploading {} files to server", upload_files.len()));
+        let progress = self.progress.then(ProgressDisplay::network);
+        if let Some(ref progress) = progress {
+            progress.set_total(upload_files.iter().map(|f| f.size).sum());
+        }
 
 
-        for file in &local_files {
-            if file.is_directory() {
-                continue;
+        let sent: Result<()> = async {
+            stream.write_varint(upload_files.len() as i32).await?;
+
+
+            for file in upload_files {
+                let wire_name = match self.top_dir {
+                    Some(ref top_dir) if !base.is_file() => format!("{}/{}", top_dir, file.path.to_string_lossy()),
+                    _ => file.path.to_string_lossy().into_owned(),
+                };
+                stream.write_string(&wire_name).await?;
+
+
+                let file_data = fs::read(root.join(&file.path))?;
+                if let Some(ref progress) = progress {
+                    progress.start_file(&file.path.to_string_lossy(), file_data.len() as u64);
+                }
+
+
+                stream.write_varlong30(file_data.len() as i64, 3).await?;
+
+                let packed = match compression {
+                    Some((ref compressor, ref dont_compress)) if !dont_compress.matches(&file.path) => {
+                        let packed = compressor.compress(&file_data)?;
+                        stream.write_i8(1).await?;
+                        stream.write_varlong30(packed.len() as i64, 3).await?;
+                        Some(packed)
+                    }
+                    Some(_) => {
+                        stream.write_i8(0).await?;
+                        None
+                    }
+                    None => None,
+                };
+                let payload = packed.as_deref().unwrap_or(&file_data);
+                let sent_bytes = payload.len();
+                let mut reported = 0u64;
+                for (index, chunk) in payload.chunks(UPLOAD_CHUNK_SIZE).enumerate() {
+                    stream.write_all(chunk).await?;
+                    if let Some(ref progress) = progress {
+                        let sent = (index * UPLOAD_CHUNK_SIZE + chunk.len()) as u64;
+                        let done = file_data.len() as u64 * sent / sent_bytes as u64;
+                        progress.advance(done - reported, chunk.len() as u64);
+                        reported = done;
+                    }
+                }
+                if let Some(ref progress) = progress {
+                    progress.advance(file_data.len() as u64 - reported, 0);
+                    progress.finish_file();
+                }
+
+                stats.transferred_files += 1;
+                stats.transferred_bytes += file_data.len() as u64;
+                json::transferred(&file.path, file_data.len() as u64);
+                stats.literal_bytes += file_data.len() as u64;
+                if compression.is_some() {
+                    stats.compressed_bytes += sent_bytes as u64;
+                }
+
+                if sent_bytes != file_data.len() {
+                    verbose.print_basic(format!("Uploaded: {} ({} bytes, {} on the wire)", file.path.display(), file_data.len(), sent_bytes));
+                } else {
+                    verbose.print_basic(format!("Uploaded: {} ({} bytes)", file.path.display(), file_data.len()));
+                }
             }
+            stream.flush().await?;
+            Ok(())
+        }.await;
 
-            let relative_path = file.path.strip_prefix(local_path)
-                .unwrap_or(&file.path);
-
-
-            stream.write_string(&relative_path.to_string_lossy()).await?;
-
-
-            let file_path = local_path.join(&file.path);
-            let file_data = fs::read(&file_path)?;
-
-
-            stream.write_varint(file_data.len() as i64).await?;
-
-
-            stream.write_all(&file_data).await?;
-
-            stats.transferred_files += 1;
-            stats.transferred_bytes += file_data.len() as u64;
-
-            verbose.print_basic(&format!("Uploaded: {} ({} bytes)", relative_path.display(), file_data.len()));
+        if let Some(progress) = progress {
+            progress.finish();
         }
 
-        stream.flush().await?;
+        if let Some(message) = Self::finish_session(&mut stream).await {
+            bail!("server rejected upload: {}", message);
+        }
+        sent?;
 
         stats.scanned_files = local_files.len();
         stats.execution_time_secs = start_time.elapsed().as_secs_f64();
 
-        verbose.print_basic(&format!("Upload completed in {:.2}s", stats.execution_time_secs));
-        verbose.print_basic(&format!("Transferred {} files, {} bytes", stats.transferred_files, stats.transferred_bytes));
+        verbose.print_basic(format!("Upload completed in {:.2}s", stats.execution_time_secs));
+        verbose.print_basic(format!("Transferred {} files, {} bytes", stats.transferred_files, stats.transferred_bytes));
 
         Ok(stats)
     }
 }
+
+
+fn read_password_file(path: &Path) -> Result<String> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read password file {}", path.display()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if fs::metadata(path)?.permissions().mode() & 0o006 != 0 {
+            bail!("password file {} must not be other-accessible", path.display());
+        }
+    }
+    Ok(contents.lines().next().unwrap_or("").to_string())
+}
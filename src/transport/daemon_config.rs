@@ -1,20 +1,160 @@
+use crate::transport::daemon_log::LogLevel;
+use anyhow::{bail, Result, Context};
 use serde::Deserialize;
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::fs;
+use std::path::{Path, PathBuf};
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct DaemonConfig {
     pub address: String,
     pub port: u16,
+    #[serde(default)]
+    pub bwlimit: Option<u64>,
+    #[serde(default)]
+    pub metrics_port: Option<u16>,
+    #[serde(default)]
+    pub socket_options: Option<String>,
+    #[serde(default)]
+    pub log_file: Option<PathBuf>,
+    #[serde(default)]
+    pub log_level: LogLevel,
+    #[serde(default)]
+    pub max_log_size: Option<u64>,
+    #[serde(default)]
+    pub max_log_files: Option<usize>,
+    #[serde(default)]
+    pub event_log: bool,
+    #[serde(default)]
+    pub hosts_allow: Vec<String>,
+    #[serde(default)]
+    pub hosts_deny: Vec<String>,
     #[serde(flatten)]
     pub modules: HashMap<String, ModuleConfig>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+impl DaemonConfig {
+    #[allow(dead_code)]
+    pub fn load(path: &Path) -> Result<Self> {
+        Self::load_with(path, &[])
+    }
+
+
+    pub fn load_with(path: &Path, dparams: &[String]) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .context(format!("Failed to read {}", path.display()))?;
+        let mut table: toml::Table = toml::from_str(&contents).context(format!("Failed to parse {}", path.display()))?;
+        for dparam in dparams {
+            apply_dparam(&mut table, dparam)?;
+        }
+        table.try_into().context(format!("Failed to parse {}", path.display()))
+    }
+}
+
+
+fn apply_dparam(table: &mut toml::Table, dparam: &str) -> Result<()> {
+    let Some((name, value)) = dparam.split_once('=') else {
+        bail!("--dparam={} must have the form NAME=VALUE", dparam);
+    };
+    let value = format!("value = {}", value.trim()).parse::<toml::Table>()
+        .ok()
+        .and_then(|mut parsed| parsed.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(value.trim().to_string()));
+
+    let (module, name) = match name.trim().rsplit_once('.') {
+        Some((module, name)) => (Some(module), name),
+        None => (None, name.trim()),
+    };
+    let key = name.trim().replace([' ', '-'], "_").to_lowercase();
+    let section = match module {
+        Some(module) => match table.get_mut(module) {
+            Some(toml::Value::Table(section)) => section,
+            _ => bail!("--dparam={}: no module named [{}]", dparam, module),
+        },
+        None => table,
+    };
+    section.insert(key, value);
+    Ok(())
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
 pub struct ModuleConfig {
     pub path: PathBuf,
+    pub comment: Option<String>,
     #[serde(default)]
     pub read_only: bool,
     pub auth_users: Option<Vec<String>>,
     pub secrets_file: Option<PathBuf>,
+    pub hosts_allow: Option<Vec<String>>,
+    pub hosts_deny: Option<Vec<String>>,
+    #[serde(default)]
+    pub bwlimit: Option<u64>,
+    pub dont_compress: Option<Vec<String>>,
+    #[serde(default)]
+    pub max_session_size: Option<u64>,
+    #[serde(default)]
+    pub max_session_files: Option<usize>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bwlimit_global_and_per_module() {
+        let config: DaemonConfig = toml::from_str(r#"
+            address = "0.0.0.0"
+            port = 873
+            bwlimit = 500
+
+            [backup]
+            path = "/srv/backup"
+            bwlimit = 100
+
+            [public]
+            path = "/srv/public"
+        "#).unwrap();
+
+        assert_eq!(config.bwlimit, Some(500));
+        assert_eq!(config.modules.len(), 2);
+        assert_eq!(config.modules["backup"].bwlimit, Some(100));
+        assert_eq!(config.modules["public"].bwlimit, None);
+    }
+
+    #[test]
+    fn test_dparam_overrides() -> Result<()> {
+        let dir = tempfile::TempDir::new()?;
+        let path = dir.path().join("rsyncd.conf");
+        fs::write(&path, "address = \"127.0.0.1\"\nport = 873\n\n[data]\npath = \"/srv/data\"\n")?;
+
+        let config = DaemonConfig::load_with(&path, &[
+            "port=8873".to_string(),
+            "log level = debug".to_string(),
+            "data.path=/mnt/data".to_string(),
+            "data.read-only=true".to_string(),
+        ])?;
+        assert_eq!(config.port, 8873);
+        assert_eq!(config.address, "127.0.0.1");
+        assert_eq!(config.log_level, LogLevel::Debug);
+        assert_eq!(config.modules["data"].path, PathBuf::from("/mnt/data"));
+        assert!(config.modules["data"].read_only);
+
+        assert!(DaemonConfig::load_with(&path, &["port".to_string()]).is_err());
+        assert!(DaemonConfig::load_with(&path, &["missing.path=/x".to_string()]).is_err());
+        assert!(DaemonConfig::load_with(&path, &["port=high".to_string()]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_reports_path_on_error() -> Result<()> {
+        let dir = tempfile::TempDir::new()?;
+        let path = dir.path().join("rsyncd.conf");
+        fs::write(&path, "address = \"127.0.0.1\"\nport = 873\n\n[data]\npath = \"/srv/data\"\n")?;
+        assert_eq!(DaemonConfig::load(&path)?.modules["data"].path, PathBuf::from("/srv/data"));
+
+        fs::write(&path, "port = \"not a number\"\n")?;
+        let err = DaemonConfig::load(&path).unwrap_err();
+        assert!(err.to_string().contains("rsyncd.conf"));
+        Ok(())
+    }
 }
@@ -0,0 +1,206 @@
+use crate::transport::daemon_config::{DaemonConfig, ModuleConfig};
+use anyhow::{bail, Context, Result};
+use std::net::{IpAddr, Ipv4Addr};
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HostPattern {
+    Address(IpAddr),
+    Network(IpAddr, u32),
+    Name(String),
+}
+
+impl HostPattern {
+    pub fn parse(pattern: &str) -> Result<Self> {
+        let pattern = pattern.trim();
+        if let Some((address, mask)) = pattern.split_once('/') {
+            let address: IpAddr = address.parse()
+                .with_context(|| format!("invalid address in host pattern '{}'", pattern))?;
+            let width = if address.is_ipv4() { 32 } else { 128 };
+            let prefix = match (mask.parse::<u32>(), mask.parse::<Ipv4Addr>()) {
+                (Ok(prefix), _) => prefix,
+                (_, Ok(netmask)) if address.is_ipv4() => {
+                    let bits = u32::from(netmask);
+                    if bits.leading_ones() + bits.trailing_zeros() != 32 {
+                        bail!("netmask {} in host pattern '{}' is not contiguous", netmask, pattern);
+                    }
+                    bits.leading_ones()
+                }
+                _ => bail!("invalid netmask in host pattern '{}'", pattern),
+            };
+            if prefix > width {
+                bail!("prefix /{} is too long for host pattern '{}'", prefix, pattern);
+            }
+            return Ok(HostPattern::Network(canonical(address), prefix));
+        }
+        if let Ok(address) = pattern.parse::<IpAddr>() {
+            return Ok(HostPattern::Address(canonical(address)));
+        }
+        if pattern.is_empty() || !pattern.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.') {
+            bail!("invalid host pattern '{}': use an address, a CIDR range or a host name", pattern);
+        }
+        Ok(HostPattern::Name(pattern.to_ascii_lowercase()))
+    }
+
+
+    pub async fn matches(&self, peer: IpAddr) -> bool {
+        let peer = canonical(peer);
+        match self {
+            HostPattern::Address(address) => *address == peer,
+            HostPattern::Network(network, prefix) => in_network(peer, *network, *prefix),
+            HostPattern::Name(name) => match tokio::net::lookup_host((name.as_str(), 0)).await {
+                Ok(mut addresses) => addresses.any(|address| canonical(address.ip()) == peer),
+                Err(_) => false,
+            },
+        }
+    }
+}
+
+
+fn canonical(address: IpAddr) -> IpAddr {
+    match address {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(address, IpAddr::V4),
+        IpAddr::V4(_) => address,
+    }
+}
+
+
+fn in_network(peer: IpAddr, network: IpAddr, prefix: u32) -> bool {
+    let (peer, network, width) = match (peer, network) {
+        (IpAddr::V4(peer), IpAddr::V4(network)) => (u32::from(peer) as u128, u32::from(network) as u128, 32),
+        (IpAddr::V6(peer), IpAddr::V6(network)) => (u128::from(peer), u128::from(network), 128),
+        _ => return false,
+    };
+    let shift = width - prefix;
+    shift >= 128 || peer >> shift == network >> shift
+}
+
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HostAccess {
+    allow: Vec<HostPattern>,
+    deny: Vec<HostPattern>,
+}
+
+impl HostAccess {
+    pub fn new(allow: &[String], deny: &[String]) -> Result<Self> {
+        Ok(HostAccess {
+            allow: allow.iter().map(|pattern| HostPattern::parse(pattern)).collect::<Result<_>>()?,
+            deny: deny.iter().map(|pattern| HostPattern::parse(pattern)).collect::<Result<_>>()?,
+        })
+    }
+
+
+    pub fn for_module(config: &DaemonConfig, module: &ModuleConfig) -> Result<Self> {
+        Self::new(
+            module.hosts_allow.as_deref().unwrap_or(&config.hosts_allow),
+            module.hosts_deny.as_deref().unwrap_or(&config.hosts_deny),
+        )
+    }
+
+
+    pub async fn permits(&self, peer: IpAddr) -> bool {
+        for pattern in &self.allow {
+            if pattern.matches(peer).await {
+                return true;
+            }
+        }
+        for pattern in &self.deny {
+            if pattern.matches(peer).await {
+                return false;
+            }
+        }
+        self.allow.is_empty() || !self.deny.is_empty()
+    }
+}
+
+
+pub fn validate(config: &DaemonConfig) -> Result<()> {
+    HostAccess::new(&config.hosts_allow, &config.hosts_deny).context("global hosts_allow/hosts_deny")?;
+    for (name, module) in &config.modules {
+        HostAccess::for_module(config, module).with_context(|| format!("module [{}]", name))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patterns(list: &[&str]) -> Vec<String> {
+        list.iter().map(|pattern| pattern.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_host_patterns() -> Result<()> {
+        assert_eq!(HostPattern::parse("10.0.0.5")?, HostPattern::Address("10.0.0.5".parse()?));
+        assert_eq!(HostPattern::parse("192.168.1.0/24")?, HostPattern::Network("192.168.1.0".parse()?, 24));
+        assert_eq!(HostPattern::parse("192.168.0.0/255.255.0.0")?, HostPattern::Network("192.168.0.0".parse()?, 16));
+        assert_eq!(HostPattern::parse("fd00::/8")?, HostPattern::Network("fd00::".parse()?, 8));
+        assert_eq!(HostPattern::parse("NAS.lan")?, HostPattern::Name("nas.lan".to_string()));
+
+        assert!(HostPattern::parse("10.0.0.0/33").is_err());
+        assert!(HostPattern::parse("10.0.0.0/255.0.255.0").is_err());
+        assert!(HostPattern::parse("*.lan").is_err());
+        assert!(HostPattern::parse("").is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_host_access_rules() -> Result<()> {
+        let lan: IpAddr = "192.168.1.20".parse()?;
+        let printer: IpAddr = "192.168.1.99".parse()?;
+        let outside: IpAddr = "203.0.113.7".parse()?;
+        let mapped: IpAddr = "::ffff:192.168.1.20".parse()?;
+
+        assert!(HostAccess::default().permits(outside).await);
+
+        let access = HostAccess::new(&patterns(&["192.168.1.0/24"]), &[])?;
+        assert!(access.permits(lan).await);
+        assert!(access.permits(mapped).await);
+        assert!(!access.permits(outside).await);
+
+        let access = HostAccess::new(&[], &patterns(&["192.168.1.99", "0.0.0.0/0"]))?;
+        assert!(!access.permits(printer).await);
+        assert!(!access.permits(outside).await);
+
+        let access = HostAccess::new(&patterns(&["192.168.1.20"]), &patterns(&["192.168.1.0/24"]))?;
+        assert!(access.permits(lan).await);
+        assert!(!access.permits(printer).await);
+        assert!(access.permits(outside).await);
+
+        let access = HostAccess::new(&patterns(&["localhost"]), &[])?;
+        assert!(access.permits("127.0.0.1".parse()?).await);
+        assert!(!access.permits(outside).await);
+        Ok(())
+    }
+
+    #[test]
+    fn test_module_lists_override_global() -> Result<()> {
+        let config: DaemonConfig = toml::from_str(r#"
+            address = "0.0.0.0"
+            port = 873
+            hosts_allow = ["10.0.0.0/8"]
+
+            [backup]
+            path = "/srv/backup"
+
+            [public]
+            path = "/srv/public"
+            hosts_allow = []
+            hosts_deny = ["10.0.0.13"]
+
+            [broken]
+            path = "/srv/broken"
+            hosts_deny = ["not a host"]
+        "#)?;
+
+        assert_eq!(HostAccess::for_module(&config, &config.modules["backup"])?,
+            HostAccess::new(&patterns(&["10.0.0.0/8"]), &[])?);
+        assert_eq!(HostAccess::for_module(&config, &config.modules["public"])?,
+            HostAccess::new(&[], &patterns(&["10.0.0.13"]))?);
+        let err = validate(&config).unwrap_err();
+        assert!(format!("{:#}", err).contains("[broken]"));
+        Ok(())
+    }
+}
@@ -0,0 +1,355 @@
+use crate::transport::daemon_config::DaemonConfig;
+use anyhow::{Result, Context as _};
+use serde::Deserialize;
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+
+pub const DEFAULT_MAX_LOG_SIZE: u64 = 10 * 1024 * 1024;
+
+
+pub const DEFAULT_MAX_LOG_FILES: usize = 5;
+
+
+const EVENT_SOURCE: &str = "YARW";
+
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+}
+
+impl LogLevel {
+    pub fn label(&self) -> &'static str {
+        match self {
+            LogLevel::Error => "ERROR",
+            LogLevel::Warn => "WARN",
+            LogLevel::Info => "INFO",
+            LogLevel::Debug => "DEBUG",
+        }
+    }
+}
+
+
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    max_files: usize,
+}
+
+impl RotatingFile {
+    fn open(path: &Path, max_size: u64, max_files: usize) -> io::Result<Self> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(Self { path: path.to_path_buf(), file, size, max_size, max_files })
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+        if self.max_size > 0 && self.size > 0 && self.size + len > self.max_size {
+            self.rotate()?;
+        }
+
+        writeln!(self.file, "{}", line)?;
+        self.file.flush()?;
+        self.size += len;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.max_files > 0 {
+            let _ = fs::remove_file(self.rotated_path(self.max_files));
+            for i in (1..self.max_files).rev() {
+                let from = self.rotated_path(i);
+                if from.exists() {
+                    fs::rename(&from, self.rotated_path(i + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated_path(1))?;
+        }
+
+        self.file = OpenOptions::new().create(true).write(true).truncate(true).open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut name = OsString::from(self.path.as_os_str());
+        name.push(format!(".{}", index));
+        PathBuf::from(name)
+    }
+}
+
+
+pub struct DaemonLogger {
+    level: LogLevel,
+    console: bool,
+    file: Option<Mutex<RotatingFile>>,
+    event_log: Option<platform::EventLog>,
+    next_session: AtomicU64,
+}
+
+impl DaemonLogger {
+    pub fn new(level: LogLevel) -> Self {
+        Self {
+            level,
+            console: true,
+            file: None,
+            event_log: None,
+            next_session: AtomicU64::new(1),
+        }
+    }
+
+
+    pub fn from_config(config: &DaemonConfig) -> Result<Self> {
+        let mut logger = Self::new(config.log_level);
+
+        if let Some(ref path) = config.log_file {
+            let max_size = config.max_log_size.unwrap_or(DEFAULT_MAX_LOG_SIZE);
+            let max_files = config.max_log_files.unwrap_or(DEFAULT_MAX_LOG_FILES);
+            let file = RotatingFile::open(path, max_size, max_files)
+                .context(format!("Failed to open daemon log file {}", path.display()))?;
+            logger.file = Some(Mutex::new(file));
+        }
+
+        if config.event_log {
+            match platform::EventLog::open(EVENT_SOURCE) {
+                Ok(event_log) => logger.event_log = Some(event_log),
+                Err(e) => logger.warn(&format!("Event Log unavailable: {}", e)),
+            }
+        }
+
+        Ok(logger)
+    }
+
+
+    #[allow(dead_code)]
+    pub fn console(mut self, enabled: bool) -> Self {
+        self.console = enabled;
+        self
+    }
+
+
+    pub fn session(self: &Arc<Self>) -> SessionLog {
+        SessionLog {
+            logger: self.clone(),
+            id: self.next_session.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+
+    pub fn log(&self, level: LogLevel, session: Option<u64>, message: &str) {
+        if level > self.level {
+            return;
+        }
+
+        let message = match session {
+            Some(id) => format!("[session {}] {}", id, message),
+            None => message.to_string(),
+        };
+
+        if self.console {
+            match level {
+                LogLevel::Error => eprintln!("Error: {}", message),
+                LogLevel::Warn => eprintln!("Warning: {}", message),
+                LogLevel::Info => println!("{}", message),
+                LogLevel::Debug => println!("[DEBUG] {}", message),
+            }
+        }
+
+        if let Some(ref file) = self.file {
+            let timestamp = chrono::Local::now().format("%Y-%m-%d %H:%M:%S");
+            let line = format!("{} [{}] {}", timestamp, level.label(), message);
+            let _ = file.lock().unwrap_or_else(|e| e.into_inner()).write_line(&line);
+        }
+
+        if let Some(ref event_log) = self.event_log {
+            if level != LogLevel::Debug {
+                event_log.report(level, &message);
+            }
+        }
+    }
+
+
+    pub fn error(&self, message: &str) {
+        self.log(LogLevel::Error, None, message);
+    }
+
+
+    pub fn warn(&self, message: &str) {
+        self.log(LogLevel::Warn, None, message);
+    }
+
+
+    pub fn info(&self, message: &str) {
+        self.log(LogLevel::Info, None, message);
+    }
+}
+
+
+#[derive(Clone)]
+pub struct SessionLog {
+    logger: Arc<DaemonLogger>,
+    id: u64,
+}
+
+impl SessionLog {
+    #[allow(dead_code)]
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+
+    pub fn error(&self, message: &str) {
+        self.logger.log(LogLevel::Error, Some(self.id), message);
+    }
+
+
+    pub fn warn(&self, message: &str) {
+        self.logger.log(LogLevel::Warn, Some(self.id), message);
+    }
+
+
+    pub fn info(&self, message: &str) {
+        self.logger.log(LogLevel::Info, Some(self.id), message);
+    }
+
+
+    pub fn debug(&self, message: &str) {
+        self.logger.log(LogLevel::Debug, Some(self.id), message);
+    }
+}
+
+
+#[cfg(windows)]
+mod platform {
+    use super::LogLevel;
+    use anyhow::Result;
+    use windows::core::{HSTRING, PCWSTR};
+    use windows::Win32::Foundation::{HANDLE, PSID};
+    use windows::Win32::System::EventLog::{
+        DeregisterEventSource, RegisterEventSourceW, ReportEventW,
+        EVENTLOG_ERROR_TYPE, EVENTLOG_INFORMATION_TYPE, EVENTLOG_WARNING_TYPE,
+    };
+
+    pub struct EventLog(HANDLE);
+
+    impl EventLog {
+        pub fn open(source: &str) -> Result<Self> {
+            let handle = unsafe { RegisterEventSourceW(PCWSTR::null(), &HSTRING::from(source)) }?;
+            Ok(Self(handle))
+        }
+
+        pub fn report(&self, level: LogLevel, message: &str) {
+            let kind = match level {
+                LogLevel::Error => EVENTLOG_ERROR_TYPE,
+                LogLevel::Warn => EVENTLOG_WARNING_TYPE,
+                LogLevel::Info | LogLevel::Debug => EVENTLOG_INFORMATION_TYPE,
+            };
+            let message = HSTRING::from(message);
+            let strings = [PCWSTR(message.as_ptr())];
+            let _ = unsafe { ReportEventW(self.0, kind, 0, 0, PSID(std::ptr::null_mut()), 0, Some(&strings), None) };
+        }
+    }
+
+    impl Drop for EventLog {
+        fn drop(&mut self) {
+            let _ = unsafe { DeregisterEventSource(self.0) };
+        }
+    }
+}
+
+
+#[cfg(not(windows))]
+mod platform {
+    use super::LogLevel;
+    use anyhow::{Result, bail};
+
+    pub struct EventLog;
+
+    impl EventLog {
+        pub fn open(_source: &str) -> Result<Self> {
+            bail!("the Windows Event Log is not available on this platform")
+        }
+
+        pub fn report(&self, _level: LogLevel, _message: &str) {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_level_filter_and_session_prefix() -> Result<()> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join("rsyncd.log");
+        let mut logger = DaemonLogger::new(LogLevel::Info).console(false);
+        logger.file = Some(Mutex::new(RotatingFile::open(&path, 0, 0)?));
+        let logger = Arc::new(logger);
+
+        let first = logger.session();
+        let second = logger.session();
+        first.info("connected");
+        second.error("module not found");
+        first.debug("hidden");
+        logger.info("listening");
+
+        let contents = fs::read_to_string(&path)?;
+        assert!(contents.contains("[INFO] [session 1] connected\n"));
+        assert!(contents.contains("[ERROR] [session 2] module not found\n"));
+        assert!(contents.contains("[INFO] listening\n"));
+        assert!(!contents.contains("hidden"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_rotation_keeps_max_files() -> Result<()> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join("rsyncd.log");
+        let mut file = RotatingFile::open(&path, 16, 2)?;
+
+        for line in ["first line", "second line", "third line", "fourth line"] {
+            file.write_line(line)?;
+        }
+
+        assert_eq!(fs::read_to_string(&path)?, "fourth line\n");
+        assert_eq!(fs::read_to_string(dir.path().join("rsyncd.log.1"))?, "third line\n");
+        assert_eq!(fs::read_to_string(dir.path().join("rsyncd.log.2"))?, "second line\n");
+        assert!(!dir.path().join("rsyncd.log.3").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_log_level_from_config() {
+        let config: DaemonConfig = toml::from_str(r#"
+            address = "0.0.0.0"
+            port = 873
+            log_file = "rsyncd.log"
+            log_level = "debug"
+
+            [data]
+            path = "/srv/data"
+        "#).unwrap();
+
+        assert_eq!(config.log_level, LogLevel::Debug);
+        assert_eq!(config.log_file, Some(PathBuf::from("rsyncd.log")));
+        assert!(!config.event_log);
+        assert_eq!(config.modules.len(), 1);
+    }
+}
@@ -0,0 +1,251 @@
+use crate::output::VerboseOutput;
+use anyhow::{Result, Context as _};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+
+
+#[derive(Debug, Default, Clone)]
+struct ModuleMetrics {
+    sessions: u64,
+    errors: u64,
+    bytes_sent: u64,
+    bytes_received: u64,
+}
+
+
+type ModuleField = fn(&ModuleMetrics) -> u64;
+
+
+#[derive(Default)]
+pub struct SessionCounters {
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    module: Mutex<Option<String>>,
+}
+
+impl SessionCounters {
+    pub fn set_module(&self, module: &str) {
+        *self.module.lock().unwrap_or_else(|e| e.into_inner()) = Some(module.to_string());
+    }
+}
+
+
+#[derive(Default)]
+pub struct DaemonMetrics {
+    sessions: AtomicU64,
+    active_connections: AtomicU64,
+    errors: AtomicU64,
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    modules: Mutex<BTreeMap<String, ModuleMetrics>>,
+}
+
+impl DaemonMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+
+    pub fn session_started(&self) -> Arc<SessionCounters> {
+        self.sessions.fetch_add(1, Ordering::Relaxed);
+        self.active_connections.fetch_add(1, Ordering::Relaxed);
+        Arc::new(SessionCounters::default())
+    }
+
+
+    pub fn session_finished(&self, session: &SessionCounters, success: bool) {
+        self.active_connections.fetch_sub(1, Ordering::Relaxed);
+        if !success {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let module = session.module.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        if let Some(module) = module {
+            let mut modules = self.modules.lock().unwrap_or_else(|e| e.into_inner());
+            let entry = modules.entry(module).or_default();
+            entry.sessions += 1;
+            entry.bytes_sent += session.bytes_sent.load(Ordering::Relaxed);
+            entry.bytes_received += session.bytes_received.load(Ordering::Relaxed);
+            if !success {
+                entry.errors += 1;
+            }
+        }
+    }
+
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        let counters = [
+            ("yarw_daemon_sessions_total", "counter", "Client sessions accepted.", self.sessions.load(Ordering::Relaxed)),
+            ("yarw_daemon_active_connections", "gauge", "Client sessions currently open.", self.active_connections.load(Ordering::Relaxed)),
+            ("yarw_daemon_errors_total", "counter", "Client sessions that ended with an error.", self.errors.load(Ordering::Relaxed)),
+            ("yarw_daemon_bytes_sent_total", "counter", "Bytes written to clients.", self.bytes_sent.load(Ordering::Relaxed)),
+            ("yarw_daemon_bytes_received_total", "counter", "Bytes read from clients.", self.bytes_received.load(Ordering::Relaxed)),
+        ];
+        for (name, kind, help, value) in counters {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            let _ = writeln!(out, "{} {}", name, value);
+        }
+
+        let modules = self.modules.lock().unwrap_or_else(|e| e.into_inner());
+        let per_module: [(&str, &str, ModuleField); 4] = [
+            ("yarw_daemon_module_sessions_total", "Completed sessions per module.", |m| m.sessions),
+            ("yarw_daemon_module_errors_total", "Failed sessions per module.", |m| m.errors),
+            ("yarw_daemon_module_bytes_sent_total", "Bytes written to clients per module.", |m| m.bytes_sent),
+            ("yarw_daemon_module_bytes_received_total", "Bytes read from clients per module.", |m| m.bytes_received),
+        ];
+        for (name, help, value) in per_module {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} counter", name);
+            for (module, metrics) in modules.iter() {
+                let _ = writeln!(out, "{}{{module=\"{}\"}} {}", name, escape_label(module), value(metrics));
+            }
+        }
+
+        out
+    }
+
+
+    pub async fn serve(self: Arc<Self>, addr: String) -> Result<()> {
+        let listener = TcpListener::bind(&addr).await
+            .context(format!("Failed to bind metrics endpoint to {}", addr))?;
+        VerboseOutput::new(1, false).print_basic(format!("Metrics available at http://{}/metrics", addr));
+
+        loop {
+            let (socket, _) = listener.accept().await?;
+            let metrics = self.clone();
+            tokio::spawn(async move {
+                let _ = metrics.respond(socket).await;
+            });
+        }
+    }
+
+    async fn respond(&self, mut socket: TcpStream) -> io::Result<()> {
+        let mut request = Vec::new();
+        let mut buf = [0u8; 1024];
+        while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < 8192 {
+            let n = socket.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            request.extend_from_slice(&buf[..n]);
+        }
+
+        let request_line = String::from_utf8_lossy(&request);
+        let path = request_line.split_whitespace().nth(1).unwrap_or("/");
+
+        let (status, body) = if path == "/metrics" || path == "/" {
+            ("200 OK", self.render())
+        } else {
+            ("404 Not Found", "Not Found\n".to_string())
+        };
+
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        );
+        socket.write_all(response.as_bytes()).await?;
+        socket.shutdown().await
+    }
+}
+
+
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+
+pub struct Metered<S> {
+    inner: S,
+    metrics: Arc<DaemonMetrics>,
+    session: Arc<SessionCounters>,
+}
+
+impl<S> Metered<S> {
+    pub fn new(inner: S, metrics: Arc<DaemonMetrics>, session: Arc<SessionCounters>) -> Self {
+        Self { inner, metrics, session }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Metered<S> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+
+        let n = (buf.filled().len() - before) as u64;
+        this.metrics.bytes_received.fetch_add(n, Ordering::Relaxed);
+        this.session.bytes_received.fetch_add(n, Ordering::Relaxed);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Metered<S> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let n = ready!(Pin::new(&mut this.inner).poll_write(cx, buf))?;
+
+        this.metrics.bytes_sent.fetch_add(n as u64, Ordering::Relaxed);
+        this.session.bytes_sent.fetch_add(n as u64, Ordering::Relaxed);
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_session_bytes_roll_up_per_module() -> io::Result<()> {
+        let metrics = Arc::new(DaemonMetrics::new());
+
+        let session = metrics.session_started();
+        session.set_module("backup");
+        let mut stream = Metered::new(tokio::io::join(&b"request"[..], Vec::new()), metrics.clone(), session.clone());
+        let mut buf = Vec::new();
+        stream.read_to_end(&mut buf).await?;
+        stream.write_all(b"reply").await?;
+
+        let rendered = metrics.render();
+        assert!(rendered.contains("yarw_daemon_active_connections 1\n"));
+        assert!(rendered.contains("yarw_daemon_bytes_received_total 7\n"));
+
+        metrics.session_finished(&session, true);
+        let failed = metrics.session_started();
+        failed.set_module("backup");
+        metrics.session_finished(&failed, false);
+
+        let rendered = metrics.render();
+        assert!(rendered.contains("yarw_daemon_sessions_total 2\n"));
+        assert!(rendered.contains("yarw_daemon_active_connections 0\n"));
+        assert!(rendered.contains("yarw_daemon_errors_total 1\n"));
+        assert!(rendered.contains("yarw_daemon_module_sessions_total{module=\"backup\"} 2\n"));
+        assert!(rendered.contains("yarw_daemon_module_bytes_sent_total{module=\"backup\"} 5\n"));
+        assert!(rendered.contains("yarw_daemon_module_errors_total{module=\"backup\"} 1\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_escape_label() {
+        assert_eq!(escape_label(r#"a"b\c"#), r#"a\"b\\c"#);
+    }
+}
@@ -0,0 +1,1476 @@
+use crate::algorithm::checksum::{compute_seeded_checksum, ChecksumSeed, RollingChecksum};
+use crate::algorithm::DontCompress;
+use crate::algorithm::delta::DeltaInstruction;
+use crate::algorithm::receiver::FnameCmpType;
+use crate::filter::FilterEngine;
+use crate::options::ChecksumAlgorithm;
+use crate::protocol::iflags::{ITEM_BASIS_TYPE_FOLLOWS, ITEM_TRANSFER, ITEM_XNAME_FOLLOWS};
+use crate::protocol::message::MessageTag;
+use crate::protocol::sums::{DeltaCodec, SumHead};
+use crate::protocol::{codec, AsyncProtocolStream, MultiplexWriter, ProtocolStream, CF_CHKSUM_SEED_FIX};
+use crate::transport::daemon_config::DaemonConfig;
+use crate::transport::daemon_log::SessionLog;
+use anyhow::{bail, Context, Result};
+use base64::engine::general_purpose::STANDARD_NO_PAD;
+use base64::Engine;
+use digest::Digest;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use md5::Md5;
+use std::cmp::Ordering;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::fs;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{Cursor, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncRead, AsyncWrite, Join};
+
+
+pub const GREETING_PREFIX: &str = "@RSYNCD: ";
+
+
+pub const YARW_DIALECT: &str = "yarw";
+
+
+pub const MIN_RSYNC_PROTOCOL: i32 = 30;
+
+
+pub const NDX_DONE: i32 = -1;
+
+
+const MAX_LINE_LENGTH: usize = 4096;
+const MAX_ARGS: usize = 1024;
+const MAX_FILTER_RULE_LENGTH: i32 = 4096;
+const MAX_STRONG_LENGTH: i32 = 16;
+const MAX_PHASE: u32 = 2;
+
+
+const XMIT_TOP_DIR: u16 = 1 << 0;
+const XMIT_SAME_MODE: u16 = 1 << 1;
+const XMIT_EXTENDED_FLAGS: u16 = 1 << 2;
+const XMIT_SAME_UID: u16 = 1 << 3;
+const XMIT_SAME_GID: u16 = 1 << 4;
+const XMIT_SAME_NAME: u16 = 1 << 5;
+const XMIT_LONG_NAME: u16 = 1 << 6;
+const XMIT_SAME_TIME: u16 = 1 << 7;
+const XMIT_NO_CONTENT_DIR: u16 = 1 << 8;
+
+
+const END_FLAG: u8 = 0;
+const TOKEN_LONG: u8 = 0x20;
+const TOKENRUN_LONG: u8 = 0x21;
+const DEFLATED_DATA: u8 = 0x40;
+const TOKEN_REL: u8 = 0x80;
+const TOKENRUN_REL: u8 = 0xc0;
+const MAX_DATA_COUNT: usize = 16383;
+const MAX_INSERT_LENGTH: usize = 0xffff;
+const SYNC_TRAILER: [u8; 4] = [0, 0, 0xff, 0xff];
+
+
+const S_IFMT: u32 = 0o170000;
+const S_IFREG: u32 = 0o100000;
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Greeting {
+    pub version: i32,
+    pub extensions: Vec<String>,
+}
+
+impl Greeting {
+    pub fn new(version: i32) -> Self {
+        Greeting { version, extensions: Vec::new() }
+    }
+
+
+    pub fn with_extension(mut self, extension: &str) -> Self {
+        self.extensions.push(extension.to_string());
+        self
+    }
+
+
+    pub fn parse(line: &str) -> Result<Self> {
+        let rest = line.trim_end().strip_prefix(GREETING_PREFIX)
+            .with_context(|| format!("Expected an rsync daemon greeting, got {:?}", line))?;
+        let mut words = rest.split_whitespace();
+        let version = words.next()
+            .and_then(|version| version.split('.').next())
+            .and_then(|major| major.parse().ok())
+            .with_context(|| format!("Invalid protocol version in greeting {:?}", line))?;
+        Ok(Greeting { version, extensions: words.map(str::to_string).collect() })
+    }
+
+
+    pub fn is_yarw(&self) -> bool {
+        self.extensions.iter().any(|extension| extension == YARW_DIALECT)
+    }
+
+
+    pub fn line(&self) -> String {
+        let mut line = format!("{}{}.0", GREETING_PREFIX, self.version);
+        for extension in &self.extensions {
+            line.push(' ');
+            line.push_str(extension);
+        }
+        line.push('\n');
+        line
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ModuleRequest {
+    List,
+    Module(String),
+}
+
+impl ModuleRequest {
+    pub fn parse(line: &str) -> Self {
+        match line.trim() {
+            "" | "#list" => ModuleRequest::List,
+            name => ModuleRequest::Module(name.to_string()),
+        }
+    }
+}
+
+
+pub fn module_listing(config: &DaemonConfig) -> String {
+    let mut modules: Vec<_> = config.modules.iter().collect();
+    modules.sort_by(|a, b| a.0.cmp(b.0));
+
+    let mut listing = String::new();
+    for (name, module) in modules {
+        listing.push_str(&format!("{:<15}\t{}\n", name, module.comment.as_deref().unwrap_or("")));
+    }
+    listing.push_str(GREETING_PREFIX);
+    listing.push_str("EXIT\n");
+    listing
+}
+
+
+pub fn auth_challenge(salt: &str) -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let mut hasher = Md5::new();
+    hasher.update(salt.as_bytes());
+    hasher.update(now.as_nanos().to_le_bytes());
+    hasher.update(std::process::id().to_le_bytes());
+    hasher.update(COUNTER.fetch_add(1, AtomicOrdering::Relaxed).to_le_bytes());
+    hasher.update(RandomState::new().build_hasher().finish().to_le_bytes());
+    STANDARD_NO_PAD.encode(hasher.finalize())
+}
+
+
+pub fn auth_response(password: &str, challenge: &str) -> String {
+    let mut hasher = Md5::new();
+    hasher.update(password.as_bytes());
+    hasher.update(challenge.as_bytes());
+    STANDARD_NO_PAD.encode(hasher.finalize())
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthAccess {
+    Module,
+    ReadOnly,
+    ReadWrite,
+}
+
+impl AuthAccess {
+    pub fn read_only(self, module_read_only: bool) -> bool {
+        match self {
+            AuthAccess::Module => module_read_only,
+            AuthAccess::ReadOnly => true,
+            AuthAccess::ReadWrite => false,
+        }
+    }
+}
+
+
+pub fn parse_auth_user(entry: &str) -> Result<(&str, Option<AuthAccess>)> {
+    let Some((name, access)) = entry.rsplit_once(':') else {
+        return Ok((entry, Some(AuthAccess::Module)));
+    };
+    let access = match access.to_ascii_lowercase().as_str() {
+        "ro" => Some(AuthAccess::ReadOnly),
+        "rw" => Some(AuthAccess::ReadWrite),
+        "deny" => None,
+        _ => bail!("auth_users entry '{}' has unknown access '{}'; use ro, rw or deny", entry, access),
+    };
+    Ok((name, access))
+}
+
+
+pub fn check_auth(auth_users: &[String], secrets: &str, user: &str, response: &str, challenge: &str) -> Result<Option<AuthAccess>> {
+    let mut access = None;
+    for entry in auth_users {
+        let (name, entry_access) = parse_auth_user(entry)?;
+        if name == user {
+            access = entry_access;
+            break;
+        }
+    }
+    let Some(access) = access else {
+        return Ok(None);
+    };
+    let password_matches = secrets.lines()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| *name == user)
+        .is_some_and(|(_, password)| auth_response(password.trim_end(), challenge) == response);
+    Ok(password_matches.then_some(access))
+}
+
+
+pub async fn read_line<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut AsyncProtocolStream<S>) -> Result<String> {
+    let mut bytes = Vec::new();
+    loop {
+        match stream.read_u8().await? {
+            b'\n' => break,
+            byte => bytes.push(byte),
+        }
+        if bytes.len() > MAX_LINE_LENGTH {
+            bail!("Line from peer exceeds {} bytes", MAX_LINE_LENGTH);
+        }
+    }
+    if bytes.last() == Some(&b'\r') {
+        bytes.pop();
+    }
+    Ok(String::from_utf8(bytes)?)
+}
+
+
+pub async fn read_args<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut AsyncProtocolStream<S>) -> Result<Vec<String>> {
+    let mut args = Vec::new();
+    loop {
+        let arg = stream.read_string(MAX_LINE_LENGTH).await?;
+        if arg.is_empty() {
+            return Ok(args);
+        }
+        args.push(arg);
+        if args.len() > MAX_ARGS {
+            bail!("Client sent more than {} arguments", MAX_ARGS);
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenCompression {
+    Zlib,
+    Zlibx,
+}
+
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ServerArgs {
+    pub sender: bool,
+    pub recursive: bool,
+    pub dirs: bool,
+    pub links: bool,
+    pub owner: bool,
+    pub group: bool,
+    pub checksum: bool,
+    pub numeric_ids: bool,
+    pub compress: Option<TokenCompression>,
+    pub checksum_seed: Option<i32>,
+    pub client_info: String,
+    pub unsupported: Vec<String>,
+    pub paths: Vec<String>,
+}
+
+impl ServerArgs {
+    pub fn parse(args: &[String]) -> Result<Self> {
+        let mut parsed = ServerArgs::default();
+        let mut server = false;
+        let mut args = args.iter();
+
+        for arg in args.by_ref() {
+            match arg.as_str() {
+                "." => break,
+                "--server" => server = true,
+                "--sender" => parsed.sender = true,
+                "--recursive" => parsed.recursive = true,
+                "--dirs" => parsed.dirs = true,
+                "--links" => parsed.links = true,
+                "--owner" => parsed.owner = true,
+                "--group" => parsed.group = true,
+                "--checksum" => parsed.checksum = true,
+                "--numeric-ids" => parsed.numeric_ids = true,
+                "--compress" | "--old-compress" => parsed.compress = Some(TokenCompression::Zlib),
+                "--new-compress" => parsed.compress = Some(TokenCompression::Zlibx),
+                _ => {
+                    if let Some(seed) = arg.strip_prefix("--checksum-seed=") {
+                        parsed.checksum_seed = Some(seed.parse()
+                            .with_context(|| format!("Invalid --checksum-seed value: {}", seed))?);
+                    } else if let Some(name) = arg.strip_prefix("--compress-choice=").or_else(|| arg.strip_prefix("--zc=")) {
+                        parsed.compress_choice(name);
+                    } else if arg.starts_with("--") {
+                        continue;
+                    } else if let Some(flags) = arg.strip_prefix('-') {
+                        parsed.short_options(flags);
+                    } else {
+                        parsed.paths.push(arg.clone());
+                    }
+                }
+            }
+        }
+        parsed.paths.extend(args.cloned());
+
+        if !server {
+            bail!("Client arguments do not start an rsync server (missing --server)");
+        }
+        Ok(parsed)
+    }
+
+
+    fn short_options(&mut self, flags: &str) {
+        for (i, flag) in flags.char_indices() {
+            match flag {
+                'r' => self.recursive = true,
+                'd' => self.dirs = true,
+                'l' => self.links = true,
+                'o' => self.owner = true,
+                'g' => self.group = true,
+                'c' => self.checksum = true,
+                'z' => self.compress = Some(TokenCompression::Zlib),
+                'A' => self.unsupported("-A (ACLs)"),
+                'X' => self.unsupported("-X (extended attributes)"),
+                'R' => self.unsupported("-R (relative paths)"),
+                's' => self.unsupported("-s (protected arguments)"),
+                'e' => {
+                    self.client_info = flags[i + 1..].to_string();
+                    return;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn compress_choice(&mut self, name: &str) {
+        match name {
+            "zlib" => self.compress = Some(TokenCompression::Zlib),
+            "zlibx" => self.compress = Some(TokenCompression::Zlibx),
+            "none" => self.compress = None,
+            _ => self.unsupported(&format!("--compress-choice={}", name)),
+        }
+    }
+
+    fn unsupported(&mut self, option: &str) {
+        if !self.unsupported.iter().any(|o| o == option) {
+            self.unsupported.push(option.to_string());
+        }
+    }
+
+
+    pub fn compat_flags(&self) -> i32 {
+        if self.client_info.contains('C') {
+            CF_CHKSUM_SEED_FIX
+        } else {
+            0
+        }
+    }
+}
+
+
+pub fn resolve_source(module_name: &str, module_path: &Path, arg: &str) -> Result<(PathBuf, bool)> {
+    let rest = match arg.strip_prefix(module_name) {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => rest,
+        _ => arg,
+    };
+    let rest = rest.trim_start_matches('/');
+    let contents = rest.is_empty() || rest == "." || rest.ends_with('/') || rest.ends_with("/.");
+
+    let relative = Path::new(rest);
+    if relative.components().any(|c| !matches!(c, Component::Normal(_) | Component::CurDir)) {
+        bail!("Path {} is outside the module", arg);
+    }
+    Ok((module_path.join(relative), contents))
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WireFile {
+    pub name: String,
+    pub path: PathBuf,
+    pub is_dir: bool,
+    pub mode: u32,
+    pub size: u64,
+    pub mtime: i64,
+    pub uid: u32,
+    pub gid: u32,
+    pub link_target: Option<String>,
+    pub top_dir: bool,
+    pub content_dir: bool,
+}
+
+impl WireFile {
+    fn new(name: String, path: PathBuf, metadata: &fs::Metadata) -> Result<Self> {
+        let (mode, uid, gid) = platform::mode_and_owner(metadata);
+        let mtime = match metadata.modified()?.duration_since(UNIX_EPOCH) {
+            Ok(since) => since.as_secs() as i64,
+            Err(before) => -(before.duration().as_secs() as i64),
+        };
+        let link_target = match metadata.file_type().is_symlink() {
+            true => Some(fs::read_link(&path)?.to_string_lossy().into_owned()),
+            false => None,
+        };
+        Ok(WireFile {
+            name,
+            path,
+            is_dir: metadata.is_dir(),
+            mode,
+            size: metadata.len(),
+            mtime,
+            uid,
+            gid,
+            link_target,
+            top_dir: false,
+            content_dir: false,
+        })
+    }
+
+
+    pub fn is_regular(&self) -> bool {
+        self.mode & S_IFMT == S_IFREG
+    }
+}
+
+
+pub fn build_file_list(sources: &[(PathBuf, bool)], args: &ServerArgs, filter: &FilterEngine) -> Result<Vec<WireFile>> {
+    let mut files = Vec::new();
+
+    for (path, contents) in sources {
+        let metadata = fs::symlink_metadata(path)
+            .with_context(|| format!("link_stat \"{}\" failed", path.display()))?;
+
+        if *contents && metadata.is_dir() {
+            let mut dot = WireFile::new(".".to_string(), path.clone(), &metadata)?;
+            dot.top_dir = true;
+            dot.content_dir = args.recursive || args.dirs;
+            let walk = dot.content_dir;
+            files.push(dot);
+            if walk {
+                add_directory(path, "", args, filter, &mut files)?;
+            }
+            continue;
+        }
+
+        if metadata.is_dir() && !args.recursive && !args.dirs {
+            continue;
+        }
+        let name = match path.file_name() {
+            Some(name) => name.to_string_lossy().into_owned(),
+            None => continue,
+        };
+        let mut file = WireFile::new(name.clone(), path.clone(), &metadata)?;
+        file.top_dir = file.is_dir;
+        file.content_dir = file.is_dir && args.recursive;
+        let walk = file.content_dir;
+        files.push(file);
+        if walk {
+            add_directory(path, &format!("{}/", name), args, filter, &mut files)?;
+        }
+    }
+
+    files.sort_by(compare_names);
+    files.dedup_by(|a, b| a.name == b.name);
+    Ok(files)
+}
+
+
+fn add_directory(dir: &Path, prefix: &str, args: &ServerArgs, filter: &FilterEngine, files: &mut Vec<WireFile>) -> Result<()> {
+    for entry in fs::read_dir(dir).with_context(|| format!("opendir \"{}\" failed", dir.display()))? {
+        let entry = entry?;
+        let name = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+        if !filter.should_include(Path::new(&name)) {
+            continue;
+        }
+
+        let metadata = entry.metadata()?;
+        let file_type = metadata.file_type();
+        if !(file_type.is_file() || file_type.is_dir() || (file_type.is_symlink() && args.links)) {
+            continue;
+        }
+
+        let mut file = WireFile::new(name.clone(), entry.path(), &metadata)?;
+        file.content_dir = file.is_dir && args.recursive;
+        let walk = file.content_dir;
+        files.push(file);
+        if walk {
+            add_directory(&entry.path(), &format!("{}/", name), args, filter, files)?;
+        }
+    }
+    Ok(())
+}
+
+
+pub fn compare_names(a: &WireFile, b: &WireFile) -> Ordering {
+    fn components(file: &WireFile) -> Vec<&[u8]> {
+        match file.name.as_str() {
+            "." => Vec::new(),
+            name => name.as_bytes().split(|&c| c == b'/').collect(),
+        }
+    }
+    let (a_parts, b_parts) = (components(a), components(b));
+
+    for i in 0.. {
+        let (x, y) = match (a_parts.get(i), b_parts.get(i)) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) => (x, y),
+        };
+
+        let a_item = i + 1 == a_parts.len() && !a.is_dir;
+        let b_item = i + 1 == b_parts.len() && !b.is_dir;
+        if a_item != b_item {
+            return if a_item { Ordering::Less } else { Ordering::Greater };
+        }
+        if x != y {
+            let suffix: &[u8] = if a_item { b"" } else { b"/" };
+            return [x, suffix].concat().cmp(&[y, suffix].concat());
+        }
+    }
+    Ordering::Equal
+}
+
+
+pub fn encode_file_list(files: &[WireFile], args: &ServerArgs) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut last_name: &[u8] = b"";
+    let (mut last_mode, mut last_mtime, mut last_uid, mut last_gid) = (0u32, 0i64, 0u32, 0u32);
+
+    for file in files {
+        let name = file.name.as_bytes();
+        let mut xflags = 0u16;
+        if file.is_dir && file.top_dir {
+            xflags |= XMIT_TOP_DIR;
+        }
+        if file.is_dir && !file.content_dir {
+            xflags |= XMIT_NO_CONTENT_DIR;
+        }
+        if file.mode == last_mode {
+            xflags |= XMIT_SAME_MODE;
+        }
+        if args.owner && file.uid == last_uid {
+            xflags |= XMIT_SAME_UID;
+        }
+        if args.group && file.gid == last_gid {
+            xflags |= XMIT_SAME_GID;
+        }
+        if file.mtime == last_mtime {
+            xflags |= XMIT_SAME_TIME;
+        }
+
+        let shared = name.iter().zip(last_name).take(255).take_while(|(a, b)| a == b).count();
+        let suffix = &name[shared..];
+        if shared > 0 {
+            xflags |= XMIT_SAME_NAME;
+        }
+        if suffix.len() > 255 {
+            xflags |= XMIT_LONG_NAME;
+        }
+        if xflags == 0 && !file.is_dir {
+            xflags |= XMIT_TOP_DIR;
+        }
+
+        if xflags == 0 || xflags & 0xff00 != 0 {
+            xflags |= XMIT_EXTENDED_FLAGS;
+            out.extend_from_slice(&xflags.to_le_bytes());
+        } else {
+            out.push(xflags as u8);
+        }
+        if shared > 0 {
+            out.push(shared as u8);
+        }
+        if suffix.len() > 255 {
+            out.extend_from_slice(codec::encode_varint(suffix.len() as i32).as_slice());
+        } else {
+            out.push(suffix.len() as u8);
+        }
+        out.extend_from_slice(suffix);
+
+        out.extend_from_slice(codec::encode_varlong(file.size as i64, 3).as_slice());
+        if xflags & XMIT_SAME_TIME == 0 {
+            out.extend_from_slice(codec::encode_varlong(file.mtime, 4).as_slice());
+        }
+        if xflags & XMIT_SAME_MODE == 0 {
+            out.extend_from_slice(&file.mode.to_le_bytes());
+        }
+        if args.owner && xflags & XMIT_SAME_UID == 0 {
+            out.extend_from_slice(codec::encode_varint(file.uid as i32).as_slice());
+        }
+        if args.group && xflags & XMIT_SAME_GID == 0 {
+            out.extend_from_slice(codec::encode_varint(file.gid as i32).as_slice());
+        }
+        if let (true, Some(target)) = (args.links, file.link_target.as_ref()) {
+            out.extend_from_slice(codec::encode_varint(target.len() as i32).as_slice());
+            out.extend_from_slice(target.as_bytes());
+        }
+        if args.checksum && file.is_regular() {
+            let mut hasher = Md5::new();
+            std::io::copy(&mut fs::File::open(&file.path)?, &mut hasher)?;
+            out.extend_from_slice(&hasher.finalize());
+        }
+
+        last_name = name;
+        last_mode = file.mode;
+        last_mtime = file.mtime;
+        last_uid = file.uid;
+        last_gid = file.gid;
+    }
+    out.push(0);
+
+    if !args.numeric_ids {
+        if args.owner {
+            out.extend_from_slice(codec::encode_varint(0).as_slice());
+        }
+        if args.group {
+            out.extend_from_slice(codec::encode_varint(0).as_slice());
+        }
+    }
+    Ok(out)
+}
+
+
+#[derive(Debug, Clone, Copy)]
+pub struct NdxCodec {
+    prev_positive: i32,
+    prev_negative: i32,
+}
+
+impl Default for NdxCodec {
+    fn default() -> Self {
+        NdxCodec { prev_positive: -1, prev_negative: 1 }
+    }
+}
+
+impl NdxCodec {
+    pub fn encode(&mut self, ndx: i32) -> Vec<u8> {
+        if ndx == NDX_DONE {
+            return vec![0];
+        }
+        let (mut bytes, value, diff) = if ndx >= 0 {
+            let diff = ndx - self.prev_positive;
+            self.prev_positive = ndx;
+            (Vec::new(), ndx, diff)
+        } else {
+            let value = -ndx;
+            let diff = value - self.prev_negative;
+            self.prev_negative = value;
+            (vec![0xff], value, diff)
+        };
+
+        if diff > 0 && diff < 0xfe {
+            bytes.push(diff as u8);
+        } else if !(0..=0x7fff).contains(&diff) {
+            bytes.extend_from_slice(&[0xfe, (value >> 24) as u8 | 0x80, value as u8, (value >> 8) as u8, (value >> 16) as u8]);
+        } else {
+            bytes.extend_from_slice(&[0xfe, (diff >> 8) as u8, diff as u8]);
+        }
+        bytes
+    }
+
+
+    pub async fn read<S: AsyncRead + AsyncWrite + Unpin>(&mut self, stream: &mut AsyncProtocolStream<S>) -> Result<i32> {
+        let mut byte = stream.read_u8().await?;
+        let negative = byte == 0xff;
+        if negative {
+            byte = stream.read_u8().await?;
+        } else if byte == 0 {
+            return Ok(NDX_DONE);
+        }
+
+        let prev = if negative { self.prev_negative } else { self.prev_positive };
+        let value = if byte == 0xfe {
+            let mut head = [0u8; 2];
+            stream.read_all(&mut head).await?;
+            if head[0] & 0x80 != 0 {
+                let mut rest = [0u8; 2];
+                stream.read_all(&mut rest).await?;
+                i32::from_le_bytes([head[1], rest[0], rest[1], head[0] & 0x7f])
+            } else {
+                ((head[0] as i32) << 8) + head[1] as i32 + prev
+            }
+        } else {
+            byte as i32 + prev
+        };
+
+        if negative {
+            self.prev_negative = value;
+            Ok(-value)
+        } else {
+            self.prev_positive = value;
+            Ok(value)
+        }
+    }
+}
+
+
+pub fn block_digest(data: &[u8], seed: i32, seed_first: bool) -> [u8; 16] {
+    let mut digest = [0u8; 16];
+    digest.copy_from_slice(compute_seeded_checksum(data, &ChecksumAlgorithm::Md5, ChecksumSeed::new(seed, seed_first)).as_bytes());
+    digest
+}
+
+
+pub struct Signature {
+    pub head: SumHead,
+    pub blocks: Vec<(u32, Vec<u8>)>,
+}
+
+impl Signature {
+    pub async fn read<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut AsyncProtocolStream<S>) -> Result<Self> {
+        let head = SumHead {
+            count: stream.read_i32().await?,
+            block_length: stream.read_i32().await?,
+            checksum_length: stream.read_i32().await?,
+            remainder: stream.read_i32().await?,
+        };
+        let valid = head.count >= 0
+            && (0..=MAX_STRONG_LENGTH).contains(&head.checksum_length)
+            && (0..=head.block_length).contains(&head.remainder)
+            && (head.count == 0 || head.block_length > 0);
+        if !valid {
+            bail!("Invalid checksum header: {:?}", head);
+        }
+
+        let mut blocks = Vec::with_capacity(head.count as usize);
+        for _ in 0..head.count {
+            let weak = stream.read_i32().await? as u32;
+            let mut strong = vec![0u8; head.checksum_length as usize];
+            stream.read_all(&mut strong).await?;
+            blocks.push((weak, strong));
+        }
+        Ok(Signature { head, blocks })
+    }
+
+
+    pub fn match_blocks(&self, data: &[u8], seed: i32, seed_first: bool) -> Vec<DeltaInstruction> {
+        let mut instructions = Vec::new();
+        let block_length = self.head.block_length as usize;
+        if self.blocks.is_empty() || block_length == 0 || self.head.checksum_length == 0 {
+            if !data.is_empty() {
+                instructions.push(DeltaInstruction::literal_data(data.to_vec()));
+            }
+            return instructions;
+        }
+
+        let mut table: HashMap<u32, Vec<usize>> = HashMap::new();
+        for (index, (weak, _)) in self.blocks.iter().enumerate() {
+            table.entry(*weak).or_default().push(index);
+        }
+
+        let mut literal_start = 0;
+        let mut offset = 0;
+        let mut window = block_length.min(data.len());
+        let mut rolling = RollingChecksum::new(&data[..window]);
+        while window > 0 {
+            let weak = rolling.checksum();
+            let mut digest = None;
+            let found = table.get(&weak).and_then(|candidates| {
+                candidates.iter().copied().find(|&index| {
+                    if self.head.block_size(index as u32) as usize != window {
+                        return false;
+                    }
+                    let digest = digest.get_or_insert_with(|| block_digest(&data[offset..offset + window], seed, seed_first));
+                    digest[..self.blocks[index].1.len()] == self.blocks[index].1[..]
+                })
+            });
+
+            if let Some(index) = found {
+                if literal_start < offset {
+                    instructions.push(DeltaInstruction::literal_data(data[literal_start..offset].to_vec()));
+                }
+                instructions.push(DeltaInstruction::matched_block(index as u32));
+                offset += window;
+                literal_start = offset;
+                window = block_length.min(data.len() - offset);
+                rolling = RollingChecksum::new(&data[offset..offset + window]);
+                continue;
+            }
+
+            if offset + window < data.len() {
+                rolling.roll(data[offset], data[offset + window]);
+            } else {
+                rolling.roll_out(data[offset]);
+                window -= 1;
+            }
+            offset += 1;
+        }
+
+        if literal_start < data.len() {
+            instructions.push(DeltaInstruction::literal_data(data[literal_start..].to_vec()));
+        }
+        instructions
+    }
+}
+
+
+pub struct DeflatedTokens {
+    compression: TokenCompression,
+    protocol_version: i32,
+    encoder: DeflateEncoder<Vec<u8>>,
+    out: Vec<u8>,
+    last_token: i32,
+    run_start: i32,
+    last_run_end: i32,
+}
+
+impl DeflatedTokens {
+    pub fn new(compression: TokenCompression, level: Compression, protocol_version: i32) -> Self {
+        DeflatedTokens {
+            compression,
+            protocol_version,
+            encoder: DeflateEncoder::new(Vec::new(), level),
+            out: Vec::new(),
+            last_token: -1,
+            run_start: 0,
+            last_run_end: 0,
+        }
+    }
+
+
+    pub fn encode(mut self, instructions: &[DeltaInstruction], data: &[u8], head: &SumHead) -> Result<Vec<u8>> {
+        let mut literal_start = 0;
+        let mut offset = 0;
+        for instruction in instructions {
+            match instruction {
+                DeltaInstruction::LiteralData { data: literal } => offset += literal.len(),
+                DeltaInstruction::MatchedBlock { index } => {
+                    let length = head.block_size(*index) as usize;
+                    self.send(*index as i32, &data[literal_start..offset], &data[offset..offset + length])?;
+                    offset += length;
+                    literal_start = offset;
+                }
+            }
+        }
+        self.send(-1, &data[literal_start..offset], &[])?;
+        Ok(self.out)
+    }
+
+
+    fn send(&mut self, token: i32, literal: &[u8], block: &[u8]) -> Result<()> {
+        if self.last_token == -1 {
+            self.run_start = token;
+            self.last_run_end = 0;
+        } else if !literal.is_empty() || token != self.last_token + 1 || token >= self.run_start + 65536 {
+            self.write_run();
+            self.run_start = token;
+        }
+        self.last_token = token;
+
+        if !literal.is_empty() {
+            let compressed = self.deflate(literal)?;
+            let Some(compressed) = compressed.strip_suffix(&SYNC_TRAILER) else {
+                bail!("Compressed literal data does not end at a sync point");
+            };
+            for chunk in compressed.chunks(MAX_DATA_COUNT) {
+                self.out.push(DEFLATED_DATA | (chunk.len() >> 8) as u8);
+                self.out.push(chunk.len() as u8);
+                self.out.extend_from_slice(chunk);
+            }
+        }
+
+        if token == -1 {
+            self.out.push(END_FLAG);
+        } else if self.compression == TokenCompression::Zlib {
+            if self.protocol_version >= 31 {
+                self.deflate(block)?;
+            } else {
+                for chunk in block.chunks(MAX_INSERT_LENGTH) {
+                    self.deflate(&block[..chunk.len()])?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+
+    fn write_run(&mut self) {
+        let relative = self.run_start - self.last_run_end;
+        let run = self.last_token - self.run_start;
+        if (0..=63).contains(&relative) {
+            self.out.push(if run == 0 { TOKEN_REL } else { TOKENRUN_REL } + relative as u8);
+        } else {
+            self.out.push(if run == 0 { TOKEN_LONG } else { TOKENRUN_LONG });
+            self.out.extend_from_slice(&self.run_start.to_le_bytes());
+        }
+        if run != 0 {
+            self.out.push(run as u8);
+            self.out.push((run >> 8) as u8);
+        }
+        self.last_run_end = self.last_token;
+    }
+
+
+    fn deflate(&mut self, data: &[u8]) -> Result<Vec<u8>> {
+        self.encoder.write_all(data)?;
+        self.encoder.flush()?;
+        Ok(std::mem::take(self.encoder.get_mut()))
+    }
+}
+
+
+async fn read_filter_rules<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut AsyncProtocolStream<S>,
+    log: &SessionLog,
+) -> Result<FilterEngine> {
+    let mut filter = FilterEngine::new();
+    loop {
+        let len = stream.read_i32().await?;
+        if len == 0 {
+            return Ok(filter);
+        }
+        if !(1..=MAX_FILTER_RULE_LENGTH).contains(&len) {
+            bail!("Invalid filter rule length: {}", len);
+        }
+        let mut rule = vec![0u8; len as usize];
+        stream.read_all(&mut rule).await?;
+        let rule = String::from_utf8_lossy(&rule);
+
+        if !filter.add_rule(&rule)? {
+            log.warn(&format!("Ignoring unsupported filter rule from client: {}", rule));
+        }
+    }
+}
+
+
+async fn write_item<R, W>(
+    stream: &mut AsyncProtocolStream<Join<R, MultiplexWriter<W>>>,
+    ndx_out: &mut NdxCodec,
+    ndx: i32,
+    iflags: u16,
+    basis_type: Option<FnameCmpType>,
+    xname: Option<&[u8]>,
+) -> Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    stream.write_all(&ndx_out.encode(ndx)).await?;
+    stream.write_u16(iflags).await?;
+    if let Some(basis_type) = basis_type {
+        stream.write_u8(basis_type.as_u8()).await?;
+    }
+    if let Some(xname) = xname {
+        if xname.len() > 0x7f {
+            stream.write_u8((xname.len() >> 8) as u8 | 0x80).await?;
+        }
+        stream.write_u8(xname.len() as u8).await?;
+        stream.write_all(xname).await?;
+    }
+    Ok(())
+}
+
+
+pub async fn serve_sender<R, W>(
+    stream: &mut AsyncProtocolStream<Join<R, MultiplexWriter<W>>>,
+    sources: &[(PathBuf, bool)],
+    args: &ServerArgs,
+    dont_compress: &DontCompress,
+    protocol_version: i32,
+    log: &SessionLog,
+) -> Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let filter = read_filter_rules(stream, log).await?;
+
+    let build_start = Instant::now();
+    let files = build_file_list(sources, args, &filter)?;
+    let flist = encode_file_list(&files, args)?;
+    let build_time = build_start.elapsed().as_millis() as i64;
+    stream.write_all(&flist).await?;
+    stream.flush().await?;
+    log.debug(&format!("File list sent ({} entries)", files.len()));
+
+    let seed = stream.checksum_seed();
+    let seed_first = args.compat_flags() & CF_CHKSUM_SEED_FIX != 0;
+    let mut ndx_in = NdxCodec::default();
+    let mut ndx_out = NdxCodec::default();
+    let mut total_read = 0u64;
+    let mut total_written = flist.len() as u64;
+    let (mut sent_files, mut literal_bytes, mut matched_bytes) = (0usize, 0u64, 0u64);
+    let mut phase = 0;
+
+    loop {
+        let ndx = ndx_in.read(stream).await?;
+        if ndx == NDX_DONE {
+            phase += 1;
+            if phase > MAX_PHASE {
+                break;
+            }
+            stream.write_all(&ndx_out.encode(NDX_DONE)).await?;
+            stream.flush().await?;
+            continue;
+        }
+
+        let iflags = stream.read_u16().await?;
+        let basis_type = match iflags & ITEM_BASIS_TYPE_FOLLOWS {
+            0 => None,
+            _ => Some(FnameCmpType::from_u8(stream.read_u8().await?)?),
+        };
+        let xname = match iflags & ITEM_XNAME_FOLLOWS {
+            0 => None,
+            _ => {
+                let mut len = stream.read_u8().await? as usize;
+                if len & 0x80 != 0 {
+                    len = (len & 0x7f) << 8 | stream.read_u8().await? as usize;
+                }
+                let mut xname = vec![0u8; len];
+                stream.read_all(&mut xname).await?;
+                Some(xname)
+            }
+        };
+        let file = usize::try_from(ndx).ok()
+            .and_then(|index| files.get(index))
+            .with_context(|| format!("Client requested invalid file index {}", ndx))?;
+
+        if iflags & ITEM_TRANSFER == 0 {
+            write_item(stream, &mut ndx_out, ndx, iflags, basis_type, xname.as_deref()).await?;
+            continue;
+        }
+
+        let signature = Signature::read(stream).await?;
+        total_read += 16 + signature.blocks.len() as u64 * (4 + signature.head.checksum_length as u64);
+        let data = match fs::read(&file.path) {
+            Ok(data) => data,
+            Err(e) => {
+                log.warn(&format!("Cannot send {}: {}", file.name, e));
+                let message = format!("send_files failed to open \"{}\": {}\n", file.name, e);
+                let writer = stream.get_mut().writer_mut();
+                writer.write_message(MessageTag::ErrorXfer, message.as_bytes()).await?;
+                writer.write_message(MessageTag::NoSend, &ndx.to_le_bytes()).await?;
+                continue;
+            }
+        };
+
+        let instructions = signature.match_blocks(&data, seed, seed_first);
+        let mut tokens = match args.compress {
+            Some(compression) => {
+                let level = if dont_compress.matches(&file.path) { Compression::none() } else { Compression::default() };
+                DeflatedTokens::new(compression, level, protocol_version).encode(&instructions, &data, &signature.head)?
+            }
+            None => {
+                let mut tokens = ProtocolStream::new(Cursor::new(Vec::new()), protocol_version);
+                DeltaCodec::write_tokens(&mut tokens, &instructions, |_, _| {})?;
+                tokens.get_ref().get_ref().clone()
+            }
+        };
+        tokens.extend_from_slice(&Md5::digest(&data));
+        for instruction in &instructions {
+            match instruction {
+                DeltaInstruction::LiteralData { data } => literal_bytes += data.len() as u64,
+                DeltaInstruction::MatchedBlock { index } => matched_bytes += signature.head.block_size(*index),
+            }
+        }
+
+        write_item(stream, &mut ndx_out, ndx, iflags, basis_type, xname.as_deref()).await?;
+        for value in [signature.head.count, signature.head.block_length, signature.head.checksum_length, signature.head.remainder] {
+            stream.write_i32(value).await?;
+        }
+        stream.write_all(&tokens).await?;
+        stream.flush().await?;
+        total_written += tokens.len() as u64;
+        sent_files += 1;
+        log.debug(&format!("Sent {} ({} bytes)", file.name, data.len()));
+    }
+
+    stream.write_all(&ndx_out.encode(NDX_DONE)).await?;
+    let total_size: u64 = files.iter().filter(|f| !f.is_dir).map(|f| f.size).sum();
+    for value in [total_read, total_written, total_size, build_time as u64, 0] {
+        stream.write_varlong30(value as i64, 3).await?;
+    }
+    stream.flush().await?;
+
+    if ndx_in.read(stream).await? != NDX_DONE {
+        bail!("Client did not finish the session cleanly");
+    }
+    if protocol_version >= 31 {
+        stream.write_all(&ndx_out.encode(NDX_DONE)).await?;
+        stream.flush().await?;
+        if ndx_in.read(stream).await? != NDX_DONE {
+            bail!("Client did not finish the session cleanly");
+        }
+    }
+
+    log.info(&format!(
+        "Sent {} files ({} literal bytes, {} matched bytes)",
+        sent_files, literal_bytes, matched_bytes
+    ));
+    Ok(())
+}
+
+
+#[cfg(unix)]
+mod platform {
+    use std::fs::Metadata;
+    use std::os::unix::fs::MetadataExt;
+
+    pub fn mode_and_owner(metadata: &Metadata) -> (u32, u32, u32) {
+        (metadata.mode(), metadata.uid(), metadata.gid())
+    }
+}
+
+
+#[cfg(not(unix))]
+mod platform {
+    use std::fs::Metadata;
+
+    pub fn mode_and_owner(metadata: &Metadata) -> (u32, u32, u32) {
+        let mode = if metadata.file_type().is_symlink() {
+            0o120777
+        } else if metadata.is_dir() {
+            0o040755
+        } else if metadata.permissions().readonly() {
+            0o100444
+        } else {
+            0o100644
+        };
+        (mode, 0, 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{Decompress, FlushDecompress};
+    use tempfile::TempDir;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn wire(name: &str, is_dir: bool) -> WireFile {
+        WireFile {
+            name: name.to_string(),
+            path: PathBuf::from(name),
+            is_dir,
+            mode: if is_dir { 0o040755 } else { 0o100644 },
+            size: 0,
+            mtime: 0,
+            uid: 0,
+            gid: 0,
+            link_target: None,
+            top_dir: false,
+            content_dir: is_dir,
+        }
+    }
+
+    #[test]
+    fn test_greeting_and_module_request() -> Result<()> {
+        let greeting = Greeting::parse("@RSYNCD: 31.0 sha512 sha256 md5 md4\n")?;
+        assert_eq!(greeting.version, 31);
+        assert_eq!(greeting.extensions, vec!["sha512", "sha256", "md5", "md4"]);
+        assert!(!greeting.is_yarw());
+
+        let ours = Greeting::new(31).with_extension(YARW_DIALECT);
+        assert_eq!(ours.line(), "@RSYNCD: 31.0 yarw\n");
+        assert!(Greeting::parse(&ours.line())?.is_yarw());
+        assert_eq!(Greeting::parse("@RSYNCD: 29")?.version, 29);
+        assert!(Greeting::parse("SSH-2.0-OpenSSH").is_err());
+        assert!(Greeting::parse("@RSYNCD: x.0").is_err());
+
+        assert_eq!(ModuleRequest::parse(""), ModuleRequest::List);
+        assert_eq!(ModuleRequest::parse("#list"), ModuleRequest::List);
+        assert_eq!(ModuleRequest::parse("backup\r"), ModuleRequest::Module("backup".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_module_listing() {
+        let config: DaemonConfig = toml::from_str(r#"
+            address = "0.0.0.0"
+            port = 873
+
+            [public]
+            path = "/srv/public"
+            comment = "Public mirror"
+
+            [backup]
+            path = "/srv/backup"
+        "#).unwrap();
+
+        assert_eq!(
+            module_listing(&config),
+            "backup         \t\npublic         \tPublic mirror\n@RSYNCD: EXIT\n"
+        );
+    }
+
+    #[test]
+    fn test_auth_challenge_response() -> Result<()> {
+        assert_eq!(auth_response("secret", "MTIzNDU2Nzg5MGFiY2RlZg"), "DwaqKxhjo4CzXBdPN86sJw");
+        let challenge = auth_challenge("backup");
+        assert_eq!(challenge.len(), 22);
+        assert_ne!(challenge, auth_challenge("backup"));
+
+        let users: Vec<String> = ["alice", "bob:ro", "mallory:deny", "dave:RW"].iter().map(|entry| entry.to_string()).collect();
+        let secrets = "# daemon users\nalice:secret\nbob:hunter2\nmallory:secret\ncarol:secret\ndave:secret\n";
+        let response = auth_response("secret", &challenge);
+        assert_eq!(check_auth(&users, secrets, "alice", &response, &challenge)?, Some(AuthAccess::Module));
+        assert_eq!(check_auth(&users, secrets, "bob", &auth_response("hunter2", &challenge), &challenge)?, Some(AuthAccess::ReadOnly));
+        assert_eq!(check_auth(&users, secrets, "dave", &response, &challenge)?, Some(AuthAccess::ReadWrite));
+        assert_eq!(check_auth(&users, secrets, "alice", &auth_response("wrong", &challenge), &challenge)?, None);
+        assert_eq!(check_auth(&users, secrets, "alice", &response, "another-challenge")?, None);
+        assert_eq!(check_auth(&users, secrets, "mallory", &response, &challenge)?, None);
+        assert_eq!(check_auth(&users, secrets, "carol", &response, &challenge)?, None);
+
+        assert!(AuthAccess::ReadOnly.read_only(false));
+        assert!(!AuthAccess::ReadWrite.read_only(true));
+        assert!(AuthAccess::Module.read_only(true));
+        assert!(check_auth(&["alice:write".to_string()], secrets, "alice", &response, &challenge).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_server_args() -> Result<()> {
+        let parsed = ServerArgs::parse(&args(&[
+            "--server", "--sender", "-vlogDtpre.iLsfxCIvu", "--numeric-ids", "--checksum-seed=7", ".", "backup/docs/",
+        ]))?;
+        assert!(parsed.sender && parsed.recursive && parsed.links && parsed.owner && parsed.group);
+        assert!(parsed.numeric_ids && !parsed.dirs && !parsed.checksum);
+        assert_eq!(parsed.checksum_seed, Some(7));
+        assert_eq!(parsed.client_info, ".iLsfxCIvu");
+        assert_eq!(parsed.compat_flags(), CF_CHKSUM_SEED_FIX);
+        assert_eq!(parsed.paths, vec!["backup/docs/"]);
+        assert!(parsed.unsupported.is_empty());
+
+        assert_eq!(parsed.compress, None);
+
+        let parsed = ServerArgs::parse(&args(&["--server", "--sender", "-logDtprze.iLsfxC", "-A", ".", "backup"]))?;
+        assert_eq!(parsed.compress, Some(TokenCompression::Zlib));
+        assert_eq!(parsed.unsupported, vec!["-A (ACLs)"]);
+
+        let parsed = ServerArgs::parse(&args(&["--server", "--sender", "-logDtpre.iLsfxC", "--new-compress", ".", "backup"]))?;
+        assert_eq!(parsed.compress, Some(TokenCompression::Zlibx));
+        let parsed = ServerArgs::parse(&args(&["--server", "--sender", "-rze.iLsfxC", "--compress-choice=zstd", ".", "backup"]))?;
+        assert_eq!(parsed.unsupported, vec!["--compress-choice=zstd"]);
+
+        assert!(ServerArgs::parse(&args(&["--sender", ".", "backup"])).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_source() -> Result<()> {
+        let root = Path::new("/srv/backup");
+        assert_eq!(resolve_source("backup", root, "backup")?, (root.to_path_buf(), true));
+        assert_eq!(resolve_source("backup", root, "backup/")?, (root.to_path_buf(), true));
+        assert_eq!(resolve_source("backup", root, "backup/docs")?, (root.join("docs"), false));
+        assert_eq!(resolve_source("backup", root, "backup/docs/")?, (root.join("docs"), true));
+        assert_eq!(resolve_source("backup", root, "backups/x")?, (root.join("backups/x"), false));
+        assert!(resolve_source("backup", root, "backup/../etc").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_compare_names_matches_rsync_order() {
+        let mut files = vec![
+            wire("b", true),
+            wire("b/z.txt", false),
+            wire("a-b", true),
+            wire("a", true),
+            wire("a/x", false),
+            wire("a.txt", false),
+            wire("c", false),
+            wire("b/sub", true),
+            wire("b/a", false),
+            wire(".", true),
+        ];
+        files.sort_by(compare_names);
+        let names: Vec<&str> = files.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, vec![".", "a.txt", "c", "a-b", "a", "a/x", "b", "b/a", "b/z.txt", "b/sub"]);
+    }
+
+    #[test]
+    fn test_build_and_encode_file_list() -> Result<()> {
+        let dir = TempDir::new()?;
+        fs::create_dir(dir.path().join("sub"))?;
+        fs::write(dir.path().join("sub/deep.txt"), b"deep")?;
+        fs::write(dir.path().join("top.txt"), b"top")?;
+        fs::write(dir.path().join("skip.tmp"), b"tmp")?;
+
+        let mut filter = FilterEngine::new();
+        filter.add_exclude("*.tmp")?;
+        let args = ServerArgs { recursive: true, ..ServerArgs::default() };
+        let files = build_file_list(&[(dir.path().to_path_buf(), true)], &args, &filter)?;
+        let names: Vec<&str> = files.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, vec![".", "top.txt", "sub", "sub/deep.txt"]);
+        assert!(files[0].top_dir && files[0].content_dir);
+
+        let encoded = encode_file_list(&files[1..2], &args)?;
+        let file = &files[1];
+        let mut expected = vec![XMIT_TOP_DIR as u8, 7];
+        expected.extend_from_slice(b"top.txt");
+        expected.extend_from_slice(codec::encode_varlong(3, 3).as_slice());
+        expected.extend_from_slice(codec::encode_varlong(file.mtime, 4).as_slice());
+        expected.extend_from_slice(&file.mode.to_le_bytes());
+        expected.push(0);
+        assert_eq!(encoded, expected);
+
+        let dirs = ServerArgs { dirs: true, ..ServerArgs::default() };
+        let files = build_file_list(&[(dir.path().to_path_buf(), true)], &dirs, &FilterEngine::new())?;
+        let names: Vec<&str> = files.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, vec![".", "skip.tmp", "top.txt", "sub"]);
+        assert!(!files[3].content_dir);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_ndx_round_trip() -> Result<()> {
+        let values = [0, 1, 2, 300, 70000, NDX_DONE, 5, -2, -3, 100000];
+        let mut encoder = NdxCodec::default();
+        let mut bytes = Vec::new();
+        for value in values {
+            bytes.extend(encoder.encode(value));
+        }
+        assert_eq!(&bytes[..3], &[1, 1, 1]);
+
+        let mut stream = AsyncProtocolStream::new(Cursor::new(bytes), 31);
+        let mut decoder = NdxCodec::default();
+        for value in values {
+            assert_eq!(decoder.read(&mut stream).await?, value);
+        }
+        Ok(())
+    }
+
+    fn inflate(inflater: &mut Decompress, input: &[u8]) -> Vec<u8> {
+        let start = inflater.total_in();
+        let mut output = Vec::new();
+        loop {
+            output.reserve(64 * 1024);
+            let consumed = (inflater.total_in() - start) as usize;
+            inflater.decompress_vec(&input[consumed..], &mut output, FlushDecompress::Sync).unwrap();
+            if (inflater.total_in() - start) as usize == input.len() && output.len() < output.capacity() {
+                return output;
+            }
+        }
+    }
+
+    fn receive_deflated_tokens(tokens: &[u8], basis: &[u8], block_length: usize, compression: TokenCompression) -> Vec<u8> {
+        let mut inflater = Decompress::new(false);
+        let mut rebuilt = Vec::new();
+        let mut pending = Vec::new();
+        let mut last_token = 0i32;
+        let mut i = 0;
+        loop {
+            let flag = tokens[i];
+            if flag & 0xc0 == DEFLATED_DATA {
+                let n = ((flag & 0x3f) as usize) << 8 | tokens[i + 1] as usize;
+                pending.extend_from_slice(&tokens[i + 2..i + 2 + n]);
+                i += 2 + n;
+                continue;
+            }
+            i += 1;
+            if !pending.is_empty() {
+                pending.extend_from_slice(&SYNC_TRAILER);
+                rebuilt.extend(inflate(&mut inflater, &pending));
+                pending.clear();
+            }
+            if flag == END_FLAG {
+                assert_eq!(i, tokens.len());
+                return rebuilt;
+            }
+
+            let (token, has_run) = if flag & TOKEN_REL != 0 {
+                (last_token + (flag & 0x3f) as i32, flag & 0x40 != 0)
+            } else {
+                i += 4;
+                (i32::from_le_bytes(tokens[i - 4..i].try_into().unwrap()), flag & 1 != 0)
+            };
+            let run = if has_run {
+                i += 2;
+                tokens[i - 2] as i32 | (tokens[i - 1] as i32) << 8
+            } else {
+                0
+            };
+            for index in token..=token + run {
+                let start = index as usize * block_length;
+                let block = &basis[start..(start + block_length).min(basis.len())];
+                rebuilt.extend_from_slice(block);
+                if compression == TokenCompression::Zlib {
+                    let len = block.len() as u16;
+                    let mut stored = vec![0, len as u8, (len >> 8) as u8, !len as u8, !(len >> 8) as u8];
+                    stored.extend_from_slice(block);
+                    assert_eq!(inflate(&mut inflater, &stored), block);
+                }
+            }
+            last_token = token + run;
+        }
+    }
+
+    #[test]
+    fn test_deflated_tokens_rebuild_file() -> Result<()> {
+        let basis: Vec<u8> = (0..20000).flat_map(|i| format!("line {} of the basis file\n", i).into_bytes()).collect();
+        let mut seed = 1u32;
+        let noise: Vec<u8> = (0..40000).map(|_| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 16) as u8
+        }).collect();
+        let mut data = basis.clone();
+        data.splice(300_000..300_100, noise);
+        data.extend_from_slice(b"line 1 of the basis file\nline 2 of the basis file\n");
+        data.drain(1000..1500);
+
+        let block_length = 2048usize;
+        let head = SumHead { checksum_length: 16, ..SumHead::for_length(basis.len() as u64, block_length) };
+        let blocks = basis.chunks(block_length)
+            .map(|block| (RollingChecksum::new(block).checksum(), block_digest(block, 0, true).to_vec()))
+            .collect();
+        let signature = Signature { head, blocks };
+        let instructions = signature.match_blocks(&data, 0, true);
+
+        for compression in [TokenCompression::Zlib, TokenCompression::Zlibx] {
+            for level in [Compression::default(), Compression::none()] {
+                let tokens = DeflatedTokens::new(compression, level, 31).encode(&instructions, &data, &signature.head)?;
+                assert_eq!(receive_deflated_tokens(&tokens, &basis, block_length, compression), data);
+                if level == Compression::default() {
+                    assert!(tokens.len() < 60000, "{} bytes on the wire", tokens.len());
+                }
+            }
+        }
+
+        let tokens = DeflatedTokens::new(TokenCompression::Zlib, Compression::default(), 31).encode(&[], &[], &signature.head)?;
+        assert_eq!(tokens, vec![END_FLAG]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_match_blocks_rebuilds_file() {
+        let basis: Vec<u8> = (0..5000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let mut data = basis.clone();
+        data.splice(1200..1210, b"inserted bytes".iter().copied());
+        data.truncate(4700);
+
+        let block_length = 512usize;
+        let head = SumHead::for_length(basis.len() as u64, block_length);
+        let head = SumHead { checksum_length: 2, ..head };
+        let blocks = basis.chunks(block_length)
+            .map(|block| (RollingChecksum::new(block).checksum(), block_digest(block, 42, true)[..2].to_vec()))
+            .collect();
+        let signature = Signature { head, blocks };
+
+        let instructions = signature.match_blocks(&data, 42, true);
+        let mut rebuilt = Vec::new();
+        for instruction in &instructions {
+            match instruction {
+                DeltaInstruction::LiteralData { data } => rebuilt.extend_from_slice(data),
+                DeltaInstruction::MatchedBlock { index } => {
+                    let start = *index as usize * block_length;
+                    rebuilt.extend_from_slice(&basis[start..(start + block_length).min(basis.len())]);
+                }
+            }
+        }
+        assert_eq!(rebuilt, data);
+        let matched = instructions.iter().filter(|i| matches!(i, DeltaInstruction::MatchedBlock { .. })).count();
+        assert!(matched >= 7, "only {} blocks matched", matched);
+
+        let empty = Signature { head: SumHead::default(), blocks: Vec::new() };
+        assert_eq!(empty.match_blocks(&data, 0, false), vec![DeltaInstruction::literal_data(data.clone())]);
+    }
+}
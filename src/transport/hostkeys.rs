@@ -0,0 +1,196 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use base64::engine::general_purpose::{STANDARD, STANDARD_NO_PAD};
+use base64::Engine;
+use ssh2::{CheckResult, HashType, KnownHostFileKind, Session};
+use crate::error::{Result, RsyncError};
+use super::ssh_config::expand_tilde;
+
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StrictHostKeyChecking {
+
+    Yes,
+
+    #[default]
+    Ask,
+
+    AcceptNew,
+
+    No,
+}
+
+impl StrictHostKeyChecking {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "yes" => Some(Self::Yes),
+            "ask" => Some(Self::Ask),
+            "accept-new" => Some(Self::AcceptNew),
+            "no" | "off" => Some(Self::No),
+            _ => None,
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostKeyCheck {
+
+    pub host: String,
+
+    pub port: u16,
+
+    pub policy: StrictHostKeyChecking,
+
+    pub known_hosts: PathBuf,
+
+    pub prompt: bool,
+}
+
+impl HostKeyCheck {
+
+    pub fn verify(&self, session: &Session) -> Result<()> {
+        let (key, _) = session.host_key()
+            .ok_or_else(|| RsyncError::HostKey(format!("{} did not present a host key", self.host)))?;
+        let key_type = key_type_name(key).unwrap_or("unknown");
+        let fingerprint = session.host_key_hash(HashType::Sha256)
+            .map(|hash| format!("SHA256:{}", STANDARD_NO_PAD.encode(hash)))
+            .unwrap_or_default();
+
+        let mut known_hosts = session.known_hosts()?;
+        if self.known_hosts.is_file() {
+            known_hosts.read_file(&self.known_hosts, KnownHostFileKind::OpenSSH).map_err(|e| RsyncError::HostKey(format!(
+                "could not read {}: {}",
+                self.known_hosts.display(),
+                e
+            )))?;
+        }
+
+        match known_hosts.check_port(&self.host, self.port, key) {
+            CheckResult::Match => Ok(()),
+            CheckResult::Mismatch => Err(RsyncError::HostKey(format!(
+                "the {} key for {} has changed and now has fingerprint {}. Someone could be eavesdropping on you, \
+                or the key was replaced. Remove the old entry from {} if the change is expected.",
+                key_type,
+                host_entry(&self.host, self.port),
+                fingerprint,
+                self.known_hosts.display()
+            ))),
+            CheckResult::Failure => Err(RsyncError::HostKey(format!(
+                "could not check the key of {} against {}",
+                self.host,
+                self.known_hosts.display()
+            ))),
+            CheckResult::NotFound => {
+                let entry = host_entry(&self.host, self.port);
+                match self.policy {
+                    StrictHostKeyChecking::Yes => return Err(RsyncError::HostKey(format!(
+                        "no {} host key is known for {} ({}) and --strict-host-key-checking=yes is in effect",
+                        key_type, entry, fingerprint
+                    ))),
+                    StrictHostKeyChecking::Ask if !self.prompt => return Err(RsyncError::HostKey(format!(
+                        "{} is not in {} and prompting is disabled ({} key fingerprint is {})",
+                        entry,
+                        self.known_hosts.display(),
+                        key_type,
+                        fingerprint
+                    ))),
+                    StrictHostKeyChecking::Ask if !confirm_new_host(&entry, key_type, &fingerprint)? => {
+                        return Err(RsyncError::HostKey(format!("{} was not trusted", entry)));
+                    }
+                    _ => {}
+                }
+                append_known_host(&self.known_hosts, &self.host, self.port, key)?;
+                eprintln!("Warning: Permanently added '{}' ({}) to the list of known hosts.", entry, key_type);
+                Ok(())
+            }
+        }
+    }
+}
+
+
+pub fn default_known_hosts() -> PathBuf {
+    expand_tilde("~/.ssh/known_hosts")
+}
+
+
+pub fn host_entry(host: &str, port: u16) -> String {
+    if port == 22 {
+        host.to_string()
+    } else {
+        format!("[{}]:{}", host, port)
+    }
+}
+
+
+pub fn append_known_host(path: &Path, host: &str, port: u16, key: &[u8]) -> Result<()> {
+    let key_type = key_type_name(key)
+        .ok_or_else(|| RsyncError::HostKey(format!("unrecognized host key from {}", host)))?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{} {} {}", host_entry(host, port), key_type, STANDARD.encode(key))?;
+    Ok(())
+}
+
+
+fn key_type_name(key: &[u8]) -> Option<&str> {
+    let len = u32::from_be_bytes(key.get(..4)?.try_into().ok()?) as usize;
+    std::str::from_utf8(key.get(4..4 + len)?).ok()
+}
+
+
+fn confirm_new_host(entry: &str, key_type: &str, fingerprint: &str) -> Result<bool> {
+    eprint!(
+        "The authenticity of host '{}' can't be established.\n{} key fingerprint is {}.\n\
+        Are you sure you want to continue connecting (yes/no)? ",
+        entry, key_type, fingerprint
+    );
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(answer.trim().eq_ignore_ascii_case("yes"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn ed25519_key(fill: u8) -> Vec<u8> {
+        let mut key = Vec::new();
+        key.extend_from_slice(&11u32.to_be_bytes());
+        key.extend_from_slice(b"ssh-ed25519");
+        key.extend_from_slice(&32u32.to_be_bytes());
+        key.extend_from_slice(&[fill; 32]);
+        key
+    }
+
+    #[test]
+    fn test_policy_names() {
+        assert_eq!(StrictHostKeyChecking::from_name("accept-new"), Some(StrictHostKeyChecking::AcceptNew));
+        assert_eq!(StrictHostKeyChecking::from_name("NO"), Some(StrictHostKeyChecking::No));
+        assert_eq!(StrictHostKeyChecking::from_name("maybe"), None);
+        assert_eq!(host_entry("example.com", 22), "example.com");
+        assert_eq!(host_entry("example.com", 2222), "[example.com]:2222");
+    }
+
+    #[test]
+    fn test_append_known_host() -> Result<()> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join(".ssh/known_hosts");
+        let key = ed25519_key(7);
+        assert_eq!(key_type_name(&key), Some("ssh-ed25519"));
+        append_known_host(&path, "example.com", 2222, &key)?;
+
+        let session = Session::new()?;
+        let mut known_hosts = session.known_hosts()?;
+        known_hosts.read_file(&path, KnownHostFileKind::OpenSSH)?;
+        assert!(matches!(known_hosts.check_port("example.com", 2222, &key), CheckResult::Match));
+        assert!(matches!(known_hosts.check_port("example.com", 2222, &ed25519_key(8)), CheckResult::Mismatch));
+        assert!(matches!(known_hosts.check_port("example.com", 22, &key), CheckResult::NotFound));
+        Ok(())
+    }
+}
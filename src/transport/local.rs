@@ -1,13 +1,30 @@
-use std::path::{Path, PathBuf};
-use std::collections::HashMap;
-use std::time::Instant;
-use crate::error::Result;
-use crate::options::{Options, ChecksumAlgorithm};
-use crate::filesystem::{Scanner, FileInfo};
+use std::path::{Component, Path, PathBuf};
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+use rayon::prelude::*;
+use serde::Serialize;
+use crate::error::{Result, RsyncError};
+use crate::options::{Options, BasisMode, ChecksumAlgorithm, PrescanMode};
+use crate::filesystem::{Scanner, FileInfo, set_file_times};
+use crate::filesystem::acl::{acl_differs, copy_acl};
+use crate::filesystem::cache::FileStateCache;
+use crate::filesystem::archive::{self, ArchiveEntry, ArchiveFormat, ArchiveWriter};
 use crate::filesystem::file_info::human_readable_size;
-use crate::algorithm::{Generator, Sender, Receiver, BandwidthLimiter, Compressor};
+use crate::filesystem::streams::copy_streams;
+use crate::filesystem::sparse::SparseWriter;
+use crate::filesystem::path_utils::{copies_contents, to_unix_separators};
+use crate::filesystem::hard_links::{is_hard_linked, is_same_file, link_leaders, link_to, DiskUsage, load_link_identities};
+use crate::algorithm::{Generator, DeltaStats, Sender, Receiver, BandwidthLimiter, Compressor};
+use crate::algorithm::receiver::{self, FnameCmpType};
 use crate::filter::FilterEngine;
-use crate::output::{ProgressDisplay, ItemizeChange, VerboseOutput};
+use crate::transport::copy_as;
+use crate::output::{json, ProgressDisplay, ItemizeChange, VerboseOutput};
+
+
+const DELETIONS_MANIFEST: &str = ".yarw-deletions";
+const APPEND_VERIFY_BLOCK: usize = 64 * 1024;
 
 
 macro_rules! log_operation {
@@ -19,7 +36,117 @@ macro_rules! log_operation {
 }
 
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SkipReason {
+
+    Filtered,
+
+    Uptodate,
+
+    Newer,
+
+    Protected,
+
+    SameFile,
+
+    NotRegular,
+
+    Directory,
+
+    Error(String),
+}
+
+impl SkipReason {
+    pub fn as_str(&self) -> &str {
+        match self {
+            SkipReason::Filtered => "filtered",
+            SkipReason::Uptodate => "uptodate",
+            SkipReason::Newer => "newer",
+            SkipReason::Protected => "protected",
+            SkipReason::SameFile => "same file",
+            SkipReason::NotRegular => "not a regular file",
+            SkipReason::Directory => "directory",
+            SkipReason::Error(message) => message,
+        }
+    }
+
+
+    pub fn info_level(&self) -> u8 {
+        match self {
+            SkipReason::Filtered | SkipReason::Uptodate => 2,
+            _ => 1,
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct SkipStats {
+
+    pub filtered: usize,
+
+    pub uptodate: usize,
+
+    pub newer: usize,
+
+    pub protected: usize,
+
+    pub same_file: usize,
+
+    pub not_regular: usize,
+
+    pub directories: usize,
+
+    pub errors: usize,
+}
+
+impl SkipStats {
+
+    pub fn add(&mut self, reason: &SkipReason) {
+        let count = match reason {
+            SkipReason::Filtered => &mut self.filtered,
+            SkipReason::Uptodate => &mut self.uptodate,
+            SkipReason::Newer => &mut self.newer,
+            SkipReason::Protected => &mut self.protected,
+            SkipReason::SameFile => &mut self.same_file,
+            SkipReason::NotRegular => &mut self.not_regular,
+            SkipReason::Directory => &mut self.directories,
+            SkipReason::Error(_) => &mut self.errors,
+        };
+        *count += 1;
+    }
+
+
+    pub fn total(&self) -> usize {
+        self.filtered + self.uptodate + self.newer + self.protected + self.same_file + self.not_regular + self.directories + self.errors
+    }
+
+
+    pub fn summary(&self) -> String {
+        let counts = [
+            ("filtered", self.filtered),
+            ("uptodate", self.uptodate),
+            ("newer", self.newer),
+            ("protected", self.protected),
+            ("same file", self.same_file),
+            ("not regular", self.not_regular),
+            ("directories", self.directories),
+            ("errors", self.errors),
+        ];
+        let parts: Vec<String> = counts.iter()
+            .filter(|(_, count)| *count > 0)
+            .map(|(name, count)| format!("{}: {}", name, count))
+            .collect();
+        if parts.is_empty() {
+            self.total().to_string()
+        } else {
+            format!("{} ({})", self.total(), parts.join(", "))
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Default, Serialize)]
 pub struct SyncStats {
 
     pub scanned_files: usize,
@@ -34,48 +161,150 @@ pub struct SyncStats {
 
     pub unchanged_files: usize,
 
+    pub hard_links: usize,
+
+    pub created_dirs: usize,
+
+    pub existing_dirs: usize,
+
+    pub logical_bytes: u64,
+
+    pub physical_bytes: u64,
+
+    pub matched_bytes: u64,
+
+    pub literal_bytes: u64,
+
+    pub compressed_bytes: u64,
+
+    pub skipped: SkipStats,
+
     pub execution_time_secs: f64,
 }
 
 impl SyncStats {
 
+    pub fn add_delta(&mut self, delta: DeltaStats) {
+        self.matched_bytes += delta.matched_bytes;
+        self.literal_bytes += delta.literal_bytes;
+        self.compressed_bytes += delta.compressed_bytes;
+    }
+
+
+    pub fn skip(&mut self, path: &Path, reason: SkipReason, verbose: &VerboseOutput) {
+        self.skipped.add(&reason);
+        json::skipped(path, reason.as_str());
+        verbose.print_skip(path, reason.as_str(), reason.info_level());
+    }
+
+
+    pub fn speedup(&self) -> Option<f64> {
+        let data_bytes = self.matched_bytes + self.literal_bytes;
+        let wire_bytes = if self.compressed_bytes > 0 { self.compressed_bytes } else { self.literal_bytes };
+        if data_bytes == 0 || wire_bytes == 0 {
+            return None;
+        }
+        Some(data_bytes as f64 / wire_bytes as f64)
+    }
+
+
     pub fn display(&self, human_readable: bool, verbose: &VerboseOutput) {
-        verbose.print_basic(&format!("\nNumber of files: {} (reg: {})",
-            self.scanned_files,
-            self.transferred_files + self.unchanged_files
-        ));
-        verbose.print_basic(&format!("Number of created files: {}", self.transferred_files));
-        verbose.print_basic(&format!("Number of deleted files: {}", self.deleted_files));
+        let dirs = self.created_dirs + self.existing_dirs;
+        if dirs > 0 {
+            verbose.print_basic(format!("\nNumber of files: {} (reg: {}, dir: {})",
+                self.scanned_files,
+                self.transferred_files + self.unchanged_files,
+                dirs
+            ));
+            verbose.print_basic(format!("Number of created files: {} (reg: {}, dir: {})",
+                self.transferred_files + self.created_dirs,
+                self.transferred_files,
+                self.created_dirs
+            ));
+        } else {
+            verbose.print_basic(format!("\nNumber of files: {} (reg: {})",
+                self.scanned_files,
+                self.transferred_files + self.unchanged_files
+            ));
+            verbose.print_basic(format!("Number of created files: {}", self.transferred_files));
+        }
+        if self.hard_links > 0 {
+            verbose.print_basic(format!("Number of hard links created: {}", self.hard_links));
+        }
+        verbose.print_basic(format!("Number of deleted files: {}", self.deleted_files));
+        if self.skipped.total() > 0 {
+            verbose.print_basic(format!("Number of skipped files: {}", self.skipped.summary()));
+        }
 
         if human_readable {
-            verbose.print_basic(&format!("Total file size: {}", human_readable_size(self.transferred_bytes)));
-            verbose.print_basic(&format!("Deleted file size: {}", human_readable_size(self.deleted_bytes)));
+            verbose.print_basic(format!("Total file size: {}", human_readable_size(self.transferred_bytes)));
+            verbose.print_basic(format!("Deleted file size: {}", human_readable_size(self.deleted_bytes)));
+            verbose.print_basic(format!("Logical source size: {}", human_readable_size(self.logical_bytes)));
+            verbose.print_basic(format!("Physical source size: {}", human_readable_size(self.physical_bytes)));
+            if self.matched_bytes + self.literal_bytes > 0 {
+                verbose.print_basic(format!("Literal data: {}", human_readable_size(self.literal_bytes)));
+                verbose.print_basic(format!("Matched data: {}", human_readable_size(self.matched_bytes)));
+            }
+            if self.compressed_bytes > 0 {
+                verbose.print_basic(format!("Compressed data: {}", human_readable_size(self.compressed_bytes)));
+            }
         } else {
-            verbose.print_basic(&format!("Total file size: {} bytes", self.transferred_bytes));
-            verbose.print_basic(&format!("Deleted file size: {} bytes", self.deleted_bytes));
+            verbose.print_basic(format!("Total file size: {} bytes", self.transferred_bytes));
+            verbose.print_basic(format!("Deleted file size: {} bytes", self.deleted_bytes));
+            verbose.print_basic(format!("Logical source size: {} bytes", self.logical_bytes));
+            verbose.print_basic(format!("Physical source size: {} bytes", self.physical_bytes));
+            if self.matched_bytes + self.literal_bytes > 0 {
+                verbose.print_basic(format!("Literal data: {} bytes", self.literal_bytes));
+                verbose.print_basic(format!("Matched data: {} bytes", self.matched_bytes));
+            }
+            if self.compressed_bytes > 0 {
+                verbose.print_basic(format!("Compressed data: {} bytes", self.compressed_bytes));
+            }
         }
 
         if self.execution_time_secs > 0.0 {
             verbose.print_transfer_rate(self.transferred_bytes, self.execution_time_secs);
             let speed = self.transferred_bytes as f64 / self.execution_time_secs;
             if human_readable {
-                verbose.print_basic(&format!("Total transfer speed: {}/s", human_readable_size(speed as u64)));
+                verbose.print_basic(format!("Total transfer speed: {}/s", human_readable_size(speed as u64)));
             } else {
-                verbose.print_basic(&format!("Total transfer speed: {:.2} bytes/s", speed));
+                verbose.print_basic(format!("Total transfer speed: {:.2} bytes/s", speed));
             }
         }
+
+        if let Some(speedup) = self.speedup() {
+            verbose.print_basic(format!("Speedup is {:.2}", speedup));
+        }
     }
 }
 
 
+struct FileJob<'a> {
+    rel_path: &'a Path,
+    source_info: &'a FileInfo,
+    source_path: PathBuf,
+    dest_path: PathBuf,
+    dest_info: Option<&'a FileInfo>,
+    basis_dir: Option<u8>,
+}
+
+
 pub struct LocalTransport {
     options: Options,
+    cache: Option<FileStateCache>,
 }
 
 impl LocalTransport {
 
     pub fn new(options: Options) -> Self {
-        Self { options }
+        let algorithm = options.checksum_choice.unwrap_or(ChecksumAlgorithm::Md5);
+        let cache = options.cache_file.as_deref().map(|path| {
+            FileStateCache::load(path, algorithm).unwrap_or_else(|e| {
+                eprintln!("Warning: starting with an empty cache: {}", e);
+                FileStateCache::empty(path, algorithm)
+            })
+        });
+        Self { options, cache }
     }
 
 
@@ -84,6 +313,7 @@ impl LocalTransport {
         let mut stats = SyncStats::default();
 
 
+        let source_names_contents = copies_contents(&source.to_string_lossy());
         let source = dunce::canonicalize(source)?;
         let destination = if destination.exists() {
             dunce::canonicalize(destination)?
@@ -98,60 +328,149 @@ impl LocalTransport {
         };
 
         let verbose = self.options.verbose_output();
-        verbose.print_basic(&format!("Syncing from {} to {}", source.display(), destination.display()));
+        verbose.print_basic(format!("Syncing from {} to {}", source.display(), destination.display()));
 
 
         log_operation!("Starting sync: {} -> {}", source.display(), destination.display());
 
 
-        if self.options.dry_run {
+        if let Some(ref export_dir) = self.options.export_changes {
+            verbose.print_basic(format!("Exporting changes to {}; the destination is left untouched", export_dir.display()));
+            log_operation!("Exporting changes to {}", export_dir.display());
+        } else if self.options.dry_run {
             verbose.print_basic("DRY RUN - no changes will be made");
             log_operation!("DRY RUN mode enabled");
         }
 
 
-        let filter_engine = self.build_filter_engine()?;
+        if source == destination || is_same_file(&source, &destination) {
+            verbose.print_warning(format!(
+                "source and destination resolve to the same path ({}); nothing to transfer",
+                source.display()
+            ));
+            stats.skip(&source, SkipReason::SameFile, &verbose);
+            stats.execution_time_secs = start_time.elapsed().as_secs_f64();
+            return Ok(stats);
+        }
 
 
-        if !destination.exists() && !self.options.dry_run {
-            std::fs::create_dir_all(&destination)?;
+        if let Some(format) = ArchiveFormat::from_path(&source).filter(|_| source.is_file()) {
+            let mut stats = self.sync_from_archive(&source, &destination, format, stats)?;
+            stats.execution_time_secs = start_time.elapsed().as_secs_f64();
+            return Ok(stats);
         }
 
 
-        let scanner = Scanner::new()
-            .recursive(self.options.recursive)
-            .follow_symlinks(self.options.copy_links);
+        let destination = match source.file_name().filter(|_| source.is_file() && destination.is_dir()) {
+            Some(name) => destination.join(name),
+            None => destination,
+        };
 
-        let mut source_files = scanner.scan(&source)?;
-        stats.scanned_files = source_files.len();
 
-        verbose.print_verbose(&format!("Found {} files in source", source_files.len()));
+        let dest_in_source = nested_subtree(&source, &destination);
+        if let Some(ref rel_path) = dest_in_source {
+            verbose.print_warning(format!(
+                "destination {} is inside the source; excluding {} from the transfer",
+                destination.display(),
+                rel_path.display()
+            ));
+        }
+        let source_in_dest = nested_subtree(&destination, &source);
+        if let Some(ref rel_path) = source_in_dest {
+            verbose.print_warning(format!(
+                "source {} is inside the destination; {} will not be compared or deleted",
+                source.display(),
+                rel_path.display()
+            ));
+        }
 
 
-        if let Some(ref files_from_path) = self.options.files_from {
-            let allowed_files = crate::filesystem::read_files_from(files_from_path)?;
+        let filter_engine = self.build_filter_engine()?;
+        let source_filter = filter_engine.for_root(&source);
+
 
-            verbose.print_verbose(&format!("Filtering {} files based on files-from list ({})",
-                source_files.len(), files_from_path.display()));
+        let archive_format = ArchiveFormat::from_path(&destination);
+        let named_dir = self.options.dirs && !self.options.recursive && self.options.files_from.is_none()
+            && archive_format.is_none() && source.is_dir() && !source_names_contents;
+        if archive_format.is_none() && !destination.exists() && !self.options.dry_run {
+            let dir = if source.is_file() { destination.parent().unwrap_or(&destination) } else { &destination };
+            std::fs::create_dir_all(dir)?;
+        }
 
 
-            source_files.retain(|file_info| {
-                let file_path = &file_info.path;
+        let scanner = Scanner::new()
+            .recursive(self.options.recursive || self.options.files_from.is_some())
+            .follow_symlinks(self.options.copy_links)
+            .streams(self.options.ads && archive_format.is_none())
+            .exclude_if_present(&self.options.exclude_if_present);
+
+        let allowed_files = match self.options.files_from {
+            Some(ref files_from_path) => {
+                verbose.print_verbose(format!("Filtering source files based on files-from list ({})",
+                    files_from_path.display()));
+                Some(crate::filesystem::read_files_from(files_from_path, self.options.from0)?)
+            }
+            None => None,
+        };
 
-                allowed_files.iter().any(|allowed| {
-                    file_path.ends_with(allowed) ||
-                    file_path == allowed ||
-                    allowed.ends_with(file_path.file_name().unwrap_or_default())
-                })
-            });
+        let mut source_map = HashMap::new();
+        let mut excluded_dirs = HashMap::new();
+        let entries = if named_dir {
+            source_map.insert(PathBuf::new(), FileInfo::from_metadata(source.clone(), &std::fs::metadata(&source)?));
+            Box::new(std::iter::empty())
+        } else {
+            scanner.scan_iter(&source)?
+        };
+        for file_info in entries {
+            let file_info = file_info?;
+            if dest_in_source.is_some() && file_info.path.starts_with(&destination) {
+                continue;
+            }
+            stats.scanned_files += 1;
+
+
+            if let Some(ref allowed_files) = allowed_files {
+                let rel_path = file_info.relative_path(&source).unwrap_or_default();
+                if !crate::filesystem::files_from_allows(allowed_files, &rel_path) {
+                    continue;
+                }
+            }
+
+            if file_info.is_directory() && !self.options.recursive && !self.options.dirs && allowed_files.is_none() {
+                if let Some(rel_path) = file_info.relative_path(&source) {
+                    stats.skip(&rel_path, SkipReason::Directory, &verbose);
+                }
+                continue;
+            }
 
-            verbose.print_verbose(&format!("After files-from filtering: {} files", source_files.len()));
+            if let Some(rel_path) = insert_file(&mut source_map, &mut excluded_dirs, file_info, &source, &source_filter) {
+                if !excluded_dirs.contains_key(&rel_path) {
+                    stats.skip(&rel_path, SkipReason::Filtered, &verbose);
+                }
+            }
+        }
+        for rel_path in prune_excluded_dirs(&mut source_map, &mut excluded_dirs, &source_filter) {
+            stats.skip(&rel_path, SkipReason::Filtered, &verbose);
         }
+        imply_parent_dirs(&mut source_map, &mut excluded_dirs);
+        let mut excluded_dirs: Vec<PathBuf> = excluded_dirs.into_keys().collect();
+        excluded_dirs.sort();
+        for rel_path in &excluded_dirs {
+            stats.skip(rel_path, SkipReason::Filtered, &verbose);
+        }
+
+        verbose.print_verbose(format!("Found {} files in source", stats.scanned_files));
 
 
-        let source_map = build_file_map(&source_files, &source, &filter_engine);
+        verbose.print_verbose(format!("Source map has {} entries", source_map.len()));
 
-        verbose.print_verbose(&format!("Source map has {} entries", source_map.len()));
+
+        if self.options.hard_links {
+            load_link_identities(source_map.values_mut());
+        }
+        let usage = DiskUsage::from_files(source_map.values());
+        stats.logical_bytes = usage.logical_bytes;
+        stats.physical_bytes = usage.physical_bytes;
 
 
         if self.options.list_only {
@@ -160,9 +479,9 @@ impl LocalTransport {
                 verbose.print_basic("File list:");
                 for (rel_path, file_info) in &source_map {
                     if file_info.is_directory() {
-                        verbose.print_basic(&format!("d         {} {}", file_info.size, rel_path.display()));
+                        verbose.print_basic(format!("d         {} {}", file_info.size, rel_path.display()));
                     } else {
-                        verbose.print_basic(&format!("f         {} {}", file_info.size, rel_path.display()));
+                        verbose.print_basic(format!("f         {} {}", file_info.size, rel_path.display()));
                     }
                 }
             }
@@ -171,23 +490,51 @@ impl LocalTransport {
         }
 
 
-        let dest_files = if destination.exists() {
-            scanner.scan(&destination).unwrap_or_default()
+        if let Some(format) = archive_format {
+            let mut stats = self.sync_to_archive(&source_map, &destination, format, stats)?;
+            stats.execution_time_secs = start_time.elapsed().as_secs_f64();
+            return Ok(stats);
+        }
+
+
+        let mut dest_map = if destination.exists() {
+            scanner.scan_iter(&destination)
+                .map(|files| build_file_map(files.filter_map(|f| f.ok()), &destination, &filter_engine.for_root(&destination)))
+                .unwrap_or_default()
         } else {
-            Vec::new()
+            HashMap::new()
         };
-        let dest_map = build_file_map(&dest_files, &destination, &filter_engine);
+        if let Some(ref rel_path) = source_in_dest {
+            dest_map.retain(|path, _| !path.starts_with(rel_path));
+        }
 
 
         let progress = if self.options.progress && !self.options.quiet {
-            let total_bytes: u64 = source_map.values()
-                .filter(|info| !info.is_directory())
-                .map(|info| info.size)
-                .sum();
-            let file_count = source_map.values()
-                .filter(|info| !info.is_directory())
-                .count();
-            Some(ProgressDisplay::new(total_bytes, file_count))
+            match self.options.prescan {
+                PrescanMode::Skip => Some(ProgressDisplay::without_totals()),
+                PrescanMode::Estimate | PrescanMode::Accurate => {
+                    let total_bytes: u64 = source_map.values()
+                        .filter(|info| !info.is_directory())
+                        .map(|info| info.size)
+                        .sum();
+                    let file_count = source_map.values()
+                        .filter(|info| !info.is_directory())
+                        .count();
+
+                    if self.options.prescan == PrescanMode::Accurate {
+                        let dir_count = source_map.len() - file_count;
+                        let total_size = if self.options.human_readable {
+                            human_readable_size(total_bytes)
+                        } else {
+                            format!("{} bytes", total_bytes)
+                        };
+                        verbose.print_basic(format!("Prescan: {} files, {} directories, {} total, {} excluded",
+                            file_count, dir_count, total_size, stats.skipped.filtered));
+                    }
+
+                    Some(ProgressDisplay::new(total_bytes, file_count))
+                }
+            }
         } else {
             None
         };
@@ -199,43 +546,151 @@ impl LocalTransport {
 
 
 
-        if self.options.delete && (self.options.delete_before || self.options.delete_during) {
-            let deleted = self.delete_extra_files(&source_map, &dest_map, &destination)?;
+        let mut deletions = Vec::new();
+        if self.options.delete && !named_dir && (self.options.delete_before || self.options.delete_during) {
+            let deleted = self.delete_extra_files(&source_map, &dest_map, &destination, &filter_engine)?;
             stats.deleted_files = deleted.len();
+            deletions.extend(deleted.iter().map(|(path, _)| path.clone()));
             for (path, size) in deleted {
                 stats.deleted_bytes += size;
+                json::deleted(&path, size);
                 if self.options.itemize_changes {
                     let change = ItemizeChange::delete_file(&path);
-                    verbose.print_basic(&change.format());
+                    verbose.print_basic(change.format());
                 } else {
-                    verbose.print_basic(&format!("deleting {}", path.display()));
+                    verbose.print_basic(format!("deleting {}", path.display()));
                 }
             }
         }
 
 
+        let dest_for = |rel_path: &Path| if self.options.relative {
+            destination.join(source.strip_prefix(source.ancestors().nth(1).unwrap_or(&source)).unwrap_or(&source)).join(rel_path)
+        } else {
+            join_relative(&destination, rel_path)
+        };
+
+        let hard_links = if self.options.hard_links {
+            let mut linked: Vec<(&PathBuf, &FileInfo)> = source_map.iter()
+                .filter(|(_, info)| is_hard_linked(info))
+                .collect();
+            linked.sort_by(|a, b| a.0.cmp(b.0));
+            link_leaders(linked.iter().map(|(_, info)| *info))
+                .into_iter()
+                .zip(&linked)
+                .filter_map(|(leader, (rel_path, _))| leader.map(|leader| (rel_path.as_path(), linked[leader].0.as_path())))
+                .collect()
+        } else {
+            Vec::new()
+        };
+        let followers: HashSet<&Path> = hard_links.iter().map(|(rel_path, _)| *rel_path).collect();
+
+        let workers = self.options.parallel.max(1);
+        let mut jobs = Vec::new();
+        let mut dir_times = Vec::new();
+        let mut dir_acls = Vec::new();
         for (rel_path, source_info) in &source_map {
-            let dest_path = if self.options.relative {
-                destination.join(source.strip_prefix(source.ancestors().nth(1).unwrap_or(&source)).unwrap_or(&source)).join(rel_path)
-            } else {
-                destination.join(rel_path)
-            };
+            let dest_path = dest_for(rel_path);
 
             if source_info.is_directory() {
 
-                if !dest_path.exists() && !self.options.dry_run {
-                    std::fs::create_dir_all(&dest_path)?;
-                    verbose.print_basic(&format!("created directory {}", rel_path.display()));
+                let existed = if self.options.relative {
+                    dest_path.is_dir()
+                } else {
+                    rel_path.as_os_str().is_empty()
+                        || dest_map.get(rel_path).is_some_and(|d| d.is_directory())
+                };
+                if existed {
+                    stats.existing_dirs += 1;
+                } else {
+                    stats.created_dirs += 1;
+                    if !self.options.dry_run {
+                        std::fs::create_dir_all(&dest_path)?;
+                    } else if let Some(ref export_dir) = self.options.export_changes {
+                        std::fs::create_dir_all(export_dir.join(rel_path))?;
+                    }
                     if self.options.itemize_changes {
                         let change = ItemizeChange::new_directory(rel_path);
-                        verbose.print_basic(&change.format());
+                        verbose.print_basic(change.format());
+                    } else {
+                        verbose.print_basic(format!("created directory {}", rel_path.display()));
+                    }
+                }
+                if self.options.acls {
+                    let source_dir = source.join(rel_path);
+                    let acl_changed = existed && acl_differs(&source_dir, &dest_path)?;
+                    if acl_changed {
+                        self.report_acl_change(rel_path, true, &verbose);
+                    }
+                    if (acl_changed || !existed) && !self.options.dry_run {
+                        dir_acls.push((source_dir, dest_path.clone()));
                     }
                 }
+                if self.options.times && !self.options.dry_run {
+                    dir_times.push((dest_path, source_info.mtime));
+                }
+                continue;
+            }
+
+            if followers.contains(rel_path.as_path()) {
+                continue;
+            }
+
+            if self.skip_protected(&filter_engine, rel_path, &dest_path, &mut stats) {
                 continue;
             }
 
-            let source_path = source.join(rel_path);
+            let source_path = join_relative(&source, rel_path);
+
+
+            if dest_map.contains_key(rel_path) && is_same_file(&source_path, &dest_path) {
+                stats.unchanged_files += 1;
+                verbose.print_warning(format!("skipping {}: source and destination are the same file", rel_path.display()));
+                stats.skip(rel_path, SkipReason::SameFile, &verbose);
+                continue;
+            }
 
+            let mut basis_dir = None;
+            if let Some((mode, _)) = self.options.basis_dirs().filter(|_| !dest_map.contains_key(rel_path)) {
+                let dest_rel = dest_path.strip_prefix(&destination).unwrap_or(rel_path);
+                let found = receiver::find_basis_dir(&dest_path, dest_rel, &self.options, |basis, basis_info| {
+                    self.basis_matches(&source_path, source_info, basis, basis_info)
+                })?;
+                match found {
+                    Some(found) if found.identical => {
+                        let applied = if self.options.dry_run {
+                            mode
+                        } else {
+                            receiver::adopt_basis(&found.path, &dest_path, mode)?
+                        };
+                        if applied == BasisMode::Copy && self.options.times && !self.options.dry_run {
+                            set_file_times(&dest_path, source_info.mtime, None)?;
+                        }
+                        match applied {
+                            BasisMode::Compare => {
+                                stats.unchanged_files += 1;
+                                verbose.print_verbose(format!("skipping {}: matches {}", rel_path.display(), found.path.display()));
+                                json::skipped(rel_path, "compare-dest");
+                            }
+                            BasisMode::Copy => {
+                                stats.transferred_files += 1;
+                                verbose.print_basic(format!("{} copied from {}", rel_path.display(), found.path.display()));
+                                log_operation!("Copied: {} <= {}", dest_path.display(), found.path.display());
+                                json::skipped(rel_path, "copied");
+                            }
+                            BasisMode::Link => {
+                                stats.hard_links += 1;
+                                verbose.print_verbose(format!("{} => {}", rel_path.display(), found.path.display()));
+                                log_operation!("Linked: {} => {}", dest_path.display(), found.path.display());
+                                json::skipped(rel_path, "linked");
+                            }
+                        }
+                        continue;
+                    }
+                    Some(found) => basis_dir = Some(found.index),
+                    None => {}
+                }
+            }
 
             if self.should_sync(&source_path, &dest_path, source_info, dest_map.get(rel_path))? {
 
@@ -247,35 +702,37 @@ impl LocalTransport {
                     let change = if dest_info.is_none() {
                         ItemizeChange::new_file(rel_path)
                     } else {
-                        ItemizeChange::update_file(rel_path, size_diff, time_diff)
+                        let mut change = ItemizeChange::update_file(rel_path, size_diff, time_diff);
+                        change.acl_diff = self.options.acls && acl_differs(&source_path, &dest_path)?;
+                        change
                     };
-                    verbose.print_basic(&change.format());
+                    verbose.print_basic(change.format());
                 } else {
-                    verbose.print_basic(&format!("transferring {}", rel_path.display()));
+                    verbose.print_basic(format!("transferring {}", rel_path.display()));
                 }
 
 
-                if let Some(ref progress) = progress {
+                if let Some(progress) = progress.as_ref().filter(|_| workers == 1 || self.options.dry_run) {
                     progress.update(transferred_bytes_so_far, &rel_path.to_string_lossy());
                 }
 
                 if !self.options.dry_run {
-                    self.sync_file(&source_path, &dest_path, dest_map.get(rel_path))?;
-                    log_operation!("Transferred: {} ({} bytes)", rel_path.display(), source_info.size);
-
-
-                    if self.options.remove_source_files {
-                        match std::fs::remove_file(&source_path) {
-                            Ok(_) => {
-                                verbose.print_verbose(&format!("removed source file {}", rel_path.display()));
-                                log_operation!("Removed source: {}", rel_path.display());
-                            }
-                            Err(e) => {
-                                verbose.print_warning(&format!("Failed to remove source file {}: {}", rel_path.display(), e));
-                                log_operation!("Failed to remove source {}: {}", rel_path.display(), e);
-                            }
-                        }
+                    let job = FileJob {
+                        rel_path,
+                        source_info,
+                        source_path,
+                        dest_path,
+                        dest_info: dest_map.get(rel_path),
+                        basis_dir,
+                    };
+                    if workers == 1 {
+                        stats.add_delta(self.transfer_file(&job, &verbose)?);
+                    } else {
+                        jobs.push(job);
                     }
+                } else if let Some(ref export_dir) = self.options.export_changes {
+                    export_file(&source_path, &join_relative(export_dir, rel_path))?;
+                    log_operation!("Exported: {} ({} bytes)", rel_path.display(), source_info.size);
                 } else {
                     log_operation!("DRY RUN - Would transfer: {}", rel_path.display());
                     if self.options.remove_source_files {
@@ -285,44 +742,145 @@ impl LocalTransport {
 
                 stats.transferred_files += 1;
                 stats.transferred_bytes += source_info.size;
+                json::transferred(rel_path, source_info.size);
                 transferred_bytes_so_far += source_info.size;
 
 
-                if let Some(ref mut limiter) = bw_limiter {
+                if let Some(limiter) = bw_limiter.as_mut().filter(|_| workers == 1) {
                     limiter.limit(source_info.size);
                 }
             } else {
                 stats.unchanged_files += 1;
-                verbose.print_verbose(&format!("skipping {}", rel_path.display()));
+                verbose.print_verbose(format!("skipping {}", rel_path.display()));
+                let reason = dest_map.get(rel_path)
+                    .map_or(SkipReason::Uptodate, |d| self.skip_reason(source_info, d));
+                stats.skip(rel_path, reason, &verbose);
+                let stale_streams = self.options.ads && dest_map.get(rel_path).is_some_and(|d| d.streams != source_info.streams);
+                if stale_streams {
+                    verbose.print_verbose(format!("updated streams of {}", rel_path.display()));
+                    log_operation!("Streams updated: {}", rel_path.display());
+                    if !self.options.dry_run {
+                        copy_streams(&source_path, &dest_path, &source_info.streams)?;
+                    }
+                }
+                let stale_time = dest_map.get(rel_path).is_some_and(|d| d.mtime != source_info.mtime);
+                if self.options.times && (stale_time || stale_streams) && !self.options.dry_run {
+                    set_file_times(&dest_path, source_info.mtime, None)?;
+                }
+                if self.options.acls && acl_differs(&source_path, &dest_path)? {
+                    self.report_acl_change(rel_path, false, &verbose);
+                    if !self.options.dry_run {
+                        copy_acl(&source_path, &dest_path)?;
+                    }
+                }
+            }
+        }
+
+
+        if !jobs.is_empty() {
+            verbose.print_verbose(format!("Transferring {} files with {} workers", jobs.len(), workers));
+            let done_bytes = AtomicU64::new(transferred_bytes_so_far - jobs.iter().map(|job| job.source_info.size).sum::<u64>());
+            let bw_limiter = Mutex::new(bw_limiter.take());
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(workers)
+                .start_handler(|_| {
+                    let _ = copy_as::impersonate_current_thread();
+                })
+                .build()
+                .map_err(|e| RsyncError::Other(format!("Failed to start transfer workers: {}", e)))?;
+            let deltas = pool.install(|| jobs.par_iter().map(|job| {
+                let delta = self.transfer_file(job, &verbose)?;
+                let size = job.source_info.size;
+                if let Some(ref progress) = progress {
+                    progress.update(done_bytes.fetch_add(size, Ordering::Relaxed) + size, &job.rel_path.to_string_lossy());
+                }
+                if let Some(limiter) = bw_limiter.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+                    limiter.limit(size);
+                }
+                Ok(delta)
+            }).collect::<Result<Vec<DeltaStats>>>())?;
+            for delta in deltas {
+                stats.add_delta(delta);
             }
         }
 
 
+        for (rel_path, leader) in hard_links {
+            let dest_path = dest_for(rel_path);
+            if is_same_file(&dest_for(leader), &dest_path) {
+                stats.unchanged_files += 1;
+                verbose.print_verbose(format!("skipping {}", rel_path.display()));
+                stats.skip(rel_path, SkipReason::Uptodate, &verbose);
+                continue;
+            }
+            verbose.print_basic(format!("{} => {}", rel_path.display(), leader.display()));
+            if !self.options.dry_run {
+                link_to(&dest_for(leader), &dest_path)?;
+                log_operation!("Hard linked: {} => {}", rel_path.display(), leader.display());
+            }
+            stats.hard_links += 1;
+        }
+
 
-        let should_delete_after = self.options.delete &&
+        let should_delete_after = self.options.delete && !named_dir &&
             (self.options.delete_after ||
              (!self.options.delete_before && !self.options.delete_during));
 
         if should_delete_after {
-            let deleted = self.delete_extra_files(&source_map, &dest_map, &destination)?;
+            let deleted = self.delete_extra_files(&source_map, &dest_map, &destination, &filter_engine)?;
             stats.deleted_files += deleted.len();
+            deletions.extend(deleted.iter().map(|(path, _)| path.clone()));
             for (path, size) in deleted {
                 stats.deleted_bytes += size;
+                json::deleted(&path, size);
                 if self.options.itemize_changes {
                     let change = ItemizeChange::delete_file(&path);
-                    verbose.print_basic(&change.format());
+                    verbose.print_basic(change.format());
                 } else {
-                    verbose.print_basic(&format!("deleting {}", path.display()));
+                    verbose.print_basic(format!("deleting {}", path.display()));
                 }
             }
         }
 
 
+        for (source_dir, dest_path) in dir_acls {
+            copy_acl(&source_dir, &dest_path)?;
+        }
+
+
+        for (dest_path, mtime) in dir_times {
+            set_file_times(&dest_path, mtime, None)?;
+        }
+
+
+        if let Some(ref export_dir) = self.options.export_changes {
+            write_deletion_manifest(export_dir, &deletions)?;
+            verbose.print_verbose(format!(
+                "Wrote {} deletion(s) to {}",
+                deletions.len(),
+                export_dir.join(DELETIONS_MANIFEST).display()
+            ));
+        }
+
+
         if let Some(progress) = progress {
             progress.finish();
         }
 
 
+        if let Some(ref cache) = self.cache {
+            let cache_stats = cache.stats();
+            verbose.print_verbose(format!(
+                "Checksum cache: {} reused, {} computed",
+                cache_stats.hits,
+                cache_stats.misses
+            ));
+            if !self.options.dry_run {
+                cache.save()?;
+            }
+        }
+
+
         stats.execution_time_secs = start_time.elapsed().as_secs_f64();
 
 
@@ -338,54 +896,347 @@ impl LocalTransport {
 
 
     fn build_filter_engine(&self) -> Result<FilterEngine> {
-        let mut engine = FilterEngine::new();
+        let verbose = self.options.verbose_output();
+        let engine = FilterEngine::from_options(&self.options)?.trace(verbose);
 
+        verbose.print_verbose(format!("Loaded {} filter pattern(s)", engine.pattern_count()));
 
-        for pattern in &self.options.exclude {
-            engine.add_exclude(pattern)?;
-        }
+        Ok(engine)
+    }
 
 
-        for pattern in &self.options.include {
-            engine.add_include(pattern)?;
-        }
+    fn sync_to_archive(
+        &self,
+        source_map: &HashMap<PathBuf, FileInfo>,
+        destination: &Path,
+        format: ArchiveFormat,
+        mut stats: SyncStats,
+    ) -> Result<SyncStats> {
+        let verbose = self.options.verbose_output();
+        let index: HashMap<String, ArchiveEntry> = if destination.exists() {
+            archive::read_index(destination, format)?
+                .into_iter()
+                .map(|entry| (entry.path.clone(), entry))
+                .collect()
+        } else {
+            HashMap::new()
+        };
+        verbose.print_verbose(format!("Archive {} has {} entries", destination.display(), index.len()));
+
+        let mut sources: Vec<(String, &FileInfo)> = source_map.iter()
+            .filter(|(rel_path, _)| !rel_path.as_os_str().is_empty())
+            .map(|(rel_path, info)| (to_unix_separators(&rel_path.to_string_lossy()), info))
+            .collect();
+        sources.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut keep = HashSet::new();
+        let mut additions = Vec::new();
+        for (name, info) in sources {
+            let rel_path = Path::new(&name);
+            let existing = index.get(&name);
+            if info.is_directory() {
+                match existing {
+                    Some(entry) if entry.is_dir => {
+                        stats.existing_dirs += 1;
+                        keep.insert(name);
+                    }
+                    _ => {
+                        stats.created_dirs += 1;
+                        if self.options.itemize_changes {
+                            verbose.print_basic(ItemizeChange::new_directory(rel_path).format());
+                        } else {
+                            verbose.print_basic(format!("created directory {}", name));
+                        }
+                        additions.push((name, info));
+                    }
+                }
+                continue;
+            }
+            if !info.is_file() {
+                verbose.print_warning(format!("skipping non-regular file {}", name));
+                stats.skip(rel_path, SkipReason::NotRegular, &verbose);
+                continue;
+            }
 
+            if let Some(entry) = existing.map(ArchiveEntry::file_info)
+                .filter(|entry| !self.archive_outdated(info, entry, format.mtime_window()))
+            {
+                stats.unchanged_files += 1;
+                verbose.print_verbose(format!("skipping {}", name));
+                stats.skip(rel_path, self.skip_reason(info, &entry), &verbose);
+                keep.insert(name);
+                continue;
+            }
 
-        for file_path in &self.options.exclude_from {
-            engine.add_exclude_from(file_path)?;
+            if self.options.itemize_changes {
+                let change = match existing {
+                    None => ItemizeChange::new_file(rel_path),
+                    Some(entry) => ItemizeChange::update_file(
+                        rel_path,
+                        entry.size != info.size,
+                        !mtime_within(entry.mtime, info.mtime, format.mtime_window()),
+                    ),
+                };
+                verbose.print_basic(change.format());
+            } else {
+                verbose.print_basic(format!("transferring {}", name));
+            }
+            stats.transferred_files += 1;
+            stats.transferred_bytes += info.size;
+            json::transferred(rel_path, info.size);
+            additions.push((name, info));
         }
 
+        let replaced: HashSet<&str> = additions.iter().map(|(name, _)| name.as_str()).collect();
+        let mut stale: Vec<&ArchiveEntry> = index.values()
+            .filter(|entry| !keep.contains(&entry.path) && !replaced.contains(entry.path.as_str()))
+            .collect();
+        stale.sort_by(|a, b| a.path.cmp(&b.path));
+        for entry in stale {
+            if !self.options.delete {
+                keep.insert(entry.path.clone());
+                continue;
+            }
+            let rel_path = Path::new(&entry.path);
+            stats.deleted_files += 1;
+            stats.deleted_bytes += entry.size;
+            json::deleted(rel_path, entry.size);
+            if self.options.itemize_changes {
+                verbose.print_basic(ItemizeChange::delete_file(rel_path).format());
+            } else {
+                verbose.print_basic(format!("deleting {}", entry.path));
+            }
+        }
 
-        for file_path in &self.options.include_from {
-            engine.add_include_from(file_path)?;
+        let unchanged = additions.is_empty() && keep.len() == index.len() && destination.exists();
+        if self.options.dry_run || unchanged {
+            return Ok(stats);
         }
 
-        let verbose = self.options.verbose_output();
-        verbose.print_verbose(&format!("Loaded {} filter pattern(s)", engine.pattern_count()));
+        let parent = destination.parent().unwrap_or(Path::new("."));
+        std::fs::create_dir_all(parent)?;
+        let temp = tempfile::NamedTempFile::new_in(parent)?;
+        let mut writer = ArchiveWriter::new(temp.reopen()?, format);
+        if !keep.is_empty() {
+            writer.copy_entries(destination, &keep)?;
+        }
+        for (name, info) in additions {
+            if info.is_directory() {
+                writer.add_directory(&name, &info.path)?;
+            } else {
+                writer.add_file(&name, &info.path)?;
+                log_operation!("Archived: {} ({} bytes)", name, info.size);
+            }
+        }
+        writer.finish()?.sync_all()?;
+        temp.persist(destination).map_err(|e| e.error)?;
+        log_operation!("Wrote archive {}", destination.display());
 
-        Ok(engine)
+        Ok(stats)
     }
 
 
-    fn should_sync(
+    fn sync_from_archive(
         &self,
-        source_path: &Path,
-        dest_path: &Path,
-        source_info: &FileInfo,
-        dest_info: Option<&FileInfo>,
-    ) -> Result<bool> {
-
-        let Some(dest_info) = dest_info else {
-            return Ok(true);
-        };
-
+        archive_path: &Path,
+        destination: &Path,
+        format: ArchiveFormat,
+        mut stats: SyncStats,
+    ) -> Result<SyncStats> {
+        let verbose = self.options.verbose_output();
+        let filter_engine = self.build_filter_engine()?;
 
-        if self.options.update {
-            if dest_info.mtime > source_info.mtime {
-                return Ok(false);
+        let mut source_map = HashMap::new();
+        for entry in archive::read_index(archive_path, format)? {
+            stats.scanned_files += 1;
+            let info = entry.file_info();
+            if !info.path.components().all(|c| matches!(c, Component::Normal(_))) {
+                verbose.print_warning(format!("skipping unsafe archive entry {}", entry.path));
+                continue;
+            }
+            if !entry.is_dir && !entry.is_file {
+                verbose.print_warning(format!("skipping non-regular archive entry {}", entry.path));
+                stats.skip(&info.path, SkipReason::NotRegular, &verbose);
+                continue;
+            }
+            if filter_engine.should_include(&info.path) {
+                source_map.insert(info.path.clone(), info);
+            }
+        }
+        verbose.print_verbose(format!("Archive {} has {} entries", archive_path.display(), source_map.len()));
+
+        if !destination.exists() && !self.options.dry_run {
+            std::fs::create_dir_all(destination)?;
+        }
+        let mut dest_map = if destination.exists() {
+            Scanner::new().recursive(true).scan_iter(destination)
+                .map(|files| build_file_map(files.filter_map(|f| f.ok()), destination, &filter_engine.for_root(destination)))
+                .unwrap_or_default()
+        } else {
+            HashMap::new()
+        };
+        dest_map.remove(Path::new(""));
+
+        let mut entries: Vec<(&PathBuf, &FileInfo)> = source_map.iter().collect();
+        entries.sort_by(|a, b| a.0.cmp(b.0));
+
+        let window = format.mtime_window();
+        let mut targets = HashMap::new();
+        let mut times = Vec::new();
+        for (rel_path, info) in entries {
+            let dest_path = destination.join(rel_path);
+            let dest_info = dest_map.get(rel_path);
+            if info.is_directory() {
+                if dest_info.is_some_and(|d| d.is_directory()) {
+                    stats.existing_dirs += 1;
+                } else {
+                    stats.created_dirs += 1;
+                    if !self.options.dry_run {
+                        std::fs::create_dir_all(&dest_path)?;
+                    }
+                    if self.options.itemize_changes {
+                        verbose.print_basic(ItemizeChange::new_directory(rel_path).format());
+                    } else {
+                        verbose.print_basic(format!("created directory {}", rel_path.display()));
+                    }
+                }
+                times.push((dest_path, info.mtime));
+                continue;
+            }
+
+            if dest_info.is_some() && self.skip_protected(&filter_engine, rel_path, &dest_path, &mut stats) {
+                continue;
             }
-        }
-
+
+            if let Some(d) = dest_info.filter(|d| !self.archive_outdated(info, d, window)) {
+                stats.unchanged_files += 1;
+                verbose.print_verbose(format!("skipping {}", rel_path.display()));
+                stats.skip(rel_path, self.skip_reason(info, d), &verbose);
+                continue;
+            }
+
+            if self.options.itemize_changes {
+                let change = match dest_info {
+                    None => ItemizeChange::new_file(rel_path),
+                    Some(d) => ItemizeChange::update_file(rel_path, d.size != info.size, !mtime_within(d.mtime, info.mtime, window)),
+                };
+                verbose.print_basic(change.format());
+            } else {
+                verbose.print_basic(format!("transferring {}", rel_path.display()));
+            }
+            if dest_info.is_some() && self.options.backup && !self.options.dry_run {
+                self.create_backup(&dest_path, rel_path)?;
+            }
+            stats.transferred_files += 1;
+            stats.transferred_bytes += info.size;
+            json::transferred(rel_path, info.size);
+            targets.insert(to_unix_separators(&rel_path.to_string_lossy()), dest_path.clone());
+            times.push((dest_path, info.mtime));
+        }
+
+        if !self.options.dry_run && !targets.is_empty() {
+            archive::extract_entries(archive_path, format, &targets)?;
+            log_operation!("Extracted {} file(s) from {}", targets.len(), archive_path.display());
+        }
+
+        if self.options.delete {
+            let deleted = self.delete_extra_files(&source_map, &dest_map, destination, &filter_engine)?;
+            stats.deleted_files += deleted.len();
+            for (path, size) in deleted {
+                stats.deleted_bytes += size;
+                json::deleted(&path, size);
+                if self.options.itemize_changes {
+                    verbose.print_basic(ItemizeChange::delete_file(&path).format());
+                } else {
+                    verbose.print_basic(format!("deleting {}", path.display()));
+                }
+            }
+        }
+
+        if self.options.times && !self.options.dry_run {
+            times.sort_by_key(|(path, _)| std::cmp::Reverse(path.components().count()));
+            for (dest_path, mtime) in times {
+                set_file_times(&dest_path, mtime, None)?;
+            }
+        }
+
+        Ok(stats)
+    }
+
+
+    fn skip_reason(&self, source_info: &FileInfo, target_info: &FileInfo) -> SkipReason {
+        if self.options.update && target_info.mtime > source_info.mtime {
+            SkipReason::Newer
+        } else {
+            SkipReason::Uptodate
+        }
+    }
+
+
+    fn archive_outdated(&self, source_info: &FileInfo, target_info: &FileInfo, window: Duration) -> bool {
+        if self.options.update && target_info.mtime > source_info.mtime + window {
+            return false;
+        }
+        if self.options.ignore_times {
+            return true;
+        }
+        if self.options.size_only {
+            return source_info.size != target_info.size;
+        }
+        source_info.size != target_info.size || !mtime_within(target_info.mtime, source_info.mtime, window)
+    }
+
+
+    fn basis_matches(&self, source_path: &Path, source_info: &FileInfo, basis: &Path, basis_info: &FileInfo) -> Result<bool> {
+        if basis_info.size != source_info.size {
+            return Ok(false);
+        }
+        if self.options.checksum {
+            return Ok(self.compute_file_checksum(source_path)? == self.compute_file_checksum(basis)?);
+        }
+        Ok(!self.options.ignore_times && (self.options.size_only || basis_info.mtime == source_info.mtime))
+    }
+
+
+    fn report_acl_change(&self, rel_path: &Path, is_dir: bool, verbose: &VerboseOutput) {
+        if self.options.itemize_changes {
+            verbose.print_basic(ItemizeChange::update_acl(rel_path, is_dir).format());
+        } else {
+            verbose.print_verbose(format!("updated ACL of {}", rel_path.display()));
+        }
+        log_operation!("ACL updated: {}", rel_path.display());
+    }
+
+
+    fn should_sync(
+        &self,
+        source_path: &Path,
+        dest_path: &Path,
+        source_info: &FileInfo,
+        dest_info: Option<&FileInfo>,
+    ) -> Result<bool> {
+
+        let Some(dest_info) = dest_info else {
+            return Ok(true);
+        };
+
+
+        if self.options.update {
+            if dest_info.mtime > source_info.mtime {
+                return Ok(false);
+            }
+        }
+
+
+        if self.options.append && dest_info.size >= source_info.size {
+            return Ok(false);
+        }
+
+
+        if self.options.ignore_times {
+            return Ok(true);
+        }
+
 
         if self.options.size_only {
             return Ok(source_info.size != dest_info.size);
@@ -403,60 +1254,181 @@ impl LocalTransport {
     }
 
 
+    fn transfer_file(&self, job: &FileJob, verbose: &VerboseOutput) -> Result<DeltaStats> {
+        let FileJob { rel_path, source_info, ref source_path, ref dest_path, .. } = *job;
+        let delta = self.sync_file(source_path, dest_path, rel_path, job.dest_info, job.basis_dir)?;
+        if self.options.ads {
+            copy_streams(source_path, dest_path, &source_info.streams)?;
+        }
+        if self.options.acls {
+            copy_acl(source_path, dest_path)?;
+        }
+        if self.options.times {
+            set_file_times(dest_path, source_info.mtime, None)?;
+        }
+        log_operation!("Transferred: {} ({} bytes)", rel_path.display(), source_info.size);
+
+
+        if self.options.remove_source_files {
+            match std::fs::remove_file(source_path) {
+                Ok(_) => {
+                    verbose.print_verbose(format!("removed source file {}", rel_path.display()));
+                    log_operation!("Removed source: {}", rel_path.display());
+                }
+                Err(e) => {
+                    verbose.print_warning(format!("Failed to remove source file {}: {}", rel_path.display(), e));
+                    log_operation!("Failed to remove source {}: {}", rel_path.display(), e);
+                }
+            }
+        }
+        Ok(delta)
+    }
+
+
+    fn append_file(&self, source: &Path, destination: &Path, relative: &Path) -> Result<Option<DeltaStats>> {
+        use std::io::{Read, Seek, SeekFrom};
+
+        let Ok(dest_len) = std::fs::metadata(destination).map(|m| m.len()) else {
+            return Ok(None);
+        };
+        let mut source_file = std::fs::File::open(source)?;
+        if source_file.metadata()?.len() < dest_len {
+            return Ok(None);
+        }
+
+        if self.options.append_verify {
+            let mut dest_file = std::fs::File::open(destination)?;
+            let mut source_block = vec![0u8; APPEND_VERIFY_BLOCK];
+            let mut dest_block = vec![0u8; APPEND_VERIFY_BLOCK];
+            let mut remaining = dest_len;
+            while remaining > 0 {
+                let len = remaining.min(APPEND_VERIFY_BLOCK as u64) as usize;
+                source_file.read_exact(&mut source_block[..len])?;
+                dest_file.read_exact(&mut dest_block[..len])?;
+                if source_block[..len] != dest_block[..len] {
+                    self.options.verbose_output().print_verbose(format!(
+                        "  {} does not match the start of the source, sending the whole file",
+                        relative.display()
+                    ));
+                    return Ok(None);
+                }
+                remaining -= len as u64;
+            }
+        }
+
+        source_file.seek(SeekFrom::Start(dest_len))?;
+        let mut dest_file = std::fs::OpenOptions::new().append(true).open(destination)?;
+        let literal_bytes = std::io::copy(&mut source_file, &mut dest_file)?;
+        self.options.verbose_output().print_verbose(format!(
+            "  appended {} bytes after {} existing bytes",
+            literal_bytes, dest_len
+        ));
+        Ok(Some(DeltaStats { literal_bytes, matched_bytes: dest_len, ..DeltaStats::default() }))
+    }
+
+
     fn sync_file(
         &self,
         source: &Path,
         destination: &Path,
+        relative: &Path,
         base_info: Option<&FileInfo>,
-    ) -> Result<()> {
+        basis_dir: Option<u8>,
+    ) -> Result<DeltaStats> {
 
         if let Some(parent) = destination.parent() {
             std::fs::create_dir_all(parent)?;
         }
 
 
-        if self.options.backup && destination.exists() {
-            self.create_backup(destination)?;
-        }
-
+        let moved_to_backup = if self.options.backup && destination.exists() {
+            self.create_backup(destination, relative)?;
+            !self.options.inplace
+        } else {
+            false
+        };
 
-        if self.options.whole_file || base_info.is_none() {
 
-            if self.options.compress {
-                self.copy_with_compression(source, destination)?;
-            } else {
-                std::fs::copy(source, destination)?;
+        if self.options.append && !moved_to_backup {
+            if let Some(delta_stats) = self.append_file(source, destination, relative)? {
+                return Ok(delta_stats);
             }
-            return Ok(());
         }
 
 
-        let block_size = Generator::calculate_block_size(
-            std::fs::metadata(source)?.len()
-        );
+        let fnamecmp_type = match self.basis_type(destination, base_info, basis_dir, moved_to_backup) {
+            Some(fnamecmp_type) if !self.options.whole_file => fnamecmp_type,
+            _ => {
+                if self.options.compress {
+                    let (literal_bytes, compressed_bytes) = self.copy_with_compression(source, destination)?;
+                    return Ok(DeltaStats { literal_bytes, compressed_bytes, ..DeltaStats::default() });
+                }
+                let literal_bytes = if self.options.sparse {
+                    copy_sparse(source, destination)?
+                } else {
+                    std::fs::copy(source, destination)?
+                };
+                return Ok(DeltaStats { literal_bytes, ..DeltaStats::default() });
+            }
+        };
+
+
+        let source_len = std::fs::metadata(source)?.len();
+        let block_size = Generator::calculate_block_size(source_len);
 
         let checksum_algorithm = self.options.checksum_choice
             .clone()
             .unwrap_or(ChecksumAlgorithm::Md5);
 
 
+        let receiver = Receiver::new(block_size, &self.options).with_file_size(source_len);
+        let basis = receiver.basis_file(fnamecmp_type, destination, relative, None, &self.options)?;
+        if fnamecmp_type == FnameCmpType::PartialDir {
+            self.options.verbose_output().print_verbose(format!("using partial file {} as basis", basis.display()));
+        }
+
+
         let generator = Generator::new(block_size, checksum_algorithm);
-        let checksums = generator.generate_checksums(destination)?;
+        let checksums = generator.generate_checksums(&basis)?;
 
 
         let mut sender = Sender::new(block_size, &self.options);
         let delta = sender.compute_delta(source, &checksums, &self.options)?;
 
 
-        let receiver = Receiver::new(block_size, &self.options);
-        receiver.reconstruct_file(Some(destination), &delta, destination, &self.options)?;
+        receiver.reconstruct_file(Some(&basis), &delta, destination, &self.options)?;
 
-        Ok(())
+        let delta_stats = sender.stats();
+        if delta_stats.compressed_bytes > 0 {
+            self.options.verbose_output().print_verbose(format!(
+                "  matched {} bytes, literal {} bytes ({} compressed)",
+                delta_stats.matched_bytes, delta_stats.literal_bytes, delta_stats.compressed_bytes
+            ));
+        } else {
+            self.options.verbose_output().print_verbose(format!(
+                "  matched {} bytes, literal {} bytes",
+                delta_stats.matched_bytes, delta_stats.literal_bytes
+            ));
+        }
+        Ok(delta_stats)
     }
 
 
+    fn basis_type(&self, destination: &Path, base_info: Option<&FileInfo>, basis_dir: Option<u8>, moved_to_backup: bool) -> Option<FnameCmpType> {
+        if self.options.partial && !self.options.inplace
+            && receiver::partial_path(destination, &self.options).is_file()
+        {
+            return Some(FnameCmpType::PartialDir);
+        }
+        if moved_to_backup {
+            return Some(FnameCmpType::Backup);
+        }
+        base_info.map(|_| FnameCmpType::Fname).or(basis_dir.map(FnameCmpType::BasisDir))
+    }
+
 
-    fn copy_with_compression(&self, source: &Path, destination: &Path) -> Result<()> {
+
+    fn copy_with_compression(&self, source: &Path, destination: &Path) -> Result<(u64, u64)> {
         use std::io::Write;
 
 
@@ -483,8 +1455,9 @@ impl LocalTransport {
             ))?;
 
 
-        let mut file = std::fs::File::create(destination)?;
-        file.write_all(&decompressed)?;
+        let mut writer = SparseWriter::new(std::fs::File::create(destination)?, self.options.sparse);
+        writer.write_all(&decompressed)?;
+        writer.finish()?;
 
 
         let verbose = self.options.verbose_output();
@@ -493,7 +1466,7 @@ impl LocalTransport {
         } else {
             100.0
         };
-        verbose.print_verbose(&format!(
+        verbose.print_verbose(format!(
             "  Compressed: {} -> {} bytes ({:.1}%)",
             original_size, compressed_size, ratio
         ));
@@ -505,7 +1478,7 @@ impl LocalTransport {
             if original_size > 0 { (compressed_size as f64 / original_size as f64) * 100.0 } else { 100.0 }
         );
 
-        Ok(())
+        Ok((original_size as u64, compressed_size as u64))
     }
 
 
@@ -514,112 +1487,233 @@ impl LocalTransport {
         source_map: &HashMap<PathBuf, FileInfo>,
         dest_map: &HashMap<PathBuf, FileInfo>,
         destination: &Path,
+        filter: &FilterEngine,
     ) -> Result<Vec<(PathBuf, u64)>> {
+        let verbose = self.options.verbose_output();
         let mut deleted = Vec::new();
 
-        for (rel_path, dest_info) in dest_map {
+        let protect_backups = self.options.backup && self.options.backup_dir.is_none();
+        let mut extra: Vec<_> = dest_map.iter()
+            .filter(|(rel_path, _)| !source_map.contains_key(*rel_path))
+            .filter(|(rel_path, _)| !(protect_backups && rel_path.to_string_lossy().ends_with(&self.options.suffix)))
+            .filter(|(rel_path, _)| !receiver::is_partial_path(rel_path, &self.options))
+            .collect();
+        extra.sort_by_key(|(rel_path, _)| std::cmp::Reverse(rel_path.components().count()));
 
-            if !source_map.contains_key(rel_path) {
-                let full_path = destination.join(rel_path);
-                let size = dest_info.size;
+        for (rel_path, dest_info) in extra {
+            let full_path = destination.join(rel_path);
+            let size = dest_info.size;
 
-                if !self.options.dry_run {
-                    if dest_info.is_directory() {
-                        std::fs::remove_dir_all(&full_path)?;
-                        log_operation!("Deleted directory: {}", rel_path.display());
-                    } else {
-                        std::fs::remove_file(&full_path)?;
-                        log_operation!("Deleted file: {} ({} bytes)", rel_path.display(), size);
+            if let Some(notice) = filter.explain_protection(rel_path) {
+                verbose.print_verbose(&notice);
+                continue;
+            }
+            if !self.options.dry_run {
+                if dest_info.is_directory() {
+                    if !receiver::remove_unprotected_dir(&full_path, rel_path, filter)? {
+                        verbose.print_warning(format!("cannot delete non-empty directory: {}", rel_path.display()));
+                        continue;
                     }
+                    log_operation!("Deleted directory: {}", rel_path.display());
                 } else {
-                    log_operation!("DRY RUN - Would delete: {}", rel_path.display());
+                    if self.options.backup {
+                        self.create_backup(&full_path, rel_path)?;
+                    }
+                    if full_path.exists() {
+                        std::fs::remove_file(&full_path)?;
+                    }
+                    log_operation!("Deleted file: {} ({} bytes)", rel_path.display(), size);
                 }
-
-                deleted.push((rel_path.clone(), size));
+            } else {
+                log_operation!("DRY RUN - Would delete: {}", rel_path.display());
             }
+
+            deleted.push((rel_path.clone(), size));
         }
 
         Ok(deleted)
     }
 
 
+    fn skip_protected(&self, filter: &FilterEngine, rel_path: &Path, dest_path: &Path, stats: &mut SyncStats) -> bool {
+        let Some(notice) = filter.explain_protection(rel_path) else {
+            return false;
+        };
+        if std::fs::symlink_metadata(dest_path).is_err() {
+            return false;
+        }
+        let verbose = self.options.verbose_output();
+        verbose.print_verbose(&notice);
+        stats.skip(rel_path, SkipReason::Protected, &verbose);
+        stats.unchanged_files += 1;
+        true
+    }
+
+
     fn compute_file_checksum(&self, path: &Path) -> Result<Vec<u8>> {
         use crate::algorithm::checksum::compute_strong_checksum;
 
-        let data = std::fs::read(path)?;
-        let algo = self.options.checksum_choice.unwrap_or(ChecksumAlgorithm::Md5);
-        let checksum = compute_strong_checksum(&data, &algo);
-
-        Ok(checksum.as_bytes().to_vec())
+        let compute = || {
+            let data = std::fs::read(path)?;
+            let algo = self.options.checksum_choice.unwrap_or(ChecksumAlgorithm::Md5);
+            Ok(compute_strong_checksum(&data, &algo).as_bytes().to_vec())
+        };
+        match self.cache {
+            Some(ref cache) => cache.checksum(path, compute),
+            None => compute(),
+        }
     }
 
 
-    fn create_backup(&self, file: &Path) -> Result<()> {
+    fn create_backup(&self, file: &Path, relative: &Path) -> Result<()> {
         let verbose = self.options.verbose_output();
+        let backup_path = receiver::backup_file(file, relative, &self.options)?;
 
-        if let Some(ref backup_dir) = self.options.backup_dir {
+        verbose.print_verbose(format!("backed up {} to {}", file.display(), backup_path.display()));
 
+        Ok(())
+    }
+}
 
-            let backup_path = backup_dir.join(file.file_name().unwrap_or_default());
 
+fn join_relative(base: &Path, rel_path: &Path) -> PathBuf {
+    if rel_path.as_os_str().is_empty() {
+        base.to_path_buf()
+    } else {
+        base.join(rel_path)
+    }
+}
 
-            if let Some(parent) = backup_path.parent() {
-                std::fs::create_dir_all(parent)?;
-            }
 
-            std::fs::copy(file, &backup_path)?;
+fn build_file_map(files: impl Iterator<Item = FileInfo>, base: &Path, filter: &FilterEngine) -> HashMap<PathBuf, FileInfo> {
+    let mut map = HashMap::new();
+    let mut excluded_dirs = HashMap::new();
 
-            verbose.print_verbose(&format!("backed up {} to {}", file.display(), backup_path.display()));
-        } else {
+    for file_info in files {
+        insert_file(&mut map, &mut excluded_dirs, file_info, base, filter);
+    }
+    prune_excluded_dirs(&mut map, &mut excluded_dirs, filter);
 
-            let backup_path = file.with_extension(
-                format!("{}{}",
-                    file.extension().and_then(|e| e.to_str()).unwrap_or(""),
-                    self.options.suffix
-                )
-            );
+    map
+}
 
 
-            let backup_path = if file.extension().is_none() {
-                PathBuf::from(format!("{}{}", file.display(), self.options.suffix))
-            } else {
-                backup_path
-            };
+fn copy_sparse(source: &Path, destination: &Path) -> Result<u64> {
+    let mut reader = std::fs::File::open(source)?;
+    let mut writer = SparseWriter::new(std::fs::File::create(destination)?, true);
+    let copied = std::io::copy(&mut reader, &mut writer)?;
+    writer.finish()?;
+    std::fs::set_permissions(destination, reader.metadata()?.permissions())?;
+    Ok(copied)
+}
 
-            std::fs::copy(file, &backup_path)?;
 
-            verbose.print_verbose(&format!("backed up {} to {}", file.display(), backup_path.display()));
-        }
+fn export_file(source: &Path, target: &Path) -> Result<()> {
+    if let Some(parent) = target.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::copy(source, target)?;
+    Ok(())
+}
 
-        Ok(())
+
+fn write_deletion_manifest(export_dir: &Path, deletions: &[PathBuf]) -> Result<()> {
+    let mut deletions: Vec<String> = deletions.iter()
+        .map(|path| to_unix_separators(&path.to_string_lossy()))
+        .collect();
+    deletions.sort();
+    let mut manifest = deletions.join("\n");
+    if !manifest.is_empty() {
+        manifest.push('\n');
     }
+    std::fs::create_dir_all(export_dir)?;
+    std::fs::write(export_dir.join(DELETIONS_MANIFEST), manifest)?;
+    Ok(())
 }
 
 
-fn build_file_map(files: &[FileInfo], base: &Path, filter: &FilterEngine) -> HashMap<PathBuf, FileInfo> {
-    let mut map = HashMap::new();
+fn mtime_within(a: SystemTime, b: SystemTime, window: Duration) -> bool {
+    a.duration_since(b).or_else(|_| b.duration_since(a)).is_ok_and(|diff| diff < window)
+}
 
-    for file_info in files {
 
-        let rel_path = match file_info.relative_path(base) {
-            Some(path) => path,
-            None => continue,
-        };
+fn nested_subtree(outer: &Path, inner: &Path) -> Option<PathBuf> {
+    inner.strip_prefix(outer)
+        .ok()
+        .filter(|rel_path| !rel_path.as_os_str().is_empty())
+        .map(Path::to_path_buf)
+}
 
 
-        if !filter.should_include(&rel_path) {
-            continue;
+fn insert_file(
+    map: &mut HashMap<PathBuf, FileInfo>,
+    excluded_dirs: &mut HashMap<PathBuf, FileInfo>,
+    file_info: FileInfo,
+    base: &Path,
+    filter: &FilterEngine,
+) -> Option<PathBuf> {
+
+    let rel_path = file_info.relative_path(base)?;
+
+
+    if !rel_path.as_os_str().is_empty() && !filter.should_include(&rel_path) {
+        if file_info.is_directory() {
+            excluded_dirs.insert(rel_path.clone(), file_info);
         }
+        return Some(rel_path);
+    }
+
+    map.insert(rel_path, file_info);
+    None
+}
+
 
-        map.insert(rel_path, file_info.clone());
+fn prune_excluded_dirs(
+    map: &mut HashMap<PathBuf, FileInfo>,
+    excluded_dirs: &mut HashMap<PathBuf, FileInfo>,
+    filter: &FilterEngine,
+) -> Vec<PathBuf> {
+    let mut rel_paths: Vec<PathBuf> = map.keys().cloned().collect();
+    rel_paths.sort();
+
+    let mut pruned = Vec::new();
+    for rel_path in rel_paths {
+        let inside_excluded = rel_path.ancestors().skip(1).any(|dir| excluded_dirs.contains_key(dir));
+        if !inside_excluded || filter.explicitly_includes(&rel_path) {
+            continue;
+        }
+        if let Some(file_info) = map.remove(&rel_path) {
+            if file_info.is_directory() {
+                excluded_dirs.insert(rel_path, file_info);
+            } else {
+                pruned.push(rel_path);
+            }
+        }
     }
+    pruned
+}
 
-    map
+
+fn imply_parent_dirs(map: &mut HashMap<PathBuf, FileInfo>, excluded_dirs: &mut HashMap<PathBuf, FileInfo>) {
+    let implied: Vec<PathBuf> = map.keys()
+        .flat_map(|rel_path| rel_path.ancestors().skip(1))
+        .filter(|dir| excluded_dirs.contains_key(*dir))
+        .map(Path::to_path_buf)
+        .collect();
+    for dir in implied {
+        if let Some(file_info) = excluded_dirs.remove(&dir) {
+            map.insert(dir, file_info);
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::filesystem::path_utils::{nested_destination, top_dir};
+    use crate::cli::Cli;
+    use crate::options::FilterRule;
+    use clap::Parser;
     use tempfile::TempDir;
     use std::fs;
 
@@ -652,6 +1746,291 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_sync_mixed_sources() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join("docs/guides"))?;
+        fs::create_dir(root.join("photos"))?;
+        fs::write(root.join("docs/readme.md"), b"readme")?;
+        fs::write(root.join("docs/guides/setup.md"), b"setup")?;
+        fs::write(root.join("photos/cat.jpg"), b"cat")?;
+        fs::write(root.join("notes.txt"), b"notes")?;
+
+        let sources = [
+            format!("{}/", root.join("docs").display()),
+            root.join("photos").display().to_string(),
+            root.join("notes.txt").display().to_string(),
+        ];
+        let destination = root.join("dest").display().to_string();
+        let transport = LocalTransport::new(create_test_options());
+        for source in &sources {
+            let dest = match top_dir(source, &destination, sources.len() > 1) {
+                Some(name) => nested_destination(&destination, &name),
+                None => destination.clone(),
+            };
+            transport.sync(Path::new(source), Path::new(&dest))?;
+        }
+
+        let dest = root.join("dest");
+        assert_eq!(fs::read(dest.join("readme.md"))?, b"readme");
+        assert_eq!(fs::read(dest.join("guides/setup.md"))?, b"setup");
+        assert_eq!(fs::read(dest.join("photos/cat.jpg"))?, b"cat");
+        assert_eq!(fs::read(dest.join("notes.txt"))?, b"notes");
+        assert!(!dest.join("docs").exists());
+
+        transport.sync(&root.join("notes.txt"), &root.join("copy.txt"))?;
+        assert_eq!(fs::read(root.join("copy.txt"))?, b"notes");
+        transport.sync(&root.join("notes.txt"), &root.join("photos"))?;
+        assert_eq!(fs::read(root.join("photos/notes.txt"))?, b"notes");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync_excludes_nested_destination() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let data = temp_dir.path().join("data");
+        fs::create_dir_all(data.join("docs"))?;
+        fs::write(data.join("docs/a.txt"), b"a")?;
+
+        let transport = LocalTransport::new(create_test_options());
+        transport.sync(&data, &data.join("backup"))?;
+        let stats = transport.sync(&data, &data.join("backup"))?;
+        assert_eq!(fs::read(data.join("backup/docs/a.txt"))?, b"a");
+        assert!(!data.join("backup/backup").exists());
+        assert_eq!(stats.scanned_files, 3);
+
+        let mut options = create_test_options();
+        options.delete = true;
+        LocalTransport::new(options).sync(&data.join("docs"), &data)?;
+        assert_eq!(fs::read(data.join("docs/a.txt"))?, b"a");
+        assert_eq!(fs::read(data.join("a.txt"))?, b"a");
+        assert!(!data.join("backup").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync_include_before_exclude() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let source = temp_dir.path().join("source");
+        let dest = temp_dir.path().join("dest");
+        fs::create_dir_all(source.join("sub"))?;
+        fs::write(source.join("a.txt"), b"a")?;
+        fs::write(source.join("b.log"), b"b")?;
+        fs::write(source.join("sub/c.txt"), b"c")?;
+        fs::write(source.join("sub/d.log"), b"d")?;
+
+        let cli = Cli::try_parse_from(["yarw", "-r", "--include=*.txt", "--exclude=*", "src/", "dst/"]).unwrap();
+        let options = cli.into_options()?;
+        assert_eq!(options.filter_rules, vec![FilterRule::Include("*.txt".to_string()), FilterRule::Exclude("*".to_string())]);
+        LocalTransport::new(options).sync(&source, &dest)?;
+
+        assert_eq!(fs::read(dest.join("a.txt"))?, b"a");
+        assert!(!dest.join("b.log").exists());
+        assert_eq!(fs::read(dest.join("sub/c.txt"))?, b"c");
+        assert!(!dest.join("sub/d.log").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync_skips_same_file() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let file = temp_dir.path().join("file.txt");
+        fs::write(&file, b"content")?;
+
+        let mut options = create_test_options();
+        options.ignore_times = true;
+        options.remove_source_files = true;
+        let stats = LocalTransport::new(options.clone()).sync(&file, &file)?;
+        assert_eq!(fs::read(&file)?, b"content");
+        assert_eq!(stats.transferred_files, 0);
+
+        let source = temp_dir.path().join("source");
+        let dest = temp_dir.path().join("dest");
+        fs::create_dir(&source)?;
+        fs::create_dir(&dest)?;
+        fs::write(source.join("linked.txt"), b"linked")?;
+        fs::hard_link(source.join("linked.txt"), dest.join("linked.txt"))?;
+        let stats = LocalTransport::new(options).sync(&source, &dest)?;
+        assert_eq!(fs::read(dest.join("linked.txt"))?, b"linked");
+        assert!(source.join("linked.txt").exists());
+        assert_eq!(stats.transferred_files, 0);
+        assert_eq!(stats.unchanged_files, 1);
+        assert_eq!(stats.skipped.same_file, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_export_changes() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let source = temp_dir.path().join("source");
+        let dest = temp_dir.path().join("dest");
+        let export = temp_dir.path().join("export");
+        fs::create_dir_all(source.join("sub/new"))?;
+        fs::create_dir_all(dest.join("sub"))?;
+        fs::write(source.join("sub/changed.txt"), b"new content")?;
+        fs::write(dest.join("sub/changed.txt"), b"old")?;
+        fs::write(dest.join("stale.txt"), b"stale")?;
+
+        let mut options = create_test_options();
+        options.delete = true;
+        options.dry_run = true;
+        options.export_changes = Some(export.clone());
+        let stats = LocalTransport::new(options).sync(&source, &dest)?;
+
+        assert_eq!(stats.transferred_files, 1);
+        assert_eq!(fs::read(export.join("sub/changed.txt"))?, b"new content");
+        assert!(export.join("sub/new").is_dir());
+        assert_eq!(fs::read_to_string(export.join(DELETIONS_MANIFEST))?, "stale.txt\n");
+        assert_eq!(fs::read(dest.join("sub/changed.txt"))?, b"old");
+        assert!(dest.join("stale.txt").exists());
+        assert!(!dest.join("sub/new").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync_to_archive() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let source = temp_dir.path().join("source");
+        fs::create_dir_all(source.join("sub"))?;
+        fs::write(source.join("sub/keep.txt"), b"keep")?;
+        fs::write(source.join("change.txt"), b"old")?;
+        fs::write(source.join("remove.txt"), b"remove")?;
+
+        for (name, format) in [("backup.tar", ArchiveFormat::Tar), ("backup.zip", ArchiveFormat::Zip)] {
+            let archive_path = temp_dir.path().join(name);
+            fs::write(source.join("change.txt"), b"old")?;
+            fs::write(source.join("remove.txt"), b"remove")?;
+            let _ = fs::remove_file(source.join("new.txt"));
+
+            let stats = LocalTransport::new(create_test_options()).sync(&source, &archive_path)?;
+            assert!(archive_path.is_file());
+            assert_eq!(stats.transferred_files, 3);
+            assert_eq!(stats.created_dirs, 1);
+
+            let stats = LocalTransport::new(create_test_options()).sync(&source, &archive_path)?;
+            assert_eq!(stats.transferred_files, 0);
+            assert_eq!(stats.unchanged_files, 3);
+
+            fs::write(source.join("change.txt"), b"changed")?;
+            fs::write(source.join("new.txt"), b"new")?;
+            fs::remove_file(source.join("remove.txt"))?;
+            let options = Options { delete: true, ..create_test_options() };
+            let stats = LocalTransport::new(options).sync(&source, &archive_path)?;
+            assert_eq!(stats.transferred_files, 2);
+            assert_eq!(stats.unchanged_files, 1);
+            assert_eq!(stats.deleted_files, 1);
+
+            let mut entries: Vec<_> = archive::read_index(&archive_path, format)?.into_iter()
+                .map(|entry| (entry.path, entry.size))
+                .collect();
+            entries.sort();
+            assert_eq!(entries, [
+                ("change.txt".to_string(), 7),
+                ("new.txt".to_string(), 3),
+                ("sub".to_string(), 0),
+                ("sub/keep.txt".to_string(), 4),
+            ]);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync_from_archive() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let source = temp_dir.path().join("source");
+        fs::create_dir_all(source.join("sub"))?;
+        fs::write(source.join("sub/a.txt"), b"alpha")?;
+        fs::write(source.join("b.log"), b"bravo")?;
+
+        for name in ["backup.tar", "backup.zip"] {
+            let archive_path = temp_dir.path().join(name);
+            let restore = temp_dir.path().join(format!("restore-{}", name));
+            LocalTransport::new(create_test_options()).sync(&source, &archive_path)?;
+
+            let options = Options { times: true, ..create_test_options() };
+            let stats = LocalTransport::new(options.clone()).sync(&archive_path, &restore)?;
+            assert_eq!(stats.transferred_files, 2);
+            assert_eq!(fs::read(restore.join("sub/a.txt"))?, b"alpha");
+            assert_eq!(fs::read(restore.join("b.log"))?, b"bravo");
+
+            fs::write(restore.join("b.log"), b"local edit")?;
+            fs::write(restore.join("extra.txt"), b"extra")?;
+            fs::write(restore.join("gone.log"), b"gone")?;
+            let options = Options { delete: true, filter_rules: vec![FilterRule::Exclude("*.txt".to_string())], ..options };
+            let stats = LocalTransport::new(options).sync(&archive_path, &restore)?;
+            assert_eq!(stats.transferred_files, 1);
+            assert_eq!(stats.deleted_files, 1);
+            assert_eq!(fs::read(restore.join("b.log"))?, b"bravo");
+            assert!(restore.join("extra.txt").exists());
+            assert!(!restore.join("gone.log").exists());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync_link_dest() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let source = temp_dir.path().join("source");
+        let first = temp_dir.path().join("snapshots/1");
+        let second = temp_dir.path().join("snapshots/2");
+        fs::create_dir_all(source.join("sub"))?;
+        fs::write(source.join("sub/same.txt"), b"unchanged")?;
+        fs::write(source.join("changed.txt"), b"before")?;
+
+        let options = Options { times: true, ..create_test_options() };
+        LocalTransport::new(options.clone()).sync(&source, &first)?;
+
+        fs::write(source.join("changed.txt"), b"after!")?;
+        let options = Options { link_dest: vec![PathBuf::from("../1")], ..options };
+        let stats = LocalTransport::new(options).sync(&source, &second)?;
+
+        assert_eq!(stats.hard_links, 1);
+        assert_eq!(stats.transferred_files, 1);
+        assert!(is_same_file(&first.join("sub/same.txt"), &second.join("sub/same.txt")));
+        assert!(!is_same_file(&first.join("changed.txt"), &second.join("changed.txt")));
+        assert_eq!(fs::read(second.join("changed.txt"))?, b"after!");
+        assert_eq!(fs::read(first.join("changed.txt"))?, b"before");
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync_compare_and_copy_dest() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let source = temp_dir.path().join("source");
+        let base = temp_dir.path().join("base");
+        fs::create_dir_all(source.join("sub"))?;
+        fs::write(source.join("sub/same.txt"), b"unchanged")?;
+        fs::write(source.join("changed.txt"), b"before, then some")?;
+
+        let options = Options { times: true, ..create_test_options() };
+        LocalTransport::new(options.clone()).sync(&source, &base)?;
+        fs::write(source.join("changed.txt"), b"after!, then some")?;
+
+        let compare = temp_dir.path().join("compare");
+        let compare_options = Options { compare_dest: vec![PathBuf::from("../base")], ..options.clone() };
+        let stats = LocalTransport::new(compare_options).sync(&source, &compare)?;
+        assert_eq!(stats.unchanged_files, 1);
+        assert_eq!(stats.transferred_files, 1);
+        assert!(!compare.join("sub/same.txt").exists());
+        assert_eq!(fs::read(compare.join("changed.txt"))?, b"after!, then some");
+
+        let copy = temp_dir.path().join("copy");
+        let copy_options = Options { copy_dest: vec![base.clone()], ..options };
+        let stats = LocalTransport::new(copy_options).sync(&source, &copy)?;
+        assert_eq!(stats.transferred_files, 2);
+        assert_eq!(stats.transferred_bytes, 17);
+        assert!(!is_same_file(&base.join("sub/same.txt"), &copy.join("sub/same.txt")));
+        assert_eq!(fs::read(copy.join("sub/same.txt"))?, b"unchanged");
+        assert_eq!(fs::read(copy.join("changed.txt"))?, b"after!, then some");
+        Ok(())
+    }
+
     #[test]
     fn test_sync_with_delete() -> Result<()> {
         let temp_dir = TempDir::new().unwrap();
@@ -678,6 +2057,38 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_sync_with_protect() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let source = temp_dir.path().join("source");
+        let dest = temp_dir.path().join("dest");
+
+
+        fs::create_dir_all(source.join("conf"))?;
+        fs::create_dir_all(dest.join("conf"))?;
+        fs::create_dir_all(dest.join("old"))?;
+        fs::write(source.join("conf/app.conf"), b"shared")?;
+        fs::write(source.join("conf/other.conf"), b"shared")?;
+        fs::write(dest.join("conf/app.conf"), b"machine specific")?;
+        fs::write(dest.join("old/host.key"), b"key")?;
+        fs::write(dest.join("old/stale.txt"), b"stale")?;
+
+        let mut options = create_test_options();
+        options.delete = true;
+        options.protect = vec!["conf/app.conf".to_string(), "*.key".to_string()];
+
+        let transport = LocalTransport::new(options);
+        transport.sync(&source, &dest)?;
+
+
+        assert_eq!(fs::read(dest.join("conf/app.conf"))?, b"machine specific");
+        assert_eq!(fs::read(dest.join("conf/other.conf"))?, b"shared");
+        assert!(dest.join("old/host.key").exists());
+        assert!(!dest.join("old/stale.txt").exists());
+
+        Ok(())
+    }
+
     #[test]
     fn test_sync_unchanged_files() -> Result<()> {
         let temp_dir = TempDir::new().unwrap();
@@ -703,6 +2114,307 @@ mod tests {
 
         assert_eq!(stats.unchanged_files, 1);
         assert_eq!(stats.transferred_files, 0);
+        assert_eq!(stats.skipped.uptodate, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync_skip_reasons() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let source = temp_dir.path().join("source");
+        let dest = temp_dir.path().join("dest");
+        fs::create_dir(&source)?;
+        fs::create_dir(&dest)?;
+        fs::write(source.join("keep.txt"), b"keep")?;
+        fs::write(source.join("debug.log"), b"log")?;
+        fs::write(source.join("edited.txt"), b"old")?;
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        fs::write(dest.join("edited.txt"), b"edited on the destination")?;
+
+        let options = Options {
+            update: true,
+            filter_rules: vec![FilterRule::Exclude("*.log".to_string())],
+            ..create_test_options()
+        };
+        let stats = LocalTransport::new(options).sync(&source, &dest)?;
+        assert_eq!(fs::read(dest.join("edited.txt"))?, b"edited on the destination");
+        assert!(!dest.join("debug.log").exists());
+        assert_eq!(stats.transferred_files, 1);
+        assert_eq!(stats.skipped.filtered, 1);
+        assert_eq!(stats.skipped.newer, 1);
+        assert_eq!(stats.skipped.uptodate, 0);
+        assert_eq!(stats.skipped.summary(), "2 (filtered: 1, newer: 1)");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync_dirs_without_recursion() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let source = temp_dir.path().join("source");
+        fs::create_dir_all(source.join("photos/2024"))?;
+        fs::write(source.join("top.txt"), b"top")?;
+        fs::write(source.join("photos/cat.jpg"), b"cat")?;
+
+        let dest = temp_dir.path().join("files_only");
+        let stats = LocalTransport::new(Options::default()).sync(&source, &dest)?;
+        assert_eq!(fs::read(dest.join("top.txt"))?, b"top");
+        assert!(!dest.join("photos").exists());
+        assert_eq!(stats.skipped.directories, 1);
+
+        let mut contents = source.clone().into_os_string();
+        contents.push("/");
+        let contents = PathBuf::from(contents);
+        let dest = temp_dir.path().join("shells");
+        let options = Options { dirs: true, ..Options::default() };
+        let stats = LocalTransport::new(options).sync(&contents, &dest)?;
+        assert_eq!(fs::read(dest.join("top.txt"))?, b"top");
+        assert!(dest.join("photos").is_dir());
+        assert!(!dest.join("photos/cat.jpg").exists());
+        assert!(!dest.join("photos/2024").exists());
+        assert_eq!(stats.created_dirs, 1);
+        assert_eq!(stats.skipped.directories, 0);
+
+        let named = temp_dir.path().join("out/source");
+        let options = Options { dirs: true, delete: true, ..Options::default() };
+        fs::create_dir_all(&named)?;
+        fs::write(named.join("keep.txt"), b"keep")?;
+        LocalTransport::new(options.clone()).sync(&source, &named)?;
+        assert!(named.is_dir());
+        assert!(!named.join("top.txt").exists());
+        assert!(!named.join("photos").exists());
+        assert!(named.join("keep.txt").exists());
+
+        LocalTransport::new(options).sync(&contents, &named)?;
+        assert_eq!(fs::read(named.join("top.txt"))?, b"top");
+        assert!(named.join("photos").is_dir());
+        assert!(!named.join("keep.txt").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync_implies_parent_dirs_of_included_files() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let source = temp_dir.path().join("source");
+        fs::create_dir_all(source.join("docs/notes"))?;
+        fs::create_dir_all(source.join("empty"))?;
+        fs::create_dir_all(source.join(".git/objects"))?;
+        fs::write(source.join("docs/notes/todo.txt"), b"todo")?;
+        fs::write(source.join("docs/image.png"), b"png")?;
+        fs::write(source.join(".git/config"), b"config")?;
+        fs::write(source.join(".git/objects/pack"), b"pack")?;
+        fs::write(source.join("top.txt"), b"top")?;
+
+        let dest = temp_dir.path().join("only_txt");
+        let options = Options {
+            filter_rules: vec![FilterRule::Rule("+ *.txt".to_string()), FilterRule::Rule("- *".to_string())],
+            ..create_test_options()
+        };
+        let stats = LocalTransport::new(options).sync(&source, &dest)?;
+        assert_eq!(fs::read(dest.join("docs/notes/todo.txt"))?, b"todo");
+        assert_eq!(fs::read(dest.join("top.txt"))?, b"top");
+        assert!(!dest.join("docs/image.png").exists());
+        assert!(!dest.join("empty").exists());
+        assert!(!dest.join(".git").exists());
+        assert_eq!(stats.created_dirs, 2);
+
+        let dest = temp_dir.path().join("no_git");
+        let options = Options { filter_rules: vec![FilterRule::Exclude(".git".to_string())], ..create_test_options() };
+        let stats = LocalTransport::new(options).sync(&source, &dest)?;
+        assert!(dest.join("empty").is_dir());
+        assert!(dest.join("docs/image.png").exists());
+        assert!(!dest.join(".git").exists());
+        assert_eq!(stats.skipped.filtered, 4);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync_checksum_uses_cache_file() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let source = temp_dir.path().join("source");
+        let dest = temp_dir.path().join("dest");
+        let cache_file = temp_dir.path().join("checksums.cache");
+        fs::create_dir_all(&source)?;
+        fs::create_dir_all(&dest)?;
+        let mtime = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_500_000_000);
+        for name in ["a.txt", "b.txt"] {
+            fs::write(source.join(name), name)?;
+            fs::write(dest.join(name), name)?;
+            set_file_times(&source.join(name), mtime, None)?;
+            set_file_times(&dest.join(name), mtime, None)?;
+        }
+
+        let options = Options { checksum: true, cache_file: Some(cache_file.clone()), ..create_test_options() };
+        let transport = LocalTransport::new(options.clone());
+        transport.sync(&source, &dest)?;
+        assert_eq!(transport.cache.as_ref().unwrap().stats().misses, 4);
+        assert!(cache_file.exists());
+
+        fs::write(source.join("b.txt"), "B.txt")?;
+        set_file_times(&source.join("b.txt"), mtime + std::time::Duration::from_secs(60), None)?;
+        let transport = LocalTransport::new(options);
+        let stats = transport.sync(&source, &dest)?;
+        let cache_stats = transport.cache.as_ref().unwrap().stats();
+        assert_eq!((cache_stats.hits, cache_stats.misses), (3, 1));
+        assert_eq!(stats.transferred_files, 1);
+        assert_eq!(fs::read(dest.join("b.txt"))?, b"B.txt");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync_parallel_workers() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let source = temp_dir.path().join("source");
+        for dir in 0..4 {
+            fs::create_dir_all(source.join(format!("dir{}", dir)))?;
+            for file in 0..25 {
+                fs::write(source.join(format!("dir{}/file{}.bin", dir, file)), vec![dir as u8; file * 100])?;
+            }
+        }
+
+        let sequential = temp_dir.path().join("sequential");
+        let expected = LocalTransport::new(create_test_options()).sync(&source, &sequential)?;
+
+        let parallel = temp_dir.path().join("parallel");
+        let options = Options { parallel: 4, times: true, ..create_test_options() };
+        let stats = LocalTransport::new(options).sync(&source, &parallel)?;
+        assert_eq!(stats.transferred_files, 100);
+        assert_eq!(stats.transferred_files, expected.transferred_files);
+        assert_eq!(stats.transferred_bytes, expected.transferred_bytes);
+        assert_eq!(stats.created_dirs, expected.created_dirs);
+        for dir in 0..4 {
+            for file in 0..25 {
+                let rel_path = format!("dir{}/file{}.bin", dir, file);
+                assert_eq!(fs::read(parallel.join(&rel_path))?, fs::read(source.join(&rel_path))?);
+                assert_eq!(
+                    fs::metadata(parallel.join(&rel_path))?.modified()?,
+                    fs::metadata(source.join(&rel_path))?.modified()?
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync_append_modes() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let source = temp_dir.path().join("source");
+        let dest = temp_dir.path().join("dest");
+        fs::create_dir_all(&source)?;
+        fs::create_dir_all(&dest)?;
+        fs::write(source.join("app.log"), b"line 1\nline 2\nline 3\n")?;
+        fs::write(dest.join("app.log"), b"line 1\n")?;
+        fs::write(source.join("rotated.log"), b"new 1\nnew 2\n")?;
+        fs::write(dest.join("rotated.log"), b"old 1\n")?;
+        fs::write(source.join("short.log"), b"abc")?;
+        fs::write(dest.join("short.log"), b"abcdef")?;
+
+        let options = Options { append: true, ..create_test_options() };
+        let stats = LocalTransport::new(options).sync(&source, &dest)?;
+        assert_eq!(fs::read(dest.join("app.log"))?, b"line 1\nline 2\nline 3\n");
+        assert_eq!(fs::read(dest.join("rotated.log"))?, b"old 1\nnew 2\n");
+        assert_eq!(fs::read(dest.join("short.log"))?, b"abcdef");
+        assert_eq!(stats.transferred_files, 2);
+        assert_eq!(stats.literal_bytes, 14 + 6);
+
+        fs::write(dest.join("rotated.log"), b"old 1\n")?;
+        let options = Options { append: true, append_verify: true, ..create_test_options() };
+        LocalTransport::new(options).sync(&source, &dest)?;
+        assert_eq!(fs::read(dest.join("rotated.log"))?, b"new 1\nnew 2\n");
+
+        let mut data = vec![b'x'; APPEND_VERIFY_BLOCK + 100];
+        fs::write(source.join("big.log"), &data)?;
+        data[APPEND_VERIFY_BLOCK + 10] = b'y';
+        fs::write(dest.join("big.log"), &data[..APPEND_VERIFY_BLOCK + 50])?;
+        let options = Options { append: true, append_verify: true, ..create_test_options() };
+        LocalTransport::new(options).sync(&source, &dest)?;
+        assert_eq!(fs::read(dest.join("big.log"))?, fs::read(source.join("big.log"))?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync_files_from_exact_paths() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let source = temp_dir.path().join("source");
+        fs::create_dir_all(source.join("a"))?;
+        fs::create_dir_all(source.join("b"))?;
+        fs::create_dir_all(source.join("docs/img"))?;
+        fs::write(source.join("a/one.txt"), b"one")?;
+        fs::write(source.join("b/one.txt"), b"other")?;
+        fs::write(source.join("two.txt"), b"two")?;
+        fs::write(source.join("docs/img/logo.png"), b"logo")?;
+
+        let list = temp_dir.path().join("list");
+        fs::write(&list, b"./a/one.txt\0docs\0")?;
+
+        let dest = temp_dir.path().join("dest");
+        let options = Options { files_from: Some(list), from0: true, ..Options::default() };
+        LocalTransport::new(options).sync(&source, &dest)?;
+        assert_eq!(fs::read(dest.join("a/one.txt"))?, b"one");
+        assert_eq!(fs::read(dest.join("docs/img/logo.png"))?, b"logo");
+        assert!(!dest.join("b").exists());
+        assert!(!dest.join("two.txt").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync_preserves_times() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let source = temp_dir.path().join("source");
+        let dest = temp_dir.path().join("dest");
+
+        fs::create_dir_all(source.join("sub"))?;
+        fs::write(source.join("sub/file.txt"), b"content")?;
+        let mtime = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_500_000_000);
+        set_file_times(&source.join("sub/file.txt"), mtime, None)?;
+        set_file_times(&source.join("sub"), mtime, None)?;
+
+        let mut options = create_test_options();
+        options.times = true;
+
+        let transport = LocalTransport::new(options);
+        let stats = transport.sync(&source, &dest)?;
+        assert_eq!(stats.transferred_files, 1);
+        assert_eq!(fs::metadata(dest.join("sub/file.txt"))?.modified()?, mtime);
+        assert_eq!(fs::metadata(dest.join("sub"))?.modified()?, mtime);
+
+        let stats = transport.sync(&source, &dest)?;
+        assert_eq!(stats.transferred_files, 0);
+        assert_eq!(stats.unchanged_files, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync_hard_links() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let source = temp_dir.path().join("source");
+        let dest = temp_dir.path().join("dest");
+
+        fs::create_dir_all(&source)?;
+        fs::write(source.join("a.txt"), b"shared")?;
+        fs::hard_link(source.join("a.txt"), source.join("b.txt"))?;
+
+        let mut options = create_test_options();
+        options.hard_links = true;
+        options.times = true;
+
+        let transport = LocalTransport::new(options);
+        let stats = transport.sync(&source, &dest)?;
+        assert_eq!(stats.transferred_files, 1);
+        assert_eq!(stats.hard_links, 1);
+        assert!(is_same_file(&dest.join("a.txt"), &dest.join("b.txt")));
+
+        let stats = transport.sync(&source, &dest)?;
+        assert_eq!(stats.hard_links, 0);
+        assert_eq!(stats.unchanged_files, 2);
 
         Ok(())
     }
@@ -727,4 +2439,87 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_sync_compressed_stats() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let source = temp_dir.path().join("source");
+        let dest = temp_dir.path().join("dest");
+
+        fs::create_dir(&source)?;
+        let content = "compressible line of text\n".repeat(500);
+        fs::write(source.join("file.txt"), &content)?;
+
+        let mut options = create_test_options();
+        options.compress = true;
+
+        let transport = LocalTransport::new(options);
+        let stats = transport.sync(&source, &dest)?;
+
+        assert_eq!(fs::read_to_string(dest.join("file.txt"))?, content);
+        assert_eq!(stats.literal_bytes, content.len() as u64);
+        assert_eq!(stats.matched_bytes, 0);
+        assert!(stats.compressed_bytes > 0 && stats.compressed_bytes < stats.literal_bytes);
+        assert!(stats.speedup().unwrap() > 1.0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync_without_prescan() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let source = temp_dir.path().join("source");
+        let dest = temp_dir.path().join("dest");
+
+        fs::create_dir_all(source.join("nested"))?;
+        fs::write(source.join("a.txt"), b"alpha")?;
+        fs::write(source.join("nested/b.txt"), b"bravo")?;
+
+        for prescan in [PrescanMode::Skip, PrescanMode::Accurate] {
+            let mut options = create_test_options();
+            options.progress = true;
+            options.prescan = prescan;
+            let dest = dest.join(format!("{:?}", prescan));
+
+            let stats = LocalTransport::new(options).sync(&source, &dest)?;
+            assert_eq!(stats.transferred_files, 2);
+            assert_eq!(stats.transferred_bytes, 10);
+            assert_eq!(fs::read(dest.join("nested/b.txt"))?, b"bravo");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync_sparse_files() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let source = temp_dir.path().join("source");
+        let dest = temp_dir.path().join("dest");
+
+        fs::create_dir(&source)?;
+        let mut image = vec![0u8; 256 * 1024];
+        image[100_000..100_004].copy_from_slice(b"data");
+        fs::write(source.join("disk.img"), &image)?;
+
+        let mut options = create_test_options();
+        options.sparse = true;
+
+        let transport = LocalTransport::new(options);
+        transport.sync(&source, &dest)?;
+        assert_eq!(fs::read(dest.join("disk.img"))?, image);
+
+        image[200_000..200_004].copy_from_slice(b"more");
+        fs::write(source.join("disk.img"), &image)?;
+        set_file_times(&source.join("disk.img"), SystemTime::now() + Duration::from_secs(10), None)?;
+        transport.sync(&source, &dest)?;
+        assert_eq!(fs::read(dest.join("disk.img"))?, image);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            let metadata = fs::metadata(dest.join("disk.img"))?;
+            assert!(metadata.blocks() * 512 < metadata.len());
+        }
+
+        Ok(())
+    }
 }
@@ -1,14 +1,29 @@
+mod copy_as;
+mod credentials;
 mod daemon;
 mod daemon_config;
 mod daemon_client;
+mod daemon_hosts;
+mod daemon_metrics;
+mod daemon_protocol;
+mod daemon_log;
+mod hostkeys;
 mod local;
 mod remote;
+mod server;
+mod sockopts;
+mod stdio;
 mod ssh;
 mod ssh_command;
+mod ssh_config;
 
+pub use copy_as::start_copy_as;
 pub use daemon::RsyncDaemon;
 pub use daemon_config::DaemonConfig;
 pub use daemon_client::DaemonClient;
-pub use local::{LocalTransport, SyncStats};
+pub use hostkeys::StrictHostKeyChecking;
+pub use local::{LocalTransport, SkipReason, SyncStats};
 pub use remote::RemoteTransport;
-pub use ssh::{AuthMethod, SshTransport, prompt_for_password};
+pub use server::RsyncServer;
+pub use sockopts::{SocketOptions, DEFAULT_NET_BUFFER_SIZE};
+pub use ssh::{AuthMethod, SshAlgorithms, SshKeepalive, SshTransport, prompt_for_password};
@@ -1,13 +1,28 @@
-use crate::options::Options;
+use crate::options::{ChecksumAlgorithm, CompressionAlgorithm, Options};
 use crate::error::{Result, RsyncError};
-use super::{SshTransport, AuthMethod, SyncStats, prompt_for_password};
+use super::{SshAlgorithms, SshKeepalive, SshTransport, AuthMethod, SyncStats, prompt_for_password};
+use super::copy_as;
+use super::credentials;
+use super::hostkeys::{default_known_hosts, HostKeyCheck};
+use super::ssh::{certificate_for, DEFAULT_PASSWORD_PROMPTS};
 use super::ssh_command::parse_ssh_command;
-use crate::filesystem::{path_utils::{is_remote_path, parse_remote_path, to_unix_separators}, Scanner};
-use crate::protocol::{ProtocolStream, FileList, PROTOCOL_VERSION_MAX};
+use super::ssh_config::SshConfig;
+use super::server::{
+    delete_phase, local_file_list, receive_deletions, receive_files, receive_incremental, send_deletions, send_files,
+    send_filter_rules, send_files_from, send_incremental, DeletePhase,
+};
+use crate::filter::FilterEngine;
+use crate::filesystem::files_from::{files_from_allows, parse_files_from, read_files_from, remote_files_from};
+use crate::filesystem::path_utils::{is_remote_path, parse_remote_path, to_unix_separators};
+use crate::output::{ProgressDisplay, VerboseOutput};
+use crate::protocol::{
+    negotiate_checksum, negotiate_compression, ProtocolStream, FileList, CF_CHKSUM_SEED_FIX, CF_INC_RECURSE, CHECKSUM_NEGOTIATION,
+    COMPRESSION_NEGOTIATION, PROTOCOL_VERSION_MAX,
+};
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
-use std::io::Read;
 use std::fs;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 pub struct RemoteTransport {
     options: Options,
@@ -19,7 +34,22 @@ impl RemoteTransport {
         Self { options }
     }
 
+    pub async fn run(self, source: String, destination: String) -> Result<SyncStats> {
+        tokio::task::spawn_blocking(move || {
+            copy_as::impersonate_current_thread()?;
+            self.sync(&source, &destination)
+        })
+            .await
+            .map_err(|e| RsyncError::Other(format!("Remote transfer thread failed: {}", e)))?
+    }
+
+
     pub fn sync(&self, source: &str, destination: &str) -> Result<SyncStats> {
+        if self.options.compress && !remote_is_yarw(&self.options) {
+            return Err(RsyncError::InvalidOption(
+                "-z over SSH needs yarw on the remote side; set --rsync-path to the yarw executable or run the transfer without -z".to_string()
+            ));
+        }
         let start_time = Instant::now();
         let mut stats = SyncStats::default();
         let is_remote_source = is_remote_path(source);
@@ -37,218 +67,909 @@ impl RemoteTransport {
         let local_path = Path::new(local_path_str);
 
         if let Some((user, host)) = user_host {
-            let username = if user.is_empty() {
-                whoami::username()
+            let rsh_params = self.options.rsh.as_deref().map(parse_ssh_command).unwrap_or_default();
+            let ssh_config = match rsh_params.config_file {
+                Some(ref path) if path.as_os_str().eq_ignore_ascii_case("none") => SshConfig::default(),
+                Some(ref path) => SshConfig::load(path)?,
+                None => SshConfig::load_default()?,
+            };
+            let target = rsh_params.host_config().or(ssh_config.resolve(&host));
+            let algorithms = self.options.ssh_algorithms.clone().or(rsh_params.algorithms.clone());
+            let keepalive = rsh_params.keepalive;
+            let password_prompts = if self.options.no_prompt || rsh_params.batch_mode {
+                0
             } else {
-                user
+                self.options.password_prompts.or(rsh_params.password_prompts).unwrap_or(DEFAULT_PASSWORD_PROMPTS)
             };
 
-            let port = if let Some(ref rsh_command) = self.options.rsh {
-                let params = parse_ssh_command(rsh_command);
-                params.port.unwrap_or(22)
-            } else {
-                22
+            let host_key_policy = self.options.strict_host_key_checking
+                .or(rsh_params.strict_host_key_checking)
+                .unwrap_or_default();
+            let known_hosts = rsh_params.known_hosts_file.clone().unwrap_or_else(default_known_hosts);
+            let host_key = |host: &str, port: u16| HostKeyCheck {
+                host: host.to_string(),
+                port,
+                policy: host_key_policy,
+                known_hosts: known_hosts.clone(),
+                prompt: !self.options.no_prompt && !rsh_params.batch_mode,
             };
 
             let verbose = self.options.verbose_output();
-            verbose.print_verbose(&format!("Connecting to {}@{}:{} ...", username, host, port));
 
-            let mut transport_result: Option<SshTransport> = None;
-            let mut last_error: Option<String> = None;
+            let mut jump: Option<SshTransport> = None;
+            for hop in target.jump_hosts() {
+                let hop_config = ssh_config.resolve(&hop.host);
+                let (identity_file, certificate_file) = match hop_config.identity_file {
+                    Some(identity_file) => (Some(identity_file), hop_config.certificate_file),
+                    None => (target.identity_file.clone(), target.certificate_file.clone()),
+                };
+                let host = hop_config.host_name.unwrap_or(hop.host);
+                let port = hop.port.or(hop_config.port).unwrap_or(22);
+                let endpoint = SshEndpoint {
+                    user: hop.user.or(hop_config.user).unwrap_or_else(whoami::username),
+                    host_key: host_key(&host, port),
+                    port,
+                    identity_file,
+                    certificate_file,
+                    password_prompts,
+                    host,
+                };
+                verbose.print_verbose(format!("Connecting to jump host {}@{}:{} ...", endpoint.user, endpoint.host, endpoint.port));
+                jump = Some(self.authenticate(&endpoint, jump.as_ref(), &algorithms, keepalive, &verbose)?);
+            }
 
-            if let Some(ref rsh_command) = self.options.rsh {
-                let params = parse_ssh_command(rsh_command);
-                if let Some(identity_file) = params.identity_file {
-                    verbose.print_verbose(&format!("Trying public key authentication: {}", identity_file.display()));
-                    match SshTransport::connect(&host, port, &username, AuthMethod::PublicKey(identity_file.clone())) {
-                        Ok(transport) => {
-                            verbose.print_verbose("Public key authentication successful.");
-                            transport_result = Some(transport);
-                        }
-                        Err(e) => {
-                            verbose.print_verbose(&format!("Public key authentication failed: {}", e));
-                            last_error = Some(e.to_string());
-                        }
-                    }
+            let host_alias = host.clone();
+            let host = target.host_name.clone().unwrap_or(host);
+            let port = target.port.unwrap_or(22);
+            let endpoint = SshEndpoint {
+                user: if user.is_empty() { target.user.clone().unwrap_or_else(whoami::username) } else { user },
+                host_key: host_key(&host, port),
+                port,
+                identity_file: target.identity_file.clone(),
+                certificate_file: target.certificate_file.clone(),
+                password_prompts,
+                host,
+            };
+            verbose.print_verbose(format!("Connecting to {}@{}:{} ...", endpoint.user, endpoint.host, endpoint.port));
+            if self.options.blocking_io == Some(false) {
+                verbose.print_verbose("--no-blocking-io has no effect: the built-in SSH client always uses blocking I/O");
+            }
+
+            let mut transport = self.authenticate(&endpoint, jump.as_ref(), &algorithms, keepalive, &verbose)?;
+            verbose.print_verbose("SSH connection successful.");
+            let files_from = self.load_files_from(&transport, &host_alias, &verbose)?;
+
+
+            let remote_unix_path = to_unix_separators(&remote_raw_path);
+
+
+            let mut rsync_args = vec!["--server"];
+            if is_remote_source { rsync_args.push("--sender"); }
+
+
+            let flags = server_flags(&self.options, !is_remote_source, PROTOCOL_VERSION_MAX);
+            rsync_args.push(&flags);
+            if self.options.delete {
+                rsync_args.push("--delete");
+                if self.options.delete_before {
+                    rsync_args.push("--delete-before");
+                } else if self.options.delete_during {
+                    rsync_args.push("--delete-during");
+                } else if self.options.delete_after {
+                    rsync_args.push("--delete-after");
                 }
             }
+            if self.options.size_only { rsync_args.push("--size-only"); }
+            if is_remote_source && files_from.is_some() { rsync_args.push("--files-from=-"); }
+            let compress_choice_arg = self.options.compress_choice
+                .filter(|choice| self.options.compress && *choice != CompressionAlgorithm::Zlib)
+                .map(|choice| format!("--compress-choice={}", choice.name()));
+            if let Some(ref compress_choice_arg) = compress_choice_arg { rsync_args.push(compress_choice_arg); }
+            let checksum_choice_arg = self.options.checksum_choice
+                .filter(|choice| *choice != ChecksumAlgorithm::Md5)
+                .and_then(|choice| negotiate_checksum(choice.name(), CHECKSUM_NEGOTIATION))
+                .map(|choice| format!("--checksum-choice={}", choice.name()));
+            if let Some(ref checksum_choice_arg) = checksum_choice_arg { rsync_args.push(checksum_choice_arg); }
+            if self.options.inplace { rsync_args.push("--inplace"); }
+            if self.options.preallocate && !is_remote_source { rsync_args.push("--preallocate"); }
+            if self.options.append_verify {
+                rsync_args.push("--append-verify");
+            } else if self.options.append {
+                rsync_args.push("--append");
+            }
+            if self.options.partial { rsync_args.push("--partial"); }
+            let partial_dir_arg = self.options.partial_dir.as_ref()
+                .map(|dir| format!("--partial-dir={}", to_unix_separators(&dir.to_string_lossy())));
+            if let Some(ref partial_dir_arg) = partial_dir_arg { rsync_args.push(partial_dir_arg); }
+            let backup_dir_arg = self.options.backup_dir.as_ref()
+                .map(|dir| format!("--backup-dir={}", to_unix_separators(&dir.to_string_lossy())));
+            if let Some(ref backup_dir_arg) = backup_dir_arg { rsync_args.push(backup_dir_arg); }
+            let basis_dir_args: Vec<String> = [
+                ("--compare-dest", &self.options.compare_dest),
+                ("--copy-dest", &self.options.copy_dest),
+                ("--link-dest", &self.options.link_dest),
+            ]
+            .into_iter()
+            .filter(|_| !is_remote_source)
+            .flat_map(|(flag, dirs)| dirs.iter().map(move |dir| format!("{}={}", flag, to_unix_separators(&dir.to_string_lossy()))))
+            .collect();
+            rsync_args.extend(basis_dir_args.iter().map(String::as_str));
+            let marker_args: Vec<String> = self.options.exclude_if_present.iter()
+                .filter(|_| is_remote_source)
+                .map(|marker| format!("--exclude-if-present={}", marker))
+                .collect();
+            rsync_args.extend(marker_args.iter().map(String::as_str));
+            let suffix_arg = format!("--suffix={}", self.options.suffix);
+            if self.options.backup && self.options.suffix != "~" { rsync_args.push(&suffix_arg); }
+            let seed_arg = self.options.checksum_seed.map(|seed| format!("--checksum-seed={}", seed));
+            if let Some(ref seed_arg) = seed_arg { rsync_args.push(seed_arg); }
+            let remote_option_args: Vec<String> = self.options.remote_options.iter()
+                .map(|option| quote_remote_arg(option))
+                .collect();
+            rsync_args.extend(remote_option_args.iter().map(String::as_str));
+
+            rsync_args.push(".");
+            rsync_args.push(&remote_unix_path);
+
+            let rsync_program = self.options.rsync_path.as_deref().unwrap_or("rsync");
+            let rsync_command_str = format!("{} {}", rsync_program, rsync_args.join(" "));
+            verbose.print_debug(format!("Executing remote command: {}", rsync_command_str));
+
+            let io_timeout = self.options.timeout.map(Duration::from_secs);
+            match transport.execute(&rsync_command_str, io_timeout) {
+                Ok(mut channel) => {
+                    channel.set_bwlimit(self.options.bwlimit.map(|kbps| kbps * 1024));
+                    self.exchange(&mut channel, local_path, is_remote_source, files_from.as_deref(), &verbose, &mut stats)?;
+
+                    let flow = channel.flow_stats();
+                    if flow.window_stalls > 0 || !flow.throttle_wait.is_zero() {
+                        verbose.print_verbose(format!(
+                            "SSH flow control: waited {:.2}s for the remote window ({} stalls), {:.2}s throttled by --bwlimit",
+                            flow.window_wait.as_secs_f64(),
+                            flow.window_stalls,
+                            flow.throttle_wait.as_secs_f64()
+                        ));
+                    }
 
-            if transport_result.is_none() {
-                verbose.print_verbose("Trying SSH agent authentication...");
-                match SshTransport::connect(&host, port, &username, AuthMethod::Agent) {
-                    Ok(transport) => {
-                        verbose.print_verbose("SSH agent authentication successful.");
-                        transport_result = Some(transport);
+                    stats.execution_time_secs = start_time.elapsed().as_secs_f64();
+
+                    verbose.print_basic("Transfer complete!");
+                    if self.options.stats {
+                        stats.display(self.options.human_readable, &verbose);
                     }
-                    Err(e) => {
-                        verbose.print_verbose(&format!("SSH agent authentication failed: {}", e));
-                        last_error = Some(e.to_string());
+
+
+                    match channel.read_stderr() {
+                        Ok(stderr_bytes) => {
+                            if !stderr_bytes.is_empty() {
+                                verbose.print_error(format!("Remote stderr: {}", String::from_utf8_lossy(&stderr_bytes)));
+                            }
+                        },
+                        Err(e) => verbose.print_error(format!("Failed to read remote stderr: {}", e)),
                     }
+
+
+                    channel.finish()?;
+
                 }
+                Err(e) => return Err(RsyncError::RemoteExec(format!("Failed to execute remote command: {}", e))),
             }
+        } else {
+            return Err(RsyncError::InvalidPath(PathBuf::from(source)));
+        }
 
-            if transport_result.is_none() {
-                verbose.print_verbose("Trying password authentication...");
-                match prompt_for_password(&username, &host) {
-                    Ok(password) => {
-                        match SshTransport::connect(&host, port, &username, AuthMethod::Password(password)) {
-                            Ok(transport) => {
-                                verbose.print_verbose("Password authentication successful.");
-                                transport_result = Some(transport);
-                            }
-                            Err(e) => {
-                                verbose.print_error(&format!("Password authentication failed: {}", e));
-                                last_error = Some(e.to_string());
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        verbose.print_error(&format!("Failed to read password: {}", e));
-                        last_error = Some(e.to_string());
-                    }
+        Ok(stats)
+    }
+
+
+    fn load_files_from(&self, transport: &SshTransport, host: &str, verbose: &VerboseOutput) -> Result<Option<Vec<PathBuf>>> {
+        let spec = match self.options.files_from {
+            Some(ref spec) => spec,
+            None => return Ok(None),
+        };
+        match remote_files_from(spec) {
+            Some((list_host, list_path)) => {
+                if !list_host.is_empty() && list_host != host {
+                    return Err(RsyncError::Config(format!(
+                        "--files-from={} must be read from the transfer host {}",
+                        spec.display(),
+                        host
+                    )));
                 }
+                verbose.print_verbose(format!("Reading files-from list {} from {}", list_path, host));
+                let contents = transport.read_file(&list_path)?;
+                Ok(Some(parse_files_from(&String::from_utf8_lossy(&contents), self.options.from0)))
             }
+            None => Ok(Some(read_files_from(spec, self.options.from0)?)),
+        }
+    }
 
-            match transport_result {
-                Some(mut transport) => {
-                    verbose.print_verbose("SSH connection successful.");
 
+    fn exchange<S: Read + Write>(
+        &self,
+        channel: S,
+        local_path: &Path,
+        is_remote_source: bool,
+        files_from: Option<&[PathBuf]>,
+        verbose: &VerboseOutput,
+        stats: &mut SyncStats,
+    ) -> Result<()> {
+        let mut stream = ProtocolStream::new(channel, PROTOCOL_VERSION_MAX);
 
-                    let remote_unix_path = to_unix_separators(&remote_raw_path);
+        verbose.print_verbose("Negotiating protocol version...");
+        stream.write_i32(PROTOCOL_VERSION_MAX)?;
+        stream.flush()?;
+        let remote_version = stream.read_i32()?;
 
 
-                    let mut rsync_args = vec![
-                        "--server",
-                        "--sender",
-                    ];
+        stream.write_i32(PROTOCOL_VERSION_MAX)?;
+        stream.flush()?;
+        let _remote_version_ack = stream.read_i32()?;
 
+        verbose.print_verbose(format!("Negotiated protocol version: {}", remote_version));
 
-                    if self.options.recursive { rsync_args.push("-r"); }
-                    if self.options.verbose > 0 { rsync_args.push("-v"); }
-                    if self.options.delete { rsync_args.push("--delete"); }
+        let compat_flags = if remote_version.min(PROTOCOL_VERSION_MAX) >= 30 {
+            stream.read_varint()?
+        } else {
+            0
+        };
 
-                    rsync_args.push(".");
-                    rsync_args.push(&remote_unix_path);
+        let seed = stream.read_i32()?;
+        stream.set_checksum_seed(seed);
+        stream.set_seed_first(compat_flags & CF_CHKSUM_SEED_FIX != 0);
+        verbose.print_debug(format!("Checksum seed: {}", stream.checksum_seed()));
+
+        if self.options.compress {
+            let preferred = self.options.compress_choice.map_or(COMPRESSION_NEGOTIATION, |choice| choice.name());
+            stream.write_string(preferred)?;
+            stream.flush()?;
+            let server_list = stream.read_string(256)?;
+            let algorithm = negotiate_compression(preferred, &server_list);
+            match algorithm {
+                Some(algorithm) => verbose.print_verbose(format!("Compression: {}", algorithm.name())),
+                None => verbose.print_warning(format!(
+                    "Remote side offers no compression from \"{}\" (it has \"{}\"), sending uncompressed",
+                    preferred, server_list
+                )),
+            }
+            stream.set_compression(algorithm);
+        }
 
-                    let rsync_command_str = format!("rsync {}", rsync_args.join(" "));
-                    verbose.print_debug(&format!("Executing remote command: {}", rsync_command_str));
+        let filter = FilterEngine::from_options(&self.options)?.trace(*verbose);
+        if filter.pattern_count() > 0 {
+            verbose.print_verbose(format!("Sending {} filter rule(s)...", filter.pattern_count()));
+        }
+        send_filter_rules(&mut stream, &filter)?;
 
-                    match transport.execute(&rsync_command_str) {
-                        Ok(mut channel) => {
+        if let (true, Some(files_from)) = (is_remote_source, files_from) {
+            verbose.print_verbose(format!("Sending files-from list ({} entries)...", files_from.len()));
+            send_files_from(&mut stream, files_from)?;
+        }
 
-                            let mut stream = ProtocolStream::new(&mut channel, PROTOCOL_VERSION_MAX);
+        let progress = (self.options.progress && !self.options.quiet)
+            .then(ProgressDisplay::network);
+        if compat_flags & CF_INC_RECURSE != 0 {
+            verbose.print_verbose("Using incremental recursion.");
+            if is_remote_source {
+                fs::create_dir_all(local_path)?;
+                receive_incremental(&mut stream, local_path, &self.options, verbose, stats, progress.as_ref())?;
+            } else {
+                send_incremental(&mut stream, local_path, &self.options, &filter, verbose, stats, progress.as_ref())?;
+            }
+            if let Some(progress) = progress {
+                progress.finish();
+            }
+            return Ok(());
+        }
 
 
+        let mut local_file_infos = local_file_list(local_path, &self.options, &filter)?;
+        if let (false, Some(files_from)) = (is_remote_source, files_from) {
+            local_file_infos.retain(|file| files_from_allows(files_from, &file.path));
+        }
 
 
-                            verbose.print_verbose("Negotiating protocol version...");
-                            stream.write_i32(PROTOCOL_VERSION_MAX)?;
-                            stream.flush()?;
-                            let remote_version = stream.read_i32()?;
+        verbose.print_verbose("Sending file list...");
+        FileList::encode(&mut stream, &local_file_infos)?;
+        verbose.print_verbose("File list sent.");
 
 
-                            stream.write_i32(PROTOCOL_VERSION_MAX)?;
-                            stream.flush()?;
-                            let _remote_version_ack = stream.read_i32()?;
+        verbose.print_verbose("Receiving remote file list...");
+        let remote_file_infos = FileList::decode(&mut stream)?;
+        verbose.print_verbose(format!("Received {} remote files.", remote_file_infos.len()));
 
-                            verbose.print_verbose(&format!("Negotiated protocol version: {}", remote_version));
 
+        verbose.print_verbose("Starting file transfer...");
+        let phase = delete_phase(&self.options);
+        if is_remote_source {
+            stats.scanned_files = remote_file_infos.len();
+            fs::create_dir_all(local_path)?;
+            if phase == Some(DeletePhase::Before) {
+                receive_deletions(&mut stream, local_path, &self.options, verbose, stats)?;
+            }
+            receive_files(&mut stream, local_path, &remote_file_infos, &self.options, verbose, stats, progress.as_ref())?;
+            if phase == Some(DeletePhase::After) {
+                receive_deletions(&mut stream, local_path, &self.options, verbose, stats)?;
+            }
+        } else {
+            stats.scanned_files = local_file_infos.len();
+            if phase == Some(DeletePhase::Before) {
+                send_deletions(&mut stream, local_path, &local_file_infos, &remote_file_infos)?;
+            }
+            send_files(&mut stream, local_path, &local_file_infos, &self.options, verbose, stats, progress.as_ref())?;
+            if phase == Some(DeletePhase::After) {
+                send_deletions(&mut stream, local_path, &local_file_infos, &remote_file_infos)?;
+            }
+        }
+        if let Some(progress) = progress {
+            progress.finish();
+        }
+        Ok(())
+    }
+
+
+    fn authenticate(
+        &self,
+        endpoint: &SshEndpoint,
+        jump: Option<&SshTransport>,
+        algorithms: &SshAlgorithms,
+        keepalive: SshKeepalive,
+        verbose: &VerboseOutput,
+    ) -> Result<SshTransport> {
+        let connect = |auth_method: AuthMethod| {
+            let tcp = match jump {
+                Some(jump) => jump.tunnel(&endpoint.host, endpoint.port)?,
+                None => SshTransport::dial(&endpoint.host, endpoint.port, Some(&self.options.socket_options()))?,
+            };
+            SshTransport::handshake(tcp, &endpoint.user, auth_method, &endpoint.host_key, algorithms, keepalive)
+        };
+
+        if let Some(ref identity_file) = endpoint.identity_file {
+            let certificate = endpoint.certificate_file.clone().or_else(|| certificate_for(identity_file));
+            if let Some(certificate) = certificate {
+                verbose.print_verbose(format!("Trying certificate authentication: {}", certificate.display()));
+                match connect(AuthMethod::Certificate { key: identity_file.clone(), certificate }) {
+                    Ok(transport) => {
+                        verbose.print_verbose("Certificate authentication successful.");
+                        return Ok(transport);
+                    }
+                    Err(e @ RsyncError::HostKey(_)) => return Err(e),
+                    Err(e) => verbose.print_verbose(format!("Certificate authentication failed: {}", e)),
+                }
+            }
 
-                            let scanner = Scanner::new()
-                                .recursive(self.options.recursive)
-                                .follow_symlinks(self.options.copy_links);
-                            let local_file_infos = scanner.scan(local_path)?;
+            verbose.print_verbose(format!("Trying public key authentication: {}", identity_file.display()));
+            match connect(AuthMethod::PublicKey(identity_file.clone())) {
+                Ok(transport) => {
+                    verbose.print_verbose("Public key authentication successful.");
+                    return Ok(transport);
+                }
+                Err(e @ RsyncError::HostKey(_)) => return Err(e),
+                Err(e) => verbose.print_verbose(format!("Public key authentication failed: {}", e)),
+            }
+        }
 
+        verbose.print_verbose("Trying SSH agent authentication...");
+        match connect(AuthMethod::Agent) {
+            Ok(transport) => {
+                verbose.print_verbose("SSH agent authentication successful.");
+                return Ok(transport);
+            }
+            Err(e @ RsyncError::HostKey(_)) => return Err(e),
+            Err(e) => verbose.print_verbose(format!("SSH agent authentication failed: {}", e)),
+        }
 
-                            verbose.print_verbose("Sending file list...");
-                            FileList::encode(&mut stream, &local_file_infos)?;
-                            verbose.print_verbose("File list sent.");
+        let credential_target = self.options.use_credential_manager
+            .then(|| credentials::ssh_target(&endpoint.user, &endpoint.host, endpoint.port));
+        if let Some(ref target) = credential_target {
+            match credentials::load(target) {
+                Ok(Some(password)) => {
+                    verbose.print_verbose(format!("Trying password stored in Credential Manager as {}", target));
+                    match connect(AuthMethod::Password(password)) {
+                        Ok(transport) => {
+                            verbose.print_verbose("Password authentication successful.");
+                            return Ok(transport);
+                        }
+                        Err(e) => verbose.print_warning(format!("Stored password {} was rejected: {}", target, e)),
+                    }
+                }
+                Ok(None) => verbose.print_verbose(format!("No password stored in Credential Manager as {}", target)),
+                Err(e) => verbose.print_warning(e.to_string()),
+            }
+        }
 
+        if endpoint.password_prompts == 0 {
+            return Err(RsyncError::Auth(format!(
+                "SSH connection to {}@{} failed: no key, certificate or agent identity was accepted, and password \
+                 prompts are disabled (--batch-mode, --no-prompt, -o BatchMode=yes or --password-prompts=0). \
+                 Give a key with -e \"ssh -i KEYFILE\" or load one into the SSH agent",
+                endpoint.user,
+                endpoint.host
+            )));
+        }
 
-                            verbose.print_verbose("Receiving remote file list...");
-                            let remote_file_infos = FileList::decode(&mut stream)?;
-                            verbose.print_verbose(&format!("Received {} remote files.", remote_file_infos.len()));
-                            stats.scanned_files = local_file_infos.len();
+        let mut error = None;
+        for attempt in 1..=endpoint.password_prompts {
+            verbose.print_verbose("Trying password authentication...");
+            let password = match prompt_for_password(&endpoint.user, &endpoint.host) {
+                Ok(password) => password,
+                Err(e) => {
+                    verbose.print_error(format!("Failed to read password: {}", e));
+                    error = Some(e);
+                    break;
+                }
+            };
+            match connect(AuthMethod::Password(password.clone())) {
+                Ok(transport) => {
+                    verbose.print_verbose("Password authentication successful.");
+                    if let Some(ref target) = credential_target {
+                        match credentials::store(target, &endpoint.user, &password) {
+                            Ok(()) => verbose.print_verbose(format!("Saved password to Credential Manager as {}", target)),
+                            Err(e) => verbose.print_warning(e.to_string()),
+                        }
+                    }
+                    return Ok(transport);
+                }
+                Err(e) if attempt < endpoint.password_prompts => {
+                    verbose.print_error("Permission denied, please try again.");
+                    verbose.print_verbose(format!("Password authentication failed: {}", e));
+                }
+                Err(e) => {
+                    verbose.print_error(format!(
+                        "Password authentication failed after {} attempt(s): {}",
+                        endpoint.password_prompts, e
+                    ));
+                    error = Some(e);
+                }
+            }
+        }
+        Err(RsyncError::Auth(format!(
+            "SSH connection to {} failed: {}",
+            endpoint.host,
+            error.map(|e| e.to_string()).unwrap_or_default()
+        )))
+    }
+}
 
 
-                            verbose.print_verbose("Starting file transfer...");
+struct SshEndpoint {
+    host: String,
+    port: u16,
+    user: String,
+    identity_file: Option<PathBuf>,
+    certificate_file: Option<PathBuf>,
+    password_prompts: u32,
+    host_key: HostKeyCheck,
+}
 
 
-                            for local_file in &local_file_infos {
-                                if local_file.is_directory() {
+fn quote_remote_arg(arg: &str) -> String {
+    let plain = !arg.is_empty() && arg.chars().all(|c| c.is_ascii_alphanumeric() || "-_=./:,+@%".contains(c));
+    if plain {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
 
-                                    continue;
-                                }
 
+fn remote_is_yarw(options: &Options) -> bool {
+    options.rsync_path.as_deref()
+        .and_then(|command| command.split_whitespace().last())
+        .and_then(|program| Path::new(program.trim_matches(['"', '\''])).file_stem())
+        .is_some_and(|stem| stem.eq_ignore_ascii_case("yarw"))
+}
 
-                                let remote_file = remote_file_infos.iter()
-                                    .find(|f| f.path == local_file.path);
 
-                                verbose.print_basic(&format!("Processing: {}", local_file.path.display()));
+pub fn server_flags(options: &Options, local_sender: bool, protocol: i32) -> String {
+    let mut flags = String::from("-");
+    for _ in 0..options.verbose {
+        flags.push('v');
+    }
+    if options.backup { flags.push('b'); }
+    if options.update { flags.push('u'); }
+    if options.dry_run { flags.push('n'); }
+    if options.links { flags.push('l'); }
+    if !local_sender && options.copy_links { flags.push('L'); }
+    if options.whole_file { flags.push('W'); }
+    if options.hard_links { flags.push('H'); }
+    if options.owner { flags.push('o'); }
+    if options.group { flags.push('g'); }
+    if options.devices { flags.push('D'); }
+    if options.times { flags.push('t'); }
+    if options.perms { flags.push('p'); }
+    if options.recursive { flags.push('r'); }
+    if options.dirs && !options.recursive { flags.push('d'); }
+    if options.checksum { flags.push('c'); }
+    if options.ignore_times { flags.push('I'); }
+    if options.relative { flags.push('R'); }
+    if options.sparse { flags.push('S'); }
+    if options.compress { flags.push('z'); }
+
+    if protocol >= 30 {
+        flags.push_str("e.");
+        let allow_inc_recurse = options.recursive
+            && options.inc_recursive
+            && !options.delete
+            && options.files_from.is_none();
+        if allow_inc_recurse {
+            flags.push('i');
+        }
+        flags.push_str("LsfxCY");
+    }
 
+    flags
+}
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::Cli;
+    use crate::options::FilterRule;
+    use crate::transport::RsyncServer;
+    use clap::Parser;
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_run_on_blocking_thread() {
+        let transport = RemoteTransport::new(Options::default());
+        let result = transport.run("local/src".to_string(), "local/dest".to_string()).await;
+        assert!(matches!(result, Err(RsyncError::InvalidPath(_))));
+    }
 
-                                if remote_file.is_some() {
-                                    verbose.print_verbose("  Updating existing file (whole-file transfer)");
-                                } else {
-                                    verbose.print_verbose("  New file");
-                                }
+    #[test]
+    fn test_push_sends_only_changed_blocks() -> Result<()> {
+        let source = TempDir::new()?;
+        let dest = TempDir::new()?;
+        let content: Vec<u8> = (0..300_000u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8).collect();
+        let mut stale = content.clone();
+        stale[150_000..150_100].fill(0);
+        fs::write(source.path().join("data.bin"), &content)?;
+        fs::write(source.path().join("new.txt"), b"new file")?;
+        fs::write(dest.path().join("data.bin"), &stale)?;
+        let stale_mtime = std::time::SystemTime::now() - Duration::from_secs(3600);
+        fs::File::options().write(true).open(dest.path().join("data.bin"))?.set_modified(stale_mtime)?;
+
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let client = TcpStream::connect(listener.local_addr()?)?;
+        let (server, _) = listener.accept()?;
+        let dest_path = dest.path().to_path_buf();
+        let server = thread::spawn(move || {
+            RsyncServer::new(Options { recursive: true, rsh: Some(".LsfxCY".to_string()), ..Options::default() }).serve(server, &dest_path)
+        });
+
+        let transport = RemoteTransport::new(Options { recursive: true, quiet: true, ..Options::default() });
+        let mut stats = SyncStats::default();
+        transport.exchange(client, source.path(), false, None, &VerboseOutput::new(0, true), &mut stats)?;
+        server.join().unwrap()?;
+
+        assert_eq!(fs::read(dest.path().join("data.bin"))?, content);
+        assert_eq!(fs::read(dest.path().join("new.txt"))?, b"new file");
+        assert_eq!(stats.transferred_files, 2);
+        assert_eq!(stats.matched_bytes + stats.literal_bytes, content.len() as u64 + 8);
+        assert!(stats.matched_bytes >= 200_000);
+        Ok(())
+    }
 
+    #[test]
+    fn test_push_compresses_literal_tokens() -> Result<()> {
+        let source = TempDir::new()?;
+        let dest = TempDir::new()?;
+        let content = b"compressible line of text\n".repeat(10_000);
+        fs::write(source.path().join("text.txt"), &content)?;
+
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let client = TcpStream::connect(listener.local_addr()?)?;
+        let (server, _) = listener.accept()?;
+        let dest_path = dest.path().to_path_buf();
+        let server = thread::spawn(move || {
+            let options = Cli::parse_from(["yarw", "--server", "-rze.LsfxCY", "--compress-choice=zstd", ".", "dst/"])
+                .into_options()
+                .unwrap();
+            RsyncServer::new(options).serve(server, &dest_path)
+        });
+
+        let options = Options {
+            recursive: true,
+            compress: true,
+            compress_choice: Some(CompressionAlgorithm::Zstd),
+            quiet: true,
+            ..Options::default()
+        };
+        let mut stats = SyncStats::default();
+        RemoteTransport::new(options).exchange(client, source.path(), false, None, &VerboseOutput::new(0, true), &mut stats)?;
+        server.join().unwrap()?;
 
-                                let local_file_path = local_path.join(&local_file.path);
-                                if local_file_path.exists() {
-                                    let file_data = fs::read(&local_file_path)?;
+        assert_eq!(fs::read(dest.path().join("text.txt"))?, content);
+        assert_eq!(stats.literal_bytes, content.len() as u64);
+        assert!(stats.compressed_bytes > 0 && stats.compressed_bytes < content.len() as u64 / 10);
+        Ok(())
+    }
 
+    #[test]
+    fn test_push_resumes_from_partial_file() -> Result<()> {
+        let source = TempDir::new()?;
+        let dest = TempDir::new()?;
+        let content: Vec<u8> = (0..300_000u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8).collect();
+        fs::write(source.path().join("data.bin"), &content)?;
+        fs::create_dir(dest.path().join(".partial"))?;
+        fs::write(dest.path().join(".partial/data.bin"), &content[..200_000])?;
+
+        let options = Options {
+            recursive: true,
+            partial: true,
+            partial_dir: Some(PathBuf::from(".partial")),
+            delete: true,
+            quiet: true,
+            ..Options::default()
+        };
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let client = TcpStream::connect(listener.local_addr()?)?;
+        let (server, _) = listener.accept()?;
+        let dest_path = dest.path().to_path_buf();
+        let server_options = Options { rsh: Some(".LsfxCY".to_string()), ..options.clone() };
+        let server = thread::spawn(move || RsyncServer::new(server_options).serve(server, &dest_path));
 
-                                    stream.write_varint(file_data.len() as i64)?;
+        let mut stats = SyncStats::default();
+        RemoteTransport::new(options).exchange(client, source.path(), false, None, &VerboseOutput::new(0, true), &mut stats)?;
+        server.join().unwrap()?;
+
+        assert_eq!(fs::read(dest.path().join("data.bin"))?, content);
+        assert!(!dest.path().join(".partial/data.bin").exists());
+        assert!(dest.path().join(".partial").is_dir());
+        assert!(stats.literal_bytes <= 110_000);
+        Ok(())
+    }
 
+    #[test]
+    fn test_server_honors_sparse_and_preallocate() -> Result<()> {
+        let source = TempDir::new()?;
+        let dest = TempDir::new()?;
+        let mut image = vec![0u8; 512 * 1024];
+        image[300_000..300_005].copy_from_slice(b"image");
+        fs::write(source.path().join("disk.img"), &image)?;
+
+        let options = Options { recursive: true, sparse: true, preallocate: true, quiet: true, ..Options::default() };
+        let flags = server_flags(&options, true, PROTOCOL_VERSION_MAX);
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let client = TcpStream::connect(listener.local_addr()?)?;
+        let (server, _) = listener.accept()?;
+        let dest_path = dest.path().to_path_buf();
+        let server = thread::spawn(move || {
+            let options = Cli::parse_from(["yarw", "--server", &flags, "--preallocate", ".", "dst/"])
+                .into_options()
+                .unwrap();
+            assert!(options.sparse && options.preallocate);
+            RsyncServer::new(options).serve(server, &dest_path)
+        });
 
-                                    stream.write_all(&file_data)?;
-                                    stream.flush()?;
+        let mut stats = SyncStats::default();
+        RemoteTransport::new(options).exchange(client, source.path(), false, None, &VerboseOutput::new(0, true), &mut stats)?;
+        server.join().unwrap()?;
+
+        assert_eq!(fs::read(dest.path().join("disk.img"))?, image);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            let metadata = fs::metadata(dest.path().join("disk.img"))?;
+            assert!(metadata.blocks() * 512 < metadata.len());
+        }
+        Ok(())
+    }
 
-                                    stats.transferred_files += 1;
-                                    stats.transferred_bytes += file_data.len() as u64;
+    #[test]
+    fn test_push_with_delete_removes_extraneous_files() -> Result<()> {
+        let source = TempDir::new()?;
+        let dest = TempDir::new()?;
+        fs::write(source.path().join("keep.txt"), b"keep")?;
+        fs::create_dir_all(dest.path().join("gone/inner"))?;
+        fs::write(dest.path().join("gone/inner/file.txt"), b"old")?;
+        fs::write(dest.path().join("stale.txt"), b"stale")?;
+
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let client = TcpStream::connect(listener.local_addr()?)?;
+        let (server, _) = listener.accept()?;
+        let dest_path = dest.path().to_path_buf();
+        let server = thread::spawn(move || {
+            let options = Cli::parse_from(["yarw", "--server", "-re.LsfxCY", "--delete", "--delete-before", ".", "dst/"])
+                .into_options()
+                .unwrap();
+            RsyncServer::new(options).serve(server, &dest_path)
+        });
+
+        let options = Options { recursive: true, delete: true, delete_before: true, quiet: true, ..Options::default() };
+        let mut stats = SyncStats::default();
+        RemoteTransport::new(options).exchange(client, source.path(), false, None, &VerboseOutput::new(0, true), &mut stats)?;
+        server.join().unwrap()?;
 
-                                    verbose.print_basic(&format!("  Transferred {} bytes", file_data.len()));
-                                }
-                            }
+        assert_eq!(fs::read(dest.path().join("keep.txt"))?, b"keep");
+        assert!(!dest.path().join("gone").exists());
+        assert!(!dest.path().join("stale.txt").exists());
+        Ok(())
+    }
 
+    #[test]
+    fn test_pull_with_incremental_recursion() -> Result<()> {
+        let source = TempDir::new()?;
+        let dest = TempDir::new()?;
+        fs::create_dir_all(source.path().join("a/b"))?;
+        fs::create_dir_all(source.path().join("empty"))?;
+        fs::write(source.path().join("top.txt"), b"top")?;
+        fs::write(source.path().join("a/one.txt"), b"one")?;
+        fs::write(source.path().join("a/b/two.txt"), b"two")?;
+
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let client = TcpStream::connect(listener.local_addr()?)?;
+        let (server, _) = listener.accept()?;
+        let source_path = source.path().to_path_buf();
+        let server = thread::spawn(move || {
+            let options = Cli::parse_from(["yarw", "--server", "--sender", "-re.iLsfxCY", ".", "src/"])
+                .into_options()
+                .unwrap();
+            RsyncServer::new(options).serve(server, &source_path)
+        });
+
+        let transport = RemoteTransport::new(Options { recursive: true, quiet: true, ..Options::default() });
+        let mut stats = SyncStats::default();
+        transport.exchange(client, dest.path(), true, None, &VerboseOutput::new(0, true), &mut stats)?;
+        server.join().unwrap()?;
+
+        assert_eq!(fs::read(dest.path().join("top.txt"))?, b"top");
+        assert_eq!(fs::read(dest.path().join("a/one.txt"))?, b"one");
+        assert_eq!(fs::read(dest.path().join("a/b/two.txt"))?, b"two");
+        assert!(dest.path().join("empty").is_dir());
+        assert_eq!(stats.transferred_files, 3);
+        assert_eq!(stats.scanned_files, 6);
+        Ok(())
+    }
 
-                            stats.execution_time_secs = start_time.elapsed().as_secs_f64();
+    #[test]
+    fn test_pull_sends_files_from_list() -> Result<()> {
+        let source = TempDir::new()?;
+        let dest = TempDir::new()?;
+        fs::create_dir_all(source.path().join("a"))?;
+        fs::write(source.path().join("a/one.txt"), b"one")?;
+        fs::write(source.path().join("a/two.txt"), b"two")?;
+        fs::write(source.path().join("top.txt"), b"top")?;
+
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let client = TcpStream::connect(listener.local_addr()?)?;
+        let (server, _) = listener.accept()?;
+        let source_path = source.path().to_path_buf();
+        let server = thread::spawn(move || {
+            let options = Cli::parse_from(["yarw", "--server", "--sender", "--files-from=-", "-re.LsfxY", ".", "src/"])
+                .into_options()
+                .unwrap();
+            RsyncServer::new(options).serve(server, &source_path)
+        });
+
+        let transport = RemoteTransport::new(Options { recursive: true, quiet: true, ..Options::default() });
+        let files_from = [PathBuf::from("a/one.txt"), PathBuf::from("top.txt")];
+        let mut stats = SyncStats::default();
+        transport.exchange(client, dest.path(), true, Some(&files_from), &VerboseOutput::new(0, true), &mut stats)?;
+        server.join().unwrap()?;
+
+        assert_eq!(fs::read(dest.path().join("a/one.txt"))?, b"one");
+        assert_eq!(fs::read(dest.path().join("top.txt"))?, b"top");
+        assert!(!dest.path().join("a/two.txt").exists());
+        assert_eq!(stats.transferred_files, 2);
+        Ok(())
+    }
 
-                            verbose.print_basic("Transfer complete!");
-                            if self.options.stats {
-                                stats.display(self.options.human_readable, &verbose);
-                            }
+    #[test]
+    fn test_exclude_filters_remote_transfers() -> Result<()> {
+        let source = TempDir::new()?;
+        let dest = TempDir::new()?;
+        fs::create_dir_all(source.path().join("cache"))?;
+        fs::write(source.path().join("cache/blob.bin"), b"blob")?;
+        fs::write(source.path().join("keep.txt"), b"keep")?;
+        fs::write(source.path().join("debug.log"), b"log")?;
+
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let client = TcpStream::connect(listener.local_addr()?)?;
+        let (server, _) = listener.accept()?;
+        let source_path = source.path().to_path_buf();
+        let server = thread::spawn(move || {
+            let options = Cli::parse_from(["yarw", "--server", "--sender", "-re.iLsfxCY", ".", "src/"])
+                .into_options()
+                .unwrap();
+            RsyncServer::new(options).serve(server, &source_path)
+        });
+
+        let options = Options {
+            recursive: true,
+            quiet: true,
+            filter_rules: vec![FilterRule::Exclude("*.log".to_string()), FilterRule::Exclude("cache/".to_string())],
+            ..Options::default()
+        };
+        let mut stats = SyncStats::default();
+        RemoteTransport::new(options.clone()).exchange(client, dest.path(), true, None, &VerboseOutput::new(0, true), &mut stats)?;
+        server.join().unwrap()?;
+
+        assert_eq!(fs::read(dest.path().join("keep.txt"))?, b"keep");
+        assert!(!dest.path().join("debug.log").exists());
+        assert!(!dest.path().join("cache").exists());
+
+        fs::write(dest.path().join("remote.log"), b"remote")?;
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let client = TcpStream::connect(listener.local_addr()?)?;
+        let (server, _) = listener.accept()?;
+        let dest_path = dest.path().to_path_buf();
+        let server = thread::spawn(move || {
+            let options = Cli::parse_from(["yarw", "--server", "-re.LsfxCY", "--delete", "--delete-before", ".", "dst/"])
+                .into_options()
+                .unwrap();
+            RsyncServer::new(options).serve(server, &dest_path)
+        });
+
+        let options = Options { delete: true, delete_before: true, ..options };
+        let mut stats = SyncStats::default();
+        RemoteTransport::new(options).exchange(client, source.path(), false, None, &VerboseOutput::new(0, true), &mut stats)?;
+        server.join().unwrap()?;
 
+        assert_eq!(fs::read(dest.path().join("remote.log"))?, b"remote");
+        assert!(!dest.path().join("debug.log").exists());
+        Ok(())
+    }
 
-                            let mut stderr_bytes = Vec::new();
-                            match channel.stderr().read_to_end(&mut stderr_bytes) {
-                                Ok(_) => {
-                                    if !stderr_bytes.is_empty() {
-                                        verbose.print_error(&format!("Remote stderr: {}", String::from_utf8_lossy(&stderr_bytes)));
-                                    }
-                                },
-                                Err(e) => verbose.print_error(&format!("Failed to read remote stderr: {}", e)),
-                            }
+    fn parse(args: &[&str]) -> Options {
+        let mut argv = vec!["yarw"];
+        argv.extend_from_slice(args);
+        argv.extend_from_slice(&["src/", "host:dst/"]);
+        Cli::parse_from(argv).into_options().unwrap()
+    }
 
+    #[test]
+    fn test_remote_options_are_passed_through() {
+        let options = parse(&["-a", "-M--log-file=/tmp/r.log", "-M", "--bwlimit=10", "--remote-option=--out-format=%n %l"]);
+        assert_eq!(options.remote_options, ["--log-file=/tmp/r.log", "--bwlimit=10", "--out-format=%n %l"]);
+        assert_eq!(quote_remote_arg("--log-file=/tmp/r.log"), "--log-file=/tmp/r.log");
+        assert_eq!(quote_remote_arg("--out-format=%n %l"), "'--out-format=%n %l'");
+        assert_eq!(quote_remote_arg("--filter=- it's"), "'--filter=- it'\\''s'");
+    }
 
-                            channel.close()?;
-                            channel.wait_close()?;
+    #[test]
+    fn test_server_flags_match_rsync() {
+        assert_eq!(server_flags(&parse(&["-a"]), true, 31), "-logDtpre.iLsfxCY");
+        assert_eq!(server_flags(&parse(&["-av"]), false, 31), "-vlogDtpre.iLsfxCY");
+        assert_eq!(server_flags(&parse(&["-r"]), true, 31), "-re.iLsfxCY");
+        assert_eq!(server_flags(&parse(&["-auzH"]), true, 31), "-ulHogDtprze.iLsfxCY");
+        assert_eq!(server_flags(&parse(&["-rL", "--delete-after"]), false, 31), "-Lre.LsfxCY");
+        assert_eq!(server_flags(&parse(&["-vvcI"]), true, 31), "-vvcIe.LsfxCY");
+        assert_eq!(server_flags(&parse(&["-rt"]), true, 31), "-tre.iLsfxCY");
+        assert_eq!(server_flags(&parse(&["-dt"]), true, 31), "-tde.LsfxCY");
+        assert_eq!(server_flags(&parse(&["-rd"]), true, 31), "-re.iLsfxCY");
+        assert_eq!(server_flags(&parse(&["-r", "--files-from=list.txt"]), true, 31), "-re.LsfxCY");
+        assert_eq!(server_flags(&parse(&["-rS"]), true, 31), "-rSe.iLsfxCY");
+        assert_eq!(server_flags(&parse(&["-a"]), true, 29), "-logDtpr");
+        assert_eq!(server_flags(&parse(&["-a", "--no-perms", "--no-o", "--no-t"]), true, 31), "-lgDre.iLsfxCY");
+        assert_eq!(server_flags(&parse(&["-rp", "--no-D", "-a", "--no-r"]), true, 31), "-logtpe.LsfxCY");
+    }
 
-                        }
-                        Err(e) => return Err(RsyncError::RemoteExec(format!("Failed to execute remote command: {}", e))),
-                    }
-                }
-                None => {
-                    let error_msg = last_error.unwrap_or_else(|| "All authentication methods failed".to_string());
-                    return Err(RsyncError::Auth(format!("SSH connection failed: {}", error_msg)));
-                }
-            }
-        } else {
-            return Err(RsyncError::InvalidPath(PathBuf::from(source)));
-        }
+    #[test]
+    fn test_compression_needs_yarw_remote() {
+        let remote = |rsync_path: Option<&str>| Options { rsync_path: rsync_path.map(str::to_string), ..Options::default() };
+        assert!(remote_is_yarw(&remote(Some("yarw"))));
+        assert!(remote_is_yarw(&remote(Some("\"C:/Program Files/YARW/yarw.exe\""))));
+        assert!(remote_is_yarw(&remote(Some("sudo /usr/local/bin/yarw"))));
+        assert!(!remote_is_yarw(&remote(Some("/usr/bin/rsync"))));
+        assert!(!remote_is_yarw(&remote(None)));
+        let compressed = RemoteTransport::new(Options { compress: true, ..remote(Some("rsync")) }).sync("src/", "backup.example:dst/");
+        assert!(matches!(compressed, Err(RsyncError::InvalidOption(_))));
+
+        let options = Cli::parse_from(["yarw", "--server", "-re.LsfxC", ".", "dst/"]).into_options().unwrap();
+        let refused = RsyncServer::new(options).serve(std::io::Cursor::new(Vec::new()), Path::new("dst"));
+        assert!(matches!(refused, Err(RsyncError::InvalidOption(_))));
+    }
 
-        Ok(stats)
+    #[test]
+    fn test_server_parses_flags() {
+        let flags = server_flags(&parse(&["-avuI"]), true, 31);
+        let options = Cli::parse_from(["yarw", "--server", &flags, ".", "dst/"]).into_options().unwrap();
+        assert!(options.server && options.recursive && options.links && options.update && options.ignore_times);
+        assert!(options.times);
+        assert_eq!(options.verbose, 1);
     }
 }
@@ -0,0 +1,1345 @@
+use crate::options::{BasisMode, Options};
+use crate::error::{Result, RsyncError};
+use crate::algorithm::{BandwidthLimiter, DeltaStats, Generator, Receiver, Sender};
+use crate::algorithm::receiver::{
+    adopt_basis, backup_file, find_basis_dir, is_partial_path, partial_path, remove_unprotected_dir, DeltaWriter,
+};
+use crate::algorithm::checksum::ChecksumSeed;
+use crate::algorithm::delta::DeltaInstruction;
+use crate::algorithm::generator::BlockChecksum;
+use crate::filesystem::{files_from_allows, files_from_entry, read_files_from, set_file_times, FileInfo, Scanner};
+use crate::filesystem::path_utils::to_unix_separators;
+use crate::filter::FilterEngine;
+use crate::filesystem::hard_links::{is_same_file, link_leaders, link_to};
+use crate::output::{json, ItemizeChange, ProgressDisplay, VerboseOutput};
+use crate::protocol::{
+    choose_checksum_seed, negotiate_compression, ProtocolStream, FileList, FlistManager, CF_CHKSUM_SEED_FIX, CF_INC_RECURSE,
+    COMPRESSION_NEGOTIATION, PROTOCOL_VERSION_MAX,
+};
+use crate::protocol::iflags::{compute_iflags, quick_check_matches, ITEM_IS_NEW, ITEM_LOCAL_CHANGE, ITEM_TRANSFER, ITEM_XNAME_FOLLOWS};
+use crate::protocol::sums::{DeltaCodec, SumHead, Token, CHUNK_SIZE};
+use super::stdio::StdioStream;
+use super::{SkipReason, SyncStats};
+use std::io::{Read, Write};
+use std::collections::{HashSet, VecDeque};
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+use std::fs;
+use std::fs::OpenOptions;
+use crate::filesystem::sparse::SparseWriter;
+use md5::{Digest, Md5};
+
+
+pub struct RsyncServer {
+    options: Options,
+}
+
+impl RsyncServer {
+    pub fn new(options: Options) -> Self {
+        Self { options }
+    }
+
+
+    pub fn run(&self, path: &Path) -> Result<()> {
+        self.serve(StdioStream::open()?, path)
+    }
+
+    pub fn serve<S: Read + Write>(&self, channel: S, path: &Path) -> Result<()> {
+        if !client_info_has(&self.options, 'Y') {
+            return Err(RsyncError::InvalidOption(
+                "yarw --server only works with a yarw client; serve stock rsync clients with rsync or yarw --daemon".to_string(),
+            ));
+        }
+        let verbose = VerboseOutput::new(0, true);
+        let mut stream = ProtocolStream::new(channel, PROTOCOL_VERSION_MAX);
+
+
+        let client_version = stream.read_i32()?;
+        stream.write_i32(PROTOCOL_VERSION_MAX)?;
+        stream.flush()?;
+        let _client_version_ack = stream.read_i32()?;
+        stream.write_i32(PROTOCOL_VERSION_MAX)?;
+        stream.flush()?;
+
+        let mut compat_flags = 0;
+        if client_version.min(PROTOCOL_VERSION_MAX) >= 30 {
+            if client_allows_inc_recurse(&self.options) {
+                compat_flags |= CF_INC_RECURSE;
+            }
+            if client_info_has(&self.options, 'C') {
+                compat_flags |= CF_CHKSUM_SEED_FIX;
+            }
+            stream.write_varint(compat_flags)?;
+        }
+
+        let seed = choose_checksum_seed(self.options.checksum_seed);
+        stream.write_i32(seed)?;
+        stream.flush()?;
+        stream.set_checksum_seed(seed);
+        stream.set_seed_first(compat_flags & CF_CHKSUM_SEED_FIX != 0);
+
+        if self.options.compress {
+            let client_list = stream.read_string(256)?;
+            stream.write_string(COMPRESSION_NEGOTIATION)?;
+            stream.flush()?;
+            stream.set_compression(negotiate_compression(&client_list, COMPRESSION_NEGOTIATION));
+        }
+
+        let filter = receive_filter_rules(&mut stream)?;
+        let options = Options { protect: filter.protect_patterns(), ..self.options.clone() };
+        let allowed_files = match self.options.files_from {
+            Some(ref spec) if spec.as_os_str() == "-" => Some(receive_files_from(&mut stream)?),
+            Some(ref spec) => Some(read_files_from(spec, self.options.from0)?),
+            None => None,
+        };
+
+        if compat_flags & CF_INC_RECURSE != 0 {
+            let mut stats = SyncStats::default();
+            if self.options.sender {
+                send_incremental(&mut stream, path, &self.options, &filter, &verbose, &mut stats, None)?;
+            } else {
+                fs::create_dir_all(path)?;
+                receive_incremental(&mut stream, path, &options, &verbose, &mut stats, None)?;
+            }
+            stream.flush()?;
+            return Ok(());
+        }
+
+        let client_files = FileList::decode(&mut stream)?;
+
+        let mut local_files = local_file_list(path, &self.options, &filter)?;
+        if let Some(ref allowed_files) = allowed_files {
+            local_files.retain(|file| files_from_allows(allowed_files, &file.path));
+        }
+        FileList::encode(&mut stream, &local_files)?;
+
+
+        let mut stats = SyncStats::default();
+        let phase = delete_phase(&self.options);
+        if self.options.sender {
+            if phase == Some(DeletePhase::Before) {
+                send_deletions(&mut stream, path, &local_files, &client_files)?;
+            }
+            send_files(&mut stream, path, &local_files, &self.options, &verbose, &mut stats, None)?;
+            if phase == Some(DeletePhase::After) {
+                send_deletions(&mut stream, path, &local_files, &client_files)?;
+            }
+        } else {
+            fs::create_dir_all(path)?;
+            if phase == Some(DeletePhase::Before) {
+                receive_deletions(&mut stream, path, &options, &verbose, &mut stats)?;
+            }
+            receive_files(&mut stream, path, &client_files, &options, &verbose, &mut stats, None)?;
+            if phase == Some(DeletePhase::After) {
+                receive_deletions(&mut stream, path, &options, &verbose, &mut stats)?;
+            }
+        }
+
+        stream.flush()?;
+        Ok(())
+    }
+}
+
+
+pub fn local_file_list(path: &Path, options: &Options, filter: &FilterEngine) -> Result<Vec<FileInfo>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let scanner = Scanner::new()
+        .recursive(options.recursive)
+        .follow_symlinks(options.copy_links)
+        .exclude_if_present(&options.exclude_if_present);
+    let base = Scanner::resolve_scan_path(path)?;
+    let mut files = scanner.scan(path)?;
+    for file in &mut files {
+        if options.hard_links {
+            file.load_link_identity();
+        } else {
+            file.file_id = None;
+        }
+    }
+    let filter = filter.for_root(&base);
+    let mut files = FileList::relative_to(&files, &base);
+    files.retain(|file| filter.should_include(&file.path));
+    if !options.recursive && !options.dirs {
+        files.retain(|file| !file.is_directory());
+    }
+    Ok(files)
+}
+
+
+pub fn send_filter_rules<S: Read + Write>(stream: &mut ProtocolStream<S>, filter: &FilterEngine) -> Result<()> {
+    for rule in filter.rules() {
+        stream.write_string(&rule)?;
+    }
+    stream.write_string("")?;
+    stream.flush()
+}
+
+
+fn receive_filter_rules<S: Read + Write>(stream: &mut ProtocolStream<S>) -> Result<FilterEngine> {
+    let mut filter = FilterEngine::new();
+    loop {
+        let rule = stream.read_string(4096)?;
+        if rule.is_empty() {
+            return Ok(filter);
+        }
+        if !filter.add_rule(&rule)? {
+            return Err(RsyncError::InvalidPattern(format!("unsupported filter rule from client: {}", rule)));
+        }
+    }
+}
+
+
+pub fn send_files_from<S: Read + Write>(stream: &mut ProtocolStream<S>, files: &[PathBuf]) -> Result<()> {
+    for file in files {
+        stream.write_string(&to_unix_separators(&file.to_string_lossy()))?;
+    }
+    stream.write_string("")?;
+    stream.flush()
+}
+
+
+fn receive_files_from<S: Read + Write>(stream: &mut ProtocolStream<S>) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    loop {
+        let file = stream.read_string(4096)?;
+        if file.is_empty() {
+            return Ok(files);
+        }
+        files.extend(files_from_entry(&file));
+    }
+}
+
+
+pub fn send_files<S: Read + Write>(
+    stream: &mut ProtocolStream<S>,
+    base: &Path,
+    files: &[FileInfo],
+    options: &Options,
+    verbose: &VerboseOutput,
+    stats: &mut SyncStats,
+    progress: Option<&ProgressDisplay>,
+) -> Result<()> {
+    let root = if base.is_file() {
+        base.parent().unwrap_or(base)
+    } else {
+        base
+    };
+
+    let mut iflags = Vec::with_capacity(files.len());
+    for _ in files {
+        iflags.push(stream.read_u16()?);
+    }
+    if let Some(progress) = progress {
+        progress.add_total(transfer_size(files, &iflags));
+    }
+
+    let wire_options = wire_options(options);
+    let algorithm = wire_options.checksum_choice.unwrap_or_default();
+
+    for (file, &iflags) in files.iter().zip(&iflags) {
+        if options.itemize_changes && iflags != 0 {
+            verbose.print_basic(ItemizeChange::from_iflags(&file.path, file.is_directory(), iflags, true).format());
+        }
+
+        if file.is_directory() || iflags & ITEM_TRANSFER == 0 {
+            continue;
+        }
+
+        if !options.itemize_changes {
+            verbose.print_basic(format!("Processing: {}", file.path.display()));
+        }
+
+        let (head, checksums) = DeltaCodec::read_sums(stream, &algorithm)?;
+        let source_path = root.join(&file.path);
+        if let Some(progress) = progress {
+            progress.start_file(&file.path.to_string_lossy(), file.size);
+        }
+
+        let checksums = if options.whole_file { Vec::new() } else { checksums };
+        match compute_file_delta(&source_path, &head, &checksums, stream.block_seed(), &wire_options) {
+            Ok((file_size, delta, delta_stats, file_checksum)) => {
+                stream.write_varlong30(file_size as i64, 3)?;
+                let compressed = stream.compression().is_some();
+                let mut delta_stats = delta_stats;
+                DeltaCodec::write_tokens(stream, &delta, |token, wire_size| {
+                    let file_bytes = match token {
+                        Token::Literal(data) => {
+                            if compressed {
+                                delta_stats.compressed_bytes += wire_size;
+                            }
+                            data.len() as u64
+                        }
+                        Token::Block(index) => head.block_size(index),
+                    };
+                    if let Some(progress) = progress {
+                        progress.advance(file_bytes, wire_size);
+                    }
+                })?;
+                stream.write_all(&file_checksum)?;
+                stream.flush()?;
+                if let Some(progress) = progress {
+                    progress.finish_file();
+                }
+
+                stats.transferred_files += 1;
+                stats.transferred_bytes += file_size;
+                stats.add_delta(delta_stats);
+                json::transferred(&file.path, file_size);
+                verbose.print_basic(format!("  Transferred {} bytes ({} literal)", file_size, delta_stats.literal_bytes));
+            }
+            Err(e) => {
+                verbose.print_warning(format!("skipping {}: {}", file.path.display(), e));
+                stats.skip(&file.path, SkipReason::Error(e.to_string()), verbose);
+                stream.write_varlong30(-1, 3)?;
+                stream.flush()?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+
+fn client_allows_inc_recurse(options: &Options) -> bool {
+    options.recursive
+        && options.inc_recursive
+        && options.files_from.is_none()
+        && !options.delete
+        && !options.hard_links
+        && client_info_has(options, 'i')
+}
+
+
+fn client_info_has(options: &Options, flag: char) -> bool {
+    options.rsh.as_deref().is_some_and(|info| info.starts_with('.') && info.contains(flag))
+}
+
+
+pub fn send_incremental<S: Read + Write>(
+    stream: &mut ProtocolStream<S>,
+    base: &Path,
+    options: &Options,
+    filter: &FilterEngine,
+    verbose: &VerboseOutput,
+    stats: &mut SyncStats,
+    progress: Option<&ProgressDisplay>,
+) -> Result<()> {
+    let mut flists = FlistManager::new();
+    if !base.exists() {
+        FileList::encode(stream, &[])?;
+        return flists.write_eof(stream);
+    }
+
+    let root = Scanner::resolve_scan_path(base)?;
+    let filter = filter.for_root(&root);
+    let scanner = Scanner::new()
+        .recursive(false)
+        .follow_symlinks(options.copy_links)
+        .exclude_if_present(&options.exclude_if_present);
+
+    let mut files = FileList::relative_to(&scanner.scan(base)?, &root);
+    files.retain(|file| filter.should_include(&file.path));
+    FileList::encode(stream, &files)?;
+    let flist = flists.add(None, files);
+    let mut pending: VecDeque<(i32, PathBuf)> = flist.directories().collect();
+    send_files(stream, base, &flist.files, options, verbose, stats, progress)?;
+
+    while let Some((dir_ndx, dir)) = pending.pop_front() {
+        let mut files = match scanner.scan(&root.join(&dir)) {
+            Ok(files) => FileList::relative_to(&files, &root),
+            Err(e) => {
+                verbose.print_warning(format!("skipping directory {}: {}", dir.display(), e));
+                Vec::new()
+            }
+        };
+        files.retain(|file| filter.should_include(&file.path));
+        let flist = flists.write_segment(stream, dir_ndx, files)?;
+        pending.extend(flist.directories());
+        send_files(stream, base, &flist.files, options, verbose, stats, progress)?;
+    }
+
+    stats.scanned_files += flists.len();
+    flists.write_eof(stream)
+}
+
+
+pub fn receive_incremental<S: Read + Write>(
+    stream: &mut ProtocolStream<S>,
+    base: &Path,
+    options: &Options,
+    verbose: &VerboseOutput,
+    stats: &mut SyncStats,
+    progress: Option<&ProgressDisplay>,
+) -> Result<()> {
+    let mut flists = FlistManager::new();
+    let flist = flists.add(None, FileList::decode(stream)?);
+    let mut dir_times = receive_segment(stream, base, &flist.files, options, verbose, stats, progress)?;
+    while let Some(flist) = flists.read_segment(stream)? {
+        verbose.print_debug(format!(
+            "Received file list segment for directory #{} ({} entries)",
+            flist.parent_ndx.unwrap_or_default(),
+            flist.files.len()
+        ));
+        dir_times.extend(receive_segment(stream, base, &flist.files, options, verbose, stats, progress)?);
+    }
+
+    stats.scanned_files += flists.len();
+    set_dir_times(dir_times)
+}
+
+
+pub fn receive_files<S: Read + Write>(
+    stream: &mut ProtocolStream<S>,
+    base: &Path,
+    files: &[FileInfo],
+    options: &Options,
+    verbose: &VerboseOutput,
+    stats: &mut SyncStats,
+    progress: Option<&ProgressDisplay>,
+) -> Result<()> {
+    let dir_times = receive_segment(stream, base, files, options, verbose, stats, progress)?;
+    set_dir_times(dir_times)
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeletePhase {
+    Before,
+    After,
+}
+
+
+pub fn delete_phase(options: &Options) -> Option<DeletePhase> {
+    if !options.delete || !(options.recursive || options.dirs) {
+        return None;
+    }
+    if options.delete_before || options.delete_during {
+        Some(DeletePhase::Before)
+    } else {
+        Some(DeletePhase::After)
+    }
+}
+
+
+pub fn send_deletions<S: Read + Write>(
+    stream: &mut ProtocolStream<S>,
+    base: &Path,
+    files: &[FileInfo],
+    receiver_files: &[FileInfo],
+) -> Result<()> {
+    let deletions = if base.is_dir() {
+        extraneous_files(files, receiver_files)
+    } else {
+        Vec::new()
+    };
+    for file in &deletions {
+        json::deleted(&file.path, if file.is_directory() { 0 } else { file.size });
+    }
+    FileList::encode(stream, &deletions)
+}
+
+
+fn extraneous_files(files: &[FileInfo], receiver_files: &[FileInfo]) -> Vec<FileInfo> {
+    let keep: HashSet<&Path> = files.iter().map(|file| file.path.as_path()).collect();
+    let mut candidates: Vec<&FileInfo> = receiver_files.iter()
+        .filter(|file| !keep.contains(file.path.as_path()))
+        .collect();
+    candidates.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let mut deletions: Vec<FileInfo> = Vec::new();
+    for file in candidates {
+        let inside_deleted_dir = deletions.last()
+            .is_some_and(|dir| dir.is_directory() && file.path.starts_with(&dir.path));
+        if !inside_deleted_dir {
+            deletions.push(file.clone());
+        }
+    }
+    deletions
+}
+
+
+pub fn receive_deletions<S: Read + Write>(
+    stream: &mut ProtocolStream<S>,
+    base: &Path,
+    options: &Options,
+    verbose: &VerboseOutput,
+    stats: &mut SyncStats,
+) -> Result<()> {
+    let protect_backups = options.backup && options.backup_dir.is_none();
+    let protection = FilterEngine::protection(options)?;
+
+    for file in FileList::decode(stream)? {
+        if !file.path.components().all(|c| matches!(c, Component::Normal(_))) {
+            return Err(RsyncError::InvalidPath(file.path));
+        }
+        if protect_backups && file.path.to_string_lossy().ends_with(&options.suffix) {
+            continue;
+        }
+        if is_partial_path(&file.path, options) {
+            continue;
+        }
+        if let Some(notice) = protection.explain_protection(&file.path) {
+            verbose.print_verbose(&notice);
+            continue;
+        }
+        let dest_path = base.join(&file.path);
+        let Ok(metadata) = fs::symlink_metadata(&dest_path) else {
+            continue;
+        };
+        if metadata.is_dir() && !options.dry_run && !remove_unprotected_dir(&dest_path, &file.path, &protection)? {
+            verbose.print_warning(format!("cannot delete non-empty directory: {}", file.path.display()));
+            continue;
+        }
+
+        if options.itemize_changes {
+            verbose.print_basic(ItemizeChange::delete_file(&file.path).format());
+        } else {
+            verbose.print_basic(format!("deleting {}", file.path.display()));
+        }
+        if !options.dry_run && !metadata.is_dir() {
+            if options.backup {
+                backup_file(&dest_path, &file.path, options)?;
+            }
+            if fs::symlink_metadata(&dest_path).is_ok() {
+                fs::remove_file(&dest_path)?;
+            }
+        }
+        let deleted_bytes = if metadata.is_dir() { 0 } else { metadata.len() };
+        stats.deleted_files += 1;
+        stats.deleted_bytes += deleted_bytes;
+        json::deleted(&file.path, deleted_bytes);
+    }
+    Ok(())
+}
+
+
+fn set_dir_times(dir_times: Vec<(PathBuf, SystemTime)>) -> Result<()> {
+    for (dest_path, mtime) in dir_times {
+        set_file_times(&dest_path, mtime, None)?;
+    }
+    Ok(())
+}
+
+
+fn receive_segment<S: Read + Write>(
+    stream: &mut ProtocolStream<S>,
+    base: &Path,
+    files: &[FileInfo],
+    options: &Options,
+    verbose: &VerboseOutput,
+    stats: &mut SyncStats,
+    progress: Option<&ProgressDisplay>,
+) -> Result<Vec<(PathBuf, SystemTime)>> {
+    let leaders = if options.hard_links { link_leaders(files) } else { vec![None; files.len()] };
+    let protection = FilterEngine::protection(options)?;
+    let is_protected = |file: &FileInfo, dest_path: &Path| {
+        !file.is_directory() && protection.is_protected(&file.path) && fs::symlink_metadata(dest_path).is_ok()
+    };
+    let mut iflags = Vec::with_capacity(files.len());
+    let mut bases = Vec::with_capacity(files.len());
+    for (file, leader) in files.iter().zip(&leaders) {
+        let dest_path = base.join(&file.path);
+        let mut basis = None;
+        let flags = match leader {
+            _ if is_protected(file, &dest_path) => 0,
+            Some(leader) if is_same_file(&base.join(&files[*leader].path), &dest_path) => 0,
+            Some(_) => ITEM_LOCAL_CHANGE | ITEM_XNAME_FOLLOWS,
+            None => {
+                let local = fs::symlink_metadata(&dest_path)
+                    .ok()
+                    .map(|metadata| FileInfo::from_metadata(dest_path.clone(), &metadata));
+                if local.is_none() && file.is_file() {
+                    basis = find_basis_dir(&dest_path, &file.path, options, |_, basis_info| {
+                        Ok(quick_check_matches(file, basis_info, options))
+                    })?;
+                }
+                match (basis.as_ref().filter(|basis| basis.identical), options.basis_dirs()) {
+                    (Some(_), Some((BasisMode::Compare, _))) => 0,
+                    (Some(_), _) => ITEM_IS_NEW | ITEM_LOCAL_CHANGE,
+                    (None, _) => compute_iflags(file, local.as_ref(), options),
+                }
+            }
+        };
+        stream.write_u16(flags)?;
+        iflags.push(flags);
+        bases.push(basis);
+    }
+    stream.flush()?;
+    if let Some(progress) = progress {
+        progress.add_total(transfer_size(files, &iflags));
+    }
+
+    let wire_options = wire_options(options);
+    let algorithm = wire_options.checksum_choice.unwrap_or_default();
+    let mut limiter = options.bwlimit.map(|kbps| BandwidthLimiter::new(kbps * 1024));
+    let mut known_dirs = HashSet::from([base.to_path_buf()]);
+    let mut dir_times = Vec::new();
+
+    for (((file, &iflags), leader), basis_dir) in files.iter().zip(&iflags).zip(&leaders).zip(bases) {
+        let dest_path = base.join(&file.path);
+
+        if options.itemize_changes && iflags != 0 {
+            verbose.print_basic(ItemizeChange::from_iflags(&file.path, file.is_directory(), iflags, false).format());
+        }
+
+        if file.is_directory() {
+            if iflags & ITEM_IS_NEW != 0 {
+                fs::create_dir_all(&dest_path)?;
+                stats.created_dirs += 1;
+                if !options.itemize_changes {
+                    verbose.print_basic(format!("created directory {}", file.path.display()));
+                }
+            } else {
+                stats.existing_dirs += 1;
+            }
+            if options.times {
+                dir_times.push((dest_path.clone(), file.mtime));
+            }
+            known_dirs.insert(dest_path);
+            continue;
+        }
+
+        if let Some(leader) = leader.map(|leader| &files[leader]) {
+            if iflags & ITEM_XNAME_FOLLOWS == 0 {
+                verbose.print_verbose(format!("{} is uptodate", file.path.display()));
+                stats.skip(&file.path, SkipReason::Uptodate, verbose);
+                continue;
+            }
+            let parent = dest_path.parent().unwrap_or(base);
+            if !known_dirs.contains(parent) {
+                fs::create_dir_all(parent)?;
+                known_dirs.insert(parent.to_path_buf());
+            }
+            match link_to(&base.join(&leader.path), &dest_path) {
+                Ok(_) => {
+                    stats.hard_links += 1;
+                    if !options.itemize_changes {
+                        verbose.print_basic(format!("{} => {}", file.path.display(), leader.path.display()));
+                    }
+                }
+                Err(e) => verbose.print_warning(format!("failed to link {} to {}: {}", file.path.display(), leader.path.display(), e)),
+            }
+            continue;
+        }
+
+        if let Some(found) = basis_dir.as_ref().filter(|found| found.identical) {
+            let applied = match options.basis_dirs() {
+                Some((mode, _)) => adopt_basis(&found.path, &dest_path, mode)?,
+                None => BasisMode::Compare,
+            };
+            match applied {
+                BasisMode::Compare => {
+                    verbose.print_verbose(format!("{} matches {}", file.path.display(), found.path.display()));
+                    json::skipped(&file.path, "compare-dest");
+                }
+                BasisMode::Copy => {
+                    if options.times {
+                        set_file_times(&dest_path, file.mtime, None)?;
+                    }
+                    stats.transferred_files += 1;
+                    json::skipped(&file.path, "copied");
+                    if !options.itemize_changes {
+                        verbose.print_basic(format!("{} copied from {}", file.path.display(), found.path.display()));
+                    }
+                }
+                BasisMode::Link => {
+                    stats.hard_links += 1;
+                    json::skipped(&file.path, "linked");
+                    if !options.itemize_changes {
+                        verbose.print_basic(format!("{} => {}", file.path.display(), found.path.display()));
+                    }
+                }
+            }
+            continue;
+        }
+
+        if iflags & ITEM_TRANSFER == 0 {
+            match protection.explain_protection(&file.path).filter(|_| is_protected(file, &dest_path)) {
+                Some(notice) => {
+                    verbose.print_verbose(&notice);
+                    stats.skip(&file.path, SkipReason::Protected, verbose);
+                }
+                None => {
+                    verbose.print_verbose(format!("{} is uptodate", file.path.display()));
+                    let newer = options.update && fs::metadata(&dest_path)
+                        .and_then(|meta| meta.modified())
+                        .is_ok_and(|mtime| mtime > file.mtime);
+                    let reason = if newer { SkipReason::Newer } else { SkipReason::Uptodate };
+                    stats.skip(&file.path, reason, verbose);
+                }
+            }
+            continue;
+        }
+
+        let existing = Some(dest_path.clone())
+            .filter(|path| iflags & ITEM_IS_NEW == 0 && path.is_file());
+        let partial = Some(partial_path(&dest_path, options))
+            .filter(|path| options.partial && !options.inplace && !options.whole_file && path.is_file());
+        if let Some(ref partial) = partial {
+            verbose.print_verbose(format!("using partial file {} as basis", partial.display()));
+        }
+        let mut basis = partial
+            .or_else(|| existing.clone())
+            .or_else(|| basis_dir.map(|found| found.path))
+            .filter(|_| !options.whole_file);
+        let (head, checksums) = match basis {
+            Some(ref basis) => {
+                let basis_size = fs::metadata(basis)?.len();
+                let block_size = Generator::calculate_block_size(basis_size);
+                if options.append && !options.append_verify {
+                    (SumHead::for_length(basis_size, block_size), Vec::new())
+                } else {
+                    let checksums = Generator::new(block_size, algorithm)
+                        .seed(stream.block_seed())
+                        .generate_checksums(basis)?;
+                    (SumHead::new(basis_size, block_size, &checksums), checksums)
+                }
+            }
+            None => (SumHead::default(), Vec::new()),
+        };
+        DeltaCodec::write_sums(stream, &head, &checksums)?;
+
+        let file_size = stream.read_varlong30(3)?;
+        if file_size < 0 {
+            verbose.print_verbose(format!("Skipped by sender: {}", file.path.display()));
+            stats.skip(&file.path, SkipReason::Error("skipped by sender".to_string()), verbose);
+            continue;
+        }
+        if let Some(progress) = progress {
+            progress.start_file(&file.path.to_string_lossy(), file_size as u64);
+        }
+
+        let parent = dest_path.parent().unwrap_or(base);
+        if !known_dirs.contains(parent) {
+            fs::create_dir_all(parent)?;
+            known_dirs.insert(parent.to_path_buf());
+        }
+
+        if let Some(existing) = existing.filter(|_| options.backup) {
+            let backup = backup_file(&existing, &file.path, options)?;
+            verbose.print_verbose(format!("backed up {} to {}", existing.display(), backup.display()));
+            if basis.as_ref() == Some(&existing) && !options.inplace {
+                basis = Some(backup);
+            }
+        }
+
+        let receiver = Receiver::new(head.block_length as usize, &wire_options).with_file_size(file_size as u64);
+        let delta_stats = store_received_file(stream, &receiver, basis.as_deref(), &dest_path, options, &mut limiter, progress)?;
+        if let Some(progress) = progress {
+            progress.finish_file();
+        }
+        if options.times {
+            set_file_times(&dest_path, file.mtime, None)?;
+        }
+
+        stats.add_delta(delta_stats);
+        stats.transferred_files += 1;
+        stats.transferred_bytes += file_size as u64;
+        json::transferred(&file.path, file_size as u64);
+        if !options.itemize_changes {
+            verbose.print_basic(format!("Received: {} ({} bytes)", file.path.display(), file_size));
+        }
+    }
+
+    Ok(dir_times)
+}
+
+
+fn store_received_file<S: Read + Write>(
+    stream: &mut ProtocolStream<S>,
+    receiver: &Receiver,
+    basis: Option<&Path>,
+    dest_path: &Path,
+    options: &Options,
+    limiter: &mut Option<BandwidthLimiter>,
+    progress: Option<&ProgressDisplay>,
+) -> Result<DeltaStats> {
+    let (target, temp) = if options.inplace {
+        (dest_path.to_path_buf(), None)
+    } else {
+        let parent = dest_path.parent().unwrap_or(Path::new("."));
+        let name = dest_path.file_name().unwrap_or_default().to_string_lossy();
+        let temp = tempfile::Builder::new()
+            .prefix(&format!(".{}.", name))
+            .tempfile_in(parent)?
+            .into_temp_path();
+        (temp.to_path_buf(), Some(temp))
+    };
+
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(!options.inplace)
+        .open(&target)?;
+    receiver.preallocate(&file, options)?;
+    let mut output = DeltaWriter::new(receiver, basis, ChecksumWriter::new(SparseWriter::new(file, options.sparse && !options.inplace)))?;
+    let received = receive_tokens(stream, &mut output, limiter, progress);
+
+    let written = output.written();
+    let (writer, checksum) = output.into_inner().finish();
+    let file = writer.finish()?;
+    if options.inplace {
+        file.set_len(written)?;
+    }
+    drop(file);
+
+    let (delta_stats, expected) = match received {
+        Ok(received) => received,
+        Err(e) => {
+            if let Some(temp) = temp.filter(|_| options.partial && written > 0) {
+                let partial = partial_path(dest_path, options);
+                if let Some(partial_dir) = partial.parent() {
+                    fs::create_dir_all(partial_dir)?;
+                }
+                temp.persist(&partial).map_err(|e| e.error)?;
+            }
+            return Err(e);
+        }
+    };
+
+    if checksum != expected {
+        return Err(RsyncError::ChecksumMismatch(dest_path.display().to_string()));
+    }
+    if let Some(temp) = temp {
+        temp.persist(dest_path).map_err(|e| e.error)?;
+        if options.partial {
+            let partial = partial_path(dest_path, options);
+            if partial.is_file() {
+                fs::remove_file(&partial)?;
+            }
+        }
+    }
+    Ok(delta_stats)
+}
+
+
+fn receive_tokens<S: Read + Write, W: Write>(
+    stream: &mut ProtocolStream<S>,
+    output: &mut DeltaWriter<'_, W>,
+    limiter: &mut Option<BandwidthLimiter>,
+    progress: Option<&ProgressDisplay>,
+) -> Result<(DeltaStats, [u8; 16])> {
+    let mut delta_stats = DeltaStats::default();
+    let compressed = stream.compression().is_some();
+    DeltaCodec::for_each_token(stream, |token, wire_size| {
+        let before = output.written();
+        match token {
+            Token::Literal(data) => {
+                if compressed {
+                    delta_stats.compressed_bytes += wire_size;
+                }
+                if let Some(limiter) = limiter.as_mut() {
+                    limiter.limit(data.len() as u64);
+                }
+                output.write_literal(data)?;
+                delta_stats.literal_bytes += output.written() - before;
+            }
+            Token::Block(index) => {
+                output.copy_block(index)?;
+                delta_stats.matched_bytes += output.written() - before;
+            }
+        }
+        if let Some(progress) = progress {
+            progress.advance(output.written() - before, wire_size);
+        }
+        Ok(())
+    })?;
+
+    let mut expected = [0u8; 16];
+    stream.read_all(&mut expected)?;
+    Ok((delta_stats, expected))
+}
+
+
+struct ChecksumWriter<W> {
+    inner: W,
+    hasher: Md5,
+}
+
+impl<W: Write> ChecksumWriter<W> {
+    fn new(inner: W) -> Self {
+        Self { inner, hasher: Md5::new() }
+    }
+
+    fn finish(self) -> (W, [u8; 16]) {
+        (self.inner, self.hasher.finalize().into())
+    }
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+
+fn transfer_size(files: &[FileInfo], iflags: &[u16]) -> u64 {
+    files.iter()
+        .zip(iflags)
+        .filter(|(file, &iflags)| !file.is_directory() && iflags & ITEM_TRANSFER != 0)
+        .map(|(file, _)| file.size)
+        .sum()
+}
+
+
+fn wire_options(options: &Options) -> Options {
+    Options {
+        compress: false,
+        partial: false,
+        inplace: false,
+        ..options.clone()
+    }
+}
+
+
+fn compute_file_delta(
+    source: &Path,
+    head: &SumHead,
+    checksums: &[BlockChecksum],
+    seed: ChecksumSeed,
+    options: &Options,
+) -> Result<(u64, Vec<DeltaInstruction>, DeltaStats, [u8; 16])> {
+    let file_size = fs::metadata(source)?.len();
+    let block_size = if head.block_length > 0 {
+        head.block_length as usize
+    } else {
+        Generator::calculate_block_size(file_size)
+    };
+
+    let mut sender = Sender::new(block_size, options).seed(seed);
+    let append_delta = if options.append && head.count > 0 {
+        sender.compute_append_delta(source, head.basis_length(), checksums, options)?
+    } else {
+        None
+    };
+    let delta = match append_delta {
+        Some(delta) => delta,
+        None => sender.compute_delta(source, checksums, options)?,
+    };
+    Ok((file_size, delta, sender.stats(), file_checksum(source)?))
+}
+
+
+fn file_checksum(path: &Path) -> Result<[u8; 16]> {
+    let mut reader = fs::File::open(path)?;
+    let mut hasher = Md5::new();
+    let mut buffer = vec![0u8; CHUNK_SIZE];
+    loop {
+        let n = reader.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+    Ok(hasher.finalize().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algorithm::checksum::compute_strong_checksum;
+    use crate::options::ChecksumAlgorithm;
+    use std::io::Cursor;
+    use std::path::PathBuf;
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+    use std::time::UNIX_EPOCH;
+    use tempfile::TempDir;
+
+    fn file_entry(path: &str, size: u64, is_dir: bool) -> FileInfo {
+        FileInfo {
+            path: path.into(),
+            size,
+            mtime: UNIX_EPOCH,
+            file_type: if is_dir { crate::filesystem::FileType::Directory } else { crate::filesystem::FileType::File },
+            is_symlink: false,
+            symlink_target: None,
+            nlink: 1,
+            file_id: None,
+            streams: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_send_and_receive_files() -> Result<()> {
+        let source = TempDir::new()?;
+        let dest = TempDir::new()?;
+        fs::create_dir(source.path().join("sub"))?;
+        fs::write(source.path().join("sub/a.txt"), b"alpha")?;
+        fs::write(source.path().join("b.txt"), b"beta")?;
+        fs::write(source.path().join("same.txt"), b"same")?;
+        fs::write(dest.path().join("same.txt"), b"same")?;
+
+        let big: Vec<u8> = (0..200_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let mut stale = big[..120_000].to_vec();
+        stale.extend_from_slice(&[0u8; 5000]);
+        fs::write(source.path().join("big.bin"), &big)?;
+        fs::write(dest.path().join("big.bin"), &stale)?;
+        let same_mtime = fs::metadata(dest.path().join("same.txt"))?.modified()?;
+
+        let mut files = vec![
+            file_entry("sub", 0, true),
+            file_entry("sub/a.txt", 5, false),
+            file_entry("missing.txt", 0, false),
+            file_entry("b.txt", 4, false),
+            file_entry("same.txt", 4, false),
+            file_entry("big.bin", big.len() as u64, false),
+        ];
+        files[4].mtime = same_mtime;
+
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let sender_stream = TcpStream::connect(listener.local_addr()?)?;
+        let (receiver_stream, _) = listener.accept()?;
+
+        let options = Options { backup: true, ..Options::default() };
+        let sender_files = files.clone();
+        let source_path = source.path().to_path_buf();
+        let sender = thread::spawn(move || -> Result<(SyncStats, u64)> {
+            let verbose = VerboseOutput::new(0, true);
+            let mut stream = ProtocolStream::new(sender_stream, 31);
+            let mut stats = SyncStats::default();
+            let progress = ProgressDisplay::network();
+            progress.hide();
+            send_files(&mut stream, &source_path, &sender_files, &Options::default(), &verbose, &mut stats, Some(&progress))?;
+            Ok((stats, progress.wire_bytes()))
+        });
+
+        let verbose = VerboseOutput::new(0, true);
+        let mut stream = ProtocolStream::new(receiver_stream, 31);
+        let mut stats = SyncStats::default();
+        let progress = ProgressDisplay::network();
+        progress.hide();
+        receive_files(&mut stream, dest.path(), &files, &options, &verbose, &mut stats, Some(&progress))?;
+        let (sent, sent_wire_bytes) = sender.join().unwrap()?;
+
+        assert_eq!(sent.transferred_files, 3);
+        assert_eq!(stats.transferred_files, 3);
+        assert_eq!(stats.transferred_bytes, 9 + big.len() as u64);
+        assert_eq!(stats.created_dirs, 1);
+        assert_eq!(stats.existing_dirs, 0);
+        assert_eq!(progress.wire_bytes(), sent_wire_bytes);
+        assert_eq!(stats.matched_bytes + stats.literal_bytes, stats.transferred_bytes);
+        assert_eq!(stats.matched_bytes, sent.matched_bytes);
+        assert!(stats.matched_bytes >= 100_000);
+        assert!(progress.wire_bytes() < stats.transferred_bytes);
+        assert_eq!(fs::read(dest.path().join("big.bin"))?, big);
+        assert_eq!(fs::read(dest.path().join("big.bin~"))?, stale);
+        assert!(!dest.path().join("same.txt~").exists());
+        assert_eq!(fs::read(dest.path().join("sub/a.txt"))?, b"alpha");
+        assert_eq!(fs::read(dest.path().join("b.txt"))?, b"beta");
+        assert!(!dest.path().join("missing.txt").exists());
+        Ok(())
+    }
+
+    fn transfer(source: &Path, dest: &Path, files: &[FileInfo], options: &Options) -> Result<(SyncStats, SyncStats)> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let sender_stream = TcpStream::connect(listener.local_addr()?)?;
+        let (receiver_stream, _) = listener.accept()?;
+
+        let sender_files = files.to_vec();
+        let source_path = source.to_path_buf();
+        let sender_options = options.clone();
+        let sender = thread::spawn(move || -> Result<SyncStats> {
+            let mut stream = ProtocolStream::new(sender_stream, 31);
+            stream.set_checksum_seed(sender_options.checksum_seed.unwrap_or(0));
+            stream.set_seed_first(true);
+            let mut stats = SyncStats::default();
+            send_files(&mut stream, &source_path, &sender_files, &sender_options, &VerboseOutput::new(0, true), &mut stats, None)?;
+            Ok(stats)
+        });
+
+        let mut stream = ProtocolStream::new(receiver_stream, 31);
+        stream.set_checksum_seed(options.checksum_seed.unwrap_or(0));
+        stream.set_seed_first(true);
+        let mut stats = SyncStats::default();
+        receive_files(&mut stream, dest, files, options, &VerboseOutput::new(0, true), &mut stats, None)?;
+        Ok((sender.join().unwrap()?, stats))
+    }
+
+    #[test]
+    fn test_hard_links() -> Result<()> {
+        let source = TempDir::new()?;
+        let dest = TempDir::new()?;
+        fs::create_dir(source.path().join("sub"))?;
+        fs::write(source.path().join("a.txt"), b"shared")?;
+        fs::hard_link(source.path().join("a.txt"), source.path().join("sub/b.txt"))?;
+
+        let options = Options { recursive: true, hard_links: true, ..Options::default() };
+        let files = local_file_list(source.path(), &options, &FilterEngine::new())?;
+        let (sent, received) = transfer(source.path(), dest.path(), &files, &options)?;
+        assert_eq!(sent.transferred_files, 1);
+        assert_eq!(received.hard_links, 1);
+        assert!(is_same_file(&dest.path().join("a.txt"), &dest.path().join("sub/b.txt")));
+
+        let (sent, received) = transfer(source.path(), dest.path(), &files, &options)?;
+        assert_eq!(sent.transferred_files, 0);
+        assert_eq!(received.hard_links, 0);
+
+        let files = local_file_list(source.path(), &Options { recursive: true, ..Options::default() }, &FilterEngine::new())?;
+        assert!(files.iter().all(|file| file.file_id.is_none()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_dirs_without_recursion() -> Result<()> {
+        let source = TempDir::new()?;
+        let dest = TempDir::new()?;
+        fs::create_dir_all(source.path().join("photos/2024"))?;
+        fs::write(source.path().join("top.txt"), b"top")?;
+        fs::write(source.path().join("photos/cat.jpg"), b"cat")?;
+
+        let files = local_file_list(source.path(), &Options::default(), &FilterEngine::new())?;
+        let paths: Vec<_> = files.iter().map(|file| file.path.to_string_lossy().to_string()).collect();
+        assert_eq!(paths, vec!["top.txt"]);
+
+        let options = Options { dirs: true, delete: true, ..Options::default() };
+        let mut files = local_file_list(source.path(), &options, &FilterEngine::new())?;
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        let paths: Vec<_> = files.iter().map(|file| file.path.to_string_lossy().to_string()).collect();
+        assert_eq!(paths, vec!["photos", "top.txt"]);
+        assert_eq!(delete_phase(&options), Some(DeletePhase::After));
+        assert_eq!(delete_phase(&Options { delete: true, ..Options::default() }), None);
+
+        let (sent, received) = transfer(source.path(), dest.path(), &files, &options)?;
+        assert_eq!(sent.transferred_files, 1);
+        assert_eq!(received.created_dirs, 1);
+        assert!(dest.path().join("photos").is_dir());
+        assert!(!dest.path().join("photos/cat.jpg").exists());
+        assert!(!dest.path().join("photos/2024").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_deletions() -> Result<()> {
+        let source = TempDir::new()?;
+        let dest = TempDir::new()?;
+        fs::create_dir_all(dest.path().join("old/nested"))?;
+        fs::write(dest.path().join("old/nested/x.txt"), b"x")?;
+        fs::write(dest.path().join("keep.txt"), b"keep")?;
+        fs::write(dest.path().join("stale.txt"), b"stale")?;
+        fs::write(dest.path().join("stale.txt~"), b"backup")?;
+
+        let files = vec![file_entry("keep.txt", 4, false)];
+        let receiver_files = vec![
+            file_entry("keep.txt", 4, false),
+            file_entry("old", 0, true),
+            file_entry("old/nested", 0, true),
+            file_entry("old/nested/x.txt", 1, false),
+            file_entry("stale.txt", 5, false),
+            file_entry("stale.txt~", 6, false),
+        ];
+        let deletions = extraneous_files(&files, &receiver_files);
+        let paths: Vec<_> = deletions.iter().map(|file| file.path.to_string_lossy().to_string()).collect();
+        assert_eq!(paths, vec!["old", "stale.txt", "stale.txt~"]);
+
+        let mut stream = ProtocolStream::new(Cursor::new(Vec::new()), 31);
+        send_deletions(&mut stream, source.path(), &files, &receiver_files)?;
+        send_deletions(&mut stream, &source.path().join("keep.txt"), &files, &receiver_files)?;
+        FileList::encode(&mut stream, &[file_entry("../outside.txt", 0, false)])?;
+
+        stream.get_mut().set_position(0);
+        let options = Options { backup: true, ..Options::default() };
+        let mut stats = SyncStats::default();
+        receive_deletions(&mut stream, dest.path(), &options, &VerboseOutput::new(0, true), &mut stats)?;
+        assert_eq!((stats.deleted_files, stats.deleted_bytes), (2, 5));
+        assert!(!dest.path().join("old").exists());
+        assert_eq!(fs::read(dest.path().join("stale.txt~"))?, b"stale");
+        assert!(dest.path().join("keep.txt").exists());
+
+        receive_deletions(&mut stream, dest.path(), &options, &VerboseOutput::new(0, true), &mut stats)?;
+        assert_eq!(stats.deleted_files, 2);
+        assert!(matches!(
+            receive_deletions(&mut stream, dest.path(), &options, &VerboseOutput::new(0, true), &mut stats),
+            Err(RsyncError::InvalidPath(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_protected_files_survive_receiver() -> Result<()> {
+        let source = TempDir::new()?;
+        let dest = TempDir::new()?;
+        fs::write(source.path().join("app.conf"), b"shared")?;
+        fs::write(dest.path().join("app.conf"), b"local")?;
+        fs::create_dir_all(dest.path().join("old"))?;
+        fs::write(dest.path().join("old/host.key"), b"key")?;
+        fs::write(dest.path().join("old/stale.txt"), b"stale")?;
+        fs::write(dest.path().join("machine.key"), b"key")?;
+
+        let options = Options { protect: vec!["app.conf".to_string(), "*.key".to_string()], ..Options::default() };
+        let files = vec![file_entry("app.conf", 6, false)];
+        let (sent, _) = transfer(source.path(), dest.path(), &files, &options)?;
+        assert_eq!(sent.literal_bytes, 0);
+        assert_eq!(fs::read(dest.path().join("app.conf"))?, b"local");
+
+        let mut stream = ProtocolStream::new(Cursor::new(Vec::new()), 31);
+        FileList::encode(&mut stream, &[file_entry("machine.key", 3, false), file_entry("old", 0, true)])?;
+        stream.get_mut().set_position(0);
+        let mut stats = SyncStats::default();
+        receive_deletions(&mut stream, dest.path(), &options, &VerboseOutput::new(0, true), &mut stats)?;
+        assert_eq!(stats.deleted_files, 0);
+        assert!(dest.path().join("machine.key").exists());
+        assert!(dest.path().join("old/host.key").exists());
+        assert!(!dest.path().join("old/stale.txt").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_whole_file_skips_delta() -> Result<()> {
+        let source = TempDir::new()?;
+        let dest = TempDir::new()?;
+        let content: Vec<u8> = (0..50_000u32).map(|i| (i % 241) as u8).collect();
+        fs::write(source.path().join("data.bin"), &content)?;
+        fs::write(dest.path().join("data.bin"), &content[..40_000])?;
+        let files = vec![file_entry("data.bin", content.len() as u64, false)];
+
+        let options = Options { whole_file: true, ..Options::default() };
+        let (sent, received) = transfer(source.path(), dest.path(), &files, &options)?;
+
+        assert_eq!(sent.literal_bytes, content.len() as u64);
+        assert_eq!(received.matched_bytes, 0);
+        assert_eq!(fs::read(dest.path().join("data.bin"))?, content);
+        Ok(())
+    }
+
+    #[test]
+    fn test_seeded_block_checksums_match() -> Result<()> {
+        let source = TempDir::new()?;
+        let dest = TempDir::new()?;
+        let content: Vec<u8> = (0..50_000u32).map(|i| (i % 241) as u8).collect();
+        fs::write(source.path().join("data.bin"), &content)?;
+        fs::write(dest.path().join("data.bin"), &content[..40_000])?;
+        let files = vec![file_entry("data.bin", content.len() as u64, false)];
+
+        let options = Options { checksum_seed: Some(32761), ignore_times: true, ..Options::default() };
+        let (sent, _) = transfer(source.path(), dest.path(), &files, &options)?;
+
+        assert!(sent.matched_bytes > 0);
+        assert!(sent.literal_bytes < content.len() as u64);
+        assert_eq!(fs::read(dest.path().join("data.bin"))?, content);
+        Ok(())
+    }
+
+    #[test]
+    fn test_append_sends_only_the_tail() -> Result<()> {
+        let source = TempDir::new()?;
+        let dest = TempDir::new()?;
+        let content: Vec<u8> = (0..50_000u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8).collect();
+        fs::write(source.path().join("grown.log"), &content)?;
+        fs::write(source.path().join("shrunk.log"), &content[..100])?;
+        let files = vec![
+            file_entry("grown.log", content.len() as u64, false),
+            file_entry("shrunk.log", 100, false),
+        ];
+
+        for options in [
+            Options { append: true, ..Options::default() },
+            Options { append: true, append_verify: true, ..Options::default() },
+        ] {
+            fs::write(dest.path().join("grown.log"), &content[..30_000])?;
+            fs::write(dest.path().join("shrunk.log"), &content[..200])?;
+            let (sent, received) = transfer(source.path(), dest.path(), &files, &options)?;
+
+            assert_eq!(sent.transferred_files, 1);
+            assert_eq!(sent.literal_bytes, 20_000);
+            assert_eq!(received.matched_bytes, 30_000);
+            assert_eq!(fs::read(dest.path().join("grown.log"))?, content);
+            assert_eq!(fs::read(dest.path().join("shrunk.log"))?, &content[..200]);
+        }
+
+        let mut stale = content[..30_000].to_vec();
+        stale[10] ^= 0xff;
+        fs::write(dest.path().join("grown.log"), &stale)?;
+        let options = Options { append: true, append_verify: true, ..Options::default() };
+        let (sent, _) = transfer(source.path(), dest.path(), &files[..1], &options)?;
+        assert!(sent.literal_bytes > 20_000);
+        assert_eq!(fs::read(dest.path().join("grown.log"))?, content);
+        Ok(())
+    }
+
+    #[test]
+    fn test_store_received_file_is_atomic() -> Result<()> {
+        let dest = TempDir::new()?;
+        let dest_path = dest.path().join("data.txt");
+        let old = b"old contents, longer than the new ones";
+
+        let content = b"new contents".to_vec();
+        let checksum = compute_strong_checksum(&content, &ChecksumAlgorithm::Md5);
+        let delta = vec![DeltaInstruction::literal_data(content.clone())];
+
+        for options in [
+            Options::default(),
+            Options { partial: true, ..Options::default() },
+            Options { inplace: true, ..Options::default() },
+        ] {
+            let receiver = Receiver::new(16, &options);
+            let mut buffer = Cursor::new(Vec::new());
+            let mut stream = ProtocolStream::new(&mut buffer, 31);
+            DeltaCodec::write_tokens(&mut stream, &delta, |_, _| {})?;
+            stream.write_all(&[0u8; 16])?;
+            DeltaCodec::write_tokens(&mut stream, &delta, |_, _| {})?;
+            stream.write_all(checksum.as_bytes())?;
+            stream.get_mut().set_position(0);
+
+            fs::write(&dest_path, old)?;
+            let err = store_received_file(&mut stream, &receiver, None, &dest_path, &options, &mut None, None);
+            assert!(matches!(err, Err(RsyncError::ChecksumMismatch(_))));
+            if !options.inplace {
+                assert_eq!(fs::read(&dest_path)?, old);
+            }
+
+            store_received_file(&mut stream, &receiver, None, &dest_path, &options, &mut None, None)?;
+            assert_eq!(fs::read(&dest_path)?, content);
+            assert_eq!(fs::read_dir(dest.path())?.count(), 1);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_interrupted_receive_keeps_partial() -> Result<()> {
+        let dest = TempDir::new()?;
+        let dest_path = dest.path().join("data.bin");
+        let options = Options { partial: true, partial_dir: Some(PathBuf::from(".partial")), ..Options::default() };
+        let receiver = Receiver::new(16, &options);
+        let content: Vec<u8> = (0..40u8).collect();
+        let partial = partial_path(&dest_path, &options);
+        assert_eq!(partial, dest.path().join(".partial/data.bin"));
+
+        let mut buffer = Cursor::new(Vec::new());
+        let mut stream = ProtocolStream::new(&mut buffer, 31);
+        stream.write_i32(32)?;
+        stream.write_all(&content[..32])?;
+        stream.get_mut().set_position(0);
+
+        assert!(store_received_file(&mut stream, &receiver, None, &dest_path, &options, &mut None, None).is_err());
+        assert_eq!(fs::read(&partial)?, &content[..32]);
+        assert!(!dest_path.exists());
+
+        let checksum = compute_strong_checksum(&content, &ChecksumAlgorithm::Md5);
+        let delta = vec![
+            DeltaInstruction::matched_block(0),
+            DeltaInstruction::matched_block(1),
+            DeltaInstruction::literal_data(content[32..].to_vec()),
+        ];
+        let mut buffer = Cursor::new(Vec::new());
+        let mut stream = ProtocolStream::new(&mut buffer, 31);
+        DeltaCodec::write_tokens(&mut stream, &delta, |_, _| {})?;
+        stream.write_all(checksum.as_bytes())?;
+        stream.get_mut().set_position(0);
+
+        let stats = store_received_file(&mut stream, &receiver, Some(&partial), &dest_path, &options, &mut None, None)?;
+        assert_eq!(stats.matched_bytes, 32);
+        assert_eq!(fs::read(&dest_path)?, content);
+        assert!(!partial.exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,158 @@
+use crate::error::{Result, RsyncError};
+use socket2::SockRef;
+use std::io;
+
+
+pub const DEFAULT_NET_BUFFER_SIZE: usize = 1024 * 1024;
+
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SocketOptions {
+    pub nodelay: Option<bool>,
+    pub keepalive: Option<bool>,
+    pub send_buffer: Option<usize>,
+    pub recv_buffer: Option<usize>,
+}
+
+impl SocketOptions {
+    pub fn parse(spec: &str) -> Result<Self> {
+        let mut options = SocketOptions::default();
+
+        for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (name, value) = match entry.split_once('=') {
+                Some((name, value)) => (name.trim(), Some(value.trim())),
+                None => (entry, None),
+            };
+
+            match name.to_uppercase().as_str() {
+                "TCP_NODELAY" => options.nodelay = Some(parse_flag(name, value)?),
+                "SO_KEEPALIVE" => options.keepalive = Some(parse_flag(name, value)?),
+                "SO_SNDBUF" => options.send_buffer = Some(parse_size(name, value)?),
+                "SO_RCVBUF" => options.recv_buffer = Some(parse_size(name, value)?),
+                _ => {
+                    return Err(RsyncError::InvalidOption(format!(
+                        "Unknown socket option: {}. Valid options: TCP_NODELAY, SO_KEEPALIVE, SO_SNDBUF, SO_RCVBUF",
+                        name
+                    )))
+                }
+            }
+        }
+
+        Ok(options)
+    }
+
+
+    pub fn tuned(buffer_size: usize) -> Self {
+        let buffer = if buffer_size > 0 { Some(buffer_size) } else { None };
+        SocketOptions {
+            nodelay: Some(true),
+            keepalive: None,
+            send_buffer: buffer,
+            recv_buffer: buffer,
+        }
+    }
+
+
+    pub fn or(self, fallback: SocketOptions) -> Self {
+        SocketOptions {
+            nodelay: self.nodelay.or(fallback.nodelay),
+            keepalive: self.keepalive.or(fallback.keepalive),
+            send_buffer: self.send_buffer.or(fallback.send_buffer),
+            recv_buffer: self.recv_buffer.or(fallback.recv_buffer),
+        }
+    }
+
+
+    pub fn apply<'s, S>(&self, socket: &'s S) -> io::Result<()>
+    where
+        SockRef<'s>: From<&'s S>,
+    {
+        let socket = SockRef::from(socket);
+        if let Some(nodelay) = self.nodelay {
+            socket.set_tcp_nodelay(nodelay)?;
+        }
+        if let Some(keepalive) = self.keepalive {
+            socket.set_keepalive(keepalive)?;
+        }
+        if let Some(size) = self.send_buffer {
+            socket.set_send_buffer_size(size)?;
+        }
+        if let Some(size) = self.recv_buffer {
+            socket.set_recv_buffer_size(size)?;
+        }
+        Ok(())
+    }
+}
+
+
+fn parse_flag(name: &str, value: Option<&str>) -> Result<bool> {
+    match value {
+        None | Some("1") => Ok(true),
+        Some("0") => Ok(false),
+        Some(other) => Err(RsyncError::InvalidOption(format!(
+            "Invalid value for socket option {}: {} (expected 0 or 1)",
+            name, other
+        ))),
+    }
+}
+
+
+fn parse_size(name: &str, value: Option<&str>) -> Result<usize> {
+    value
+        .and_then(|v| v.parse().ok())
+        .ok_or_else(|| RsyncError::InvalidOption(format!(
+            "Socket option {} needs a size in bytes, e.g. {}=262144",
+            name, name
+        )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{TcpListener, TcpStream};
+
+    #[test]
+    fn test_parse() -> Result<()> {
+        let options = SocketOptions::parse("TCP_NODELAY, so_keepalive=0,SO_SNDBUF=262144,SO_RCVBUF=131072")?;
+        assert_eq!(options, SocketOptions {
+            nodelay: Some(true),
+            keepalive: Some(false),
+            send_buffer: Some(262144),
+            recv_buffer: Some(131072),
+        });
+
+        assert_eq!(SocketOptions::parse("")?, SocketOptions::default());
+        assert!(SocketOptions::parse("SO_SNDBUF").is_err());
+        assert!(SocketOptions::parse("TCP_NODELAY=yes").is_err());
+        assert!(SocketOptions::parse("IPTOS_LOWDELAY").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_tuned_defaults() -> Result<()> {
+        let tuned = SocketOptions::default().or(SocketOptions::tuned(DEFAULT_NET_BUFFER_SIZE));
+        assert_eq!(tuned.nodelay, Some(true));
+        assert_eq!(tuned.send_buffer, Some(DEFAULT_NET_BUFFER_SIZE));
+        assert_eq!(tuned.recv_buffer, Some(DEFAULT_NET_BUFFER_SIZE));
+
+        let explicit = SocketOptions::parse("TCP_NODELAY=0,SO_RCVBUF=4096")?.or(SocketOptions::tuned(0));
+        assert_eq!(explicit, SocketOptions {
+            nodelay: Some(false),
+            keepalive: None,
+            send_buffer: None,
+            recv_buffer: Some(4096),
+        });
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let stream = TcpStream::connect(listener.local_addr()?)?;
+
+        SocketOptions::parse("TCP_NODELAY,SO_KEEPALIVE,SO_SNDBUF=65536")?.apply(&stream)?;
+        assert!(stream.nodelay()?);
+        assert!(SockRef::from(&stream).keepalive()?);
+        Ok(())
+    }
+}
@@ -1,9 +1,16 @@
-use std::net::TcpStream;
-use std::path::PathBuf;
-use ssh2::{Channel, Session};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::fs;
+use std::path::{Path, PathBuf};
+use ssh2::{Channel, MethodType, Session};
+use socket2::{SockRef, TcpKeepalive};
 use crate::error::{RsyncError, Result};
-use std::io::Write;
+use super::SocketOptions;
+use super::hostkeys::HostKeyCheck;
+use crate::algorithm::TokenBucket;
+use std::io::{self, Read, Write};
 use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
 use tempfile::NamedTempFile;
 
 
@@ -11,11 +18,16 @@ pub enum AuthMethod {
 
     PublicKey(PathBuf),
 
+    Certificate { key: PathBuf, certificate: PathBuf },
+
     Password(String),
 
     Agent,
 }
 
+pub const DEFAULT_PASSWORD_PROMPTS: u32 = 3;
+
+
 pub fn prompt_for_password(username: &str, host: &str) -> Result<String> {
     print!("{}@{}'s password: ", username, host);
     std::io::stdout().flush().map_err(|e| RsyncError::Io(e))?;
@@ -30,6 +42,55 @@ pub fn prompt_for_password(username: &str, host: &str) -> Result<String> {
     Ok(password)
 }
 
+const CERTIFICATE_SUFFIX: &str = "-cert-v01@openssh.com";
+
+
+pub fn certificate_for(private_key: &Path) -> Option<PathBuf> {
+    let mut name = private_key.as_os_str().to_owned();
+    name.push("-cert.pub");
+    Some(PathBuf::from(name)).filter(|path| path.is_file())
+}
+
+
+fn check_certificate(path: &Path) -> Result<()> {
+    let contents = fs::read_to_string(path).map_err(|e| RsyncError::Auth(format!(
+        "Failed to read certificate {}: {}",
+        path.display(),
+        e
+    )))?;
+    match contents.split_whitespace().next() {
+        Some(key_type) if key_type.ends_with(CERTIFICATE_SUFFIX) => Ok(()),
+        _ => Err(RsyncError::Auth(format!("{} is not an OpenSSH certificate", path.display()))),
+    }
+}
+
+
+fn is_certificate_blob(blob: &[u8]) -> bool {
+    let key_type = blob.get(..4)
+        .map(|len| u32::from_be_bytes([len[0], len[1], len[2], len[3]]) as usize)
+        .and_then(|len| blob.get(4..4 + len));
+    key_type.is_some_and(|key_type| key_type.ends_with(CERTIFICATE_SUFFIX.as_bytes()))
+}
+
+
+fn check_private_key(private_key_path: &Path) -> Result<()> {
+    if !private_key_path.exists() {
+        return Err(RsyncError::Auth(format!(
+            "Private key file does not exist: {}",
+            private_key_path.display()
+        )));
+    }
+
+    if !private_key_path.is_file() {
+        return Err(RsyncError::Auth(format!(
+            "Private key path is not a file: {}",
+            private_key_path.display()
+        )));
+    }
+    Ok(())
+}
+
+
 fn generate_temp_public_key(private_key_path: &PathBuf) -> Result<NamedTempFile> {
     let output = Command::new("ssh-keygen")
         .arg("-y")
@@ -63,38 +124,328 @@ fn generate_temp_public_key(private_key_path: &PathBuf) -> Result<NamedTempFile>
 }
 
 
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SshAlgorithms {
+    pub ciphers: Option<String>,
+    pub kex: Option<String>,
+    pub host_keys: Option<String>,
+    pub macs: Option<String>,
+}
+
+impl SshAlgorithms {
+    pub fn or(self, fallback: SshAlgorithms) -> Self {
+        SshAlgorithms {
+            ciphers: self.ciphers.or(fallback.ciphers),
+            kex: self.kex.or(fallback.kex),
+            host_keys: self.host_keys.or(fallback.host_keys),
+            macs: self.macs.or(fallback.macs),
+        }
+    }
+
+
+    fn apply(&self, session: &Session) -> Result<()> {
+        let preferences = [
+            (MethodType::CryptCs, &self.ciphers),
+            (MethodType::CryptSc, &self.ciphers),
+            (MethodType::Kex, &self.kex),
+            (MethodType::HostKey, &self.host_keys),
+            (MethodType::MacCs, &self.macs),
+            (MethodType::MacSc, &self.macs),
+        ];
+
+        for (method, spec) in preferences {
+            let Some(spec) = spec else { continue };
+            let supported = session.supported_algs(method)?;
+            let prefs = resolve_preference(spec, &supported);
+            if prefs.is_empty() {
+                return Err(RsyncError::InvalidOption(format!(
+                    "None of the SSH algorithms in '{}' are supported (available: {})",
+                    spec,
+                    supported.join(",")
+                )));
+            }
+            session.method_pref(method, &prefs)?;
+        }
+        Ok(())
+    }
+}
+
+
+fn resolve_preference(spec: &str, supported: &[&str]) -> String {
+    let (mode, list) = match spec.chars().next() {
+        Some(c @ ('+' | '-' | '^')) => (Some(c), &spec[1..]),
+        _ => (None, spec),
+    };
+    let listed: Vec<&str> = list.split(',').map(str::trim).filter(|a| supported.contains(a)).collect();
+    let rest = supported.iter().copied().filter(|a| !listed.contains(a));
+
+    let algorithms: Vec<&str> = match mode {
+        Some('+') => supported.to_vec(),
+        Some('-') => rest.collect(),
+        Some('^') => listed.iter().copied().chain(rest).collect(),
+        _ => listed,
+    };
+    algorithms.join(",")
+}
+
+
+pub const DEFAULT_KEEPALIVE_INTERVAL: u32 = 15;
+
+
+pub const DEFAULT_KEEPALIVE_COUNT_MAX: u32 = 3;
+
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SshKeepalive {
+    pub interval: Option<u32>,
+    pub count_max: Option<u32>,
+}
+
+impl SshKeepalive {
+    pub fn interval(&self) -> u32 {
+        self.interval.unwrap_or(DEFAULT_KEEPALIVE_INTERVAL)
+    }
+
+
+    pub fn count_max(&self) -> u32 {
+        self.count_max.unwrap_or(DEFAULT_KEEPALIVE_COUNT_MAX).max(1)
+    }
+
+
+    fn apply(&self, session: &Session, tcp: &TcpStream) -> io::Result<()> {
+        let interval = self.interval();
+        if interval == 0 {
+            return Ok(());
+        }
+
+        session.set_keepalive(true, interval);
+        let period = Duration::from_secs(interval as u64);
+        let params = TcpKeepalive::new().with_time(period);
+        #[cfg(any(target_os = "linux", target_os = "macos", windows))]
+        let params = params.with_interval(period).with_retries(self.count_max());
+        SockRef::from(tcp).set_tcp_keepalive(&params)
+    }
+}
+
+
+const WRITE_BUFFER_SIZE: usize = 64 * 1024;
+
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FlowStats {
+    pub window_stalls: u64,
+    pub window_wait: Duration,
+    pub throttle_wait: Duration,
+}
+
+
+#[derive(Default)]
+struct FlowControl {
+    bucket: Option<TokenBucket>,
+    stalled_since: Option<Instant>,
+    stats: FlowStats,
+}
+
+impl FlowControl {
+    fn chunk_len(&mut self, window: usize, len: usize) -> usize {
+        if window == 0 {
+            self.stalled_since.get_or_insert_with(Instant::now);
+            len
+        } else {
+            len.min(window)
+        }
+    }
+
+
+    fn wrote(&mut self, bytes: usize) {
+        if let Some(since) = self.stalled_since.take() {
+            self.stats.window_stalls += 1;
+            self.stats.window_wait += since.elapsed();
+        }
+        if let Some(ref bucket) = self.bucket {
+            let delay = bucket.consume(bytes as u64);
+            if !delay.is_zero() {
+                thread::sleep(delay);
+                self.stats.throttle_wait += delay;
+            }
+        }
+    }
+}
+
+
+pub struct SshChannel {
+    channel: Channel,
+    session: Session,
+    io_timeout: Option<Duration>,
+    last_activity: Instant,
+    pending: Vec<u8>,
+    flow: FlowControl,
+}
+
+impl SshChannel {
+    pub fn set_bwlimit(&mut self, bytes_per_sec: Option<u64>) {
+        self.flow.bucket = bytes_per_sec.map(TokenBucket::new);
+    }
+
+
+    pub fn flow_stats(&self) -> FlowStats {
+        self.flow.stats
+    }
+
+
+    pub fn read_stderr(&mut self) -> io::Result<Vec<u8>> {
+        self.flush_pending()?;
+        let mut output = Vec::new();
+        let mut buf = [0u8; 4096];
+        loop {
+            match self.channel.stderr().read(&mut buf) {
+                Ok(0) => return Ok(output),
+                Ok(n) => {
+                    output.extend_from_slice(&buf[..n]);
+                    self.last_activity = Instant::now();
+                }
+                Err(e) => self.retry_after(e, true)?,
+            }
+        }
+    }
+
+
+    pub fn finish(&mut self) -> Result<()> {
+        self.flush_pending()?;
+        self.channel.close()?;
+        loop {
+            match self.channel.wait_close() {
+                Ok(()) => return Ok(()),
+                Err(e) => self.retry_after(e.into(), true)?,
+            }
+        }
+    }
+
+
+    fn write_channel(&mut self, mut data: &[u8]) -> io::Result<()> {
+        while !data.is_empty() {
+            let window = self.channel.write_window().remaining as usize;
+            let len = self.flow.chunk_len(window, data.len());
+            match self.channel.write(&data[..len]) {
+                Ok(n) => {
+                    self.last_activity = Instant::now();
+                    self.flow.wrote(n);
+                    data = &data[n..];
+                }
+                Err(e) => self.retry_after(e, false)?,
+            }
+        }
+        Ok(())
+    }
+
+
+    fn flush_pending(&mut self) -> io::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let pending = std::mem::take(&mut self.pending);
+        let result = self.write_channel(&pending);
+        self.pending = pending;
+        self.pending.clear();
+        result
+    }
+
+
+    fn retry_after(&mut self, error: io::Error, ping: bool) -> io::Result<()> {
+        if error.kind() != io::ErrorKind::TimedOut {
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionAborted,
+                format!("SSH connection lost: {}", error),
+            ));
+        }
+
+        if let Some(limit) = self.io_timeout {
+            if self.last_activity.elapsed() >= limit {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("No data received from the remote side for {} seconds", limit.as_secs()),
+                ));
+            }
+        }
+        if ping {
+            self.session.keepalive_send().map_err(|e| io::Error::new(
+                io::ErrorKind::ConnectionAborted,
+                format!("SSH connection lost while sending keepalive: {}", e),
+            ))?;
+        }
+        Ok(())
+    }
+}
+
+impl Read for SshChannel {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.flush_pending()?;
+        loop {
+            match self.channel.read(buf) {
+                Ok(n) => {
+                    self.last_activity = Instant::now();
+                    return Ok(n);
+                }
+                Err(e) => self.retry_after(e, true)?,
+            }
+        }
+    }
+}
+
+impl Write for SshChannel {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.pending.len() + buf.len() > WRITE_BUFFER_SIZE {
+            self.flush_pending()?;
+        }
+        if buf.len() >= WRITE_BUFFER_SIZE {
+            self.write_channel(buf)?;
+        } else {
+            self.pending.extend_from_slice(buf);
+        }
+        Ok(buf.len())
+    }
+
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.flush_pending()
+    }
+}
+
+
 pub struct SshTransport {
     session: Session,
+    keepalive: SshKeepalive,
 }
 
 impl SshTransport {
 
-    pub fn connect(
-        host: &str,
-        port: u16,
+    pub fn dial(host: &str, port: u16, socket_options: Option<&SocketOptions>) -> Result<TcpStream> {
+        let tcp = TcpStream::connect((host, port)).map_err(|e| RsyncError::Network(e.to_string()))?;
+        if let Some(options) = socket_options {
+            options.apply(&tcp).map_err(|e| RsyncError::Network(format!("Failed to apply socket options: {}", e)))?;
+        }
+        Ok(tcp)
+    }
+
+
+    pub fn handshake(
+        tcp: TcpStream,
         username: &str,
         auth_method: AuthMethod,
+        host_key: &HostKeyCheck,
+        algorithms: &SshAlgorithms,
+        keepalive: SshKeepalive,
     ) -> Result<Self> {
-        let tcp = TcpStream::connect((host, port)).map_err(|e| RsyncError::Network(e.to_string()))?;
         let mut session = Session::new()?;
+        algorithms.apply(&session)?;
+        keepalive.apply(&session, &tcp).map_err(|e| RsyncError::Network(format!("Failed to enable keepalive: {}", e)))?;
         session.set_tcp_stream(tcp);
         session.handshake().map_err(|e| RsyncError::Network(e.to_string()))?;
+        host_key.verify(&session)?;
 
         match auth_method {
             AuthMethod::PublicKey(private_key_path) => {
-                if !private_key_path.exists() {
-                    return Err(RsyncError::Auth(format!(
-                        "Private key file does not exist: {}",
-                        private_key_path.display()
-                    )));
-                }
-
-                if !private_key_path.is_file() {
-                    return Err(RsyncError::Auth(format!(
-                        "Private key path is not a file: {}",
-                        private_key_path.display()
-                    )));
-                }
+                check_private_key(&private_key_path)?;
 
                 let temp_public_key = generate_temp_public_key(&private_key_path)?;
                 let public_key_path = temp_public_key.path();
@@ -120,6 +471,16 @@ impl SshTransport {
                     }
                 })?;
             }
+            AuthMethod::Certificate { key, certificate } => {
+                check_private_key(&key)?;
+                check_certificate(&certificate)?;
+                session.userauth_pubkey_file(username, Some(&certificate), &key, None)
+                    .map_err(|e| RsyncError::Auth(format!(
+                        "Certificate {} was not accepted: {}",
+                        certificate.display(),
+                        e
+                    )))?;
+            }
             AuthMethod::Password(password) => {
                 session.userauth_password(username, &password)
                     .map_err(|e| RsyncError::Auth(e.to_string()))?;
@@ -141,15 +502,30 @@ impl SshTransport {
                     RsyncError::Auth(format!("Failed to list SSH agent identities: {}", e))
                 })?;
 
-                let identities = agent.identities().map_err(|e| {
+                let mut identities = agent.identities().map_err(|e| {
                     RsyncError::Auth(format!("Failed to get SSH agent identities: {}", e))
                 })?;
-                let identity = identities.get(0).ok_or_else(|| {
-                    RsyncError::Auth("No SSH keys found in agent. Add a key with 'ssh-add' or use public key authentication.".to_string())
-                })?;
+                if identities.is_empty() {
+                    return Err(RsyncError::Auth(
+                        "No SSH keys found in agent. Add a key with 'ssh-add' or use public key authentication.".to_string()
+                    ));
+                }
+                identities.sort_by_key(|identity| !is_certificate_blob(identity.blob()));
 
-                agent.userauth(username, identity)
-                     .map_err(|e| RsyncError::Auth(format!("SSH agent authentication failed: {}", e)))?;
+                let mut last_error = None;
+                for identity in &identities {
+                    match agent.userauth(username, identity) {
+                        Ok(()) => break,
+                        Err(e) => last_error = Some(e),
+                    }
+                }
+                if let Some(e) = last_error.filter(|_| !session.authenticated()) {
+                    return Err(RsyncError::Auth(format!(
+                        "SSH agent authentication failed with all {} identities: {}",
+                        identities.len(),
+                        e
+                    )));
+                }
             }
         }
 
@@ -157,13 +533,218 @@ impl SshTransport {
             return Err(RsyncError::Auth("SSH authentication failed".to_string()));
         }
 
-        Ok(SshTransport { session })
+        Ok(SshTransport { session, keepalive })
     }
 
 
-    pub fn execute(&mut self, command: &str) -> Result<Channel> {
+    pub fn tunnel(&self, host: &str, port: u16) -> Result<TcpStream> {
+        let listener = TcpListener::bind(("127.0.0.1", 0))?;
+        let local = TcpStream::connect(listener.local_addr()?)?;
+        let peer = loop {
+            let (peer, addr) = listener.accept()?;
+            if addr == local.local_addr()? {
+                break peer;
+            }
+        };
+        peer.set_nonblocking(true)?;
+
+        self.session.set_blocking(false);
+        let channel = loop {
+            match self.session.channel_direct_tcpip(host, port, None) {
+                Ok(channel) => break channel,
+                Err(e) => {
+                    let e = io::Error::from(e);
+                    if e.kind() != io::ErrorKind::WouldBlock {
+                        return Err(RsyncError::Network(format!(
+                            "Jump host could not open a connection to {}:{}: {}",
+                            host, port, e
+                        )));
+                    }
+                    thread::sleep(Duration::from_millis(5));
+                }
+            }
+        };
+
+        let session = self.session.clone();
+        thread::spawn(move || {
+            let _session = session;
+            forward(peer, channel);
+        });
+        Ok(local)
+    }
+
+
+    pub fn read_file(&self, path: &str) -> Result<Vec<u8>> {
+        let failed = |e: &dyn std::fmt::Display| RsyncError::RemoteExec(format!("Failed to read remote file {}: {}", path, e));
+        let mut file = self.session.sftp().and_then(|sftp| sftp.open(Path::new(path))).map_err(|e| failed(&e))?;
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents).map_err(|e| failed(&e))?;
+        Ok(contents)
+    }
+
+
+    pub fn execute(&mut self, command: &str, io_timeout: Option<Duration>) -> Result<SshChannel> {
         let mut channel = self.session.channel_session().map_err(|e| RsyncError::RemoteExec(e.to_string()))?;
         channel.exec(command).map_err(|e| RsyncError::RemoteExec(e.to_string()))?;
-        Ok(channel)
+
+        let wait = [Some(Duration::from_secs(self.keepalive.interval() as u64)), io_timeout]
+            .into_iter()
+            .flatten()
+            .filter(|d| !d.is_zero())
+            .min();
+        self.session.set_timeout(wait.map_or(0, |d| d.as_millis().min(u32::MAX as u128) as u32));
+
+        Ok(SshChannel {
+            channel,
+            session: self.session.clone(),
+            io_timeout: io_timeout.filter(|d| !d.is_zero()),
+            last_activity: Instant::now(),
+            pending: Vec::with_capacity(WRITE_BUFFER_SIZE),
+            flow: FlowControl::default(),
+        })
+    }
+}
+
+fn forward(mut local: TcpStream, mut channel: Channel) {
+    let mut buf = vec![0u8; 32 * 1024];
+    let mut upstream = Vec::new();
+    let mut downstream = Vec::new();
+    let mut local_open = true;
+
+    loop {
+        let mut busy = false;
+
+        if local_open && upstream.is_empty() {
+            match local.read(&mut buf) {
+                Ok(0) => {
+                    local_open = false;
+                    let _ = channel.send_eof();
+                }
+                Ok(n) => upstream.extend_from_slice(&buf[..n]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(_) => break,
+            }
+        }
+        if !upstream.is_empty() {
+            match channel.write(&upstream) {
+                Ok(n) => {
+                    upstream.drain(..n);
+                    busy = true;
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(_) => break,
+            }
+        }
+
+        if downstream.is_empty() {
+            match channel.read(&mut buf) {
+                Ok(0) if channel.eof() => break,
+                Ok(n) => downstream.extend_from_slice(&buf[..n]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(_) => break,
+            }
+        }
+        if !downstream.is_empty() {
+            match local.write(&downstream) {
+                Ok(n) => {
+                    downstream.drain(..n);
+                    busy = true;
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(_) => break,
+            }
+        }
+
+        if !busy {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    let _ = local.shutdown(Shutdown::Both);
+    let _ = channel.close();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SUPPORTED: &[&str] = &["aes256-ctr", "aes128-ctr", "aes256-cbc", "3des-cbc"];
+
+    #[test]
+    fn test_resolve_preference() {
+        assert_eq!(resolve_preference("aes128-ctr, aes256-ctr", SUPPORTED), "aes128-ctr,aes256-ctr");
+        assert_eq!(resolve_preference("-aes256-cbc,3des-cbc", SUPPORTED), "aes256-ctr,aes128-ctr");
+        assert_eq!(resolve_preference("^3des-cbc", SUPPORTED), "3des-cbc,aes256-ctr,aes128-ctr,aes256-cbc");
+        assert_eq!(resolve_preference("+3des-cbc,blowfish-cbc", SUPPORTED), "aes256-ctr,aes128-ctr,aes256-cbc,3des-cbc");
+        assert_eq!(resolve_preference("blowfish-cbc,aes128-ctr", SUPPORTED), "aes128-ctr");
+        assert_eq!(resolve_preference("blowfish-cbc", SUPPORTED), "");
+    }
+
+    #[test]
+    fn test_certificates() -> Result<()> {
+        let dir = tempfile::TempDir::new()?;
+        let key = dir.path().join("id_ed25519");
+        fs::write(&key, "")?;
+        assert_eq!(certificate_for(&key), None);
+
+        let certificate = dir.path().join("id_ed25519-cert.pub");
+        fs::write(&certificate, "ssh-ed25519-cert-v01@openssh.com AAAAIHNzaC1lZDI1NTE5 user@host\n")?;
+        assert_eq!(certificate_for(&key), Some(certificate.clone()));
+        check_certificate(&certificate)?;
+        assert!(matches!(check_certificate(&key), Err(RsyncError::Auth(_))));
+
+        let blob = |key_type: &str| [&(key_type.len() as u32).to_be_bytes()[..], key_type.as_bytes(), &[0u8; 8]].concat();
+        assert!(is_certificate_blob(&blob("ssh-rsa-cert-v01@openssh.com")));
+        assert!(!is_certificate_blob(&blob("ssh-ed25519")));
+        assert!(!is_certificate_blob(&[0, 0, 1]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_flow_control() {
+        let mut flow = FlowControl::default();
+        assert_eq!(flow.chunk_len(1000, 300), 300);
+        assert_eq!(flow.chunk_len(1000, 5000), 1000);
+        flow.wrote(1000);
+        assert_eq!(flow.stats, FlowStats::default());
+
+        assert_eq!(flow.chunk_len(0, 5000), 5000);
+        assert_eq!(flow.chunk_len(0, 5000), 5000);
+        flow.wrote(2000);
+        assert_eq!(flow.stats.window_stalls, 1);
+
+        flow.bucket = Some(TokenBucket::new(1_000_000));
+        flow.wrote(150_000);
+        assert!(flow.stats.throttle_wait >= Duration::from_millis(40));
+    }
+
+    #[test]
+    fn test_apply_rejects_unknown_algorithms() -> Result<()> {
+        let session = Session::new()?;
+        let fips = SshAlgorithms { ciphers: Some("aes256-ctr,aes128-ctr".to_string()), ..SshAlgorithms::default() };
+        fips.apply(&session)?;
+
+        let bogus = SshAlgorithms { kex: Some("no-such-kex".to_string()), ..SshAlgorithms::default() };
+        assert!(matches!(bogus.apply(&session), Err(RsyncError::InvalidOption(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_keepalive_enables_tcp_probes() -> Result<()> {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        let session = Session::new()?;
+
+        let tcp = TcpStream::connect(listener.local_addr()?)?;
+        SshKeepalive { interval: Some(0), count_max: None }.apply(&session, &tcp)?;
+        assert!(!SockRef::from(&tcp).keepalive()?);
+
+        SshKeepalive::default().apply(&session, &tcp)?;
+        assert!(SockRef::from(&tcp).keepalive()?);
+        #[cfg(target_os = "linux")]
+        {
+            assert_eq!(SockRef::from(&tcp).tcp_keepalive_time()?, Duration::from_secs(DEFAULT_KEEPALIVE_INTERVAL as u64));
+            assert_eq!(SockRef::from(&tcp).tcp_keepalive_retries()?, DEFAULT_KEEPALIVE_COUNT_MAX);
+        }
+        Ok(())
     }
 }
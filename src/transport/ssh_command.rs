@@ -4,6 +4,9 @@
 
 
 use std::path::PathBuf;
+use super::{SshAlgorithms, SshKeepalive};
+use super::hostkeys::StrictHostKeyChecking;
+use super::ssh_config::{expand_tilde, HostConfig};
 
 
 #[derive(Debug, Clone)]
@@ -13,7 +16,29 @@ pub struct SshConnectionParams {
 
     pub identity_file: Option<PathBuf>,
 
+    pub certificate_file: Option<PathBuf>,
+
+    pub user: Option<String>,
+
+    pub host_name: Option<String>,
+
+    pub proxy_jump: Option<String>,
+
+    pub config_file: Option<PathBuf>,
+
     pub extra_options: Vec<String>,
+
+    pub algorithms: SshAlgorithms,
+
+    pub keepalive: SshKeepalive,
+
+    pub password_prompts: Option<u32>,
+
+    pub batch_mode: bool,
+
+    pub strict_host_key_checking: Option<StrictHostKeyChecking>,
+
+    pub known_hosts_file: Option<PathBuf>,
 }
 
 impl Default for SshConnectionParams {
@@ -21,7 +46,31 @@ impl Default for SshConnectionParams {
         Self {
             port: None,
             identity_file: None,
+            certificate_file: None,
+            user: None,
+            host_name: None,
+            proxy_jump: None,
+            config_file: None,
             extra_options: Vec::new(),
+            algorithms: SshAlgorithms::default(),
+            keepalive: SshKeepalive::default(),
+            password_prompts: None,
+            batch_mode: false,
+            strict_host_key_checking: None,
+            known_hosts_file: None,
+        }
+    }
+}
+
+impl SshConnectionParams {
+    pub fn host_config(&self) -> HostConfig {
+        HostConfig {
+            host_name: self.host_name.clone(),
+            user: self.user.clone(),
+            port: self.port,
+            identity_file: self.identity_file.clone(),
+            certificate_file: self.certificate_file.clone(),
+            proxy_jump: self.proxy_jump.clone(),
         }
     }
 }
@@ -58,29 +107,46 @@ pub fn parse_ssh_command(command: &str) -> SshConnectionParams {
             "-i" | "--identity" => {
 
                 if i + 1 < parts.len() {
-                    let path = &parts[i + 1];
-                    let path = path.trim_matches('"');
-
-                    let expanded_path = if path.starts_with("~/") {
-                        if let Some(home) = dirs::home_dir() {
-                            home.join(&path[2..])
-                        } else {
-                            PathBuf::from(path)
-                        }
-                    } else {
-                        PathBuf::from(path)
-                    };
-                    params.identity_file = Some(expanded_path);
+                    params.identity_file = Some(expand_tilde(parts[i + 1].trim_matches('"')));
+                    i += 1;
+                }
+            }
+            "-l" => {
+
+                if i + 1 < parts.len() {
+                    params.user = Some(parts[i + 1].clone());
+                    i += 1;
+                }
+            }
+            "-F" => {
+
+                if i + 1 < parts.len() {
+                    params.config_file = Some(expand_tilde(parts[i + 1].trim_matches('"')));
+                    i += 1;
+                }
+            }
+            "-J" => {
+
+                if i + 1 < parts.len() {
+                    params.proxy_jump = Some(parts[i + 1].clone());
                     i += 1;
                 }
             }
             "-o" => {
 
                 if i + 1 < parts.len() {
+                    apply_config_option(&mut params, &parts[i + 1]);
                     params.extra_options.push(parts[i + 1].clone());
                     i += 1;
                 }
             }
+            "-c" => {
+
+                if i + 1 < parts.len() {
+                    params.algorithms.ciphers = Some(parts[i + 1].clone());
+                    i += 1;
+                }
+            }
             _ => {
 
                 if part.starts_with('-') {
@@ -95,6 +161,34 @@ pub fn parse_ssh_command(command: &str) -> SshConnectionParams {
     params
 }
 
+fn apply_config_option(params: &mut SshConnectionParams, option: &str) {
+    let Some((key, value)) = option.split_once(['=', ' ']) else {
+        return;
+    };
+    let value = value.trim();
+    let algorithms = &mut params.algorithms;
+
+    match key.trim().to_lowercase().as_str() {
+        "user" => params.user = Some(value.to_string()),
+        "port" => params.port = value.parse().ok().or(params.port),
+        "hostname" => params.host_name = Some(value.to_string()),
+        "identityfile" => params.identity_file = Some(expand_tilde(value.trim_matches('"'))),
+        "certificatefile" => params.certificate_file = Some(expand_tilde(value.trim_matches('"'))),
+        "proxyjump" => params.proxy_jump = Some(value.to_string()),
+        "ciphers" => algorithms.ciphers = Some(value.to_string()),
+        "kexalgorithms" => algorithms.kex = Some(value.to_string()),
+        "hostkeyalgorithms" => algorithms.host_keys = Some(value.to_string()),
+        "macs" => algorithms.macs = Some(value.to_string()),
+        "serveraliveinterval" => params.keepalive.interval = value.parse().ok(),
+        "serveralivecountmax" => params.keepalive.count_max = value.parse().ok(),
+        "numberofpasswordprompts" => params.password_prompts = value.parse().ok(),
+        "batchmode" => params.batch_mode = value.eq_ignore_ascii_case("yes"),
+        "stricthostkeychecking" => params.strict_host_key_checking = StrictHostKeyChecking::from_name(value),
+        "userknownhostsfile" => params.known_hosts_file = Some(expand_tilde(value.trim_matches('"'))),
+        _ => {}
+    }
+}
+
 fn tokenize_command(command: &str) -> Vec<String> {
     let mut tokens = Vec::new();
     let mut current_token = String::new();
@@ -135,6 +229,7 @@ fn tokenize_command(command: &str) -> Vec<String> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::transport::ssh::{DEFAULT_KEEPALIVE_COUNT_MAX, DEFAULT_KEEPALIVE_INTERVAL};
 
     #[test]
     fn test_parse_ssh_port() {
@@ -163,6 +258,70 @@ mod tests {
         assert_eq!(params.extra_options.len(), 1);
     }
 
+    #[test]
+    fn test_parse_ssh_algorithms() {
+        let params = parse_ssh_command(
+            r#"ssh -c aes256-ctr -o KexAlgorithms=+diffie-hellman-group1-sha1 -o "HostKeyAlgorithms ssh-rsa" -o MACs=hmac-sha2-256"#
+        );
+        assert_eq!(params.algorithms, SshAlgorithms {
+            ciphers: Some("aes256-ctr".to_string()),
+            kex: Some("+diffie-hellman-group1-sha1".to_string()),
+            host_keys: Some("ssh-rsa".to_string()),
+            macs: Some("hmac-sha2-256".to_string()),
+        });
+        assert_eq!(params.extra_options.len(), 3);
+    }
+
+    #[test]
+    fn test_parse_ssh_login_config_and_jump() {
+        let params = parse_ssh_command(
+            r#"ssh -l deploy -F "C:\Users\Test User\ssh_config" -J ops@bastion:2200 -o Port=2022 -o HostName=10.0.0.5 -o CertificateFile=/keys/id-cert.pub"#
+        );
+        assert_eq!(params.config_file, Some(PathBuf::from(r"C:\Users\Test User\ssh_config")));
+        assert_eq!(params.host_config(), HostConfig {
+            host_name: Some("10.0.0.5".to_string()),
+            user: Some("deploy".to_string()),
+            port: Some(2022),
+            identity_file: None,
+            certificate_file: Some(PathBuf::from("/keys/id-cert.pub")),
+            proxy_jump: Some("ops@bastion:2200".to_string()),
+        });
+        assert!(params.extra_options.iter().all(|option| !option.starts_with('-')));
+    }
+
+    #[test]
+    fn test_parse_ssh_keepalive() {
+        let params = parse_ssh_command("ssh -o ServerAliveInterval=30 -o ServerAliveCountMax=5");
+        assert_eq!(params.keepalive, SshKeepalive { interval: Some(30), count_max: Some(5) });
+        assert_eq!(params.keepalive.interval(), 30);
+
+        let defaults = parse_ssh_command("ssh -o ServerAliveInterval=never");
+        assert_eq!(defaults.keepalive.interval(), DEFAULT_KEEPALIVE_INTERVAL);
+        assert_eq!(defaults.keepalive.count_max(), DEFAULT_KEEPALIVE_COUNT_MAX);
+    }
+
+    #[test]
+    fn test_parse_ssh_password_prompts() {
+        let params = parse_ssh_command("ssh -o NumberOfPasswordPrompts=1 -o BatchMode=yes");
+        assert_eq!(params.password_prompts, Some(1));
+        assert!(params.batch_mode);
+
+        let defaults = parse_ssh_command("ssh -o BatchMode=no");
+        assert_eq!(defaults.password_prompts, None);
+        assert!(!defaults.batch_mode);
+    }
+
+    #[test]
+    fn test_parse_ssh_host_key_options() {
+        let params = parse_ssh_command("ssh -o StrictHostKeyChecking=accept-new -o UserKnownHostsFile=C:/keys/known_hosts");
+        assert_eq!(params.strict_host_key_checking, Some(StrictHostKeyChecking::AcceptNew));
+        assert_eq!(params.known_hosts_file, Some(PathBuf::from("C:/keys/known_hosts")));
+
+        let defaults = parse_ssh_command("ssh");
+        assert_eq!(defaults.strict_host_key_checking, None);
+        assert_eq!(defaults.known_hosts_file, None);
+    }
+
     #[test]
     fn test_parse_ssh_quoted_path() {
         let params = parse_ssh_command(r#"ssh -p 10022 -i "C:\Users\Test User\key.pem""#);
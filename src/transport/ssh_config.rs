@@ -0,0 +1,280 @@
+use crate::error::{Result, RsyncError};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HostConfig {
+    pub host_name: Option<String>,
+    pub user: Option<String>,
+    pub port: Option<u16>,
+    pub identity_file: Option<PathBuf>,
+    pub certificate_file: Option<PathBuf>,
+    pub proxy_jump: Option<String>,
+}
+
+impl HostConfig {
+    pub fn or(self, fallback: HostConfig) -> Self {
+        HostConfig {
+            host_name: self.host_name.or(fallback.host_name),
+            user: self.user.or(fallback.user),
+            port: self.port.or(fallback.port),
+            identity_file: self.identity_file.or(fallback.identity_file),
+            certificate_file: self.certificate_file.or(fallback.certificate_file),
+            proxy_jump: self.proxy_jump.or(fallback.proxy_jump),
+        }
+    }
+
+
+    pub fn set(&mut self, key: &str, value: &str) -> bool {
+        let value = value.trim().trim_matches('"');
+        match key.to_lowercase().as_str() {
+            "hostname" => self.host_name = Some(value.to_string()),
+            "user" => self.user = Some(value.to_string()),
+            "port" => self.port = value.parse().ok(),
+            "identityfile" => self.identity_file = Some(expand_tilde(value)),
+            "certificatefile" => self.certificate_file = Some(expand_tilde(value)),
+            "proxyjump" => self.proxy_jump = Some(value.to_string()),
+            _ => return false,
+        }
+        true
+    }
+
+
+    pub fn jump_hosts(&self) -> Vec<JumpHost> {
+        match self.proxy_jump.as_deref() {
+            None | Some("none") => Vec::new(),
+            Some(spec) => spec.split(',').map(str::trim).filter(|hop| !hop.is_empty()).map(JumpHost::parse).collect(),
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JumpHost {
+    pub user: Option<String>,
+    pub host: String,
+    pub port: Option<u16>,
+}
+
+impl JumpHost {
+    pub fn parse(spec: &str) -> Self {
+        let spec = spec.strip_prefix("ssh://").unwrap_or(spec);
+        let (user, host_port) = match spec.rsplit_once('@') {
+            Some((user, rest)) => (Some(user.to_string()), rest),
+            None => (None, spec),
+        };
+        let (host, port) = match host_port.strip_prefix('[').and_then(|rest| rest.split_once(']')) {
+            Some((host, rest)) => (host, rest.strip_prefix(':').and_then(|port| port.parse().ok())),
+            None => match host_port.split_once(':') {
+                Some((host, port)) => (host, port.parse().ok()),
+                None => (host_port, None),
+            },
+        };
+        JumpHost { user, host: host.to_string(), port }
+    }
+}
+
+
+#[derive(Debug, Clone, Default)]
+struct HostBlock {
+    patterns: Vec<String>,
+    settings: Vec<(String, String)>,
+}
+
+
+#[derive(Debug, Clone, Default)]
+pub struct SshConfig {
+    blocks: Vec<HostBlock>,
+}
+
+impl SshConfig {
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path).map_err(|e| RsyncError::Config(format!(
+            "Failed to read SSH config file {}: {}",
+            path.display(),
+            e
+        )))?;
+        Ok(Self::parse(&contents))
+    }
+
+
+    pub fn load_default() -> Result<Self> {
+        let path = expand_tilde("~/.ssh/config");
+        if !path.is_file() {
+            return Ok(Self::default());
+        }
+        Self::load(&path)
+    }
+
+
+    pub fn parse(contents: &str) -> Self {
+        let mut blocks = vec![HostBlock { patterns: vec!["*".to_string()], settings: Vec::new() }];
+
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = match line.split_once(|c: char| c == '=' || c.is_whitespace()) {
+                Some((key, value)) => (key.trim(), value.trim().trim_start_matches('=').trim()),
+                None => continue,
+            };
+
+            match key.to_lowercase().as_str() {
+                "host" => blocks.push(HostBlock {
+                    patterns: value.split_whitespace().map(str::to_string).collect(),
+                    settings: Vec::new(),
+                }),
+                "match" => blocks.push(HostBlock::default()),
+                _ => {
+                    if let Some(block) = blocks.last_mut() {
+                        block.settings.push((key.to_string(), value.to_string()));
+                    }
+                }
+            }
+        }
+
+        SshConfig { blocks }
+    }
+
+
+    pub fn resolve(&self, alias: &str) -> HostConfig {
+        let mut config = HostConfig::default();
+        let mut seen = Vec::new();
+
+        for block in &self.blocks {
+            if !host_matches(&block.patterns, alias) {
+                continue;
+            }
+            for (key, value) in &block.settings {
+                let key = key.to_lowercase();
+                if seen.contains(&key) {
+                    continue;
+                }
+                if config.set(&key, value) {
+                    seen.push(key);
+                }
+            }
+        }
+
+        if let Some(ref mut host_name) = config.host_name {
+            *host_name = host_name.replace("%h", alias);
+        }
+        config
+    }
+}
+
+
+pub fn expand_tilde(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), dirs::home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+
+fn host_matches(patterns: &[String], alias: &str) -> bool {
+    let mut matched = false;
+    for pattern in patterns {
+        match pattern.strip_prefix('!') {
+            Some(negated) if wildcard_match(negated, alias) => return false,
+            Some(_) => {}
+            None => matched |= wildcard_match(pattern, alias),
+        }
+    }
+    matched
+}
+
+
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            t = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = "
+# Corporate hosts
+Host build build.internal
+    HostName 10.0.0.12
+    User deploy
+    Port 2222
+    IdentityFile /keys/build
+    CertificateFile /keys/build-cert.pub
+
+Host *.internal !bastion.internal
+    ProxyJump admin@bastion.internal:2200
+    User fallback
+
+Host *
+    User everyone
+";
+
+    #[test]
+    fn test_resolve_first_value_wins() {
+        let config = SshConfig::parse(CONFIG);
+
+        let build = config.resolve("build");
+        assert_eq!(build, HostConfig {
+            host_name: Some("10.0.0.12".to_string()),
+            user: Some("deploy".to_string()),
+            port: Some(2222),
+            identity_file: Some(PathBuf::from("/keys/build")),
+            certificate_file: Some(PathBuf::from("/keys/build-cert.pub")),
+            proxy_jump: None,
+        });
+
+        let db = config.resolve("db.internal");
+        assert_eq!(db.user.as_deref(), Some("fallback"));
+        assert_eq!(db.jump_hosts(), vec![JumpHost {
+            user: Some("admin".to_string()),
+            host: "bastion.internal".to_string(),
+            port: Some(2200),
+        }]);
+
+        let bastion = config.resolve("bastion.internal");
+        assert_eq!(bastion.user.as_deref(), Some("everyone"));
+        assert!(bastion.jump_hosts().is_empty());
+    }
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match("*.example.com", "www.EXAMPLE.com"));
+        assert!(wildcard_match("web?", "web1"));
+        assert!(!wildcard_match("web?", "web10"));
+        assert!(wildcard_match("*", ""));
+        assert!(!wildcard_match("db*", "web"));
+    }
+
+    #[test]
+    fn test_jump_host_parse() {
+        assert_eq!(JumpHost::parse("gw"), JumpHost { user: None, host: "gw".to_string(), port: None });
+        assert_eq!(
+            JumpHost::parse("ssh://ops@[fe80::1]:22"),
+            JumpHost { user: Some("ops".to_string()), host: "fe80::1".to_string(), port: Some(22) }
+        );
+        assert_eq!(HostConfig { proxy_jump: Some("a,b:2022".to_string()), ..HostConfig::default() }.jump_hosts().len(), 2);
+    }
+}
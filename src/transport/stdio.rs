@@ -0,0 +1,193 @@
+use crate::error::Result;
+use std::io::{self, BufWriter, Read, Write};
+
+
+pub struct StdioStream {
+    stdin: Box<dyn Read + Send>,
+    stdout: BufWriter<Box<dyn Write + Send>>,
+}
+
+impl StdioStream {
+
+    pub fn open() -> Result<Self> {
+        let (stdin, stdout) = platform::binary_stdio()?;
+        Ok(Self::from_parts(stdin, stdout))
+    }
+
+    fn from_parts(stdin: Box<dyn Read + Send>, stdout: Box<dyn Write + Send>) -> Self {
+        Self {
+            stdin,
+            stdout: BufWriter::new(stdout),
+        }
+    }
+}
+
+impl Read for StdioStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stdout.flush()?;
+
+        loop {
+            match self.stdin.read(buf) {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) if e.kind() == io::ErrorKind::BrokenPipe => return Ok(0),
+                result => return result,
+            }
+        }
+    }
+}
+
+impl Write for StdioStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stdout.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stdout.flush()
+    }
+}
+
+
+#[cfg(windows)]
+mod platform {
+    use crate::error::Result;
+    use std::fs::File;
+    use std::io::{self, Read, Write};
+    use std::mem::ManuallyDrop;
+    use std::os::windows::io::FromRawHandle;
+    use windows::Win32::Foundation::HANDLE;
+    use windows::Win32::Storage::FileSystem::{GetFileType, FILE_TYPE_CHAR};
+    use windows::Win32::System::Console::{GetStdHandle, STD_HANDLE, STD_INPUT_HANDLE, STD_OUTPUT_HANDLE};
+
+    const O_BINARY: i32 = 0x8000;
+
+    extern "C" {
+        fn _setmode(fd: i32, mode: i32) -> i32;
+    }
+
+
+    struct RawStdHandle(ManuallyDrop<File>);
+
+    impl Read for RawStdHandle {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    impl Write for RawStdHandle {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.0.flush()
+        }
+    }
+
+    fn raw_handle(kind: STD_HANDLE) -> Option<RawStdHandle> {
+        let handle: HANDLE = unsafe { GetStdHandle(kind) }.ok()?;
+        if handle.is_invalid() || unsafe { GetFileType(handle) } == FILE_TYPE_CHAR {
+            return None;
+        }
+
+        let file = unsafe { File::from_raw_handle(handle.0 as _) };
+        Some(RawStdHandle(ManuallyDrop::new(file)))
+    }
+
+
+    pub fn binary_stdio() -> Result<(Box<dyn Read + Send>, Box<dyn Write + Send>)> {
+        unsafe {
+            _setmode(0, O_BINARY);
+            _setmode(1, O_BINARY);
+        }
+
+        let stdin: Box<dyn Read + Send> = match raw_handle(STD_INPUT_HANDLE) {
+            Some(handle) => Box::new(handle),
+            None => Box::new(io::stdin()),
+        };
+        let stdout: Box<dyn Write + Send> = match raw_handle(STD_OUTPUT_HANDLE) {
+            Some(handle) => Box::new(handle),
+            None => Box::new(io::stdout()),
+        };
+
+        Ok((stdin, stdout))
+    }
+}
+
+
+#[cfg(not(windows))]
+mod platform {
+    use crate::error::Result;
+    use std::io::{self, Read, Write};
+
+    pub fn binary_stdio() -> Result<(Box<dyn Read + Send>, Box<dyn Write + Send>)> {
+        Ok((Box::new(io::stdin()), Box::new(io::stdout())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    struct FlakyReader {
+        steps: Vec<io::Result<Vec<u8>>>,
+    }
+
+    impl Read for FlakyReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.steps.is_empty() {
+                return Ok(0);
+            }
+            let data = self.steps.remove(0)?;
+            buf[..data.len()].copy_from_slice(&data);
+            Ok(data.len())
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct SharedWriter(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_partial_reads_and_broken_pipe() -> Result<()> {
+        let reader = FlakyReader {
+            steps: vec![
+                Err(io::ErrorKind::Interrupted.into()),
+                Ok(b"ab".to_vec()),
+                Ok(b"\r\n".to_vec()),
+                Err(io::ErrorKind::BrokenPipe.into()),
+            ],
+        };
+        let mut stream = StdioStream::from_parts(Box::new(reader), Box::new(SharedWriter::default()));
+
+        let mut data = Vec::new();
+        stream.read_to_end(&mut data)?;
+        assert_eq!(data, b"ab\r\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_pending_output_flushed_before_read() -> Result<()> {
+        let output = SharedWriter::default();
+        let reader = FlakyReader { steps: vec![Ok(vec![1])] };
+        let mut stream = StdioStream::from_parts(Box::new(reader), Box::new(output.clone()));
+
+        stream.write_all(b"\n\x00\r")?;
+        assert!(output.0.lock().unwrap().is_empty());
+
+        let mut byte = [0u8; 1];
+        stream.read_exact(&mut byte)?;
+        assert_eq!(output.0.lock().unwrap().as_slice(), b"\n\x00\r");
+        Ok(())
+    }
+}